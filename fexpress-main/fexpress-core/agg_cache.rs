@@ -0,0 +1,150 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+use crate::ast::core::{AggrExpr, Expr, ExprFunc};
+use crate::ast::traverse::traverse_expr;
+use crate::event_index::EventScopeConfig;
+use crate::interval::NaiveDateTimeInterval;
+use crate::map::HashMap;
+use crate::value::Value;
+
+// the time zone of the query, entities with their own time zone don't share the results
+type CacheKey = (AggrExpr, NaiveDateTimeInterval, Option<String>);
+
+/// Cache of aggregation results that do not depend on the entity they are evaluated for.
+///
+/// A single cache is shared by all the entities of one `extract_records_from_expr` call,
+/// so an aggregation like the platform wide average order value over the last 7 days
+/// is calculated once per materialized interval instead of once per entity.
+#[derive(Debug, Default)]
+pub struct AggregationCache {
+    inner: RwLock<HashMap<CacheKey, Value>>,
+    // number of the results returned by `get`
+    hits: AtomicUsize,
+}
+
+impl AggregationCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(
+        &self,
+        agg: &AggrExpr,
+        interval: &NaiveDateTimeInterval,
+        time_zone: Option<&str>,
+    ) -> Option<Value> {
+        let inner = self.inner.read().ok()?;
+        let value = inner
+            .get(&(agg.clone(), interval.clone(), time_zone.map(String::from)))
+            .cloned()?;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(value)
+    }
+
+    pub fn insert(
+        &self,
+        agg: &AggrExpr,
+        interval: &NaiveDateTimeInterval,
+        time_zone: Option<&str>,
+        value: Value,
+    ) {
+        if let Ok(mut inner) = self.inner.write() {
+            inner.insert(
+                (agg.clone(), interval.clone(), time_zone.map(String::from)),
+                value,
+            );
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.read().map(|inner| inner.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of the aggregations answered from the cache
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns true if the result of the aggregation depends only on the materialized interval.
+///
+/// This requires the events to be queried regardless of the entity (a global aggregation or
+/// the `AllEvents` scope) and the aggregation to never reference the entity, the context event
+/// or the observation date.
+/// Nested aggregations are excluded as well because their intervals are materialized
+/// against the observation date rather than the interval of the outer aggregation.
+pub fn is_entity_independent(agg: &AggrExpr, event_scope_config: &EventScopeConfig) -> bool {
+    if !agg.global && !matches!(event_scope_config, EventScopeConfig::AllEvents) {
+        return false;
+    }
+    let dependencies = traverse_expr(&Expr::Aggr(agg.clone()), &|expr| match expr {
+        Expr::ContextAttr(_)
+        | Expr::AsOf(_, _)
+        | Expr::EntityId(_)
+        | Expr::ObservationDate
+        | Expr::Function(ExprFunc::Now)
+        | Expr::Function(ExprFunc::CurrentDate)
+        | Expr::Function(ExprFunc::CurrentTime) => Some(()),
+        Expr::Aggr(inner) if inner != agg => Some(()),
+        _ => None,
+    });
+    dependencies.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::event::EntityType;
+
+    fn parse_aggr(expr: &str) -> AggrExpr {
+        Expr::from_str(expr)
+            .unwrap()
+            .into_aggr()
+            .expect("aggregation expected")
+    }
+
+    #[test]
+    fn test_is_entity_independent() {
+        let all_events = EventScopeConfig::AllEvents;
+        let related = EventScopeConfig::RelatedEntitiesEvents(vec![EntityType::from("location")]);
+
+        let agg = parse_aggr("avg(value) over last 7 days where event_type = 'order'");
+        assert!(is_entity_independent(&agg, &all_events));
+        assert!(!is_entity_independent(&agg, &related));
+
+        let agg = parse_aggr("avg(value) over last 7 days global where event_type = 'order'");
+        assert!(is_entity_independent(&agg, &related));
+
+        let agg = parse_aggr("count(*) over past where value > @threshold");
+        assert!(!is_entity_independent(&agg, &all_events));
+
+        let agg = parse_aggr("count(*) over past where event_time > obs_dt");
+        assert!(!is_entity_independent(&agg, &all_events));
+    }
+
+    #[test]
+    fn test_cache_get_insert() {
+        let cache = AggregationCache::new();
+        let agg = parse_aggr("sum(value) over past");
+        let interval = NaiveDateTimeInterval {
+            start_dt: None,
+            end_dt: Some(NaiveDate::from_ymd(2020, 1, 1).and_hms(0, 0, 0)),
+        };
+        assert!(cache.get(&agg, &interval, None).is_none());
+        cache.insert(&agg, &interval, None, Value::Num(1.0));
+        assert_eq!(cache.get(&agg, &interval, None), Some(Value::Num(1.0)));
+        // results calculated in another time zone are not shared
+        assert!(cache.get(&agg, &interval, Some("Asia/Tokyo")).is_none());
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.hits(), 1);
+    }
+}
@@ -38,10 +38,18 @@ pub fn eval_agg_using_partial_agg_backup(
     stored_variables: &HashMap<SmallString, HashMap<Timestamp, Value>>,
 ) -> Result<HashMap<NaiveDateTime, Value>> {
     let obs_dates: Vec<_> = context.get_sorted_obs_dates()?;
+    let calendar = context.holiday_calendar()?;
+    let time_zone = context.time_zone()?;
 
     let intervals: Vec<_> = obs_dates
         .iter()
-        .filter_map(|obs_date| Some(agg.when.materialize_interval(obs_date)))
+        .filter_map(|obs_date| {
+            Some(agg.when.materialize_interval_in_zone(
+                obs_date,
+                calendar.as_ref(),
+                time_zone.as_ref(),
+            ))
+        })
         .flatten()
         .collect();
 
@@ -147,10 +155,18 @@ pub fn eval_agg_using_partial_agg(
     stored_variables: &HashMap<SmallString, HashMap<Timestamp, Value>>,
 ) -> Result<HashMap<NaiveDateTime, Value>> {
     let obs_dates: Vec<_> = context.get_sorted_obs_dates()?;
+    let calendar = context.holiday_calendar()?;
+    let time_zone = context.time_zone()?;
 
     let intervals: Vec<_> = obs_dates
         .iter()
-        .filter_map(|obs_date| Some(agg.when.materialize_interval(obs_date)))
+        .filter_map(|obs_date| {
+            Some(agg.when.materialize_interval_in_zone(
+                obs_date,
+                calendar.as_ref(),
+                time_zone.as_ref(),
+            ))
+        })
         .flatten()
         .collect();
 
@@ -398,6 +414,7 @@ mod tests {
                 event_types: event_types.clone(),
                 event: None,
                 event_on_obs_date: None,
+                agg_cache: context.agg_cache,
             };
             let stored_variables = HashMap::new();
             let value = eval_agg(&agg, &context, &stored_variables)?;
@@ -480,6 +497,7 @@ mod tests {
             event_types: vec![],
             event: None,
             event_on_obs_date: None,
+            agg_cache: None,
         };
 
         for agg in vec![
@@ -0,0 +1,787 @@
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use arrow::array::{
+    new_null_array, Array, ArrayRef, AsArray, BooleanArray, BooleanBuilder, Date32Array,
+    Float32Array, Float32Builder, Int32Array, Int32Builder, ListBuilder, MapBuilder, StringArray,
+    StringBuilder, TimestampMicrosecondArray,
+};
+use arrow::compute::cast;
+use arrow::datatypes::{
+    DataType, Date32Type, Field, Float32Type, Int32Type, Int64Type, Schema, TimeUnit,
+    TimestampMicrosecondType, UInt64Type,
+};
+use arrow::record_batch::RecordBatch;
+use chrono::{NaiveDate, NaiveDateTime};
+use itertools::Itertools;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use rayon::prelude::*;
+
+use crate::event::{AttributeName, EntityID, EntityType, Event, EventType};
+use crate::event_store::EventStoreImpl;
+use crate::ingest::{
+    insert_parallel, parse_timestamp, AttributeType, EventMapping, EventTypeMapping,
+};
+use crate::map::HashMap;
+use crate::sstring::SmallString;
+use crate::types::{Entities, FLOAT, INT};
+use crate::value::Value;
+
+fn unix_epoch() -> NaiveDate {
+    NaiveDate::from_ymd_opt(1970, 1, 1).expect("Cannot build the unix epoch")
+}
+
+impl AttributeType {
+    fn arrow_data_type(&self) -> DataType {
+        match self {
+            AttributeType::Bool => DataType::Boolean,
+            AttributeType::Int => DataType::Int32,
+            AttributeType::Num => DataType::Float32,
+            AttributeType::Str => DataType::Utf8,
+            AttributeType::Date => DataType::Date32,
+            AttributeType::DateTime => DataType::Timestamp(TimeUnit::Microsecond, None),
+        }
+    }
+}
+
+/// Converts an Arrow array to values. Integers are `Int` and fail if they don't fit, floats are
+/// `Num`, lists are `Vec*` and maps with string keys are `MapNum` / `MapStr`
+pub fn array_to_values(array: &dyn Array) -> Result<Vec<Value>> {
+    let values = match array.data_type() {
+        DataType::Null => vec![Value::None; array.len()],
+        DataType::Boolean => array
+            .as_boolean()
+            .iter()
+            .map(|v| v.map(Value::Bool).unwrap_or(Value::None))
+            .collect(),
+        DataType::Int8 | DataType::Int16 | DataType::UInt8 | DataType::UInt16 => {
+            array_to_values(cast(array, &DataType::Int32)?.as_ref())?
+        }
+        DataType::Int32 => array
+            .as_primitive::<Int32Type>()
+            .iter()
+            .map(|v| v.map(|v| Value::Int(v as INT)).unwrap_or(Value::None))
+            .collect(),
+        DataType::Int64 | DataType::UInt32 => cast(array, &DataType::Int64)?
+            .as_primitive::<Int64Type>()
+            .iter()
+            .map(|v| v.map(int_value).unwrap_or(Ok(Value::None)))
+            .collect::<Result<_>>()?,
+        DataType::UInt64 => array
+            .as_primitive::<UInt64Type>()
+            .iter()
+            .map(|v| v.map(int_value).unwrap_or(Ok(Value::None)))
+            .collect::<Result<_>>()?,
+        DataType::Float16 | DataType::Float64 | DataType::Decimal128(_, _) => {
+            array_to_values(cast(array, &DataType::Float32)?.as_ref())?
+        }
+        DataType::Float32 => array
+            .as_primitive::<Float32Type>()
+            .iter()
+            .map(|v| v.map(Value::Num).unwrap_or(Value::None))
+            .collect(),
+        DataType::Utf8 => array
+            .as_string::<i32>()
+            .iter()
+            .map(|v| v.map(|v| Value::Str(v.into())).unwrap_or(Value::None))
+            .collect(),
+        DataType::LargeUtf8 | DataType::Dictionary(_, _) => {
+            array_to_values(cast(array, &DataType::Utf8)?.as_ref())?
+        }
+        DataType::Date64 => array_to_values(cast(array, &DataType::Date32)?.as_ref())?,
+        DataType::Date32 => array
+            .as_primitive::<Date32Type>()
+            .iter()
+            .map(|v| {
+                v.map(|days| Value::Date(unix_epoch() + chrono::Duration::days(days as i64)))
+                    .unwrap_or(Value::None)
+            })
+            .collect(),
+        // the values of timestamps with a time zone are in UTC, only the unit is converted
+        DataType::Timestamp(TimeUnit::Microsecond, _) => array
+            .as_primitive::<TimestampMicrosecondType>()
+            .iter()
+            .map(|v| {
+                v.and_then(NaiveDateTime::from_timestamp_micros)
+                    .map(Value::DateTime)
+                    .unwrap_or(Value::None)
+            })
+            .collect(),
+        DataType::Timestamp(_, time_zone) => array_to_values(
+            cast(
+                array,
+                &DataType::Timestamp(TimeUnit::Microsecond, time_zone.clone()),
+            )?
+            .as_ref(),
+        )?,
+        DataType::List(field) => {
+            let list = array.as_list::<i32>();
+            (0..list.len())
+                .map(|i| {
+                    if list.is_null(i) {
+                        return Ok(Value::None);
+                    }
+                    list_value(field.data_type(), array_to_values(list.value(i).as_ref())?)
+                })
+                .collect::<Result<_>>()?
+        }
+        DataType::LargeList(field) => array_to_values(
+            cast(array, &DataType::List(Arc::new(field.as_ref().clone())))?.as_ref(),
+        )?,
+        DataType::Map(entries, _) => {
+            let string_values = match entries.data_type() {
+                DataType::Struct(fields) => matches!(
+                    fields.get(1).map(|field| field.data_type()),
+                    Some(DataType::Utf8 | DataType::LargeUtf8 | DataType::Dictionary(_, _))
+                ),
+                _ => false,
+            };
+            let map = array.as_map();
+            let keys = array_to_values(map.keys().as_ref())?;
+            let values = array_to_values(map.values().as_ref())?;
+            map.value_offsets()
+                .windows(2)
+                .enumerate()
+                .map(|(i, offsets)| {
+                    if map.is_null(i) {
+                        return Ok(Value::None);
+                    }
+                    let (start, end) = (offsets[0] as usize, offsets[1] as usize);
+                    map_value(&keys[start..end], &values[start..end], string_values)
+                })
+                .collect::<Result<_>>()?
+        }
+        typ => bail!("Unsupported Arrow type {:?}", typ),
+    };
+    Ok(values)
+}
+
+/// The integers which don't fit are rejected instead of being converted to floats which would
+/// lose their precision
+fn int_value<T: std::convert::TryInto<INT> + Copy + std::fmt::Display>(value: T) -> Result<Value> {
+    value
+        .try_into()
+        .map(Value::Int)
+        .map_err(|_| anyhow!("Integer {} is out of the supported range", value))
+}
+
+fn is_integer(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+    )
+}
+
+fn list_value(item_type: &DataType, items: Vec<Value>) -> Result<Value> {
+    let items = items.into_iter().filter(|item| !item.is_null());
+    Ok(match item_type {
+        DataType::Boolean => Value::VecBool(items.filter_map(|v| as_bool(&v)).collect()),
+        DataType::Float16 | DataType::Float32 | DataType::Float64 => {
+            Value::VecNum(items.filter_map(Option::<FLOAT>::from).collect())
+        }
+        DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::UInt8 | DataType::UInt16 => {
+            Value::VecInt(
+                items
+                    .filter_map(|v| match v {
+                        Value::Int(v) => Some(v),
+                        _ => None,
+                    })
+                    .collect(),
+            )
+        }
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Dictionary(_, _) => Value::VecStr(
+            items
+                .filter_map(|v| match v {
+                    Value::Str(v) => Some(v),
+                    _ => None,
+                })
+                .collect(),
+        ),
+        typ => bail!("Unsupported Arrow list type {:?}", typ),
+    })
+}
+
+fn map_value(keys: &[Value], values: &[Value], string_values: bool) -> Result<Value> {
+    let keys = keys.iter().map(|key| match key {
+        Value::Str(key) => Ok(AttributeName(key.clone())),
+        _ => Err(anyhow!("Map keys must be strings")),
+    });
+    if string_values {
+        let mut map = HashMap::new();
+        for (key, value) in keys.zip(values) {
+            if let Value::Str(value) = value {
+                map.insert(key?, value.clone());
+            }
+        }
+        Ok(Value::MapStr(map))
+    } else {
+        let mut map = HashMap::new();
+        for (key, value) in keys.zip(values) {
+            if let Some(value) = Option::<FLOAT>::from(value.clone()) {
+                map.insert(key?, value);
+            }
+        }
+        Ok(Value::MapNum(map))
+    }
+}
+
+fn as_bool(value: &Value) -> Option<bool> {
+    match value {
+        Value::Bool(v) => Some(*v),
+        _ => None,
+    }
+}
+
+fn text(value: &Value) -> Option<SmallString> {
+    match value {
+        Value::None => None,
+        Value::Str(v) => Some(v.clone()),
+        Value::Int(v) => Some(from_string!(v.to_string())),
+        Value::Num(v) => Some(from_string!(v.to_string())),
+        Value::Bool(v) => Some(from_string!(v.to_string())),
+        value => Some(from_string!(format!("{:?}", value))),
+    }
+}
+
+fn event_time(value: &Value, mapping: &EventMapping) -> Result<NaiveDateTime> {
+    match value {
+        Value::DateTime(dt) => Ok(*dt),
+        Value::Date(date) => Ok(date.and_hms_opt(0, 0, 0).context("Invalid date")?),
+        Value::None => bail!("Missing event time in column {}", mapping.event_time),
+        value => parse_timestamp(
+            text(value).context("Missing event time")?.as_str(),
+            &mapping.timestamp_format,
+        ),
+    }
+}
+
+/// Converts the rows of the record batch to events
+pub fn record_batch_to_events(batch: &RecordBatch, mapping: &EventMapping) -> Result<Vec<Event>> {
+    let schema = batch.schema();
+    let reserved_columns = mapping.reserved_columns();
+    let columns: HashMap<&str, Vec<Value>> = schema
+        .fields()
+        .iter()
+        .zip(batch.columns())
+        .filter(|(field, _)| {
+            reserved_columns.contains(field.name().as_str())
+                || mapping.is_attribute(&reserved_columns, field.name())
+        })
+        .collect_vec()
+        .into_par_iter()
+        .map(|(field, array)| {
+            // integer ids and epoch timestamps are converted to text directly so that they
+            // don't have to fit the attribute integers
+            let values = if reserved_columns.contains(field.name().as_str())
+                && is_integer(field.data_type())
+            {
+                array_to_values(cast(array, &DataType::Utf8)?.as_ref())
+            } else {
+                match mapping.attributes.get(field.name()) {
+                    Some(typ) => array_to_values(cast(array, &typ.arrow_data_type())?.as_ref()),
+                    None => array_to_values(array.as_ref()),
+                }
+            }
+            .with_context(|| format!("Cannot convert column {}", field.name()))?;
+            Ok((field.name().as_str(), values))
+        })
+        .collect::<Result<_>>()?;
+    let column = |name: &str| {
+        columns
+            .get(name)
+            .with_context(|| format!("Missing column {}", name))
+    };
+    let event_times = column(&mapping.event_time)?;
+    let event_types = match &mapping.event_type {
+        EventTypeMapping::Constant(_) => None,
+        EventTypeMapping::Column(name) => Some(column(name)?),
+    };
+    let entities = mapping
+        .entities
+        .iter()
+        .map(|(entity_type, name)| Ok((EntityType(entity_type.as_str().into()), column(name)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let event_ids = mapping.event_id.as_deref().map(column).transpose()?;
+    let experiment_ids = mapping.experiment_id.as_deref().map(column).transpose()?;
+    let attributes = columns
+        .iter()
+        .filter(|(name, _)| !reserved_columns.contains(*name))
+        .map(|(name, values)| (AttributeName(SmallString::from(*name)), values))
+        .collect_vec();
+
+    (0..batch.num_rows())
+        .into_par_iter()
+        .map(|row| {
+            let event_type = match (&mapping.event_type, event_types) {
+                (EventTypeMapping::Constant(event_type), _) => event_type.as_str().into(),
+                (EventTypeMapping::Column(name), Some(values)) => text(&values[row])
+                    .with_context(|| format!("Missing event type in column {}", name))?,
+                _ => unreachable!(),
+            };
+            let event_entities: Entities = entities
+                .iter()
+                .filter_map(|(entity_type, values)| {
+                    text(&values[row]).map(|id| (entity_type.clone(), EntityID(id)))
+                })
+                .collect();
+            let attrs: HashMap<AttributeName, Value> = attributes
+                .iter()
+                .filter(|(_, values)| !values[row].is_null())
+                .map(|(name, values)| (name.clone(), values[row].clone()))
+                .collect();
+            Ok(Event {
+                event_type: EventType(event_type),
+                event_time: event_time(&event_times[row], mapping)
+                    .with_context(|| format!("Cannot load row {}", row + 1))?,
+                entities: event_entities,
+                event_id: event_ids.and_then(|ids| text(&ids[row])),
+                experiment_id: experiment_ids.and_then(|ids| text(&ids[row])),
+                attrs: if attrs.is_empty() { None } else { Some(attrs) },
+            })
+        })
+        .collect()
+}
+
+/// Inserts the rows of the record batch as events, returns the number of loaded events
+pub fn load_record_batch(
+    event_store: &EventStoreImpl,
+    batch: &RecordBatch,
+    mapping: &EventMapping,
+) -> Result<usize> {
+    let events = record_batch_to_events(batch, mapping)?;
+    let n_events = events.len();
+    insert_parallel(event_store, events)?;
+    Ok(n_events)
+}
+
+/// Loads the events from a Parquet file, returns the number of loaded events
+pub fn load_parquet(
+    event_store: &EventStoreImpl,
+    path: impl AsRef<Path>,
+    mapping: &EventMapping,
+) -> Result<usize> {
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+    let reader = ParquetRecordBatchReaderBuilder::try_new(file)?.build()?;
+    let mut n_events = 0;
+    for batch in reader {
+        n_events += load_record_batch(event_store, &batch?, mapping)
+            .with_context(|| format!("Cannot load {}", path.display()))?;
+    }
+    Ok(n_events)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnType {
+    Null,
+    Bool,
+    Int,
+    Num,
+    Str,
+    Date,
+    DateTime,
+    VecBool,
+    VecNum,
+    VecInt,
+    VecStr,
+    MapNum,
+    MapStr,
+    // values of different types are written as text
+    Other,
+}
+
+impl ColumnType {
+    fn of(value: &Value) -> ColumnType {
+        match value {
+            Value::None => ColumnType::Null,
+            Value::Bool(_) => ColumnType::Bool,
+            Value::Int(_) => ColumnType::Int,
+            Value::Num(_) => ColumnType::Num,
+            Value::Str(_) => ColumnType::Str,
+            Value::Date(_) => ColumnType::Date,
+            Value::DateTime(_) => ColumnType::DateTime,
+            Value::VecBool(_) => ColumnType::VecBool,
+            Value::VecNum(_) => ColumnType::VecNum,
+            Value::VecInt(_) => ColumnType::VecInt,
+            Value::VecStr(_) => ColumnType::VecStr,
+            Value::MapNum(_) => ColumnType::MapNum,
+            Value::MapStr(_) => ColumnType::MapStr,
+            _ => ColumnType::Other,
+        }
+    }
+
+    fn merge(self, other: ColumnType) -> ColumnType {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (ColumnType::Null, b) => b,
+            (a, ColumnType::Null) => a,
+            (ColumnType::Int, ColumnType::Num) | (ColumnType::Num, ColumnType::Int) => {
+                ColumnType::Num
+            }
+            _ => ColumnType::Other,
+        }
+    }
+}
+
+fn map_builder<V: arrow::array::ArrayBuilder>(values: V) -> MapBuilder<StringBuilder, V> {
+    MapBuilder::new(None, StringBuilder::new(), values)
+}
+
+/// Builds an Arrow array from the values of a single column
+pub fn values_to_array<'a>(values: impl Iterator<Item = &'a Value> + Clone) -> Result<ArrayRef> {
+    let column_type = values.clone().fold(ColumnType::Null, |typ, value| {
+        typ.merge(ColumnType::of(value))
+    });
+    let array: ArrayRef = match column_type {
+        ColumnType::Null => new_null_array(&DataType::Null, values.count()),
+        ColumnType::Bool => Arc::new(values.map(as_bool).collect::<BooleanArray>()),
+        ColumnType::Int => Arc::new(
+            values
+                .map(|v| match v {
+                    Value::Int(v) => Some(*v),
+                    _ => None,
+                })
+                .collect::<Int32Array>(),
+        ),
+        ColumnType::Num => Arc::new(
+            values
+                .map(|v| Option::<FLOAT>::from(v.clone()))
+                .collect::<Float32Array>(),
+        ),
+        ColumnType::Str => Arc::new(
+            values
+                .map(|v| match v {
+                    Value::Str(v) => Some(v.as_str()),
+                    _ => None,
+                })
+                .collect::<StringArray>(),
+        ),
+        ColumnType::Date => Arc::new(
+            values
+                .map(|v| match v {
+                    Value::Date(date) => Some((*date - unix_epoch()).num_days() as i32),
+                    _ => None,
+                })
+                .collect::<Date32Array>(),
+        ),
+        ColumnType::DateTime => Arc::new(
+            values
+                .map(|v| match v {
+                    Value::DateTime(dt) => Some(dt.timestamp_micros()),
+                    _ => None,
+                })
+                .collect::<TimestampMicrosecondArray>(),
+        ),
+        ColumnType::VecBool => {
+            let mut builder = ListBuilder::new(BooleanBuilder::new());
+            for value in values {
+                match value {
+                    Value::VecBool(items) => {
+                        builder.values().append_slice(items);
+                        builder.append(true);
+                    }
+                    _ => builder.append(false),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        ColumnType::VecNum => {
+            let mut builder = ListBuilder::new(Float32Builder::new());
+            for value in values {
+                match value {
+                    Value::VecNum(items) => {
+                        builder.values().append_slice(items);
+                        builder.append(true);
+                    }
+                    _ => builder.append(false),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        ColumnType::VecInt => {
+            let mut builder = ListBuilder::new(Int32Builder::new());
+            for value in values {
+                match value {
+                    Value::VecInt(items) => {
+                        builder.values().append_slice(items);
+                        builder.append(true);
+                    }
+                    _ => builder.append(false),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        ColumnType::VecStr => {
+            let mut builder = ListBuilder::new(StringBuilder::new());
+            for value in values {
+                match value {
+                    Value::VecStr(items) => {
+                        for item in items {
+                            builder.values().append_value(item.as_str());
+                        }
+                        builder.append(true);
+                    }
+                    _ => builder.append(false),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+        ColumnType::MapNum => {
+            let mut builder = map_builder(Float32Builder::new());
+            for value in values {
+                if let Value::MapNum(map) = value {
+                    // sorted keys keep the output deterministic
+                    for (key, item) in map
+                        .iter()
+                        .sorted_by(|a, b| a.0 .0.as_str().cmp(b.0 .0.as_str()))
+                    {
+                        builder.keys().append_value(key.0.as_str());
+                        builder.values().append_value(*item);
+                    }
+                }
+                builder.append(matches!(value, Value::MapNum(_)))?;
+            }
+            Arc::new(builder.finish())
+        }
+        ColumnType::MapStr => {
+            let mut builder = map_builder(StringBuilder::new());
+            for value in values {
+                if let Value::MapStr(map) = value {
+                    for (key, item) in map
+                        .iter()
+                        .sorted_by(|a, b| a.0 .0.as_str().cmp(b.0 .0.as_str()))
+                    {
+                        builder.keys().append_value(key.0.as_str());
+                        builder.values().append_value(item.as_str());
+                    }
+                }
+                builder.append(matches!(value, Value::MapStr(_)))?;
+            }
+            Arc::new(builder.finish())
+        }
+        ColumnType::Other => Arc::new(values.map(text).collect::<StringArray>()),
+    };
+    Ok(array)
+}
+
+/// Converts the result of `EventContext::extract_records_from_expr` to a record batch
+pub fn records_to_record_batch(columns: &[String], rows: &[Vec<Value>]) -> Result<RecordBatch> {
+    let arrays = (0..columns.len())
+        .into_par_iter()
+        .map(|i| values_to_array(rows.iter().map(move |row| &row[i])))
+        .collect::<Result<Vec<_>>>()?;
+    let fields = columns
+        .iter()
+        .zip(arrays.iter())
+        .map(|(name, array)| Field::new(name, array.data_type().clone(), true))
+        .collect_vec();
+    Ok(RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)?)
+}
+
+/// Writes the record batch to a Parquet file
+pub fn write_parquet(batch: &RecordBatch, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let file = File::create(path).with_context(|| format!("Cannot create {}", path.display()))?;
+    let mut writer = ArrowWriter::try_new(file, batch.schema(), None)?;
+    writer.write(batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_index::QueryConfig;
+    use crate::event_store::EventStore;
+    use crate::ingest::TimestampFormat;
+    use arrow::array::{Int64Array, UInt64Array};
+
+    fn mapping() -> EventMapping {
+        serde_json::from_value(serde_json::json!({
+            "event_time": "ts",
+            "event_type": {"Column": "type"},
+            "entities": {"user": "user_id"},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_record_batch_round_trip() {
+        let mut tags = ListBuilder::new(StringBuilder::new());
+        tags.values().append_value("a");
+        tags.values().append_value("b");
+        tags.append(true);
+        tags.append(false);
+        let mut scores = map_builder(Float32Builder::new());
+        scores.keys().append_value("x");
+        scores.values().append_value(1.5);
+        scores.append(true).unwrap();
+        scores.append(true).unwrap();
+        let columns: Vec<(&str, ArrayRef)> = vec![
+            (
+                "ts",
+                Arc::new(StringArray::from(vec!["2023-01-01T10:00:00", "2023-01-02"])),
+            ),
+            ("type", Arc::new(StringArray::from(vec!["order", "visit"]))),
+            ("user_id", Arc::new(Int32Array::from(vec![7, 7]))),
+            (
+                "amount",
+                Arc::new(Float32Array::from(vec![Some(2.5), None])),
+            ),
+            ("tags", Arc::new(tags.finish())),
+            ("scores", Arc::new(scores.finish())),
+        ];
+        let batch = RecordBatch::try_from_iter(columns).unwrap();
+
+        let event_store = EventStoreImpl::default();
+        assert_eq!(
+            load_record_batch(&event_store, &batch, &mapping()).unwrap(),
+            2
+        );
+        let entities = btreemap!(EntityType("user".into()) => EntityID("7".into()));
+        let events = event_store
+            .query_entity(&entities, &QueryConfig::default(), None)
+            .unwrap()
+            .into_iter()
+            .flat_map(|(_, events)| events)
+            .collect_vec();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event_type, EventType("order".into()));
+        let attrs = events[0].attrs.as_ref().unwrap();
+        assert_eq!(attrs.get("amount"), Some(&Value::Num(2.5)));
+        assert_eq!(
+            attrs.get("tags"),
+            Some(&Value::VecStr(vec!["a".into(), "b".into()]))
+        );
+        assert_eq!(
+            attrs.get("scores"),
+            Some(&Value::MapNum(hashmap! {AttributeName("x".into()) => 1.5}))
+        );
+        // nulls are not loaded
+        assert!(events[1].attrs.as_ref().unwrap().get("amount").is_none());
+
+        let rows = events
+            .iter()
+            .map(|event| {
+                let attrs = event.attrs.as_ref().unwrap();
+                vec![
+                    Value::DateTime(event.event_time),
+                    attrs.get("amount").cloned().unwrap_or(Value::None),
+                    attrs.get("tags").cloned().unwrap_or(Value::None),
+                    attrs.get("scores").cloned().unwrap_or(Value::None),
+                ]
+            })
+            .collect_vec();
+        let names = vec!["ts", "amount", "tags", "scores"]
+            .into_iter()
+            .map(String::from)
+            .collect_vec();
+        let exported = records_to_record_batch(&names, &rows).unwrap();
+        assert_eq!(
+            exported.schema().field(0).data_type(),
+            &DataType::Timestamp(TimeUnit::Microsecond, None)
+        );
+        for (i, column) in exported.columns().iter().enumerate() {
+            assert_eq!(
+                array_to_values(column.as_ref()).unwrap(),
+                rows.iter().map(|row| row[i].clone()).collect_vec()
+            );
+        }
+    }
+
+    #[test]
+    fn test_large_integers() {
+        let columns: Vec<(&str, ArrayRef)> = vec![
+            (
+                "ts",
+                Arc::new(Int64Array::from(vec![1_672_567_200_000, 1_672_653_600_000])),
+            ),
+            ("type", Arc::new(StringArray::from(vec!["order", "visit"]))),
+            (
+                "user_id",
+                Arc::new(Int64Array::from(vec![9_007_199_254_740_993, 3])),
+            ),
+            ("amount", Arc::new(Int64Array::from(vec![Some(5), None]))),
+        ];
+        let batch = RecordBatch::try_from_iter(columns).unwrap();
+        let mut epoch_mapping = mapping();
+        epoch_mapping.timestamp_format = TimestampFormat::EpochMilliseconds;
+        let events = record_batch_to_events(&batch, &epoch_mapping).unwrap();
+        assert_eq!(
+            events[0].entities.get(&EntityType("user".into())),
+            Some(&EntityID("9007199254740993".into()))
+        );
+        assert_eq!(
+            events[0].event_time,
+            NaiveDateTime::parse_from_str("2023-01-01T10:00:00", "%Y-%m-%dT%H:%M:%S").unwrap()
+        );
+        assert_eq!(
+            events[0].attrs.as_ref().unwrap().get("amount"),
+            Some(&Value::Int(5))
+        );
+
+        assert!(array_to_values(&Int64Array::from(vec![INT::MAX as i64 + 1])).is_err());
+        assert!(array_to_values(&UInt64Array::from(vec![u64::MAX])).is_err());
+        let batch = RecordBatch::try_from_iter(vec![
+            (
+                "ts",
+                Arc::new(StringArray::from(vec!["2023-01-01"])) as ArrayRef,
+            ),
+            ("type", Arc::new(StringArray::from(vec!["order"]))),
+            ("user_id", Arc::new(Int32Array::from(vec![1]))),
+            ("amount", Arc::new(Int64Array::from(vec![1i64 << 40]))),
+        ])
+        .unwrap();
+        let err = record_batch_to_events(&batch, &mapping()).unwrap_err();
+        assert!(format!("{:#}", err).contains("Cannot convert column amount"));
+    }
+
+    #[test]
+    fn test_parquet_round_trip() {
+        let rows = vec![
+            vec![Value::Int(1), Value::Num(0.5), Value::Str("a".into())],
+            vec![Value::None, Value::Int(2), Value::Bool(true)],
+        ];
+        let names = vec!["int".to_string(), "num".to_string(), "mixed".to_string()];
+        let batch = records_to_record_batch(&names, &rows).unwrap();
+        let path = std::env::temp_dir().join("fexpress_test_parquet_round_trip.parquet");
+        write_parquet(&batch, &path).unwrap();
+
+        let mut mapping = mapping();
+        mapping.event_time = "int".into();
+        mapping.timestamp_format = TimestampFormat::EpochSeconds;
+        mapping.event_type = EventTypeMapping::Constant("row".into());
+        mapping.entities.clear();
+        let event_store = EventStoreImpl::default();
+        let err = load_parquet(&event_store, &path, &mapping).unwrap_err();
+        assert!(format!("{:#}", err).contains("Missing event time"));
+
+        let read = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(read, batch);
+        assert_eq!(
+            array_to_values(read.column(1).as_ref()).unwrap(),
+            vec![Value::Num(0.5), Value::Num(2.0)]
+        );
+        assert_eq!(
+            array_to_values(read.column(2).as_ref()).unwrap(),
+            vec![Value::Str("a".into()), Value::Str("true".into())]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
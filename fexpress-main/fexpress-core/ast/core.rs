@@ -9,7 +9,7 @@ use ordered_float::OrderedFloat;
 
 use strum::{EnumDiscriminants, EnumIter};
 
-use crate::event::{AttributeKey, EntityType};
+use crate::event::{AttributeKey, EntityType, EventType};
 use crate::features::Feature;
 use crate::interval::NewInterval;
 
@@ -52,6 +52,8 @@ pub enum Expr {
     AttrUntyped(AttributeKey),
     // attribute or entity refers to the current
     ContextAttr(AttributeKey),
+    // latest known value of the attribute of the given event type at the observation time
+    AsOf(EventType, AttributeKey),
 
     // literal values
     None,
@@ -99,6 +101,8 @@ pub enum Expr {
     Having(HavingExpr),
     Alias(SmallString, BExpr),
     VariableAssign(SmallString, BExpr),
+    // target column which is allowed to look into the future of the observation date
+    Label(BExpr),
 
     // full query
     Select(SelectExpr),
@@ -382,18 +386,7 @@ impl Display for ExprFunc {
 
 impl Into<Feature> for Expr {
     fn into(self) -> Feature {
-        match self {
-            Expr::Alias(alias, expr) => Feature {
-                raw: "".into(),
-                expr: *expr.clone(),
-                alias: Some(alias.to_string()),
-            },
-            _ => Feature {
-                raw: "".into(),
-                expr: self,
-                alias: None,
-            },
-        }
+        Feature::from_expr("".into(), self)
     }
 }
 
@@ -453,13 +446,39 @@ impl Display for HavingExpr {
     }
 }
 
+/// `EXPAND [TOP n] [WITH OTHER]` after `GROUP BY`, turns the map returned by the
+/// grouped aggregation into one output column per key
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ExpandExpr {
+    /// keep only the columns of the n most frequent keys
+    pub top: Option<usize>,
+    /// sum the values of the remaining keys into the `__other` column, only for `count` and `sum`
+    pub other: bool,
+}
+
+impl Display for ExpandExpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expand")?;
+        if let Some(top) = self.top {
+            write!(f, " top {}", top)?;
+        }
+        if self.other {
+            write!(f, " with other")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct AggrExpr {
     pub agg_func: AggregateFunction,
     pub agg_expr: BExpr,
     pub from: Option<SmallString>,
     pub when: NewInterval,
+    /// evaluate against all the events regardless of the entity (`GLOBAL` or `FROM *`)
+    pub global: bool,
     pub groupby: Option<BExpr>,
+    pub expand: Option<ExpandExpr>,
     pub cond: Option<BExpr>,
     pub having: Option<HavingExpr>,
 }
@@ -469,9 +488,15 @@ impl Display for AggrExpr {
         write!(f, "{:?}", self.agg_func)?;
         write!(f, "({})", self.agg_expr)?;
         write!(f, " when {} ", self.when)?;
+        if self.global {
+            write!(f, " global ")?;
+        }
         if let Some(groupby) = &self.groupby {
             write!(f, " group by {} ", *groupby)?;
         }
+        if let Some(expand) = &self.expand {
+            write!(f, " {} ", expand)?;
+        }
         if let Some(cond) = &self.cond {
             write!(f, " where {} ", *cond)?;
         }
@@ -24,6 +24,7 @@ impl Display for Expr {
             Expr::AttrDateTime(_) => unimplemented!(),
             Expr::AttrUntyped(a) => write!(f, "{}", a),
             Expr::ContextAttr(a) => write!(f, "@{}", a),
+            Expr::AsOf(event_type, a) => write!(f, "asof({}.{})", event_type, a),
             Expr::None => write!(f, "null"),
             Expr::Wildcard => write!(f, "*"),
             Expr::LitBool(v) => write!(f, "{}", v),
@@ -56,6 +57,7 @@ impl Display for Expr {
             Expr::Having(v) => write!(f, "{:}", v),
             Expr::Alias(_a, v) => write!(f, "{}", v),
             Expr::VariableAssign(a, b) => write!(f, "{}:={}", a, b),
+            Expr::Label(v) => write!(f, "label {}", v),
             Expr::Select(v) => write!(f, "{}", v),
             Expr::Function(v) => write!(f, "{}", v),
             Expr::ParsingError(v) => write!(f, "{}", v),
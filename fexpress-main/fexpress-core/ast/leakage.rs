@@ -0,0 +1,439 @@
+use std::fmt::{Display, Formatter};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::ast::core::{Expr, ExprFunc};
+use crate::ast::traverse::traverse_expr;
+use crate::event::AttributeKey;
+use crate::event_index::QueryConfig;
+use crate::features::{Feature, Features};
+use crate::interval::{Direction, DirectionOnly, KeywordInterval, NewInterval};
+use crate::map::HashSet;
+use crate::sstring::SmallString;
+
+/*
+Static analysis of the windows used by the features of a query, relative to the observation date:
+- past only - the window ends before the observation date (or at it, excluding events on it)
+- obs date inclusive - the window ends at the observation date and the events on the observation
+  date are included (`QueryConfig.include_events_on_obs_date`)
+- future touching - the window can contain events after the observation date
+
+Windows that cannot be related to the observation date (fixed calendar dates, bounds read from
+attributes, now() and current_date()) are reported as future touching because nothing guarantees
+that they end before the observation date.
+*/
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema,
+)]
+pub enum WindowClass {
+    PastOnly,
+    ObsDateInclusive,
+    FutureTouching,
+}
+
+impl Display for WindowClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WindowClass::PastOnly => write!(f, "past only"),
+            WindowClass::ObsDateInclusive => write!(f, "obs date inclusive"),
+            WindowClass::FutureTouching => write!(f, "future touching"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct WindowReport {
+    /// the aggregation or the function call the window comes from
+    pub expr: String,
+    pub class: WindowClass,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct FeatureLeakageReport {
+    pub feature: String,
+    pub label: bool,
+    pub windows: Vec<WindowReport>,
+    /// the worst class of the windows of the feature and of the variables it uses
+    pub class: WindowClass,
+}
+
+impl FeatureLeakageReport {
+    /// Labels are expected to look into the future, any other feature that does leaks
+    pub fn is_leaking(&self) -> bool {
+        !self.label && self.class == WindowClass::FutureTouching
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct LeakageReport {
+    pub features: Vec<FeatureLeakageReport>,
+}
+
+impl LeakageReport {
+    pub fn leaking_features(&self) -> Vec<&FeatureLeakageReport> {
+        self.features.iter().filter(|f| f.is_leaking()).collect()
+    }
+
+    pub fn is_leaking(&self) -> bool {
+        self.features.iter().any(|f| f.is_leaking())
+    }
+}
+
+impl Display for LeakageReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for feature in &self.features {
+            let label = if feature.label { " (label)" } else { "" };
+            writeln!(f, "{}{}: {}", feature.feature, label, feature.class)?;
+            for window in &feature.windows {
+                writeln!(
+                    f,
+                    "    {}: {} - {}",
+                    window.expr.trim(),
+                    window.class,
+                    window.reason
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Position of the end of a `between ... to ...` bound relative to the observation date,
+/// expressed in days. Dates cover the whole day, datetimes are exact.
+enum BoundEnd {
+    Date(i64),
+    DateTime(i64),
+}
+
+fn bound_end(expr: &Expr) -> Option<BoundEnd> {
+    match expr {
+        Expr::ObservationDate => Some(BoundEnd::DateTime(0)),
+        Expr::Function(ExprFunc::Date(inner)) => match bound_end(inner)? {
+            BoundEnd::Date(days) | BoundEnd::DateTime(days) => Some(BoundEnd::Date(days)),
+        },
+        Expr::Function(ExprFunc::DateAdd(inner, days)) => shift_bound(inner, days, 1),
+        Expr::Function(ExprFunc::DateSub(inner, days)) => shift_bound(inner, days, -1),
+        _ => None,
+    }
+}
+
+fn shift_bound(inner: &Expr, days: &Expr, sign: i64) -> Option<BoundEnd> {
+    let days = match days {
+        Expr::LitInt(days) => *days as i64 * sign,
+        _ => return None,
+    };
+    match bound_end(inner)? {
+        BoundEnd::Date(d) => Some(BoundEnd::Date(d + days)),
+        BoundEnd::DateTime(d) => Some(BoundEnd::DateTime(d + days)),
+    }
+}
+
+fn uses_wall_clock(expr: &Expr) -> bool {
+    !traverse_expr(expr, &|e| match e {
+        Expr::Function(ExprFunc::Now | ExprFunc::CurrentDate | ExprFunc::CurrentTime) => Some(()),
+        _ => None,
+    })
+    .is_empty()
+}
+
+fn ends_at_obs_date(query_config: &QueryConfig) -> (WindowClass, String) {
+    if query_config.include_events_on_obs_date {
+        (
+            WindowClass::ObsDateInclusive,
+            "ends at the observation date and includes events on it".into(),
+        )
+    } else {
+        (
+            WindowClass::PastOnly,
+            "ends at the observation date (exclusive)".into(),
+        )
+    }
+}
+
+/// Classifies the window of an aggregation.
+pub fn classify_interval(
+    interval: &NewInterval,
+    query_config: &QueryConfig,
+) -> (WindowClass, String) {
+    let future = |reason: &str| (WindowClass::FutureTouching, reason.to_string());
+    match interval {
+        NewInterval::FixedInterval(fixed) => match fixed.direction {
+            Direction::Next => future("looks forward from the observation date"),
+            Direction::Last | Direction::Previous => ends_at_obs_date(query_config),
+        },
+        NewInterval::DirectionOnly(DirectionOnly::Future) => {
+            future("looks forward from the observation date")
+        }
+        NewInterval::DirectionOnly(DirectionOnly::Past) => ends_at_obs_date(query_config),
+        NewInterval::KeywordDate(keyword) => match keyword {
+            KeywordInterval::YTD | KeywordInterval::MTD | KeywordInterval::WTD => {
+                ends_at_obs_date(query_config)
+            }
+            KeywordInterval::Yesterday
+            | KeywordInterval::LastWeek
+            | KeywordInterval::LastMonth
+            | KeywordInterval::LastQuarter
+            | KeywordInterval::LastYear
+            | KeywordInterval::SameDayLastWeek
+            | KeywordInterval::SameDayLastMonth
+            | KeywordInterval::SameDayLastYear
+            | KeywordInterval::PreviousWorkDay => (
+                WindowClass::PastOnly,
+                "ends before the day of the observation date".into(),
+            ),
+            KeywordInterval::Tomorrow
+            | KeywordInterval::NextWeek
+            | KeywordInterval::NextMonth
+            | KeywordInterval::NextQuarter
+            | KeywordInterval::NextYear
+            | KeywordInterval::SameDayNextWeek
+            | KeywordInterval::SameDayNextMonth
+            | KeywordInterval::SameDayNextYear
+            | KeywordInterval::NextWorkDay => future("covers days after the observation date"),
+        },
+        NewInterval::OffsetInterval(_) => {
+            future("fixed calendar dates are not relative to the observation date")
+        }
+        NewInterval::BetweenDatesExpressions(between) => {
+            if uses_wall_clock(&between.from_date) || uses_wall_clock(&between.to_date) {
+                return future("bounds depend on the current time instead of the observation date");
+            }
+            match bound_end(&between.to_date) {
+                Some(BoundEnd::DateTime(days)) if days < 0 => (
+                    WindowClass::PastOnly,
+                    "ends before the observation date".into(),
+                ),
+                Some(BoundEnd::DateTime(0)) => ends_at_obs_date(query_config),
+                Some(BoundEnd::Date(days)) if days < 0 => (
+                    WindowClass::PastOnly,
+                    "ends before the day of the observation date".into(),
+                ),
+                Some(BoundEnd::Date(_)) | Some(BoundEnd::DateTime(_)) => {
+                    future("ends after the observation date")
+                }
+                None => future("end bound is not relative to the observation date"),
+            }
+        }
+        NewInterval::SessionBasedMax(session) if session.direction == Direction::Next => {
+            future("looks forward from the observation date")
+        }
+        NewInterval::SessionBasedAttr(session) if session.direction == Direction::Next => {
+            future("looks forward from the observation date")
+        }
+        NewInterval::SinceEvent(since) if since.direction == Direction::Next => {
+            future("looks forward from the observation date")
+        }
+        NewInterval::EventBased(event_based) if event_based.direction == Direction::Next => {
+            future("looks forward from the observation date")
+        }
+        NewInterval::SessionBasedMax(_)
+        | NewInterval::SessionBasedAttr(_)
+        | NewInterval::SinceEvent(_)
+        | NewInterval::EventBased(_)
+        | NewInterval::EventCountBased(_) => ends_at_obs_date(query_config),
+    }
+}
+
+fn analyze_windows(expr: &Expr, query_config: &QueryConfig) -> Vec<WindowReport> {
+    traverse_expr(expr, &|e| match e {
+        Expr::Aggr(aggr) => {
+            let (class, reason) = classify_interval(&aggr.when, query_config);
+            Some(WindowReport {
+                expr: e.to_string(),
+                class,
+                reason,
+            })
+        }
+        Expr::Function(ExprFunc::Now | ExprFunc::CurrentDate | ExprFunc::CurrentTime) => {
+            Some(WindowReport {
+                expr: e.to_string(),
+                class: WindowClass::FutureTouching,
+                reason: "depends on the current time instead of the observation date".into(),
+            })
+        }
+        _ => None,
+    })
+}
+
+fn assigned_variable(feature: &Feature) -> Option<SmallString> {
+    match &feature.expr {
+        Expr::VariableAssign(name, _) => Some(name.clone()),
+        _ => None,
+    }
+}
+
+fn used_variables(feature: &Feature) -> HashSet<SmallString> {
+    traverse_expr(&feature.expr, &|e| match e {
+        Expr::ContextAttr(AttributeKey::Single(name)) => Some(name.clone()),
+        _ => None,
+    })
+    .into_iter()
+    .collect()
+}
+
+/// Classifies the windows of every feature. Features using variables (`@var`) inherit
+/// the class of the variable definitions.
+pub fn analyze_leakage(features: &Features, query_config: &QueryConfig) -> LeakageReport {
+    let mut reports: Vec<FeatureLeakageReport> = features
+        .features
+        .iter()
+        .map(|feature| {
+            let windows = analyze_windows(&feature.expr, query_config);
+            let class = windows
+                .iter()
+                .map(|w| w.class)
+                .max()
+                .unwrap_or(WindowClass::PastOnly);
+            FeatureLeakageReport {
+                feature: feature.get_name(),
+                label: feature.label,
+                windows,
+                class,
+            }
+        })
+        .collect();
+
+    // propagate the classes through the variables until nothing changes,
+    // the number of iterations is bounded by the number of features
+    let usages: Vec<HashSet<SmallString>> = features.features.iter().map(used_variables).collect();
+    for _ in 0..features.features.len() {
+        let mut changed = false;
+        for (feature_index, used) in usages.iter().enumerate() {
+            let inherited = features
+                .features
+                .iter()
+                .enumerate()
+                .filter(|(_, f)| assigned_variable(f).is_some_and(|v| used.contains(&v)))
+                .map(|(index, _)| reports[index].class)
+                .max();
+            if let Some(inherited) = inherited {
+                if inherited > reports[feature_index].class {
+                    reports[feature_index].class = inherited;
+                    changed = true;
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    LeakageReport { features: reports }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+    use crate::event_index::RawQuery;
+
+    fn report(exprs: Vec<&str>, include_events_on_obs_date: bool) -> LeakageReport {
+        let query = RawQuery::VecExpr(exprs.into_iter().map(|e| e.to_string()).collect());
+        let features = Features::try_from(query).unwrap();
+        let query_config = QueryConfig {
+            include_events_on_obs_date,
+            ..Default::default()
+        };
+        analyze_leakage(&features, &query_config)
+    }
+
+    fn classes(report: &LeakageReport) -> Vec<WindowClass> {
+        report.features.iter().map(|f| f.class).collect()
+    }
+
+    #[test]
+    fn test_classify_windows() {
+        let exprs = vec![
+            "count(*) over past",
+            "count(*) over last 3 days",
+            "count(*) over yesterday",
+            "count(*) over nextweek",
+            "count(*) over next 2 days",
+            "1 + 2",
+        ];
+        assert_eq!(
+            classes(&report(exprs.clone(), false)),
+            vec![
+                WindowClass::PastOnly,
+                WindowClass::PastOnly,
+                WindowClass::PastOnly,
+                WindowClass::FutureTouching,
+                WindowClass::FutureTouching,
+                WindowClass::PastOnly,
+            ]
+        );
+        assert_eq!(
+            classes(&report(exprs, true)),
+            vec![
+                WindowClass::ObsDateInclusive,
+                WindowClass::ObsDateInclusive,
+                WindowClass::PastOnly,
+                WindowClass::FutureTouching,
+                WindowClass::FutureTouching,
+                WindowClass::PastOnly,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_classify_between_dates_and_wall_clock() {
+        let report = report(
+            vec![
+                "count(*) over between date_sub(date(obs_dt), 7) to date_sub(date(obs_dt), 1)",
+                "count(*) over between date_sub(date(obs_dt), 7) to date(obs_dt)",
+                "count(*) over between date_sub(date(obs_dt), 7) to obs_dt",
+                "count(*) over between date_sub(date(obs_dt), 7) to current_date()",
+                "count(*) over past where event_time > now()",
+            ],
+            false,
+        );
+        assert_eq!(
+            classes(&report),
+            vec![
+                WindowClass::PastOnly,
+                WindowClass::FutureTouching,
+                WindowClass::PastOnly,
+                WindowClass::FutureTouching,
+                WindowClass::FutureTouching,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_leakage_through_variables_and_labels() {
+        let report = report(
+            vec![
+                "@future := count(*) over future",
+                "@future + 1 as uses_future",
+                "count(*) over past as uses_past",
+            ],
+            false,
+        );
+        assert_eq!(
+            classes(&report),
+            vec![
+                WindowClass::FutureTouching,
+                WindowClass::FutureTouching,
+                WindowClass::PastOnly
+            ],
+            "{}",
+            report
+        );
+        assert_eq!(report.leaking_features().len(), 2);
+
+        let report = self::report(
+            vec![
+                "count(*) over past",
+                "label count(*) over next 7 days as target",
+            ],
+            false,
+        );
+        assert!(report.features[1].label);
+        assert_eq!(report.features[1].class, WindowClass::FutureTouching);
+        assert!(!report.is_leaking());
+    }
+}
@@ -1,5 +1,6 @@
 pub mod analyze;
 pub mod core;
 pub mod display;
+pub mod leakage;
 pub mod simple_graph;
 pub mod traverse;
@@ -134,6 +134,7 @@ impl Expr {
             Expr::ParsingError(_e1) => vec![],
             Expr::FullQuery(fq) => fq.select_exprs.clone().into_iter().collect_vec(),
             Expr::ContextAttr(_e1) => vec![],
+            Expr::AsOf(_, _) => vec![],
             Expr::Cons(e1, e2) => vec![*e1.clone(), *e2.clone()],
             Expr::Aggr(aggr) => {
                 let mut v = vec![];
@@ -152,6 +153,7 @@ impl Expr {
             Expr::Having(having) => vec![*having.expr.clone()],
             Expr::Alias(_, e) => vec![*e.clone()],
             Expr::VariableAssign(_, e) => vec![*e.clone()],
+            Expr::Label(e) => vec![*e.clone()],
             Expr::Select(select) => select.clone().expressions,
         }
     }
@@ -177,7 +179,10 @@ impl Expr {
                 left.visit(visitor);
                 right.visit(visitor);
             }
-            Expr::Not(expr) | Expr::Alias(_, expr) | Expr::VariableAssign(_, expr) => {
+            Expr::Not(expr)
+            | Expr::Alias(_, expr)
+            | Expr::VariableAssign(_, expr)
+            | Expr::Label(expr) => {
                 expr.visit(visitor);
             }
             Expr::Aggr(aggr) => {
@@ -222,6 +227,7 @@ impl Expr {
             Expr::AttrDateTime(_) => {}
             Expr::AttrUntyped(_) => {}
             Expr::ContextAttr(_) => {}
+            Expr::AsOf(_, _) => {}
             Expr::None => {}
             Expr::Wildcard => {}
             Expr::LitBool(_) => {}
@@ -4,6 +4,7 @@ extern crate fexpress_core;
 
 use fexpress_core::event::Event;
 use fexpress_core::event_index::{EventScopeConfig, QueryConfig};
+use fexpress_core::ingest::IngestionConfig;
 use fexpress_core::obs_dates::ObservationDatesConfig;
 use schemars::schema_for;
 
@@ -26,6 +27,10 @@ pub fn main() {
         "fexpress-py/fexpress/sdk/observation_dates_config.json"
     );
     write_schema_to_file!(Event, "fexpress-py/fexpress/sdk/event.json");
+    write_schema_to_file!(
+        IngestionConfig,
+        "fexpress-py/fexpress/sdk/ingestion_config.json"
+    );
     write_schema_to_file!(QueryConfig, "fexpress-py/fexpress/sdk/query_config.json");
     write_schema_to_file!(
         EventScopeConfig,
@@ -0,0 +1,129 @@
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Duration, NaiveDate};
+use lazy_static::lazy_static;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::map::HashMap;
+
+// business days are searched at most this far, a calendar without business days
+// falls back to the next (or previous) day
+const MAX_BUSINESS_DAY_SEARCH: i64 = 366;
+
+/// Decides which days are business days. Used by the `NextWorkDay` / `PreviousWorkDay`
+/// intervals and by the calendar based observation dates.
+pub trait HolidayCalendar: Debug + Send + Sync {
+    fn is_business_day(&self, date: NaiveDate) -> bool;
+
+    fn next_business_day(&self, date: NaiveDate) -> NaiveDate {
+        (1..=MAX_BUSINESS_DAY_SEARCH)
+            .map(|days| date + Duration::days(days))
+            .find(|d| self.is_business_day(*d))
+            .unwrap_or(date + Duration::days(1))
+    }
+
+    fn previous_business_day(&self, date: NaiveDate) -> NaiveDate {
+        (1..=MAX_BUSINESS_DAY_SEARCH)
+            .map(|days| date - Duration::days(days))
+            .find(|d| self.is_business_day(*d))
+            .unwrap_or(date - Duration::days(1))
+    }
+}
+
+fn default_weekend() -> Vec<u32> {
+    vec![6, 7]
+}
+
+/// Calendar with fixed weekend days and a list of holidays
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct FixedHolidayCalendar {
+    /// days of the week that are never business days, 1 = Monday ... 7 = Sunday
+    #[serde(default = "default_weekend")]
+    pub weekend: Vec<u32>,
+    #[serde(default)]
+    pub holidays: BTreeSet<NaiveDate>,
+}
+
+impl Default for FixedHolidayCalendar {
+    fn default() -> Self {
+        FixedHolidayCalendar {
+            weekend: default_weekend(),
+            holidays: BTreeSet::new(),
+        }
+    }
+}
+
+impl HolidayCalendar for FixedHolidayCalendar {
+    fn is_business_day(&self, date: NaiveDate) -> bool {
+        !self.weekend.contains(&date.weekday().number_from_monday())
+            && !self.holidays.contains(&date)
+    }
+}
+
+lazy_static! {
+    static ref CALENDARS: RwLock<HashMap<String, Arc<dyn HolidayCalendar>>> =
+        RwLock::new(HashMap::new());
+    static ref DEFAULT_CALENDAR: Arc<dyn HolidayCalendar> =
+        Arc::new(FixedHolidayCalendar::default());
+}
+
+/// Registers a calendar under a name that can be used in `QueryConfig.business_calendar`
+/// and in the calendar based observation dates. Registering the same name again replaces it.
+pub fn register_calendar(name: &str, calendar: Arc<dyn HolidayCalendar>) -> Result<()> {
+    let mut calendars = CALENDARS
+        .write()
+        .map_err(|_| anyhow!("Cannot register calendar {}", name))?;
+    calendars.insert(name.to_string(), calendar);
+    Ok(())
+}
+
+/// Returns the registered calendar or the default calendar (Saturdays and Sundays
+/// are not business days) if the name is not given.
+pub fn get_calendar(name: Option<&str>) -> Result<Arc<dyn HolidayCalendar>> {
+    match name {
+        None => Ok(DEFAULT_CALENDAR.clone()),
+        Some(name) => CALENDARS
+            .read()
+            .map_err(|_| anyhow!("Cannot read calendars"))?
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Calendar {} is not registered", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_holiday_calendar() {
+        let calendar = FixedHolidayCalendar {
+            holidays: vec![NaiveDate::from_ymd(2023, 12, 25)]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        // Friday before Christmas
+        let friday = NaiveDate::from_ymd(2023, 12, 22);
+        assert_eq!(
+            calendar.next_business_day(friday),
+            NaiveDate::from_ymd(2023, 12, 26)
+        );
+        assert_eq!(
+            calendar.previous_business_day(NaiveDate::from_ymd(2023, 12, 26)),
+            friday
+        );
+
+        register_calendar("test_christmas", Arc::new(calendar)).unwrap();
+        let registered = get_calendar(Some("test_christmas")).unwrap();
+        assert!(!registered.is_business_day(NaiveDate::from_ymd(2023, 12, 25)));
+        assert!(get_calendar(None)
+            .unwrap()
+            .is_business_day(NaiveDate::from_ymd(2023, 12, 25)));
+        assert!(get_calendar(Some("missing")).is_err());
+    }
+}
@@ -1,6 +1,8 @@
 use std::ops::{Add, Sub};
 
-use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Offset, TimeZone};
+use chrono_tz::Tz;
 use serde::{Deserialize, Deserializer};
 
 use crate::types::Timestamp;
@@ -57,10 +59,16 @@ where
         ));
     }
 
+    parse_naive_date_time(&s).ok_or_else(|| serde::de::Error::custom("Failed to parse date time"))
+}
+
+/// Parses date times without time zone information, dates are converted to midnight
+pub fn parse_naive_date_time(s: &str) -> Option<NaiveDateTime> {
     // List of supported formats, ordered from most specific to least specific
     let date_time_formats = [
         "%Y-%m-%d %H:%M:%S%.f", // e.g. "2023-06-10 15:30:00.123456"
         "%Y-%m-%d %H:%M:%S",    // e.g. "2023-06-10 15:30:00"
+        "%Y-%m-%dT%H:%M:%S%.f", // e.g. "2023-06-10T15:30:00.123456"
         "%Y-%m-%dT%H:%M:%S",    // e.g. "2023-06-10T15:30:00"
         "%Y-%m-%d %H:%M",       // e.g. "2023-06-10 15:30"
         "%Y-%m-%dT%H:%M",       // e.g. "2023-06-10T15:30"
@@ -68,20 +76,16 @@ where
 
     // Try parsing the string using each format
     for format in &date_time_formats {
-        if let Ok(dt) = NaiveDateTime::parse_from_str(&s, format) {
-            return Ok(dt);
+        if let Ok(dt) = NaiveDateTime::parse_from_str(s, format) {
+            return Some(dt);
         }
     }
 
     // If none of the date-time formats worked, try parsing as a date
     let date_format = "%Y-%m-%d"; // e.g. "2023-06-10"
-    if let Ok(date) = NaiveDate::parse_from_str(&s, date_format) {
-        // If the string is a valid date, assume a time of midnight
-        return Ok(date.and_hms(0, 0, 0));
-    }
-
-    // If all parsing attempts fail, return an error
-    Err(serde::de::Error::custom("Failed to parse date time"))
+    NaiveDate::parse_from_str(s, date_format)
+        .ok()
+        .map(|date| date.and_hms(0, 0, 0))
 }
 
 pub fn middle_datetime(datetime1: NaiveDateTime, datetime2: NaiveDateTime) -> NaiveDateTime {
@@ -95,6 +99,33 @@ pub fn middle_datetime(datetime1: NaiveDateTime, datetime2: NaiveDateTime) -> Na
     NaiveDateTime::from_timestamp(middle_timestamp, 0)
 }
 
+/// Parses an IANA time zone name, e.g. `Europe/Warsaw` or `UTC`
+pub fn parse_time_zone(name: &str) -> Result<Tz> {
+    name.parse::<Tz>()
+        .map_err(|e| anyhow!("Invalid time zone {}: {}", name, e))
+}
+
+/// Converts a UTC timestamp to the wall-clock time in the time zone
+pub fn utc_to_local(datetime: NaiveDateTime, time_zone: &Tz) -> NaiveDateTime {
+    time_zone.from_utc_datetime(&datetime).naive_local()
+}
+
+/// Converts a wall-clock time in the time zone to UTC. Ambiguous times (when clocks go back)
+/// resolve to the earlier instant, times skipped when clocks go forward are moved forward
+/// by the size of the gap.
+pub fn local_to_utc(datetime: NaiveDateTime, time_zone: &Tz) -> NaiveDateTime {
+    match time_zone.from_local_datetime(&datetime).earliest() {
+        Some(dt) => dt.naive_utc(),
+        None => {
+            // use the offset from before the gap
+            let offset = time_zone
+                .offset_from_utc_datetime(&(datetime - Duration::days(1)))
+                .fix();
+            datetime - Duration::seconds(offset.local_minus_utc() as i64)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -107,4 +138,21 @@ mod tests {
         let result = parse_naivedate("1996-12-20").unwrap();
         assert_eq!(result, NaiveDate::from_ymd(1996, 12, 20));
     }
+
+    #[test]
+    fn test_time_zone_conversion() {
+        let warsaw = parse_time_zone("Europe/Warsaw").unwrap();
+        let utc = NaiveDate::from_ymd(2023, 6, 1).and_hms(22, 30, 0);
+        let local = NaiveDate::from_ymd(2023, 6, 2).and_hms(0, 30, 0);
+        assert_eq!(utc_to_local(utc, &warsaw), local);
+        assert_eq!(local_to_utc(local, &warsaw), utc);
+
+        // 02:30 does not exist on 2023-03-26 in Warsaw
+        let skipped = NaiveDate::from_ymd(2023, 3, 26).and_hms(2, 30, 0);
+        assert_eq!(
+            local_to_utc(skipped, &warsaw),
+            NaiveDate::from_ymd(2023, 3, 26).and_hms(1, 30, 0)
+        );
+        assert!(parse_time_zone("Mars/Olympus").is_err());
+    }
 }
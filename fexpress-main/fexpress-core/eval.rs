@@ -4,14 +4,18 @@ use std::sync::Arc;
 
 use anyhow::{anyhow, bail, Context, Error, Result};
 use chrono::NaiveDateTime;
+use chrono_tz::Tz;
 use itertools::Itertools;
 
 use vec1::Vec1;
 
+use crate::agg_cache::{is_entity_independent, AggregationCache};
 use crate::aggr::eval_agg_using_partial_agg;
 use crate::ast::core::{
     AggrExpr, AggregateFunction, BExpr, Expr, ExprFunc, HavingExprType, PartialAggregateType,
 };
+use crate::calendar::{get_calendar, HolidayCalendar};
+use crate::datetime_utils::{parse_time_zone, utc_to_local};
 use crate::evaluation::date;
 use crate::evaluation::date::{
     eval_current_date, eval_current_time, eval_date_add, eval_date_part, eval_date_sub, eval_day,
@@ -27,7 +31,12 @@ use crate::evaluation::text::{
     eval_starts_with, eval_substr, eval_trim, eval_upper,
 };
 use crate::event::{AttributeKey, AttributeName, EntityType, Event, EventType};
-use crate::event_index::{check_agg_event_type_index, EventContext, EventScopeConfig, QueryConfig};
+use crate::event_index::{
+    check_agg_attribute_index, check_agg_event_type_index, EventContext, EventScopeConfig,
+    QueryConfig,
+};
+use crate::event_store::row_event_store::attribute_index::IndexScope;
+use crate::event_store::schema::widen_value;
 use crate::event_store::EventStore;
 use crate::interval::NaiveDateTimeInterval;
 use crate::map::HashMap;
@@ -52,9 +61,45 @@ pub struct EvalContext<'a> {
     pub event_types: Vec<SmallString>,
     pub event: Option<Arc<Event>>,
     pub event_on_obs_date: Option<Arc<Event>>,
+    pub agg_cache: Option<&'a AggregationCache>,
 }
 
-impl EvalContext<'_> {
+impl<'a> EvalContext<'a> {
+    /// Holiday calendar configured in the query config
+    pub fn holiday_calendar(&self) -> Result<Arc<dyn HolidayCalendar>> {
+        get_calendar(
+            self.query_config
+                .and_then(|query_config| query_config.business_calendar.as_deref()),
+        )
+    }
+
+    /// Time zone configured in the query config, `None` means UTC
+    pub fn time_zone(&self) -> Result<Option<Tz>> {
+        self.query_config
+            .and_then(|query_config| query_config.time_zone.as_deref())
+            .map(parse_time_zone)
+            .transpose()
+    }
+
+    /// Converts date times to the wall-clock time of the query time zone
+    pub fn to_local_value(&self, value: Value) -> Result<Value> {
+        match (value, self.time_zone()?) {
+            (Value::DateTime(datetime), Some(time_zone)) => {
+                Ok(Value::DateTime(utc_to_local(datetime, &time_zone)))
+            }
+            (value, _) => Ok(value),
+        }
+    }
+
+    /// Cache of the aggregation if it is shared between the entities of the query
+    fn agg_cache(&self, agg: &AggrExpr) -> Option<&'a AggregationCache> {
+        self.agg_cache.filter(|_| {
+            self.event_query_config
+                .as_ref()
+                .is_some_and(|scope| is_entity_independent(agg, scope))
+        })
+    }
+
     pub fn get_sorted_obs_dates(&self) -> Result<Vec<Timestamp>> {
         Ok(self
             .obs_date
@@ -99,7 +144,7 @@ pub fn eval_context_dispatcher(
                 // optimized version of
                 {
                     if agg_expr.having.is_none() && agg_expr.groupby.is_none() {
-                        eval_agg_using_partial_agg(agg_expr, context, stored_variables)?
+                        eval_agg_using_partial_agg_cached(agg_expr, context, stored_variables)?
                     } else {
                         eval_expr_many_obsdates(context, expr, stored_variables)?
                     }
@@ -112,6 +157,53 @@ pub fn eval_context_dispatcher(
     Ok(result)
 }
 
+// the partial aggregates of the entity independent aggregations are calculated once for
+// all the entities of the query, unless an interval is missing in the cache
+fn eval_agg_using_partial_agg_cached(
+    agg: &AggrExpr,
+    context: &EvalContext,
+    stored_variables: &HashMap<SmallString, HashMap<Timestamp, Value>>,
+) -> Result<HashMap<Timestamp, Value>> {
+    let agg_cache = match context.agg_cache(agg) {
+        Some(agg_cache) => agg_cache,
+        None => return eval_agg_using_partial_agg(agg, context, stored_variables),
+    };
+    let calendar = context.holiday_calendar()?;
+    let time_zone = context.time_zone()?;
+    let time_zone_name = context
+        .query_config
+        .and_then(|query_config| query_config.time_zone.as_deref());
+    let intervals = context
+        .get_sorted_obs_dates()?
+        .into_iter()
+        .map(|obs_date| {
+            let interval = agg
+                .when
+                .materialize_interval_in_zone(&obs_date, calendar.as_ref(), time_zone.as_ref())
+                .context("Couldn't parse the interval")?;
+            Ok((obs_date, interval))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let cached: Option<HashMap<Timestamp, Value>> = intervals
+        .iter()
+        .map(|(obs_date, interval)| {
+            Some((*obs_date, agg_cache.get(agg, interval, time_zone_name)?))
+        })
+        .collect();
+    if let Some(cached) = cached {
+        return Ok(cached);
+    }
+
+    let result = eval_agg_using_partial_agg(agg, context, stored_variables)?;
+    for (obs_date, interval) in &intervals {
+        if let Some(value) = result.get(obs_date) {
+            agg_cache.insert(agg, interval, time_zone_name, value.clone());
+        }
+    }
+    Ok(result)
+}
+
 // non vectorized implementation of expression evaluation
 fn eval_expr_many_obsdates(
     context: &EvalContext,
@@ -149,6 +241,7 @@ fn eval_expr_many_obsdates(
             event_types: vec![],
             event: event.clone(),
             event_on_obs_date: event.clone(),
+            agg_cache: context.agg_cache,
         };
 
         match event {
@@ -301,7 +394,11 @@ pub fn eval_simple_expr(
                 .extract_attribute(attribute)
                 .with_context(|| format!("Cannot extract attribute {:?}", attribute));
             match result {
-                Ok(v) => Ok(v),
+                // values stored before the type of the attribute was widened are widened too,
+                // large ints read as nums are approximate
+                Ok(v) => Ok(attribute_value_type(expr)
+                    .and_then(|value_type| widen_value(&v, &value_type))
+                    .unwrap_or(v)),
                 // TODO: temporary solution must check if the attribute exists in the schema
                 Err(_) => Ok(Value::None),
             }
@@ -318,6 +415,9 @@ pub fn eval_simple_expr(
         Expr::ContextAttr(ref attribute) => {
             evaluate_context_attribute(expr, context, stored_variables, &attribute)
         }
+        Expr::AsOf(event_type, attribute) => {
+            evaluate_asof(context_with_context?, event_type, attribute)
+        }
         Expr::LitBool(v) => Ok(Value::Bool(*v)),
         Expr::LitNum(v) => Ok(Value::Num(**v)),
         Expr::LitInt(v) => Ok(Value::Int(*v)),
@@ -339,6 +439,7 @@ pub fn eval_simple_expr(
 
         // this shouldn't be really implemented because it is only evaluated as a part of aggregate
         Expr::Alias(alias, expr) => eval_alias(event, context, alias, expr, stored_variables),
+        Expr::Label(expr) => eval_simple_expr(expr, event, context, stored_variables),
         Expr::Wildcard => Ok(Value::Wildcard),
         Expr::Having(_) => unimplemented!(),
         Expr::None => Ok(Value::None),
@@ -574,6 +675,51 @@ fn evaluate_context_attribute(
     )?)
 }
 
+// point in time lookup of the latest attribute value known at the observation time
+fn evaluate_asof(
+    context: &EvalContext,
+    event_type: &EventType,
+    attribute: &AttributeKey,
+) -> Result<Value> {
+    let obs_time = context
+        .obs_time
+        .as_ref()
+        .context("Need observation date to evaluate asof")?;
+    let entities = context
+        .entities
+        .as_ref()
+        .ok_or(anyhow!("Entities needed"))?;
+    let query_config = context
+        .query_config
+        .context("Query config needed to evaluate asof")?;
+    Ok(context
+        .event_index
+        .ok_or(anyhow!("event index needed"))?
+        .event_store
+        .query_attribute_as_of(
+            entities,
+            event_type,
+            &a!(attribute.to_kstring()),
+            &obs_time.datetime,
+            query_config,
+        )
+        .map(|(_, value)| value)
+        .unwrap_or(Value::None))
+}
+
+/// Type of the values of a typed attribute which can be widened to it
+fn attribute_value_type(expr: &Expr) -> Option<ValueType> {
+    match expr {
+        Expr::AttrBool(_) => Some(ValueType::Bool),
+        Expr::AttrInt(_) => Some(ValueType::Int),
+        Expr::AttrNum(_) => Some(ValueType::Num),
+        Expr::AttrStr(_) => Some(ValueType::Str),
+        Expr::AttrDate(_) => Some(ValueType::Date),
+        Expr::AttrDateTime(_) => Some(ValueType::DateTime),
+        _ => None,
+    }
+}
+
 fn evaluate_attribute_key(
     event: Option<&Event>,
     attribute: &AttributeKey,
@@ -778,18 +924,46 @@ pub fn eval_agg(
     context: &EvalContext,
     stored_variables: &HashMap<SmallString, HashMap<Timestamp, Value>>,
 ) -> Result<Value> {
+    let calendar = context.holiday_calendar()?;
+    let time_zone = context.time_zone()?;
     let interval = agg
         .when
-        .materialize_interval(
+        .materialize_interval_in_zone(
             &context
                 .obs_time
                 .clone()
                 .ok_or(anyhow!("Cannot extract date"))?
                 .datetime,
+            calendar.as_ref(),
+            time_zone.as_ref(),
         )
         .context("Couldn't parse the interval")?;
 
-    let interval_events = extract_interval_events(agg, context, &interval);
+    // entity independent aggregations are shared between all the entities of the query
+    let agg_cache = context.agg_cache(agg);
+    // date functions of the aggregation depend on the time zone, which can be the time zone
+    // of the entity
+    let time_zone_name = context
+        .query_config
+        .and_then(|query_config| query_config.time_zone.as_deref());
+    if let Some(cached) = agg_cache.and_then(|cache| cache.get(agg, &interval, time_zone_name)) {
+        return Ok(cached);
+    }
+
+    let result = eval_agg_in_interval(agg, context, &interval, stored_variables)?;
+    if let Some(cache) = agg_cache {
+        cache.insert(agg, &interval, time_zone_name, result.clone());
+    }
+    Ok(result)
+}
+
+fn eval_agg_in_interval(
+    agg: &AggrExpr,
+    context: &EvalContext,
+    interval: &NaiveDateTimeInterval,
+    stored_variables: &HashMap<SmallString, HashMap<Timestamp, Value>>,
+) -> Result<Value> {
+    let interval_events = extract_interval_events(agg, context, interval);
 
     if agg.groupby.is_some() && agg.having.is_some() {
         bail!("Group by and Having cannot be defined in the same aggregation");
@@ -973,6 +1147,9 @@ pub fn extract_interval_events(
     context: &EvalContext,
     interval: &NaiveDateTimeInterval,
 ) -> Option<Vec<(NaiveDateTime, Vec<Arc<Event>>)>> {
+    if agg_expr.global {
+        return extract_global_interval_events(agg_expr, context, interval);
+    }
     match &context.event_query_config.as_ref()? {
         EventScopeConfig::RelatedEntitiesEvents(selected_entities) => {
             let mut new_entities = BTreeMap::new();
@@ -982,6 +1159,25 @@ pub fn extract_interval_events(
                 }
             });
             let event_type_index_name = check_agg_event_type_index(agg_expr);
+            let attribute_filters = check_agg_attribute_index(agg_expr);
+            if !attribute_filters.is_empty() {
+                let event_type = event_type_index_name
+                    .as_ref()
+                    .map(|event_type| EventType(from_string!(event_type)));
+                let indexed_events = context.event_index?.event_store.query_attribute_index(
+                    &attribute_filters,
+                    &IndexScope::Entities {
+                        entities: context.entities.as_ref()?,
+                        experiment_id: &context.experiment_id,
+                    },
+                    event_type.as_ref(),
+                    interval,
+                    context.query_config?,
+                );
+                if let Some(indexed_events) = indexed_events {
+                    return non_empty(indexed_events);
+                }
+            }
             let interval_events: Option<Vec<_>> = match event_type_index_name {
                 Some(event_type) => context.event_index?.event_store.query_entity_event_type(
                     context.entities.as_ref()?,
@@ -1006,6 +1202,43 @@ pub fn extract_interval_events(
     }
 }
 
+/// The queries of the event stores return `None` instead of empty windows
+fn non_empty<T>(events: Vec<T>) -> Option<Vec<T>> {
+    if events.is_empty() {
+        None
+    } else {
+        Some(events)
+    }
+}
+
+/// Extracts events of all the entities (and the events without any entity)
+/// for aggregations marked as global
+fn extract_global_interval_events(
+    agg_expr: &AggrExpr,
+    context: &EvalContext,
+    interval: &NaiveDateTimeInterval,
+) -> Option<Vec<(NaiveDateTime, Vec<Arc<Event>>)>> {
+    let event_store = &context.event_index?.event_store;
+    let attribute_filters = check_agg_attribute_index(agg_expr);
+    if !attribute_filters.is_empty() {
+        let event_type = check_agg_event_type_index(agg_expr)
+            .map(|event_type| EventType(from_string!(event_type)));
+        let indexed_events = event_store.query_attribute_index(
+            &attribute_filters,
+            &IndexScope::All,
+            event_type.as_ref(),
+            interval,
+            context.query_config?,
+        );
+        if let Some(indexed_events) = indexed_events {
+            return non_empty(indexed_events);
+        }
+    }
+    let event_type =
+        check_agg_event_type_index(agg_expr).map(|event_type| EventType(from_string!(event_type)));
+    event_store.query_global_events(event_type.as_ref(), interval, context.query_config?)
+}
+
 fn evaluate_where_expr(
     where_expr: Option<&BExpr>,
     event: &Event,
@@ -1336,6 +1569,7 @@ mod tests {
                 event_id: None,
             }),
             event_on_obs_date: None,
+            agg_cache: None,
             event_query_config: Some(event_query_config),
         };
 
@@ -1384,6 +1618,7 @@ mod tests {
                 event_id: None,
             }),
             event_on_obs_date: None,
+            agg_cache: None,
             event_query_config: Some(EventScopeConfig::AllEvents),
         };
 
@@ -1456,6 +1691,7 @@ mod tests {
                 event_id: None,
             }),
             event_on_obs_date: None,
+            agg_cache: None,
             event_query_config: Some(EventScopeConfig::AllEvents),
         };
         let hm = HashMap::new();
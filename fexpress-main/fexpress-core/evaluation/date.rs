@@ -1,9 +1,10 @@
 use crate::map::HashMap;
 use crate::sstring::SmallString;
 use anyhow::{anyhow, Result};
-use chrono::NaiveDate;
+use chrono::{NaiveDate, NaiveDateTime, Utc};
 
 use crate::ast::core::BExpr;
+use crate::datetime_utils::utc_to_local;
 use crate::eval::{eval_simple_expr, EvalContext};
 use crate::evaluation::date_common::*;
 use crate::event::Event;
@@ -88,10 +89,14 @@ pub fn eval_date_diff(
     d1expr: &BExpr,
     d2expr: &BExpr,
 ) -> Result<Value> {
-    let d1_eval: Option<NaiveDate> =
-        eval_simple_expr(d1expr, event, context, stored_variables)?.into();
-    let d2_eval: Option<NaiveDate> =
-        eval_simple_expr(d2expr, event, context, stored_variables)?.into();
+    let mut d1 = eval_simple_expr(d1expr, event, context, stored_variables)?;
+    let mut d2 = eval_simple_expr(d2expr, event, context, stored_variables)?;
+    if let Some(context) = context {
+        d1 = context.to_local_value(d1)?;
+        d2 = context.to_local_value(d2)?;
+    }
+    let d1_eval: Option<NaiveDate> = d1.into();
+    let d2_eval: Option<NaiveDate> = d2.into();
     match (d1_eval, d2_eval) {
         (Some(d1), Some(d2)) => Ok(Value::Int(eval_date_diff_common(d1, d2))),
         _ => Ok(Value::None),
@@ -171,21 +176,41 @@ pub fn eval_extract(
 }
 
 // For Now, CurrentDate, CurrentTime we don't need any expression:
-pub fn eval_now(_event: Option<&Event>, _context: Option<&EvalContext>) -> Result<Value> {
-    Ok(Value::DateTime(eval_now_common()))
+// wall-clock time in the query time zone, `None` if the query has no time zone
+fn now_in_query_zone(context: Option<&EvalContext>) -> Result<Option<NaiveDateTime>> {
+    Ok(context
+        .map(|context| context.time_zone())
+        .transpose()?
+        .flatten()
+        .map(|time_zone| utc_to_local(Utc::now().naive_utc(), &time_zone)))
+}
+
+pub fn eval_now(_event: Option<&Event>, context: Option<&EvalContext>) -> Result<Value> {
+    Ok(Value::DateTime(
+        now_in_query_zone(context)?.unwrap_or_else(eval_now_common),
+    ))
 }
 
-pub fn eval_current_date(_event: Option<&Event>, _context: Option<&EvalContext>) -> Result<Value> {
-    Ok(Value::Date(eval_current_date_common()))
+pub fn eval_current_date(_event: Option<&Event>, context: Option<&EvalContext>) -> Result<Value> {
+    Ok(Value::Date(
+        now_in_query_zone(context)?
+            .map(|now| now.date())
+            .unwrap_or_else(eval_current_date_common),
+    ))
 }
 
-pub fn eval_current_time(_event: Option<&Event>, _context: Option<&EvalContext>) -> Result<Value> {
-    Ok(Value::Str(SmallString::from(eval_current_time_common())))
+pub fn eval_current_time(_event: Option<&Event>, context: Option<&EvalContext>) -> Result<Value> {
+    Ok(Value::Str(
+        now_in_query_zone(context)?
+            .map(|now| now.time().format("%H:%M:%S").to_string())
+            .unwrap_or_else(|| SmallString::from(eval_current_time_common())),
+    ))
 }
 
 #[cfg(test)]
 mod tests {
     use crate::ast::core::Expr;
+    use crate::event_index::QueryConfig;
     use crate::sstring::SmallString;
     use chrono::{NaiveDate, NaiveDateTime};
 
@@ -304,4 +329,24 @@ mod tests {
         assert!(result.is_err());
         assert_eq!(result.unwrap_err().to_string(), "Invalid arguments for DateAdd. It expects compatible types. But the provided value types are Str, Int");
     }
+
+    #[test]
+    pub fn test_eval_hour_in_time_zone() {
+        let datetime_expr = Box::new(Expr::LitDateTime(
+            NaiveDateTime::parse_from_str("2022-10-10 23:00:00", "%Y-%m-%d %H:%M:%S").unwrap(),
+        ));
+        let query_config = QueryConfig {
+            time_zone: Some("Europe/Warsaw".into()),
+            ..Default::default()
+        };
+        let context = EvalContext {
+            query_config: Some(&query_config),
+            ..Default::default()
+        };
+        let stored_variables = HashMap::new();
+        let result = eval_hour(None, Some(&context), &stored_variables, &datetime_expr).unwrap();
+        assert_eq!(result, Value::Int(1));
+        let result = eval_to_date(None, Some(&context), &stored_variables, &datetime_expr).unwrap();
+        assert_eq!(result, Value::Date(NaiveDate::from_ymd(2022, 10, 11)));
+    }
 }
@@ -39,16 +39,17 @@ pub fn eval_date_part_common(
     }
 }
 
+// event times are stored in UTC so the current time is in UTC too
 pub fn eval_now_common() -> NaiveDateTime {
-    chrono::Local::now().naive_local()
+    chrono::Utc::now().naive_utc()
 }
 
 pub fn eval_current_date_common() -> NaiveDate {
-    chrono::Local::today().naive_local()
+    chrono::Utc::now().date_naive()
 }
 
 pub fn eval_current_time_common() -> String {
-    chrono::Local::now().time().format("%H:%M:%S").to_string()
+    chrono::Utc::now().time().format("%H:%M:%S").to_string()
 }
 
 // Concrete functions
@@ -7,6 +7,11 @@ macro_rules! define_single_expr_eval_fn {
             expr: &BExpr,
         ) -> Result<Value> {
             let val = eval_simple_expr(expr, event, context, stored_variables)?;
+            // date times are evaluated in the wall-clock time of the query time zone
+            let val = match context {
+                Some(context) => context.to_local_value(val)?,
+                None => val,
+            };
             match val {
                 $( $arm )*,
                 _ => {
@@ -33,6 +38,11 @@ macro_rules! define_double_expr_eval_fn {
         ) -> Result<Value> {
             let val1 = eval_simple_expr(expr1, event, context, stored_variables)?;
             let val2 = eval_simple_expr(expr2, event, context, stored_variables)?;
+            // date times are evaluated in the wall-clock time of the query time zone
+            let (val1, val2) = match context {
+                Some(context) => (context.to_local_value(val1)?, context.to_local_value(val2)?),
+                None => (val1, val2),
+            };
 
             match (&val1, &val2) {
                 $( $arm )*,
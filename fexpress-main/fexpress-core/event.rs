@@ -310,7 +310,9 @@ impl From<SmallString> for EntityType {
     }
 }
 
-#[derive(Clone, Debug, Hash, Eq, PartialOrd, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[derive(
+    Clone, Debug, Hash, Eq, PartialOrd, Ord, PartialEq, Serialize, Deserialize, JsonSchema,
+)]
 pub struct EntityID(pub SmallString);
 
 impl From<&str> for EntityID {
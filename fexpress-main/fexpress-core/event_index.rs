@@ -1,36 +1,67 @@
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
+use std::ops::Bound;
 use std::str::FromStr;
 use std::sync::Arc;
 
+use crate::agg_cache::AggregationCache;
 use crate::ast::core::{AggrExpr, Expr};
+use crate::ast::leakage::{analyze_leakage, LeakageReport};
 use crate::map::HashMap;
 use crate::sstring::SmallString;
 use anyhow::{anyhow, bail, Context, Error, Result};
+use arrow::record_batch::RecordBatch;
 use chrono::NaiveDateTime;
 use itertools::Itertools;
 use rayon::prelude::*;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use vec1::Vec1;
 
+use crate::arrow_io::{load_parquet, load_record_batch, records_to_record_batch};
+use crate::datetime_utils::parse_time_zone;
 use crate::eval::{eval_context_dispatcher, EvalContext};
-use crate::event::{EntityType, Event};
+use crate::event::{AttributeKey, AttributeName, Entity, EntityType, Event, EventType};
+use crate::event_store::retention::RetentionPolicy;
+use crate::event_store::row_event_store::attribute_index::{AttributeFilter, AttributeIndexKind};
 use crate::event_store::row_event_store::memory_event_store::MemoryEventStore;
+use crate::event_store::row_event_store::wal::DurabilityConfig;
+use crate::event_store::schema::{EventTypeSchema, SchemaVersion, SchemaViolation};
 use crate::event_store::{EventStore, EventStoreImpl};
+use crate::expand::{expand_rows, ExpandedRows};
+use crate::feature_frame::{FeatureFrame, FeatureMeta, FrameIndex};
 use crate::features::{Feature, FeatureExtractor, Features};
 use crate::features_rewrite::rewrite_untyped_attributes;
+use crate::ingest::{load_file, EventMapping, IngestionConfig};
 use crate::interval::NaiveDateTimeInterval;
-use crate::obs_dates::{ObsDate, ObservationDates, ObservationDatesConfig};
+use crate::obs_dates::{ObsDate, ObservationDates, ObservationDatesConfig, ObservationTime};
 use crate::types::{Entities, Timestamp};
 use crate::utils::transpose_vv;
 use crate::value::Value;
 
+/// Entities and observation time of a row of the query output
+pub type RowIndex = (Entities, ObservationTime);
+
+// rows of feature values calculated for each entity
+type EntityRows = Vec<(Entities, Vec<Vec<Value>>)>;
+
+// features that have a column in the output, variable assignments are only used
+// by the other features
+fn output_features(features: &Features) -> Vec<&Feature> {
+    features
+        .features
+        .iter()
+        .filter(|feature| !matches!(feature.expr, Expr::VariableAssign(_, _)))
+        .collect()
+}
+
 #[derive(Debug, Clone)]
 pub struct Query {
     pub features: Vec<Feature>,
     pub event_scope: EventScopeConfig,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum RawQuery {
     VecExpr(Vec<String>),
     SelectExpr(String),
@@ -42,6 +73,27 @@ pub struct QueryConfig {
     pub parallel: bool,
     #[serde(default)]
     pub include_events_on_obs_date: bool,
+    /// Reject queries with features that can see events after the observation date,
+    /// see `crate::ast::leakage`
+    #[serde(default)]
+    pub strict_leakage: bool,
+    /// Name of the registered holiday calendar used by `NextWorkDay` / `PreviousWorkDay`,
+    /// weekends only if not set
+    #[serde(default)]
+    pub business_calendar: Option<String>,
+    /// IANA time zone (e.g. `America/New_York`) used for calendar intervals like `yesterday`
+    /// and for date functions, UTC if not set
+    #[serde(default)]
+    pub time_zone: Option<String>,
+    /// Event attribute holding the time zone of the entity, the most recent value
+    /// overrides `time_zone`
+    #[serde(default)]
+    pub time_zone_attribute: Option<String>,
+    /// Keys of the features with `GROUP BY ... EXPAND` by feature name, usually taken from
+    /// `FeatureFrame::expanded_keys` of the training run so that scoring returns the same
+    /// columns. Features not listed take the keys from the data.
+    #[serde(default)]
+    pub expanded_keys: BTreeMap<String, Vec<String>>,
 }
 
 impl Default for QueryConfig {
@@ -49,6 +101,11 @@ impl Default for QueryConfig {
         QueryConfig {
             parallel: false,
             include_events_on_obs_date: false,
+            strict_leakage: false,
+            business_calendar: None,
+            time_zone: None,
+            time_zone_attribute: None,
+            expanded_keys: Default::default(),
         }
     }
 }
@@ -104,6 +161,11 @@ impl RawQuery {
         };
         features_vec
     }
+
+    /// Names of the output columns defined with `LABEL`, in the order of the query.
+    pub fn label_names(&self) -> Result<Vec<String>> {
+        Ok(Features::try_from(self.clone())?.label_names())
+    }
 }
 
 impl From<Vec<String>> for RawQuery {
@@ -152,16 +214,150 @@ impl EventContext {
     //     }
     // }
 
+    /// Memory event store that logs every insert to `dir` and is restored from it when the
+    /// context is created again
+    pub fn new_durable(dir: &str, config: DurabilityConfig) -> Result<EventContext> {
+        Ok(Self {
+            event_store: EventStoreImpl::MemoryEventStore(MemoryEventStore::open_durable(
+                dir, config,
+            )?),
+        })
+    }
+
     pub fn new_event(&mut self, event: Event) -> Result<()> {
         self.event_store.insert(event)?;
         Ok(())
     }
 
+    /// Declares the schema of an event type, the events inserted afterwards are checked against
+    /// it and rejected, converted or reported depending on the mode of the schema
+    pub fn declare_schema(&mut self, schema: EventTypeSchema) -> Result<()> {
+        self.event_store.declare_schema(schema)
+    }
+
+    /// Violations of the schemas declared in warn mode
+    pub fn schema_warnings(&self) -> Vec<SchemaViolation> {
+        self.event_store.schema_warnings()
+    }
+
+    /// Versions of the inferred schema of the event type
+    pub fn schema_versions(&self, event_type: &str) -> Vec<SchemaVersion> {
+        self.event_store
+            .schema_versions(&EventType(SmallString::from(event_type)))
+    }
+
+    /// Removes the event with the id, returns whether it existed
+    pub fn delete_event(&mut self, event_id: &str) -> Result<bool> {
+        self.event_store.delete_event(&SmallString::from(event_id))
+    }
+
+    /// Inserts the event or replaces the event with the same event_id
+    pub fn upsert(&mut self, event: Event) -> Result<()> {
+        self.event_store.upsert(event)
+    }
+
+    /// Removes all the events of the entity, returns the number of removed events
+    pub fn delete_entity(&mut self, entity_type: &str, entity_id: &str) -> Result<usize> {
+        self.event_store.delete_entity(&Entity {
+            typ: EntityType(SmallString::from(entity_type)),
+            id: SmallString::from(entity_id),
+        })
+    }
+
+    /// Removes the events older than the time to live of their event type at `now`, returns
+    /// the number of removed events
+    pub fn apply_retention(&mut self, policy: &RetentionPolicy, now: &Timestamp) -> Result<usize> {
+        self.event_store.apply_retention(policy, now)
+    }
+
+    /// Loads the events from a CSV or NDJSON file, returns the number of loaded events
+    pub fn load_file(&mut self, path: &str, config: &IngestionConfig) -> Result<usize> {
+        load_file(&self.event_store, path, config)
+    }
+
+    /// Loads the rows of an Arrow record batch as events, returns the number of loaded events
+    pub fn load_record_batch(
+        &mut self,
+        batch: &RecordBatch,
+        mapping: &EventMapping,
+    ) -> Result<usize> {
+        load_record_batch(&self.event_store, batch, mapping)
+    }
+
+    /// Loads the events from a Parquet file, returns the number of loaded events
+    pub fn load_parquet(&mut self, path: &str, mapping: &EventMapping) -> Result<usize> {
+        load_parquet(&self.event_store, path, mapping)
+    }
+
+    /// Writes the events of the memory event store to a snapshot file, returns the number of
+    /// written events
+    pub fn save_snapshot(&self, path: &str) -> Result<usize> {
+        match &self.event_store {
+            EventStoreImpl::MemoryEventStore(store) => store.save_snapshot(path),
+            _ => bail!("Snapshots are only supported by the memory event store"),
+        }
+    }
+
+    /// Compacts the log of a durable event store into a snapshot
+    pub fn compact(&self) -> Result<()> {
+        match &self.event_store {
+            EventStoreImpl::MemoryEventStore(store) => store.compact(),
+            _ => bail!("Compaction is only supported by the memory event store"),
+        }
+    }
+
+    /// Replaces the events with the events of a snapshot, returns the number of loaded events
+    pub fn load_snapshot(&mut self, path: &str) -> Result<usize> {
+        if let EventStoreImpl::MemoryEventStore(previous) = &self.event_store {
+            // the loaded store would not write to the log of the durable store
+            if previous.is_durable() {
+                bail!("Cannot load a snapshot into a durable event store, its log would miss the events");
+            }
+        }
+        let store = MemoryEventStore::load_snapshot(path)?;
+        // the declared attribute indices are rebuilt on the loaded events and the declared
+        // schemas are kept
+        if let EventStoreImpl::MemoryEventStore(previous) = &self.event_store {
+            for (attribute, kind) in previous.attribute_indexes() {
+                store.create_attribute_index(&attribute, kind)?;
+            }
+            for schema in previous.declared_schemas() {
+                store.declare_schema(schema)?;
+            }
+        }
+        let n_events = store.get_n_events();
+        self.event_store = EventStoreImpl::MemoryEventStore(store);
+        Ok(n_events)
+    }
+
+    /// Declares a secondary index on an attribute, where clauses comparing the attribute with
+    /// literals then only read the matching events. Hash indices answer `=` and `in`,
+    /// BTree indices answer ranges too
+    pub fn create_attribute_index(&self, attribute: &str, kind: AttributeIndexKind) -> Result<()> {
+        match &self.event_store {
+            EventStoreImpl::MemoryEventStore(store) => {
+                store.create_attribute_index(&AttributeName::new(attribute), kind)
+            }
+            _ => bail!("Attribute indices are only supported by the memory event store"),
+        }
+    }
+
     pub fn query(&mut self, _query: String) -> Result<Vec<String>, Vec<Vec<Value>>> {
         todo!()
     }
 
-    pub fn extract_records_from_expr(
+    /// Classifies the windows of the features of the query relative to the observation date
+    pub fn analyze_leakage(
+        &self,
+        query: RawQuery,
+        query_config: &QueryConfig,
+    ) -> Result<LeakageReport> {
+        let features = Features::try_from(query)?;
+        Ok(analyze_leakage(&features, query_config))
+    }
+
+    /// Calculates the features, the rows of each entity are sorted by the observation time
+    fn extract_entity_rows(
         &mut self,
         obs_dates: ObservationDatesConfig,
         event_scope_config: EventScopeConfig,
@@ -169,17 +365,30 @@ impl EventContext {
         query_config: &QueryConfig,
         experiment_id: Option<SmallString>,
         chunk_size: Option<usize>,
-    ) -> Result<(Vec<String>, Vec<Vec<Value>>)> {
+    ) -> Result<(Features, ObservationDates, EntityRows)> {
         let obs_dates_materialized = obs_dates
             .clone()
-            .materialize_observation_dates(Box::new(self.event_store.clone()), query_config)?;
+            .materialize_observation_dates(self.event_store.clone(), query_config)?;
 
         let mut features = Features::try_from(query)?;
 
+        if query_config.strict_leakage {
+            let report = analyze_leakage(&features, query_config);
+            if report.is_leaking() {
+                bail!(
+                    "Features can see events after the observation date:\n{}",
+                    report
+                );
+            }
+        }
+
         rewrite_untyped_attributes(&mut features, &mut self.event_store)
             .context("Cannot rewrite features")?;
 
-        let entities: Vec<_> = obs_dates_materialized.inner.keys().collect_vec();
+        // sorted entities give the same order of rows in every run, the parallel
+        // folds below keep the order of the entities
+        let entities: Vec<_> = obs_dates_materialized.inner.keys().sorted().collect_vec();
+        let agg_cache = AggregationCache::new();
 
         let results: Vec<_> = match (query_config.parallel, chunk_size) {
             (true, Some(chunk_size)) => entities
@@ -193,10 +402,11 @@ impl EventContext {
                             entity,
                             query_config,
                             &event_scope_config,
+                            &agg_cache,
                         )?;
-                        acc.push(res);
+                        acc.push(((*entity).clone(), res));
                     }
-                    Ok::<Vec<Vec<Vec<Value>>>, Error>(acc)
+                    Ok::<EntityRows, Error>(acc)
                 })
                 .try_reduce(Vec::new, |mut a, mut b| {
                     a.append(&mut b);
@@ -212,9 +422,10 @@ impl EventContext {
                         entity_id,
                         query_config,
                         &event_scope_config,
+                        &agg_cache,
                     )?;
-                    acc.push(res);
-                    Ok::<Vec<Vec<Vec<Value>>>, Error>(acc)
+                    acc.push(((*entity_id).clone(), res));
+                    Ok::<EntityRows, Error>(acc)
                 })
                 .try_reduce(Vec::new, |mut a, mut b| {
                     a.append(&mut b);
@@ -232,32 +443,135 @@ impl EventContext {
                         entity_id,
                         query_config,
                         &event_scope_config,
+                        &agg_cache,
                     )?;
-                    results.push(output);
+                    results.push(((*entity_id).clone(), output));
                 }
                 results
             }
         };
 
-        let variable_assign_feature_names = features
-            .features
-            .iter()
-            .map(|feature| {
-                (
-                    matches!(feature.expr, Expr::VariableAssign(_, _)),
-                    feature.get_name(),
-                )
-            })
-            .collect_vec();
+        Ok((features, obs_dates_materialized, results))
+    }
 
-        let feature_names = variable_assign_feature_names
-            .iter()
-            .filter(|(var_assign, _feature_name)| !*var_assign)
-            .map(|(_var_assign, feature_name)| feature_name)
-            .cloned()
+    /// Calculates the features and expands the grouped ones, returns the rows together with
+    /// the entities and the observation time of each row
+    fn extract_indexed_rows(
+        &mut self,
+        obs_dates: ObservationDatesConfig,
+        event_scope_config: EventScopeConfig,
+        query: RawQuery,
+        query_config: &QueryConfig,
+        experiment_id: Option<SmallString>,
+        chunk_size: Option<usize>,
+    ) -> Result<(Features, ExpandedRows, Vec<RowIndex>)> {
+        let (features, obs_dates, results) = self.extract_entity_rows(
+            obs_dates,
+            event_scope_config,
+            query,
+            query_config,
+            experiment_id,
+            chunk_size,
+        )?;
+
+        let output_features = output_features(&features);
+
+        let mut index = vec![];
+        let mut rows = vec![];
+        for (entities, entity_rows) in results {
+            let obs_times = match obs_dates.inner.get(&entities) {
+                Some(obs_times) => obs_times.iter().cloned().collect_vec(),
+                None => continue,
+            };
+            // features are calculated once per observation time
+            let obs_times = obs_times
+                .into_iter()
+                .sorted_by_key(|obs_time| obs_time.datetime)
+                .dedup_by(|a, b| a.datetime == b.datetime)
+                .collect_vec();
+            if !output_features.is_empty() && entity_rows.len() != obs_times.len() {
+                bail!(
+                    "{} rows for {} observation times of {:?}",
+                    entity_rows.len(),
+                    obs_times.len(),
+                    entities
+                );
+            }
+            index.extend(obs_times.into_iter().map(|t| (entities.clone(), t)));
+            rows.extend(entity_rows);
+        }
+
+        let expanded = expand_rows(
+            output_features.iter().map(|f| f.get_name()).collect(),
+            &output_features
+                .iter()
+                .map(|f| f.expand().cloned())
+                .collect_vec(),
+            rows,
+            &query_config.expanded_keys,
+        );
+
+        Ok((features, expanded, index))
+    }
+
+    /// Returns the feature names, the rows of feature values and the entities and the
+    /// observation time of each row. Rows are sorted by the entities and then by the
+    /// observation time.
+    pub fn extract_records_from_expr(
+        &mut self,
+        obs_dates: ObservationDatesConfig,
+        event_scope_config: EventScopeConfig,
+        query: RawQuery,
+        query_config: &QueryConfig,
+        experiment_id: Option<SmallString>,
+        chunk_size: Option<usize>,
+    ) -> Result<(Vec<String>, Vec<Vec<Value>>, Vec<RowIndex>)> {
+        let (_features, expanded, index) = self.extract_indexed_rows(
+            obs_dates,
+            event_scope_config,
+            query,
+            query_config,
+            experiment_id,
+            chunk_size,
+        )?;
+
+        Ok((expanded.names, expanded.rows, index))
+    }
+
+    /// Same as `extract_records_from_expr` but returns typed columns together with
+    /// the entities and the observation time of each row
+    pub fn extract_frame_from_expr(
+        &mut self,
+        obs_dates: ObservationDatesConfig,
+        event_scope_config: EventScopeConfig,
+        query: RawQuery,
+        query_config: &QueryConfig,
+        experiment_id: Option<SmallString>,
+        chunk_size: Option<usize>,
+    ) -> Result<FeatureFrame> {
+        let (features, expanded, index) = self.extract_indexed_rows(
+            obs_dates,
+            event_scope_config,
+            query,
+            query_config,
+            experiment_id,
+            chunk_size,
+        )?;
+
+        let output_features = output_features(&features);
+        let feature_meta = expanded
+            .names
+            .into_iter()
+            .zip(expanded.sources)
+            .map(|(name, (i, expanded))| FeatureMeta {
+                expr: Some(output_features[i].raw.clone()),
+                label: output_features[i].label,
+                expanded,
+                ..FeatureMeta::new(name)
+            })
             .collect_vec();
 
-        Ok((feature_names, results.concat()))
+        FeatureFrame::from_rows(Some(FrameIndex::new(&index)), feature_meta, expanded.rows)
     }
 
     pub fn extract_features_from_expr(
@@ -272,7 +586,7 @@ impl EventContext {
         // let obs_dates_materialied = obs_dates
         //     .clone()
         //     .materialize_observation_dates(Box::new(self.event_store.clone()))?;
-        let (feature_names, results) = self.extract_records_from_expr(
+        let (feature_names, results, _index) = self.extract_records_from_expr(
             obs_dates.clone(),
             entity_query,
             query,
@@ -288,6 +602,36 @@ impl EventContext {
             .collect())
     }
 
+    /// Same as `extract_records_from_expr` but returns an Arrow record batch,
+    /// the index columns (see `FrameIndex::columns`) come before the features
+    pub fn extract_record_batch_from_expr(
+        &mut self,
+        obs_dates: ObservationDatesConfig,
+        event_scope_config: EventScopeConfig,
+        query: RawQuery,
+        query_config: &QueryConfig,
+        experiment_id: Option<SmallString>,
+        chunk_size: Option<usize>,
+    ) -> Result<RecordBatch> {
+        let (feature_names, rows, index) = self.extract_records_from_expr(
+            obs_dates,
+            event_scope_config,
+            query,
+            query_config,
+            experiment_id,
+            chunk_size,
+        )?;
+        let index = FrameIndex::new(&index);
+        let columns = index.names().into_iter().chain(feature_names).collect_vec();
+        let rows = index
+            .to_rows()
+            .into_iter()
+            .zip(rows)
+            .map(|(index_row, row)| index_row.into_iter().chain(row).collect())
+            .collect_vec();
+        records_to_record_batch(&columns, &rows)
+    }
+
     fn extract_features_for_entity(
         &self,
         obs_dates: &ObservationDates,
@@ -296,6 +640,7 @@ impl EventContext {
         entities: &Entities,
         query_config: &QueryConfig,
         event_query_config: &EventScopeConfig,
+        agg_cache: &AggregationCache,
     ) -> Result<Vec<Vec<Value>>> {
         // let events = match event_query_config {
         //     EventQueryConfig::RelatedEntitiesEvents(entities_vec) => {
@@ -324,6 +669,7 @@ impl EventContext {
             entities,
             query_config,
             experiment_id,
+            agg_cache,
         )
     }
 
@@ -335,6 +681,7 @@ impl EventContext {
         entities: &Entities,
         query_config: &QueryConfig,
         experiment_id: &Option<SmallString>,
+        agg_cache: &AggregationCache,
     ) -> Result<Vec<Vec<Value>>> {
         // Number of real features excluding variable assignments
         // we need to map the original feature index to the real features index
@@ -359,53 +706,129 @@ impl EventContext {
 
         // allocate 2d matrix with values
         if let Some(obs_datetime) = obs_dates.inner.get(entities) {
-            let mut value_matrix: Vec<Vec<Value>> =
-                vec![vec![Value::None; obs_datetime.0.len()]; n_real_features];
-            let context = EvalContext {
-                entities: Some(entities.clone()),
-                experiment_id: experiment_id.clone(),
-                query_config: Some(query_config),
-                obs_date: Some(ObsDate {
-                    inner: obs_datetime.0.clone(),
-                }),
-                event_index: Some(self),
-                event_types: vec![],
-                event: None,
-                obs_time: None,
-                event_on_obs_date: None,
-                event_query_config: Some(event_query_config.clone()),
-            };
-
+            let mut feature_values: Vec<HashMap<Timestamp, Value>> =
+                vec![HashMap::new(); n_real_features];
             let mut stored_variables: HashMap<SmallString, HashMap<Timestamp, Value>> =
                 HashMap::new();
-            for feature_index in &features.calculation_order {
-                let feature = features
-                    .features
-                    .get(*feature_index)
-                    .context("Cannot extract feature")?;
-                let expr_result_many =
-                    eval_context_dispatcher(&(feature.expr), &context, &stored_variables)?;
-
-                match &feature.expr {
-                    Expr::VariableAssign(variable_name, _) => {
-                        stored_variables.insert(variable_name.clone(), expr_result_many);
-                    }
-                    _ => {
-                        let sorted_vec = expr_result_many
-                            .into_iter()
-                            .sorted_by_key(|(ts, _value)| *ts)
-                            .map(|(_ts, value)| value)
-                            .collect_vec();
-                        value_matrix[feature_index_mapping[&feature_index]] = sorted_vec;
+            // the time zone of the entity overrides the time zone of the query, the
+            // observation dates with the same time zone are evaluated together
+            for (query_config, obs_times) in
+                self.group_by_entity_time_zone(entities, &obs_datetime.0, query_config)?
+            {
+                let context = EvalContext {
+                    entities: Some(entities.clone()),
+                    experiment_id: experiment_id.clone(),
+                    query_config: Some(&query_config),
+                    obs_date: Some(ObsDate { inner: obs_times }),
+                    event_index: Some(self),
+                    event_types: vec![],
+                    event: None,
+                    obs_time: None,
+                    event_on_obs_date: None,
+                    event_query_config: Some(event_query_config.clone()),
+                    agg_cache: Some(agg_cache),
+                };
+
+                for feature_index in &features.calculation_order {
+                    let feature = features
+                        .features
+                        .get(*feature_index)
+                        .context("Cannot extract feature")?;
+                    let expr_result_many =
+                        eval_context_dispatcher(&(feature.expr), &context, &stored_variables)?;
+
+                    match &feature.expr {
+                        Expr::VariableAssign(variable_name, _) => {
+                            stored_variables
+                                .entry(variable_name.clone())
+                                .or_default()
+                                .extend(expr_result_many);
+                        }
+                        _ => {
+                            feature_values[feature_index_mapping[feature_index]]
+                                .extend(expr_result_many);
+                        }
                     }
                 }
             }
+            let value_matrix = feature_values
+                .into_iter()
+                .map(|values| {
+                    values
+                        .into_iter()
+                        .sorted_by_key(|(ts, _value)| *ts)
+                        .map(|(_ts, value)| value)
+                        .collect_vec()
+                })
+                .collect_vec();
             Ok(transpose_vv(value_matrix))
         } else {
             Ok(vec![])
         }
     }
 
+    /// Splits the observation dates by the time zone of the entity known at each of them:
+    /// the latest value of `time_zone_attribute` in the events of the entity before the
+    /// observation date
+    fn group_by_entity_time_zone(
+        &self,
+        entities: &Entities,
+        obs_times: &Vec1<ObservationTime>,
+        query_config: &QueryConfig,
+    ) -> Result<Vec<(QueryConfig, Vec1<ObservationTime>)>> {
+        let attribute = match &query_config.time_zone_attribute {
+            Some(attribute) => AttributeName::new(attribute),
+            None => return Ok(vec![(query_config.clone(), obs_times.clone())]),
+        };
+        let event_types = self
+            .event_store
+            .get_schema()
+            .into_iter()
+            .filter(|(_, attributes)| attributes.contains_key(&attribute))
+            .map(|(event_type, _)| EventType(event_type))
+            .collect_vec();
+
+        let mut groups: BTreeMap<Option<String>, Vec<ObservationTime>> = BTreeMap::new();
+        for obs_time in obs_times.iter() {
+            let time_zone = event_types
+                .iter()
+                .filter_map(|event_type| {
+                    self.event_store.query_attribute_as_of(
+                        entities,
+                        event_type,
+                        &attribute,
+                        &obs_time.datetime,
+                        query_config,
+                    )
+                })
+                .max_by_key(|(version_ts, _)| *version_ts)
+                .and_then(|(_, value)| match value {
+                    Value::Str(time_zone) => Some(time_zone.to_string()),
+                    _ => None,
+                });
+            if let Some(time_zone) = &time_zone {
+                parse_time_zone(time_zone).with_context(|| {
+                    format!(
+                        "Invalid time zone in attribute {} of {:?}",
+                        attribute, entities
+                    )
+                })?;
+            }
+            groups.entry(time_zone).or_default().push(obs_time.clone());
+        }
+
+        groups
+            .into_iter()
+            .map(|(time_zone, obs_times)| {
+                let query_config = QueryConfig {
+                    time_zone: time_zone.or_else(|| query_config.time_zone.clone()),
+                    ..query_config.clone()
+                };
+                Ok((query_config, Vec1::try_from_vec(obs_times)?))
+            })
+            .collect()
+    }
+
     pub fn concat_events(
         &self,
         interval_events: Vec<(NaiveDateTime, Vec<Arc<Event>>)>,
@@ -456,11 +879,134 @@ pub fn check_event_type_index(expr: Expr) -> Option<String> {
     }
 }
 
+/// Returns the conditions of the where clause which may be answered by attribute indices
+pub fn check_agg_attribute_index(expr: &AggrExpr) -> Vec<AttributeFilter> {
+    match &expr.cond {
+        Some(cond) => check_attribute_index(cond),
+        None => vec![],
+    }
+}
+
+/// Attribute read from the event as is, the typed attributes are not casted
+fn indexed_attribute(expr: &Expr) -> Option<AttributeName> {
+    match expr {
+        Expr::AttrBool(key)
+        | Expr::AttrNum(key)
+        | Expr::AttrInt(key)
+        | Expr::AttrStr(key)
+        | Expr::AttrMapNum(key)
+        | Expr::AttrMapStr(key) => Some(AttributeName(key.to_kstring())),
+        // `entities.<type>` reads the entity of the event
+        Expr::AttrUntyped(AttributeKey::Nested(keys)) if keys.first() == "entities" => None,
+        Expr::AttrUntyped(key) => Some(AttributeName(key.to_kstring())),
+        _ => None,
+    }
+}
+
+fn scalar_literal(expr: &Expr) -> Option<Value> {
+    match expr {
+        Expr::LitBool(v) => Some(Value::Bool(*v)),
+        Expr::LitNum(v) => Some(Value::Num(**v)),
+        Expr::LitInt(v) => Some(Value::Int(*v)),
+        Expr::LitStr(v) => Some(Value::Str(SmallString::from(v))),
+        _ => None,
+    }
+}
+
+fn tuple_literal(expr: &Expr) -> Option<Vec<Value>> {
+    match expr {
+        Expr::TupleLitNum(v) => Some(v.iter().map(|v| Value::Num(**v)).collect()),
+        Expr::TupleLitInt(v) => Some(v.iter().map(|v| Value::Int(*v)).collect()),
+        Expr::TupleLitStr(v) => Some(v.iter().map(|v| Value::Str(SmallString::from(v))).collect()),
+        _ => None,
+    }
+}
+
+fn range_filter(lhs: &Expr, rhs: &Expr, greater: bool, inclusive: bool) -> Option<AttributeFilter> {
+    let bound = |value: Value| {
+        if inclusive {
+            Bound::Included(value)
+        } else {
+            Bound::Excluded(value)
+        }
+    };
+    // `literal > attr` is the same as `attr < literal`
+    let (attribute, value, greater) = match (indexed_attribute(lhs), indexed_attribute(rhs)) {
+        (Some(attribute), None) => (attribute, scalar_literal(rhs)?, greater),
+        (None, Some(attribute)) => (attribute, scalar_literal(lhs)?, !greater),
+        _ => return None,
+    };
+    if !matches!(value, Value::Int(_) | Value::Num(_)) {
+        return None;
+    }
+    Some(if greater {
+        AttributeFilter::Range(attribute, bound(value), Bound::Unbounded)
+    } else {
+        AttributeFilter::Range(attribute, Bound::Unbounded, bound(value))
+    })
+}
+
+/// Returns the conditions on attributes compared to literals joined by `and` in the expression:
+/// attr = 'a', attr in (1, 2), attr > 1.0 and attr <= 2.0 (merged into one range)
+pub fn check_attribute_index(expr: &Expr) -> Vec<AttributeFilter> {
+    let mut filters = vec![];
+    collect_attribute_filters(expr, &mut filters);
+
+    let mut merged: Vec<AttributeFilter> = vec![];
+    for filter in filters {
+        if let AttributeFilter::Range(attribute, low, high) = &filter {
+            let previous = merged.iter_mut().find(|previous| {
+                matches!(previous, AttributeFilter::Range(previous_attribute, _, _) if previous_attribute == attribute)
+            });
+            if let Some(AttributeFilter::Range(_, previous_low, previous_high)) = previous {
+                if matches!(previous_low, Bound::Unbounded) {
+                    *previous_low = low.clone();
+                }
+                if matches!(previous_high, Bound::Unbounded) {
+                    *previous_high = high.clone();
+                }
+                continue;
+            }
+        }
+        merged.push(filter);
+    }
+    merged
+}
+
+fn collect_attribute_filters(expr: &Expr, filters: &mut Vec<AttributeFilter>) {
+    let filter = match expr {
+        Expr::And(lhs, rhs) => {
+            collect_attribute_filters(lhs, filters);
+            collect_attribute_filters(rhs, filters);
+            return;
+        }
+        Expr::Eq(lhs, rhs) => match (indexed_attribute(lhs), indexed_attribute(rhs)) {
+            (Some(attribute), None) => {
+                scalar_literal(rhs).map(|value| AttributeFilter::Eq(attribute, value))
+            }
+            (None, Some(attribute)) => {
+                scalar_literal(lhs).map(|value| AttributeFilter::Eq(attribute, value))
+            }
+            _ => None,
+        },
+        Expr::In(needle, haystack) => indexed_attribute(needle)
+            .zip(tuple_literal(haystack))
+            .map(|(attribute, values)| AttributeFilter::In(attribute, values)),
+        Expr::Greater(lhs, rhs) => range_filter(lhs, rhs, true, false),
+        Expr::GreaterEq(lhs, rhs) => range_filter(lhs, rhs, true, true),
+        Expr::Less(lhs, rhs) => range_filter(lhs, rhs, false, false),
+        Expr::LessEq(lhs, rhs) => range_filter(lhs, rhs, false, true),
+        _ => None,
+    };
+    filters.extend(filter);
+}
+
 #[cfg(test)]
 mod test {
     use chrono::Utc;
 
-    use crate::event::{AttributeName, EventType};
+    use crate::event::EventType;
+    use crate::value::ValueType;
 
     use super::*;
 
@@ -520,6 +1066,41 @@ mod test {
         assert_eq!(check_agg_event_type_index(&expr), None);
     }
 
+    #[test]
+    fn check_if_expr_uses_attribute_index() {
+        let filters = |query: &str| {
+            check_agg_attribute_index(&convert_to_aggrexpr(Expr::from_str(query).unwrap()))
+        };
+        assert_eq!(
+            filters("count(*) over past where category = 'a' and num1 > 100.0"),
+            vec![
+                AttributeFilter::Eq(AttributeName::new("category"), Value::Str("a".into())),
+                AttributeFilter::Range(
+                    AttributeName::new("num1"),
+                    Bound::Excluded(Value::Num(100.0)),
+                    Bound::Unbounded
+                ),
+            ]
+        );
+        assert_eq!(
+            filters("count(*) over past where 10 <= num1 and num1 < 20 and category in ('a', 'b')"),
+            vec![
+                AttributeFilter::Range(
+                    AttributeName::new("num1"),
+                    Bound::Included(Value::Int(10)),
+                    Bound::Excluded(Value::Int(20))
+                ),
+                AttributeFilter::In(
+                    AttributeName::new("category"),
+                    vec![Value::Str("a".into()), Value::Str("b".into())]
+                ),
+            ]
+        );
+        assert!(filters("count(*) over past where category = 'a' or num1 > 100.0").is_empty());
+        assert!(filters("count(*) over past where entities.user = 'a'").is_empty());
+        assert!(filters("count(*) over past").is_empty());
+    }
+
     #[test]
     fn check_if_expr_uses_event_type_from() {
         let expr = Expr::from_str("last(num1) over past from test").unwrap();
@@ -531,6 +1112,428 @@ mod test {
         assert_eq!(check_agg_event_type_index(&expr), Some("test".into()));
     }
 
+    #[test]
+    fn test_entity_independent_aggregation_shared_between_entities() {
+        let mut event_context = EventContext::default();
+        for (i, entity) in ["a", "b", "c"].iter().enumerate() {
+            let event = Event {
+                event_type: EventType("order".into()),
+                event_time: NaiveDateTime::from_str("2020-01-01T00:00:00").unwrap()
+                    + chrono::Duration::days(i as i64),
+                entities: btreemap!["user".into() => (*entity).into()],
+                event_id: Some(format!("{}", i).into()),
+                experiment_id: None,
+                attrs: Some(hashmap! {a!("value") => Value::Num(10.0 * (i + 1) as f32)}),
+            };
+            event_context.new_event(event).unwrap();
+        }
+        let mut entity_types = crate::map::HashSet::new();
+        entity_types.insert(EntityType("user".into()));
+        let obs_dates = ObservationDatesConfig::Fixed(crate::obs_dates::Fixed::new_from_str_vec(
+            entity_types,
+            vec!["2020-01-10T00:00:00".into()],
+        ));
+        let features = event_context
+            .extract_features_from_expr(
+                &obs_dates,
+                EventScopeConfig::AllEvents,
+                RawQuery::VecExpr(vec!["avg(value) over last 30 days as avg_value".into()]),
+                &QueryConfig::default(),
+                None,
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            features.get("avg_value").unwrap().clone(),
+            vec![Value::Num(20.0); 3]
+        );
+
+        // the aggregation is calculated for the first entity, the others read the cache
+        let obs_dates = obs_dates
+            .clone()
+            .materialize_observation_dates(
+                event_context.event_store.clone(),
+                &QueryConfig::default(),
+            )
+            .unwrap();
+        let features = Features::try_from(RawQuery::VecExpr(vec![
+            "avg(value) over last 30 days as avg_value".into(),
+        ]))
+        .unwrap();
+        let agg_cache = AggregationCache::new();
+        for entities in obs_dates.inner.keys() {
+            let rows = event_context
+                .extract_features_for_entity(
+                    &obs_dates,
+                    &None,
+                    &features,
+                    entities,
+                    &QueryConfig::default(),
+                    &EventScopeConfig::AllEvents,
+                    &agg_cache,
+                )
+                .unwrap();
+            assert_eq!(rows, vec![vec![Value::Num(20.0)]]);
+        }
+        assert_eq!(agg_cache.len(), 1);
+        assert_eq!(agg_cache.hits(), 2);
+    }
+
+    #[test]
+    fn test_strict_leakage_rejects_future_features() {
+        let mut event_context = EventContext::default();
+        let mut entity_types = crate::map::HashSet::new();
+        entity_types.insert(EntityType("user".into()));
+        let obs_dates = ObservationDatesConfig::Fixed(crate::obs_dates::Fixed::new_from_str_vec(
+            entity_types,
+            vec!["2020-01-10T00:00:00".into()],
+        ));
+        let query_config = QueryConfig {
+            strict_leakage: true,
+            ..Default::default()
+        };
+        let query = RawQuery::VecExpr(vec![
+            "count(*) over past as count_past".into(),
+            "count(*) over tomorrow as count_tomorrow".into(),
+        ]);
+        let report = event_context
+            .analyze_leakage(query.clone(), &query_config)
+            .unwrap();
+        assert_eq!(report.leaking_features().len(), 1);
+        assert_eq!(report.leaking_features()[0].feature, "count_tomorrow");
+
+        let err = event_context
+            .extract_records_from_expr(
+                obs_dates.clone(),
+                EventScopeConfig::AllEvents,
+                query,
+                &query_config,
+                None,
+                None,
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("count_tomorrow"));
+
+        assert!(event_context
+            .extract_records_from_expr(
+                obs_dates,
+                EventScopeConfig::AllEvents,
+                RawQuery::VecExpr(vec!["count(*) over past as count_past".into()]),
+                &query_config,
+                None,
+                None,
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_time_zone_of_query_and_entity() {
+        let mut event_context = EventContext::default();
+        let events = vec![
+            ("tokyo", "Asia/Tokyo", "2020-01-09T20:00:00"),
+            ("tokyo", "Asia/Tokyo", "2020-01-09T20:30:00"),
+            ("london", "Europe/London", "2020-01-09T20:00:00"),
+            // after the observation date so it doesn't change the time zone of the entity
+            ("london", "Asia/Tokyo", "2020-01-11T00:00:00"),
+        ];
+        for (i, (user, time_zone, event_time)) in events.into_iter().enumerate() {
+            let event = Event {
+                event_type: EventType("order".into()),
+                event_time: NaiveDateTime::from_str(event_time).unwrap(),
+                entities: btreemap!["user".into() => user.into()],
+                event_id: Some(format!("{}", i).into()),
+                experiment_id: None,
+                attrs: Some(hashmap! {a!("tz") => Value::Str(time_zone.into())}),
+            };
+            event_context.new_event(event).unwrap();
+        }
+        let mut entity_types = crate::map::HashSet::new();
+        entity_types.insert(EntityType("user".into()));
+        let obs_dates = ObservationDatesConfig::Fixed(crate::obs_dates::Fixed::new_from_str_vec(
+            entity_types,
+            vec!["2020-01-10T12:00:00".into()],
+        ));
+        let mut count_yesterday = |query_config: QueryConfig, scope: EventScopeConfig| {
+            let features = event_context
+                .extract_features_from_expr(
+                    &obs_dates,
+                    scope,
+                    RawQuery::VecExpr(vec!["count(*) over yesterday as cnt".into()]),
+                    &query_config,
+                    None,
+                    None,
+                )
+                .unwrap();
+            features
+                .get("cnt")
+                .unwrap()
+                .iter()
+                .map(|value| value.to_string())
+                .sorted()
+                .collect_vec()
+        };
+
+        let related = || EventScopeConfig::RelatedEntitiesEvents(vec![EntityType("user".into())]);
+        // in UTC all orders were placed yesterday
+        assert_eq!(
+            count_yesterday(QueryConfig::default(), related()),
+            vec!["1", "2"]
+        );
+        // in Tokyo the orders were placed on the day of the observation date
+        assert_eq!(
+            count_yesterday(
+                QueryConfig {
+                    time_zone: Some("Asia/Tokyo".into()),
+                    ..Default::default()
+                },
+                related()
+            ),
+            vec!["0", "0"]
+        );
+        let entity_time_zones = QueryConfig {
+            time_zone: Some("Asia/Tokyo".into()),
+            time_zone_attribute: Some("tz".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            count_yesterday(entity_time_zones.clone(), related()),
+            vec!["0", "1"]
+        );
+        // the cached results of the aggregations over all the events are not shared between
+        // the entities in different time zones
+        assert_eq!(
+            count_yesterday(entity_time_zones, EventScopeConfig::AllEvents),
+            vec!["0", "3"]
+        );
+    }
+
+    #[test]
+    fn test_extract_frame_from_expr() {
+        let mut event_context = EventContext::default();
+        let events = vec![
+            ("a", "2020-01-01T10:00:00", 1.5),
+            ("a", "2020-01-02T10:00:00", 2.5),
+            ("b", "2020-01-02T11:00:00", 4.0),
+        ];
+        for (i, (user, event_time, price)) in events.into_iter().enumerate() {
+            let event = Event {
+                event_type: EventType("order".into()),
+                event_time: NaiveDateTime::from_str(event_time).unwrap(),
+                entities: btreemap!["user".into() => user.into()],
+                event_id: Some(format!("{}", i).into()),
+                experiment_id: None,
+                attrs: Some(hashmap! {a!("price") => Value::Num(price)}),
+            };
+            event_context.new_event(event).unwrap();
+        }
+        let mut entity_types = crate::map::HashSet::new();
+        entity_types.insert(EntityType("user".into()));
+        let obs_dates = ObservationDatesConfig::Fixed(crate::obs_dates::Fixed::new_from_str_vec(
+            entity_types,
+            vec!["2020-01-02T00:00:00".into(), "2020-01-03T00:00:00".into()],
+        ));
+        let frame = event_context
+            .extract_frame_from_expr(
+                obs_dates,
+                EventScopeConfig::RelatedEntitiesEvents(vec![EntityType("user".into())]),
+                RawQuery::VecExpr(vec![
+                    "count(*) over past as cnt".into(),
+                    "sum(price) over past as total".into(),
+                ]),
+                &QueryConfig::default(),
+                None,
+                None,
+            )
+            .unwrap();
+
+        assert_eq!(frame.len(), 4);
+        assert_eq!(frame.feature_names(), vec!["cnt", "total"]);
+        assert_eq!(frame.features[0].value_type, ValueType::Int);
+        assert_eq!(frame.features[1].value_type, ValueType::Num);
+        assert_eq!(
+            frame.features[1].expr.as_deref(),
+            Some("sum(price) over past as total")
+        );
+
+        // each row is matched with the entity and the observation time it was calculated for
+        let rows = (0..frame.len())
+            .map(|row| {
+                (
+                    frame.col("user").unwrap().get(row).to_string(),
+                    frame.col("obs_dt").unwrap().get(row).to_string(),
+                    frame.col("cnt").unwrap().get(row).to_string(),
+                )
+            })
+            .sorted()
+            .collect_vec();
+        let cnt_by_row = rows.iter().map(|(_, _, cnt)| cnt.as_str()).collect_vec();
+        assert_eq!(cnt_by_row, vec!["1", "2", "0", "1"]);
+        assert_eq!(frame.col("total").unwrap().sum(), 1.5 + 4.0 + 0.0 + 4.0);
+    }
+
+    #[test]
+    fn test_records_index() {
+        let mut event_context = EventContext::default();
+        for (i, user) in vec!["c", "a", "b", "a"].into_iter().enumerate() {
+            let event = Event {
+                event_type: EventType("order".into()),
+                event_time: NaiveDateTime::from_str("2020-01-01T10:00:00").unwrap()
+                    + chrono::Duration::hours(i as i64),
+                entities: btreemap!["user".into() => user.into()],
+                event_id: Some(format!("{}", i).into()),
+                experiment_id: None,
+                attrs: None,
+            };
+            event_context.new_event(event).unwrap();
+        }
+        let mut entity_types = crate::map::HashSet::new();
+        entity_types.insert(EntityType("user".into()));
+        let obs_dates = ObservationDatesConfig::Fixed(crate::obs_dates::Fixed::new_from_str_vec(
+            entity_types,
+            vec!["2020-01-02T00:00:00".into(), "2020-01-03T00:00:00".into()],
+        ));
+        let query = RawQuery::VecExpr(vec!["count(*) over past as cnt".into()]);
+
+        let (names, rows, index) = event_context
+            .extract_records_from_expr(
+                obs_dates.clone(),
+                EventScopeConfig::RelatedEntitiesEvents(vec![EntityType("user".into())]),
+                query.clone(),
+                &QueryConfig {
+                    parallel: true,
+                    ..Default::default()
+                },
+                None,
+                Some(1),
+            )
+            .unwrap();
+        assert_eq!(names, vec!["cnt"]);
+        assert_eq!(rows.len(), index.len());
+        let index_order = index
+            .iter()
+            .map(|(entities, obs_time)| {
+                format!(
+                    "{} {}",
+                    entities[&EntityType("user".into())].0,
+                    obs_time.datetime.date()
+                )
+            })
+            .collect_vec();
+        assert_eq!(
+            index_order,
+            vec![
+                "a 2020-01-02",
+                "a 2020-01-03",
+                "b 2020-01-02",
+                "b 2020-01-03",
+                "c 2020-01-02",
+                "c 2020-01-03"
+            ]
+        );
+        assert_eq!(rows[0], vec![Value::Int(2)]);
+
+        let batch = event_context
+            .extract_record_batch_from_expr(
+                obs_dates,
+                EventScopeConfig::RelatedEntitiesEvents(vec![EntityType("user".into())]),
+                query,
+                &QueryConfig::default(),
+                None,
+                None,
+            )
+            .unwrap();
+        let fields = batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect_vec();
+        assert_eq!(fields, vec!["user", "obs_dt", "cnt"]);
+    }
+
+    #[test]
+    fn test_load_snapshot_into_durable_store() {
+        let dir = std::env::temp_dir().join("fexpress_test_load_snapshot_durable");
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = std::env::temp_dir().join("fexpress_test_load_snapshot_durable.snapshot");
+        let path = path.to_str().unwrap();
+        EventContext::default().save_snapshot(path).unwrap();
+
+        let mut event_context =
+            EventContext::new_durable(dir.to_str().unwrap(), Default::default()).unwrap();
+        let err = event_context.load_snapshot(path).unwrap_err();
+        assert!(err.to_string().contains("durable"));
+        // a store which is not durable is replaced
+        assert_eq!(EventContext::default().load_snapshot(path).unwrap(), 0);
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_expand_group_by() {
+        let mut event_context = EventContext::default();
+        let events = vec![
+            ("a", "2020-01-01T10:00:00", "books"),
+            ("a", "2020-01-01T11:00:00", "books"),
+            ("a", "2020-01-01T12:00:00", "games"),
+            ("b", "2020-01-01T10:00:00", "toys"),
+            ("b", "2020-01-01T11:00:00", "books"),
+        ];
+        for (i, (user, event_time, category)) in events.into_iter().enumerate() {
+            let event = Event {
+                event_type: EventType("order".into()),
+                event_time: NaiveDateTime::from_str(event_time).unwrap(),
+                entities: btreemap!["user".into() => user.into()],
+                event_id: Some(format!("{}", i).into()),
+                experiment_id: None,
+                attrs: Some(hashmap! {a!("category") => Value::Str(category.into())}),
+            };
+            event_context.new_event(event).unwrap();
+        }
+        let mut entity_types = crate::map::HashSet::new();
+        entity_types.insert(EntityType("user".into()));
+        let obs_dates = ObservationDatesConfig::Fixed(crate::obs_dates::Fixed::new_from_str_vec(
+            entity_types,
+            vec!["2020-01-02T00:00:00".into()],
+        ));
+        let mut extract = |query_config: &QueryConfig| {
+            event_context
+                .extract_frame_from_expr(
+                    obs_dates.clone(),
+                    EventScopeConfig::RelatedEntitiesEvents(vec![EntityType("user".into())]),
+                    RawQuery::VecExpr(vec![
+                        "count(*) over past group by category expand top 1 with other as cnt"
+                            .into(),
+                    ]),
+                    query_config,
+                    None,
+                    None,
+                )
+                .unwrap()
+        };
+
+        let training = extract(&QueryConfig::default());
+        assert_eq!(training.feature_names(), vec!["cnt__books", "cnt__other"]);
+        assert_eq!(training.col("cnt__books").unwrap().sum(), 3.0);
+        assert_eq!(training.col("cnt__other").unwrap().sum(), 2.0);
+
+        // the frozen keys are used even if another key is more frequent
+        let mut expanded_keys = training.expanded_keys();
+        assert_eq!(expanded_keys["cnt"], vec!["books".to_string()]);
+        expanded_keys.insert("cnt".into(), vec!["toys".into(), "music".into()]);
+        let scoring = extract(&QueryConfig {
+            expanded_keys,
+            ..Default::default()
+        });
+        assert_eq!(
+            scoring.feature_names(),
+            vec!["cnt__toys", "cnt__music", "cnt__other"]
+        );
+        assert_eq!(scoring.col("cnt__toys").unwrap().sum(), 1.0);
+        assert_eq!(scoring.col("cnt__music").unwrap().null_count(), 2);
+        assert_eq!(scoring.col("cnt__other").unwrap().sum(), 4.0);
+    }
+
     fn convert_to_aggrexpr(expr: Expr) -> AggrExpr {
         let expr = match expr {
             Expr::Aggr(expr) => Some(expr),
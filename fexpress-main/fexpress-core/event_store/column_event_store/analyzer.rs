@@ -0,0 +1,274 @@
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::event_store::column_event_store::encoded_column::{ColumnEncoding, EncodedColumnVec};
+use crate::event_store::column_event_store::encoding::bitpacking_int::bits_for_range;
+use crate::event_store::column_event_store::raw_column::{RawColumnVec, RawColumnVecGen};
+use crate::types::{BITS_PER_INT, INT};
+
+/*
+The analyzer looks at a raw column before it is encoded and picks the encoding:
+- long runs of the same value (sorted columns, mostly constant columns) -> run length
+- few distinct values -> dictionary
+- integers in a narrow range -> frame of reference bit packing
+- everything else -> LZ4
+The cardinality is estimated on a sample of the rows, the runs, nulls, range and sortedness
+are cheap enough to be computed on the whole column.
+ */
+
+/// Number of rows sampled to estimate the cardinality of a column
+pub const CARDINALITY_SAMPLE_SIZE: usize = 1024;
+/// Minimum average run length for run length encoding
+pub const MIN_AVG_RUN_LENGTH: f64 = 4.0;
+/// Maximum ratio of distinct values in the sample for dictionary encoding
+pub const MAX_DICTIONARY_CARDINALITY_RATIO: f64 = 0.5;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColumnStats {
+    pub n_rows: usize,
+    pub n_nulls: usize,
+    pub sample_size: usize,
+    /// Distinct values (nulls included) in the sample
+    pub sample_cardinality: usize,
+    pub avg_run_length: f64,
+    /// Whether the non null values are in ascending order
+    pub is_sorted: bool,
+    /// Minimum and maximum of integer columns
+    pub int_range: Option<(INT, INT)>,
+}
+
+impl ColumnStats {
+    pub fn cardinality_ratio(&self) -> f64 {
+        if self.sample_size == 0 {
+            return 0.0;
+        }
+        self.sample_cardinality as f64 / self.sample_size as f64
+    }
+
+    /// Bits per value needed by bit packing, `None` for non integer columns
+    pub fn bits_per_value(&self) -> Option<usize> {
+        self.int_range.map(|(min, max)| bits_for_range(min, max))
+    }
+}
+
+fn analyze_values<T: Hash + Eq + Ord>(values: Vec<Option<&T>>) -> ColumnStats {
+    let n_rows = values.len();
+    let n_nulls = values.iter().filter(|value| value.is_none()).count();
+
+    // evenly spaced rows so that sorted columns are not judged on their first values only
+    let step = (n_rows / CARDINALITY_SAMPLE_SIZE).max(1);
+    let sample: HashSet<_> = values.iter().step_by(step).collect();
+    let sample_size = n_rows.div_ceil(step);
+
+    let n_runs = 1 + values.windows(2).filter(|pair| pair[0] != pair[1]).count();
+    let avg_run_length = if n_rows == 0 {
+        0.0
+    } else {
+        n_rows as f64 / n_runs as f64
+    };
+
+    let non_nulls: Vec<_> = values.iter().flatten().collect();
+    let is_sorted = non_nulls.windows(2).all(|pair| pair[0] <= pair[1]);
+
+    ColumnStats {
+        n_rows,
+        n_nulls,
+        sample_size,
+        sample_cardinality: sample.len(),
+        avg_run_length,
+        is_sorted,
+        int_range: None,
+    }
+}
+
+fn analyze_column<T: Hash + Eq + Ord>(column: &RawColumnVecGen<T>) -> ColumnStats {
+    match column {
+        RawColumnVecGen::Nullable(v) => analyze_values(v.iter().map(|v| v.as_ref()).collect()),
+        RawColumnVecGen::NonNullable(v) => analyze_values(v.iter().map(Some).collect()),
+    }
+}
+
+pub fn analyze(column: &RawColumnVec) -> ColumnStats {
+    match column {
+        RawColumnVec::Bool(v) => analyze_column(v),
+        RawColumnVec::Num(v) => analyze_column(v),
+        RawColumnVec::Int(v) => {
+            let mut stats = analyze_column(v);
+            let ints: Vec<INT> = match v {
+                RawColumnVecGen::Nullable(v) => v.iter().flatten().copied().collect(),
+                RawColumnVecGen::NonNullable(v) => v.clone(),
+            };
+            stats.int_range = ints.iter().min().copied().zip(ints.iter().max().copied());
+            stats
+        }
+        RawColumnVec::Str(v) => analyze_column(v),
+        RawColumnVec::VecBool(v) => analyze_column(v),
+        RawColumnVec::VecNum(v) => analyze_column(v),
+        RawColumnVec::VecInt(v) => analyze_column(v),
+        RawColumnVec::VecStr(v) => analyze_column(v),
+        RawColumnVec::Date(v) => analyze_column(v),
+        RawColumnVec::DateTime(v) => analyze_column(v),
+    }
+}
+
+/// Picks the encoding of a raw column from its stats
+pub fn choose_encoding(column: &RawColumnVec) -> ColumnEncoding {
+    let stats = analyze(column);
+    // run length and dictionary are not implemented for floats
+    let float_column = matches!(column, RawColumnVec::Num(_) | RawColumnVec::VecNum(_));
+
+    if !float_column && stats.avg_run_length >= MIN_AVG_RUN_LENGTH {
+        return ColumnEncoding::RunLength;
+    }
+    if !float_column && stats.cardinality_ratio() <= MAX_DICTIONARY_CARDINALITY_RATIO {
+        // the values of a sorted low cardinality column are grouped in runs
+        if stats.is_sorted && stats.avg_run_length > 1.0 {
+            return ColumnEncoding::RunLength;
+        }
+        return ColumnEncoding::Dictionary;
+    }
+    if let Some(bits) = stats.bits_per_value() {
+        if bits <= BITS_PER_INT / 2 {
+            return ColumnEncoding::BitPacked;
+        }
+    }
+    ColumnEncoding::LZ4
+}
+
+/// Size of a column before and after encoding, the sizes are the bincode serialized sizes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColumnCompressionStats {
+    pub encoding: ColumnEncoding,
+    pub raw_size: u64,
+    pub encoded_size: u64,
+}
+
+impl ColumnCompressionStats {
+    pub fn new(raw: &RawColumnVec, encoded: Option<&EncodedColumnVec>) -> Result<Self> {
+        let raw_size = bincode::serialized_size(raw).context("Cannot compute the column size")?;
+        let (encoding, encoded_size) = match encoded {
+            Some(encoded) => (
+                encoded.encoding(),
+                bincode::serialized_size(encoded).context("Cannot compute the column size")?,
+            ),
+            None => (ColumnEncoding::Raw, raw_size),
+        };
+        Ok(ColumnCompressionStats {
+            encoding,
+            raw_size,
+            encoded_size,
+        })
+    }
+
+    pub fn compression_ratio(&self) -> f64 {
+        if self.encoded_size == 0 {
+            return 1.0;
+        }
+        self.raw_size as f64 / self.encoded_size as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ordered_float::OrderedFloat;
+
+    #[test]
+    fn test_choose_encoding() {
+        let constant = RawColumnVec::Str(RawColumnVecGen::NonNullable(vec!["a".to_string(); 100]));
+        assert_eq!(choose_encoding(&constant), ColumnEncoding::RunLength);
+
+        let categories = RawColumnVec::Str(RawColumnVecGen::NonNullable(
+            (0..100).map(|i| format!("category_{}", i % 3)).collect(),
+        ));
+        assert_eq!(choose_encoding(&categories), ColumnEncoding::Dictionary);
+
+        let small_ints = RawColumnVec::Int(RawColumnVecGen::Nullable(
+            (0..100)
+                .map(|i| {
+                    if i % 10 == 0 {
+                        None
+                    } else {
+                        Some(1000 + i * 7)
+                    }
+                })
+                .collect(),
+        ));
+        let stats = analyze(&small_ints);
+        assert_eq!(stats.n_nulls, 10);
+        assert_eq!(stats.int_range, Some((1007, 1693)));
+        assert!(stats.is_sorted);
+        assert_eq!(choose_encoding(&small_ints), ColumnEncoding::BitPacked);
+
+        let wide_ints = RawColumnVec::Int(RawColumnVecGen::NonNullable(
+            (0..100).map(|i| (i * 7919) % 101 * 20_000_000).collect(),
+        ));
+        assert!(!analyze(&wide_ints).is_sorted);
+        assert_eq!(choose_encoding(&wide_ints), ColumnEncoding::LZ4);
+
+        let floats = RawColumnVec::Num(RawColumnVecGen::NonNullable(vec![OrderedFloat(1.0); 100]));
+        assert_eq!(choose_encoding(&floats), ColumnEncoding::LZ4);
+    }
+
+    #[test]
+    fn test_encoding_round_trip() {
+        let columns = vec![
+            RawColumnVec::Int(RawColumnVecGen::NonNullable(
+                (0..100).map(|i| i % 7).collect(),
+            )),
+            RawColumnVec::Int(RawColumnVecGen::Nullable(
+                (0..100)
+                    .map(|i| if i % 3 == 0 { None } else { Some(-i) })
+                    .collect(),
+            )),
+            RawColumnVec::Num(RawColumnVecGen::Nullable(
+                (0..100)
+                    .map(|i| {
+                        if i % 3 == 0 {
+                            None
+                        } else {
+                            Some(OrderedFloat(i as f32 / 3.0))
+                        }
+                    })
+                    .collect(),
+            )),
+            RawColumnVec::Str(RawColumnVecGen::Nullable(
+                (0..100)
+                    .map(|i| {
+                        if i % 3 == 0 {
+                            None
+                        } else {
+                            Some(format!("{}", i % 5))
+                        }
+                    })
+                    .collect(),
+            )),
+        ];
+        let encodings = [
+            ColumnEncoding::RunLength,
+            ColumnEncoding::Dictionary,
+            ColumnEncoding::BitPacked,
+            ColumnEncoding::LZ4,
+        ];
+        for column in columns.iter() {
+            for encoding in encodings.iter() {
+                if let Ok(encoded) = column.encode_as(*encoding) {
+                    assert_eq!(encoded.encoding(), *encoding);
+                    assert_eq!(&encoded.decode(), column);
+                }
+            }
+            let encoded = column.encode().unwrap();
+            assert_eq!(encoded.encoding(), choose_encoding(column));
+            assert_eq!(&encoded.decode(), column);
+        }
+
+        let strings = &columns[3];
+        assert!(strings.encode_as(ColumnEncoding::BitPacked).is_err());
+        let stats = ColumnCompressionStats::new(strings, Some(&strings.encode().unwrap())).unwrap();
+        assert_eq!(stats.encoding, ColumnEncoding::Dictionary);
+        assert!(stats.compression_ratio() > 1.0);
+    }
+}
@@ -1,3 +1,9 @@
+use crate::event_store::column_event_store::encoding::bitpacking_float::{
+    BitPackedFloatVec, BitPackedFloatVecOption,
+};
+use crate::event_store::column_event_store::encoding::bitpacking_int::{
+    BitPackedIntVec, BitPackedIntVecOption,
+};
 use crate::event_store::column_event_store::encoding::dictionary::{
     DictionaryEncodedVec, DictionaryEncodedVecOption,
 };
@@ -7,13 +13,17 @@ use crate::event_store::column_event_store::encoding::lz4::{
 use crate::event_store::column_event_store::encoding::rle::{
     RunLengthEncodedVec, RunLengthEncodedVecOption,
 };
-use crate::event_store::column_event_store::raw_column::RawColumnVec;
+use crate::event_store::column_event_store::encoding::{NonNullableDecoding, NullableDecoding};
+use crate::event_store::column_event_store::raw_column::{RawColumnVec, RawColumnVecGen};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum NullableEncodedColumnVec {
     RunLengthEncodedVec(RunLengthEncodedVecOption),
     DictionaryEncodedVec(DictionaryEncodedVecOption),
     LZ4EncodedVec(LZ4CompressedVecOption),
+    BitPackedIntVec(BitPackedIntVecOption),
+    BitPackedFloatVec(BitPackedFloatVecOption),
 }
 
 impl NullableEncodedColumnVec {
@@ -22,15 +32,33 @@ impl NullableEncodedColumnVec {
             NullableEncodedColumnVec::RunLengthEncodedVec(rle) => rle.decode(),
             NullableEncodedColumnVec::DictionaryEncodedVec(dict) => dict.decode(),
             NullableEncodedColumnVec::LZ4EncodedVec(lz4) => lz4.decode(),
+            NullableEncodedColumnVec::BitPackedIntVec(bp) => {
+                RawColumnVec::Int(RawColumnVecGen::Nullable(bp.clone().decode()))
+            }
+            NullableEncodedColumnVec::BitPackedFloatVec(bp) => {
+                RawColumnVec::Num(RawColumnVecGen::Nullable(bp.clone().decode()))
+            }
+        }
+    }
+
+    pub fn encoding(&self) -> ColumnEncoding {
+        match self {
+            NullableEncodedColumnVec::RunLengthEncodedVec(_) => ColumnEncoding::RunLength,
+            NullableEncodedColumnVec::DictionaryEncodedVec(_) => ColumnEncoding::Dictionary,
+            NullableEncodedColumnVec::LZ4EncodedVec(_) => ColumnEncoding::LZ4,
+            NullableEncodedColumnVec::BitPackedIntVec(_) => ColumnEncoding::BitPacked,
+            NullableEncodedColumnVec::BitPackedFloatVec(_) => ColumnEncoding::BitPacked,
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum NonNullableEncodedColumnVec {
     RunLengthEncodedVec(RunLengthEncodedVec),
     DictionaryEncodedVec(DictionaryEncodedVec),
     LZ4EncodedVec(LZ4CompressedVec),
+    BitPackedIntVec(BitPackedIntVec),
+    BitPackedFloatVec(BitPackedFloatVec),
 }
 
 impl NonNullableEncodedColumnVec {
@@ -39,11 +67,27 @@ impl NonNullableEncodedColumnVec {
             NonNullableEncodedColumnVec::RunLengthEncodedVec(rle) => rle.decode(),
             NonNullableEncodedColumnVec::DictionaryEncodedVec(dict) => dict.decode(),
             NonNullableEncodedColumnVec::LZ4EncodedVec(lz4) => lz4.decode(),
+            NonNullableEncodedColumnVec::BitPackedIntVec(bp) => {
+                RawColumnVec::Int(RawColumnVecGen::NonNullable(bp.clone().decode()))
+            }
+            NonNullableEncodedColumnVec::BitPackedFloatVec(bp) => {
+                RawColumnVec::Num(RawColumnVecGen::NonNullable(bp.clone().decode()))
+            }
+        }
+    }
+
+    pub fn encoding(&self) -> ColumnEncoding {
+        match self {
+            NonNullableEncodedColumnVec::RunLengthEncodedVec(_) => ColumnEncoding::RunLength,
+            NonNullableEncodedColumnVec::DictionaryEncodedVec(_) => ColumnEncoding::Dictionary,
+            NonNullableEncodedColumnVec::LZ4EncodedVec(_) => ColumnEncoding::LZ4,
+            NonNullableEncodedColumnVec::BitPackedIntVec(_) => ColumnEncoding::BitPacked,
+            NonNullableEncodedColumnVec::BitPackedFloatVec(_) => ColumnEncoding::BitPacked,
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum EncodedColumnVec {
     Nullable(NullableEncodedColumnVec),
     NonNullable(NonNullableEncodedColumnVec),
@@ -56,9 +100,26 @@ impl EncodedColumnVec {
             EncodedColumnVec::NonNullable(nonnullable) => nonnullable.decode(),
         }
     }
+
+    pub fn encoding(&self) -> ColumnEncoding {
+        match self {
+            EncodedColumnVec::Nullable(nullable) => nullable.encoding(),
+            EncodedColumnVec::NonNullable(nonnullable) => nonnullable.encoding(),
+        }
+    }
+}
+
+/// Encodings a column can be stored with, `Raw` keeps the column decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ColumnEncoding {
+    Raw,
+    RunLength,
+    Dictionary,
+    BitPacked,
+    LZ4,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ColumnVecType {
     NullableType,
     NonNullableType,
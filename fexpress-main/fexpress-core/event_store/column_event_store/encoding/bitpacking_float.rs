@@ -2,9 +2,10 @@ use crate::event_store::column_event_store::encoding::{NonNullableDecoding, Null
 use crate::types::{BITS_PER_FLOAT, BITS_PER_INT, FLOAT, UINT};
 use bit_vec::BitVec;
 use ordered_float::OrderedFloat;
+use serde::{Deserialize, Serialize};
 use std::mem::size_of;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BitPackedFloatVecOption {
     values: BitVec,
     bits_per_value: usize,
@@ -67,7 +68,7 @@ impl NullableDecoding<OrderedFloat<FLOAT>> for BitPackedFloatVecOption {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BitPackedFloatVec {
     values: BitVec,
     bits_per_value: usize,
@@ -1,81 +1,113 @@
 use crate::event_store::column_event_store::encoding::{NonNullableDecoding, NullableDecoding};
-use crate::types::{BITS_PER_INT, INT, UINT};
+use crate::types::INT;
 use bit_vec::BitVec;
+use serde::{Deserialize, Serialize};
 
 use std::mem::size_of;
 
-#[derive(Debug, Clone)]
+// Frame of reference bit packing: every value is stored as the difference to the minimum
+// with as many bits as the range of the values needs
+
+pub(crate) fn bits_for_range(min: INT, max: INT) -> usize {
+    let range = (max as i64 - min as i64) as u64;
+    (u64::BITS - range.leading_zeros()) as usize
+}
+
+fn push_bits(bitvec: &mut BitVec, value: INT, min: INT, bits_per_value: usize) {
+    let offset = (value as i64 - min as i64) as u64;
+    for i in 0..bits_per_value {
+        bitvec.push(offset & (1 << i) != 0);
+    }
+}
+
+fn read_offset(bit_iter: &mut impl Iterator<Item = bool>, bits_per_value: usize) -> u64 {
+    let mut offset: u64 = 0;
+    for i in 0..bits_per_value {
+        if bit_iter.next().unwrap_or(false) {
+            offset |= 1 << i;
+        }
+    }
+    offset
+}
+
+fn read_bits(bit_iter: &mut impl Iterator<Item = bool>, min: INT, bits_per_value: usize) -> INT {
+    (min as i64 + read_offset(bit_iter, bits_per_value) as i64) as INT
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BitPackedIntVec {
     values: BitVec,
     bits_per_value: usize,
+    min: INT,
+    len: usize,
 }
 
 impl BitPackedIntVec {
     pub fn encode(vec: Vec<INT>) -> Self {
-        let bits_per_value = BITS_PER_INT;
-        let mut bitvec = BitVec::new();
+        let min = vec.iter().copied().min().unwrap_or(0);
+        let max = vec.iter().copied().max().unwrap_or(0);
+        let bits_per_value = bits_for_range(min, max);
+        let mut bitvec = BitVec::with_capacity(vec.len() * bits_per_value);
 
-        for int_value in vec {
-            let value_as_uint: UINT = unsafe { std::mem::transmute(int_value) };
-            for i in 0..bits_per_value {
-                bitvec.push(value_as_uint & (1 << i) != 0);
-            }
+        for int_value in vec.iter() {
+            push_bits(&mut bitvec, *int_value, min, bits_per_value);
         }
 
         Self {
             values: bitvec,
             bits_per_value,
+            min,
+            len: vec.len(),
         }
     }
+
+    pub fn min(&self) -> INT {
+        self.min
+    }
+
+    /// Rows whose offset to the minimum satisfies `keep`, the values are not rebuilt
+    pub fn filter_offsets(&self, keep: impl Fn(u64) -> bool) -> BitVec {
+        let mut bit_iter = self.values.iter();
+        (0..self.len)
+            .map(|_| keep(read_offset(&mut bit_iter, self.bits_per_value)))
+            .collect()
+    }
 }
 
 impl NonNullableDecoding<INT> for BitPackedIntVec {
     fn decode(self) -> Vec<INT> {
-        let num_values = self.values.len() / BITS_PER_INT;
+        let (min, bits_per_value) = (self.min, self.bits_per_value);
         let mut bit_iter = self.values.into_iter();
-        let mut vec = Vec::new();
-        let num_bits = 8 * size_of::<UINT>();
-
-        for _ in 0..num_values {
-            let mut value_as_uint: UINT = 0;
-            for i in 0..num_bits {
-                if bit_iter.next().unwrap_or(false) {
-                    value_as_uint |= 1 << i;
-                }
-            }
-            let value: INT = unsafe { std::mem::transmute(value_as_uint) };
-            vec.push(value);
-        }
-
-        vec
+        (0..self.len)
+            .map(|_| read_bits(&mut bit_iter, min, bits_per_value))
+            .collect()
     }
 
     fn size(&self) -> usize {
         let bitvec_size = self.values.len() / 8 + if self.values.len() % 8 > 0 { 1 } else { 0 };
-        let bits_per_value_size = std::mem::size_of::<usize>();
-        bitvec_size + bits_per_value_size
+        bitvec_size + 2 * size_of::<usize>() + size_of::<INT>()
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BitPackedIntVecOption {
     values: BitVec,
     bits_per_value: usize,
+    min: INT,
 }
 
 impl BitPackedIntVecOption {
     pub fn encode(vec: Vec<Option<INT>>) -> Self {
-        let bits_per_value = BITS_PER_INT;
+        let min = vec.iter().flatten().copied().min().unwrap_or(0);
+        let max = vec.iter().flatten().copied().max().unwrap_or(0);
+        let bits_per_value = bits_for_range(min, max);
         let mut bitvec = BitVec::new();
 
         for value in vec {
             match value {
                 Some(int_value) => {
                     bitvec.push(true); // flag indicating this is Some
-                    let value_as_uint: UINT = unsafe { std::mem::transmute(int_value) };
-                    for i in 0..bits_per_value {
-                        bitvec.push(value_as_uint & (1 << i) != 0);
-                    }
+                    push_bits(&mut bitvec, int_value, min, bits_per_value);
                 }
                 None => {
                     bitvec.push(false); // flag indicating this is None
@@ -86,8 +118,23 @@ impl BitPackedIntVecOption {
         Self {
             values: bitvec,
             bits_per_value,
+            min,
         }
     }
+
+    pub fn min(&self) -> INT {
+        self.min
+    }
+
+    /// Rows whose offset to the minimum satisfies `keep`, nulls never match
+    pub fn filter_offsets(&self, keep: impl Fn(u64) -> bool) -> BitVec {
+        let mut selection = BitVec::new();
+        let mut bit_iter = self.values.iter();
+        while let Some(is_some) = bit_iter.next() {
+            selection.push(is_some && keep(read_offset(&mut bit_iter, self.bits_per_value)));
+        }
+        selection
+    }
 }
 
 impl NullableDecoding<INT> for BitPackedIntVecOption {
@@ -97,14 +144,11 @@ impl NullableDecoding<INT> for BitPackedIntVecOption {
 
         while let Some(is_some) = bit_iter.next() {
             if is_some {
-                let mut value_as_uint = 0 as UINT;
-                for i in 0..self.bits_per_value {
-                    if bit_iter.next().unwrap() {
-                        value_as_uint |= 1 << i;
-                    }
-                }
-                let value: INT = unsafe { std::mem::transmute(value_as_uint) };
-                vec.push(Some(value));
+                vec.push(Some(read_bits(
+                    &mut bit_iter,
+                    self.min,
+                    self.bits_per_value,
+                )));
             } else {
                 vec.push(None);
             }
@@ -115,8 +159,7 @@ impl NullableDecoding<INT> for BitPackedIntVecOption {
 
     fn size(&self) -> usize {
         let bitvec_size = self.values.len() / 8 + if self.values.len() % 8 > 0 { 1 } else { 0 };
-        let bits_per_value_size = std::mem::size_of::<usize>();
-        bitvec_size + bits_per_value_size
+        bitvec_size + size_of::<usize>() + size_of::<INT>()
     }
 }
 
@@ -172,4 +215,21 @@ mod int_tests {
 
         assert_eq!(original, decoded);
     }
+
+    #[test]
+    fn test_bitpackedintvec_frame_of_reference() {
+        let original: Vec<INT> = (0..100).map(|i| 1000 + i % 4).collect();
+        let encoded = BitPackedIntVec::encode(original.clone());
+        assert_eq!(encoded.bits_per_value, 2);
+        assert!(encoded.size() < original.len() * size_of::<INT>());
+        assert_eq!(encoded.decode(), original);
+
+        let extremes = vec![INT::MIN, 0, INT::MAX];
+        assert_eq!(BitPackedIntVec::encode(extremes.clone()).decode(), extremes);
+        let constant = vec![Some(7), None, Some(7)];
+        assert_eq!(
+            BitPackedIntVecOption::encode(constant.clone()).decode(),
+            constant
+        );
+    }
 }
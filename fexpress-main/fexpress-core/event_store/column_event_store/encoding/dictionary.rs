@@ -3,15 +3,18 @@ use crate::event_store::column_event_store::raw_column::{RawColumnVec, RawColumn
 use crate::types::INT;
 use chrono::{NaiveDate, NaiveDateTime};
 
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::mem;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DictionaryEncodedVecGen<T: PartialEq + Eq + Hash> {
     pub values: Vec<u32>,
     pub dictionary: Vec<T>,
+    // only needed while encoding
+    #[serde(skip)]
     pub string_to_index: HashMap<T, u32>,
 }
 
@@ -60,7 +63,7 @@ impl<T: Clone + Eq + Hash + Debug> NonNullableDecoding<T> for DictionaryEncodedV
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum DictionaryEncodedVec {
     Bool(DictionaryEncodedVecGen<bool>),
     Int(DictionaryEncodedVecGen<INT>),
@@ -122,10 +125,12 @@ impl DictionaryEncodedVec {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DictionaryEncodedVecOptionGen<T: Eq + Hash> {
     pub values: Vec<u32>,
     pub dictionary: Vec<Option<T>>,
+    // only needed while encoding
+    #[serde(skip)]
     pub string_to_index: HashMap<Option<T>, u32>,
 }
 
@@ -175,7 +180,7 @@ impl<T: Clone + Eq + Hash + Debug> NullableDecoding<T> for DictionaryEncodedVecO
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum DictionaryEncodedVecOption {
     Bool(DictionaryEncodedVecOptionGen<bool>),
     Int(DictionaryEncodedVecOptionGen<INT>),
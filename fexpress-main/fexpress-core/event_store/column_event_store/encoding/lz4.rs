@@ -6,7 +6,7 @@ use lz4_flex::{compress_prepend_size, decompress_size_prepended};
 use ordered_float::OrderedFloat;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LZ4CompressedVecGen<T: PartialEq> {
     compressed_data: Vec<u8>,
     _marker: std::marker::PhantomData<T>,
@@ -29,7 +29,7 @@ impl<T: PartialEq + Serialize + for<'de> Deserialize<'de>> LZ4CompressedVecGen<T
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum LZ4CompressedVec {
     Bool(LZ4CompressedVecGen<bool>),
     Int(LZ4CompressedVecGen<INT>),
@@ -101,7 +101,7 @@ implement_from_trait!(VecNum, Vec<OrderedFloat<FLOAT>>);
 implement_from_trait!(Date, NaiveDate);
 implement_from_trait!(DateTime, NaiveDateTime);
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LZ4CompressedVecOptionGen<T> {
     compressed_data: Vec<u8>,
     _marker: std::marker::PhantomData<T>,
@@ -124,7 +124,7 @@ impl<T: Serialize + for<'de> Deserialize<'de>> LZ4CompressedVecOptionGen<T> {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum LZ4CompressedVecOption {
     Bool(LZ4CompressedVecOptionGen<bool>),
     Int(LZ4CompressedVecOptionGen<INT>),
@@ -7,9 +7,10 @@ use enum_dispatch::enum_dispatch;
 
 use std::fmt::Debug;
 
+use serde::{Deserialize, Serialize};
 use std::mem;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RunLengthEncodedVecGen<T> {
     pub values: Vec<T>,
     pub lengths: Vec<usize>,
@@ -46,7 +47,7 @@ impl<T: PartialEq> RunLengthEncodedVecGen<T> {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RunLengthEncodedVecOptionGen<T: PartialEq> {
     pub values: Vec<Option<T>>,
     pub lengths: Vec<usize>,
@@ -68,7 +69,7 @@ impl<T: Clone + PartialEq + Debug> NonNullableDecoding<T> for RunLengthEncodedVe
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[enum_dispatch(NonNullableDecoding)]
 pub enum RunLengthEncodedVec {
     Bool(RunLengthEncodedVecGen<bool>),
@@ -179,7 +180,7 @@ impl<T: Clone + PartialEq + Debug> NullableDecoding<T> for RunLengthEncodedVecOp
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[enum_dispatch(NullableDecoding)]
 pub enum RunLengthEncodedVecOption {
     Bool(RunLengthEncodedVecOptionGen<bool>),
@@ -24,6 +24,7 @@ mod tests {
         let settings = Settings {
             block_size: 100,
             enable_compression: true,
+            column_encodings: Default::default(),
         };
         let mut store = ColumnStore::new(settings);
         let mut context = ColumnStoreEvalContext { store: &store };
@@ -1,27 +1,41 @@
+pub mod analyzer;
 pub mod encoded_column;
 pub mod encoding;
 pub mod evaluation;
 mod logical_plan;
+pub mod predicate;
 pub mod raw_column;
+pub mod storage;
 
 use std::collections::BTreeMap;
 
 use std::fmt::Debug;
+use std::fs;
+use std::path::Path;
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use chrono::{DateTime, Utc};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 
 use vec1::{vec1, Vec1};
 
-use crate::event::{AttributeName, Event};
-use crate::event_store::column_event_store::encoded_column::{ColumnVecType, EncodedColumnVec};
+use crate::event::{AttributeName, EntityID, EntityType, Event, EventType};
+use crate::event_store::column_event_store::analyzer::{choose_encoding, ColumnCompressionStats};
+use crate::event_store::column_event_store::encoded_column::{
+    ColumnEncoding, ColumnVecType, EncodedColumnVec,
+};
 use crate::event_store::column_event_store::raw_column::{RawColumnVec, RawColumnVecGen};
+use crate::event_store::column_event_store::storage::TABLE_FILE_EXTENSION;
+use crate::event_store::schema::{widen_type, widen_value};
+use crate::interval::NaiveDateTimeInterval;
 use crate::map::HashMap;
 
-use crate::types::Timestamp;
+use crate::types::{Entities, Timestamp};
 use crate::value::{Value, ValueType};
 
+const ENTITY_COLUMN_PREFIX: &str = "entity.";
+
 // This represents the type including nullability (stored in the block)
 #[derive(Debug, Clone)]
 pub enum AnyColumnDataTypeWithNull {
@@ -38,7 +52,7 @@ pub enum AnyColumnDataTypeWithNull {
 }
 
 // This represents the type excluding nullability (stored in the table)
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum AnyColumnDataType {
     Bool,
     Num,
@@ -76,6 +90,29 @@ impl From<ValueType> for AnyColumnDataType {
     }
 }
 
+impl AnyColumnDataType {
+    fn value_type(&self) -> Option<ValueType> {
+        match self {
+            AnyColumnDataType::Bool => Some(ValueType::Bool),
+            AnyColumnDataType::Num => Some(ValueType::Num),
+            AnyColumnDataType::Int => Some(ValueType::Int),
+            AnyColumnDataType::Str => Some(ValueType::Str),
+            AnyColumnDataType::VecBool => Some(ValueType::VecBool),
+            AnyColumnDataType::VecNum => Some(ValueType::VecNum),
+            AnyColumnDataType::VecInt => Some(ValueType::VecInt),
+            AnyColumnDataType::VecStr => Some(ValueType::VecCat),
+            AnyColumnDataType::Date => Some(ValueType::Date),
+            AnyColumnDataType::DateTime => Some(ValueType::DateTime),
+            AnyColumnDataType::NotSupported => None,
+        }
+    }
+
+    /// Narrowest column type both types widen to along the coercion lattice
+    pub fn widen(&self, other: &AnyColumnDataType) -> Option<AnyColumnDataType> {
+        widen_type(&self.value_type()?, &other.value_type()?).map(AnyColumnDataType::from)
+    }
+}
+
 fn is_value_supported(value_type: &ValueType) -> bool {
     match value_type {
         ValueType::MapNum => false,
@@ -88,7 +125,7 @@ fn is_value_supported(value_type: &ValueType) -> bool {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ColumnData {
     Raw(RawColumnVec),
     Encoded(EncodedColumnVec),
@@ -248,6 +285,20 @@ impl ColumnData {
         Ok(())
     }
 
+    /// Copy of the column converted to a wider type of the coercion lattice, the ints of a
+    /// column widened to `Num` keep only 24 bits of precision
+    pub fn widen(&self, typ: &AnyColumnDataType) -> Result<ColumnData> {
+        let raw = match self {
+            ColumnData::Raw(raw) => raw.clone(),
+            ColumnData::Encoded(encoded) => encoded.decode(),
+        };
+        let mut widened = ColumnData::new_from_type(typ, 0)?;
+        for row in 0..raw.len() {
+            widened.push_value(raw.get_value(row))?;
+        }
+        Ok(widened)
+    }
+
     pub fn encode(&mut self) -> bool {
         match self {
             ColumnData::Raw(raw) => {
@@ -262,6 +313,15 @@ impl ColumnData {
         }
     }
 
+    pub fn encode_as(&mut self, encoding: ColumnEncoding) -> Result<()> {
+        if let ColumnData::Raw(raw) = self {
+            if encoding != ColumnEncoding::Raw {
+                *self = ColumnData::Encoded(raw.encode_as(encoding)?);
+            }
+        }
+        Ok(())
+    }
+
     pub fn decode(&mut self) {
         match self {
             ColumnData::Raw(_) => {}
@@ -280,6 +340,8 @@ pub struct Block {
     // I think that if some passes from the last insertion
     // the block can be compressed
     last_insertion_time: DateTime<Utc>,
+    // encoding and sizes of the columns, filled when the block is encoded
+    compression_stats: HashMap<String, ColumnCompressionStats>,
 }
 
 impl Block {
@@ -393,7 +455,7 @@ impl Block {
                     .clone()]))),
             );
             for (entity_type, entity_id) in &event.entities {
-                let column_name = format!("entity.{}", entity_type.0);
+                let column_name = format!("{}{}", ENTITY_COLUMN_PREFIX, entity_type.0);
                 self.columns.insert(
                     column_name,
                     ColumnData::Raw(RawColumnVec::Str(RawColumnVecGen::Nullable(vec![Some(
@@ -420,7 +482,7 @@ impl Block {
             event_id_column.push_value(event_id_value)?;
 
             for (entity_type, entity_id) in &event.entities {
-                let column_name = format!("entity.{}", entity_type.0);
+                let column_name = format!("{}{}", ENTITY_COLUMN_PREFIX, entity_type.0);
                 let entity_column = self
                     .columns
                     .get_mut(&column_name)
@@ -441,9 +503,13 @@ impl Block {
             if is_value_supported(&attr_value_type) {
                 let attr_value_column_data_type: AnyColumnDataType = attr_value_type.clone().into();
                 if let Some(existing_column_data_type) = table_schema.get(attr_name.as_str()) {
-                    if *existing_column_data_type != attr_value_column_data_type
-                        && attr_value_type != ValueType::None
-                    {
+                    // values of narrower types are widened to the type of the column
+                    let matching = *existing_column_data_type == attr_value_column_data_type
+                        || existing_column_data_type
+                            .value_type()
+                            .and_then(|value_type| widen_value(attr_value, &value_type))
+                            .is_some();
+                    if !matching && attr_value_type != ValueType::None {
                         bail!(
                             "The type for {:?} ({:?}) is not matching the previous type {:?}",
                             attr_name,
@@ -457,13 +523,36 @@ impl Block {
         Ok(())
     }
 
-    pub fn encode(&mut self) -> Result<()> {
-        for (_, column) in self.columns.iter_mut() {
-            column.encode();
+    /// Encodes the raw columns with the encoding of the settings or the one picked by the
+    /// analyzer and records their compression stats
+    pub fn encode(&mut self, settings: &Settings) -> Result<()> {
+        for (name, column) in self.columns.iter_mut() {
+            if let ColumnData::Raw(raw) = column {
+                let encoding = settings.column_encoding(name, raw);
+                let encoded = match encoding {
+                    ColumnEncoding::Raw => None,
+                    encoding => Some(
+                        raw.encode_as(encoding)
+                            .with_context(|| format!("Cannot encode column {}", name))?,
+                    ),
+                };
+                self.compression_stats.insert(
+                    name.clone(),
+                    ColumnCompressionStats::new(raw, encoded.as_ref())?,
+                );
+                if let Some(encoded) = encoded {
+                    *column = ColumnData::Encoded(encoded);
+                }
+            }
         }
         Ok(())
     }
 
+    /// Encoding and sizes of the columns encoded by `encode`
+    pub fn compression_stats(&self) -> &HashMap<String, ColumnCompressionStats> {
+        &self.compression_stats
+    }
+
     pub fn decode(&mut self) -> Result<()> {
         for (_, column) in self.columns.iter_mut() {
             column.decode()
@@ -471,6 +560,50 @@ impl Block {
         Ok(())
     }
 
+    /// Reconstructs the events of the rows, null values are left out of the attributes
+    pub fn to_events(&self, event_type: &EventType) -> Result<Vec<Event>> {
+        let columns: Vec<(&String, RawColumnVec)> = self
+            .columns
+            .iter()
+            .map(|(name, column)| match column {
+                ColumnData::Raw(raw) => (name, raw.clone()),
+                ColumnData::Encoded(encoded) => (name, encoded.decode()),
+            })
+            .collect();
+
+        let mut events = Vec::with_capacity(self.n_rows);
+        for row in 0..self.n_rows {
+            let mut event_time = None;
+            let mut event_id = None;
+            let mut entities = Entities::new();
+            let mut attrs = HashMap::new();
+            for (name, column) in &columns {
+                match (name.as_str(), column.get_value(row)) {
+                    (_, Value::None) => {}
+                    ("event_time", Value::DateTime(dt)) => event_time = Some(dt),
+                    ("event_id", Value::Str(id)) => event_id = Some(id),
+                    (name, Value::Str(id)) if name.starts_with(ENTITY_COLUMN_PREFIX) => {
+                        let entity_type = &name[ENTITY_COLUMN_PREFIX.len()..];
+                        entities.insert(EntityType(from_str!(entity_type)), EntityID(id));
+                    }
+                    (name, value) => {
+                        attrs.insert(AttributeName::new(name), value);
+                    }
+                }
+            }
+            events.push(Event {
+                event_type: event_type.clone(),
+                event_time: event_time
+                    .ok_or_else(|| anyhow!("Row {} of the block has no event_time", row))?,
+                entities,
+                event_id,
+                experiment_id: None,
+                attrs: if attrs.is_empty() { None } else { Some(attrs) },
+            });
+        }
+        Ok(events)
+    }
+
     pub fn make_projection(&self, columns: Vec<String>) -> Result<Block> {
         let mut selected_columns = HashMap::new();
         for column in &columns {
@@ -489,6 +622,7 @@ impl Block {
             n_rows: self.n_rows,
             index_by_event_time: self.index_by_event_time.clone(),
             last_insertion_time: self.last_insertion_time,
+            compression_stats: Default::default(),
         })
     }
 }
@@ -498,6 +632,8 @@ pub struct Table {
     name: String,
     schema: HashMap<String, AnyColumnDataType>,
     blocks: BTreeMap<Timestamp, Vec1<Block>>, // there can be more than 1 block with exact the same timestamp
+    // events older than the last ingested timestamp, they are merged into the blocks by compact
+    staging: Vec<Event>,
 }
 
 impl Table {
@@ -515,6 +651,7 @@ impl Table {
         // If necessary, create a new block.
         // Delegate the insertion to the block.
         let event_timestamp = event.event_time;
+        self.widen_schema(&event.extract_attributes_values())?;
 
         /*
         Blocks with the same timestamp as the event and holding exactly the same timestamp is
@@ -529,9 +666,9 @@ impl Table {
                     &mut self.schema,
                 )
             } else {
-                // TODO: compress previous block
-                let last_block = inner_blocks.last_mut();
-                last_block.encode();
+                if settings.enable_compression {
+                    inner_blocks.last_mut().encode(settings)?;
+                }
                 self.create_new_block_with_event(event, settings, event_timestamp)
             }
         } else {
@@ -539,6 +676,49 @@ impl Table {
         }
     }
 
+    /*
+    The types of the columns widen along the coercion lattice (e.g. an Int column becomes a Num
+    column when the first float arrives). The columns of all the blocks are converted before
+    the schema changes so a column which cannot be converted (a string which is not a date)
+    leaves the table untouched.
+     */
+    fn widen_schema(&mut self, attribute_values: &HashMap<AttributeName, Value>) -> Result<()> {
+        for (attr_name, attr_value) in attribute_values {
+            let attr_value_type: ValueType = attr_value.clone().into();
+            let attr_column_data_type: AnyColumnDataType = attr_value_type.into();
+            let widened = match self.schema.get(attr_name.as_str()) {
+                Some(existing) => match existing.widen(&attr_column_data_type) {
+                    Some(widened) if widened != *existing => widened,
+                    _ => continue,
+                },
+                None => continue,
+            };
+            let mut widened_columns = Vec::new();
+            for block in self.blocks.values().flat_map(|blocks| blocks.iter()) {
+                if let Some(column) = block.columns.get(attr_name.as_str()) {
+                    widened_columns.push(column.widen(&widened).with_context(|| {
+                        format!("Cannot widen column {} to {:?}", attr_name, widened)
+                    })?);
+                }
+            }
+            let mut widened_columns = widened_columns.into_iter();
+            for block in self
+                .blocks
+                .values_mut()
+                .flat_map(|blocks| blocks.iter_mut())
+            {
+                if let Some(column) = block.columns.get_mut(attr_name.as_str()) {
+                    *column = widened_columns
+                        .next()
+                        .ok_or_else(|| anyhow!("Missing widened column {}", attr_name))?;
+                    block.compression_stats.remove(attr_name.as_str());
+                }
+            }
+            self.schema.insert(attr_name.0.clone(), widened);
+        }
+        Block::check_schema(&mut self.schema, attribute_values)
+    }
+
     fn create_new_block_with_event(
         &mut self,
         event: &Event,
@@ -552,13 +732,98 @@ impl Table {
             n_rows: 0,
             index_by_event_time: Default::default(),
             last_insertion_time: Utc::now(),
+            compression_stats: Default::default(),
         };
         new_block.insert_new_event_incremental(event, settings, &mut self.schema)?;
-        self.blocks
-            .insert(event_timestamp.clone(), vec1![new_block]);
+        match self.blocks.get_mut(&event_timestamp) {
+            Some(blocks) => blocks.push(new_block),
+            None => {
+                self.blocks.insert(event_timestamp, vec1![new_block]);
+            }
+        }
         Ok(())
     }
 
+    /*
+    Compaction rewrites the blocks from the first block that needs it:
+    - the first block whose time range reaches the oldest staged event
+    - the first block (apart from the last one) filled less than half of settings.block_size
+    The events of these blocks are merged with the staged events, sorted by event time and
+    inserted again, so the rewritten blocks are full and the closed ones are encoded.
+    Returns the number of rewritten events.
+     */
+    fn compact(&mut self, settings: &Settings) -> Result<usize> {
+        let oldest_staged = self.staging.iter().map(|event| event.event_time).min();
+        let n_blocks: usize = self.blocks.values().map(|blocks| blocks.len()).sum();
+        let mut n_seen = 0;
+        let first_key = self
+            .blocks
+            .iter()
+            .find(|(_, blocks)| {
+                blocks.iter().any(|block| {
+                    n_seen += 1;
+                    let reaches_staged = oldest_staged.is_some_and(|t| block.end_time >= t);
+                    let is_small = n_seen < n_blocks && block.n_rows < settings.block_size / 2;
+                    reaches_staged || is_small
+                })
+            })
+            .map(|(key, _)| *key);
+
+        let rewritten = match first_key {
+            Some(key) => self.blocks.split_off(&key),
+            None => Default::default(),
+        };
+        if rewritten.is_empty() && self.staging.is_empty() {
+            return Ok(0);
+        }
+
+        let event_type = EventType(from_str!(self.name.as_str()));
+        let mut events = Vec::new();
+        for block in rewritten.values().flat_map(|blocks| blocks.iter()) {
+            events.extend(block.to_events(&event_type)?);
+        }
+        events.append(&mut self.staging);
+        // stable sort: the staged events come after the stored events with the same time
+        events.sort_by_key(|event| event.event_time);
+
+        for event in &events {
+            self.insert_new_event_incremental(event, settings)?;
+        }
+        Ok(events.len())
+    }
+
+    /// Blocks which may hold events of the interval (both ends inclusive)
+    pub fn blocks_in_range<'a>(
+        &'a self,
+        interval: &'a NaiveDateTimeInterval,
+    ) -> impl Iterator<Item = &'a Block> + 'a {
+        let blocks = match interval.end_dt {
+            Some(end_dt) => self.blocks.range(..=end_dt),
+            None => self.blocks.range(..),
+        };
+        blocks
+            .flat_map(|(_, blocks)| blocks.iter())
+            .filter(move |block| {
+                interval
+                    .start_dt
+                    .is_none_or(|start| block.end_time >= start)
+            })
+    }
+
+    /// Compression stats of every column, one entry per encoded block
+    pub fn compression_stats(&self) -> HashMap<String, Vec<ColumnCompressionStats>> {
+        let mut stats: HashMap<String, Vec<ColumnCompressionStats>> = HashMap::new();
+        for block in self.blocks.values().flat_map(|blocks| blocks.iter()) {
+            for (name, column_stats) in block.compression_stats.iter() {
+                stats
+                    .entry(name.clone())
+                    .or_default()
+                    .push(column_stats.clone());
+            }
+        }
+        stats
+    }
+
     pub fn make_projection(&self, columns: Vec<String>) -> Result<Table> {
         let mut new_schema = HashMap::new();
         for column in columns {
@@ -574,6 +839,7 @@ impl Table {
             name: self.name.clone(),
             schema: new_schema,
             blocks: Default::default(),
+            staging: Vec::new(),
         })
     }
 }
@@ -582,6 +848,20 @@ impl Table {
 pub struct Settings {
     pub block_size: usize,
     pub enable_compression: bool,
+    /// Encodings forced for some columns (e.g. `event_time`, `entity.home`), the other
+    /// columns and the columns whose type the forced encoding doesn't support are encoded
+    /// with the encoding picked by the analyzer
+    pub column_encodings: HashMap<String, ColumnEncoding>,
+}
+
+impl Settings {
+    pub fn column_encoding(&self, column: &str, raw: &RawColumnVec) -> ColumnEncoding {
+        self.column_encodings
+            .get(column)
+            .copied()
+            .filter(|encoding| raw.supports_encoding(*encoding))
+            .unwrap_or_else(|| choose_encoding(raw))
+    }
 }
 
 #[derive(Debug)]
@@ -606,15 +886,11 @@ impl ColumnStore {
     2. If a table does not exist create it
     3. Delegate inserting the event to the table
 
-    For now only accepts timestamps that are greater or equal than the last ingested
-    timestamp. This is because ingesting timestamps in the middle of the blocks would require
-    a lot of book keeping.
+    Events older than the last ingested timestamp are not inserted in the middle of the
+    blocks, that would require a lot of book keeping. They are kept in the staging area of
+    the table until compact merges them into the blocks.
      */
     pub fn insert_new_event_incremental_incremental(&mut self, event: &Event) -> Result<()> {
-        if event.event_time < self.last_timestamp {
-            bail!("Can only ingest timestamps that are greater or equal than the last timestamp");
-        }
-
         // Map event type to table name.
         // Create the table if it does not exist
         let table_name = event.event_type.0.clone();
@@ -625,6 +901,7 @@ impl ColumnStore {
                     name: table_name.clone(),
                     schema: Default::default(),
                     blocks: Default::default(),
+                    staging: Vec::new(),
                 },
             );
         }
@@ -640,17 +917,67 @@ impl ColumnStore {
             }
         };
 
+        if event.event_time < self.last_timestamp {
+            table.widen_schema(&event.extract_attributes_values())?;
+            table.staging.push(event.clone());
+            return Ok(());
+        }
+
         // Delegate the insertion to the table.
         table.insert_new_event_incremental(event, &self.settings)?;
         self.last_timestamp = event.event_time.clone();
         Ok(())
     }
 
+    /// Number of out of order events waiting for compact
+    pub fn n_staged_events(&self) -> usize {
+        self.tables.values().map(|table| table.staging.len()).sum()
+    }
+
+    /// Merges the staged events into the blocks and merges the small blocks, returns the
+    /// number of rewritten events
+    pub fn compact(&mut self) -> Result<usize> {
+        let mut n_events = 0;
+        for table in self.tables.values_mut() {
+            n_events += table.compact(&self.settings)?;
+        }
+        Ok(n_events)
+    }
+
+    /// Writes every table to a file `<table>.fxcol` in `dir`, the files can be mapped
+    /// with `MappedColumnStore::open`
+    pub fn save(&self, dir: &str) -> Result<()> {
+        let dir = Path::new(dir);
+        fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+        if self.n_staged_events() > 0 {
+            bail!("The column store has staged events, compact it before saving");
+        }
+        for (name, table) in self.tables.iter() {
+            table.write_to_file(
+                &dir.join(format!("{}.{}", name, TABLE_FILE_EXTENSION)),
+                &self.settings,
+            )?;
+        }
+        Ok(())
+    }
+
     /*
     Creating the projection of a column store is just selecting the right tables
     and columns and uncompressing them. Alternatively it could be done by uncompressing
     the columns of the original column store.
     */
+    /// Compression stats of the columns of the table, one entry per encoded block
+    pub fn compression_stats(
+        &self,
+        table: &str,
+    ) -> Result<HashMap<String, Vec<ColumnCompressionStats>>> {
+        Ok(self
+            .tables
+            .get(table)
+            .ok_or(anyhow!("cannot find table {}", table))?
+            .compression_stats())
+    }
+
     pub fn make_projection(
         &self,
         tables_columns: HashMap<String, Vec<String>>,
@@ -681,7 +1008,8 @@ mod tests {
     use crate::event_store::column_event_store::encoded_column::NullableEncodedColumnVec;
     use crate::event_store::column_event_store::encoding::rle::RunLengthEncodedVecOptionGen;
     use crate::tests::fake_nba::generate_nba_game_events;
-    use chrono::{Duration, NaiveDateTime};
+    use crate::types::{FLOAT, INT};
+    use chrono::{Duration, NaiveDate, NaiveDateTime};
     use std::ops::Add;
 
     #[test]
@@ -707,6 +1035,7 @@ mod tests {
             settings: Settings {
                 block_size: 10,
                 enable_compression: true,
+                column_encodings: Default::default(),
             },
             last_timestamp: NaiveDateTime::MIN,
         };
@@ -752,6 +1081,7 @@ mod tests {
             settings: Settings {
                 block_size: 10,
                 enable_compression: true,
+                column_encodings: Default::default(),
             },
             last_timestamp: NaiveDateTime::MIN,
         };
@@ -828,6 +1158,7 @@ mod tests {
             settings: Settings {
                 block_size: 10,
                 enable_compression: true,
+                column_encodings: Default::default(),
             },
             last_timestamp: NaiveDateTime::MIN,
         };
@@ -904,6 +1235,7 @@ mod tests {
             settings: Settings {
                 block_size: 10,
                 enable_compression: true,
+                column_encodings: Default::default(),
             },
             last_timestamp: NaiveDateTime::MIN,
         };
@@ -976,6 +1308,7 @@ mod tests {
             settings: Settings {
                 block_size: 10,
                 enable_compression: true,
+                column_encodings: Default::default(),
             },
             last_timestamp: NaiveDateTime::MIN,
         };
@@ -985,9 +1318,156 @@ mod tests {
             .insert_new_event_incremental_incremental(&events[0])
             .unwrap();
 
-        // The second event has an earlier timestamp, so it should throw an error.
-        let result = column_store.insert_new_event_incremental_incremental(&events[1]);
-        assert!(result.is_err());
+        // The second event has an earlier timestamp, so it is staged until the compaction.
+        column_store
+            .insert_new_event_incremental_incremental(&events[1])
+            .unwrap();
+        assert_eq!(column_store.n_staged_events(), 1);
+        assert_eq!(column_store.last_timestamp, dt1);
+
+        // A late event with a different type still fails.
+        let mut invalid_event = events[1].clone();
+        invalid_event.attrs = Some(hashmap![a!("test") => Value::Str("0".into())]);
+        assert!(column_store
+            .insert_new_event_incremental_incremental(&invalid_event)
+            .is_err());
+    }
+
+    #[test]
+    fn test_out_of_order_compaction() {
+        let mut events = generate_nba_game_events(100);
+        events.sort_by_key(|e| e.event_time);
+        // every 7th event arrives late
+        let (late, on_time): (Vec<_>, Vec<_>) = events
+            .iter()
+            .cloned()
+            .enumerate()
+            .partition(|(i, _)| i % 7 == 3);
+        let mut column_store = create_column_store_with_events(
+            on_time
+                .into_iter()
+                .chain(late.into_iter())
+                .map(|(_, e)| e)
+                .collect(),
+            10,
+        );
+        assert!(column_store.n_staged_events() > 0);
+
+        assert_eq!(column_store.compact().unwrap(), 100);
+        assert_eq!(column_store.n_staged_events(), 0);
+
+        let table = column_store.tables.get("game").unwrap();
+        let blocks: Vec<&Block> = table.blocks.values().flat_map(|b| b.iter()).collect();
+        // all the blocks apart from the last one are full and encoded
+        assert_eq!(blocks.len(), 10);
+        for block in &blocks[..blocks.len() - 1] {
+            assert_eq!(block.n_rows, 10);
+            assert!(matches!(
+                block.columns.get("event_time"),
+                Some(ColumnData::Encoded(_))
+            ));
+        }
+        let stored: Vec<Event> = blocks
+            .iter()
+            .flat_map(|block| block.to_events(&EventType("game".into())).unwrap())
+            .collect();
+        let times: Vec<_> = stored.iter().map(|e| e.event_time).collect();
+        let expected_times: Vec<_> = events.iter().map(|e| e.event_time).collect();
+        assert_eq!(times, expected_times);
+        assert_eq!(
+            stored.iter().map(|e| &e.entities).collect_vec(),
+            events.iter().map(|e| &e.entities).collect_vec()
+        );
+        for block in &blocks {
+            assert!(block.start_time <= block.end_time);
+        }
+        // nothing left to compact
+        assert_eq!(column_store.compact().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_small_block_merging() {
+        let dt = NaiveDateTime::parse_from_str("2023-01-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let events = (0..6)
+            .map(|i| Event {
+                event_type: EventType("game".into()),
+                event_time: dt.add(Duration::days(i)),
+                entities: Default::default(),
+                event_id: None,
+                experiment_id: None,
+                attrs: Some(hashmap![a!("score") => Value::Int(i as INT)]),
+            })
+            .collect_vec();
+        let mut column_store = create_column_store_with_events(events, 1);
+        column_store.settings.block_size = 4;
+        assert_eq!(column_store.tables.get("game").unwrap().blocks.len(), 6);
+
+        assert_eq!(column_store.compact().unwrap(), 6);
+        let table = column_store.tables.get("game").unwrap();
+        let n_rows = table
+            .blocks
+            .values()
+            .flat_map(|b| b.iter())
+            .map(|block| block.n_rows)
+            .collect_vec();
+        assert_eq!(n_rows, vec![4, 2]);
+    }
+
+    #[test]
+    fn test_column_encoding_selection() {
+        let dt = NaiveDateTime::parse_from_str("2023-01-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let mut column_store = ColumnStore::new(Settings {
+            block_size: 50,
+            enable_compression: true,
+            column_encodings: hashmap![
+                "team".to_string() => ColumnEncoding::LZ4,
+                // not supported for floats, the analyzer picks the encoding instead
+                "rating".to_string() => ColumnEncoding::Dictionary
+            ],
+        });
+        for i in 0..120 {
+            let event = Event {
+                event_type: EventType("game".into()),
+                event_time: dt.add(Duration::minutes(i)),
+                entities: Default::default(),
+                event_id: None,
+                experiment_id: None,
+                attrs: Some(hashmap![
+                    a!("score") => Value::Int(i as INT % 100),
+                    a!("team") => Value::Str(format!("team_{}", i % 2)),
+                    a!("league") => Value::Str("nba".to_string()),
+                    a!("rating") => Value::Num(i as FLOAT / 7.0)
+                ]),
+            };
+            column_store
+                .insert_new_event_incremental_incremental(&event)
+                .unwrap();
+        }
+
+        let stats = column_store.compression_stats("game").unwrap();
+        // the last block is still open and not encoded
+        assert_eq!(stats.get("score").unwrap().len(), 2);
+        for column_stats in stats.values().flatten() {
+            assert!(column_stats.encoded_size > 0 && column_stats.raw_size > 0);
+        }
+        let encodings = |column: &str| {
+            stats
+                .get(column)
+                .unwrap()
+                .iter()
+                .map(|s| s.encoding)
+                .collect_vec()
+        };
+        assert_eq!(encodings("score"), vec![ColumnEncoding::BitPacked; 2]);
+        assert_eq!(encodings("league"), vec![ColumnEncoding::RunLength; 2]);
+        // the analyzer would pick a dictionary, the settings force LZ4
+        assert_eq!(encodings("team"), vec![ColumnEncoding::LZ4; 2]);
+        assert_eq!(encodings("rating"), vec![ColumnEncoding::LZ4; 2]);
+        assert!(stats
+            .get("league")
+            .unwrap()
+            .iter()
+            .all(|s| s.compression_ratio() > 1.0));
     }
 
     #[test]
@@ -1017,6 +1497,7 @@ mod tests {
             settings: Settings {
                 block_size: 10,
                 enable_compression: true,
+                column_encodings: Default::default(),
             },
             last_timestamp: NaiveDateTime::MIN,
         };
@@ -1028,6 +1509,70 @@ mod tests {
             .is_err());
     }
 
+    #[test]
+    fn test_widen_column_type() {
+        let event = |day: u32, value: Value| Event {
+            event_type: EventType("game".into()),
+            event_time: NaiveDate::from_ymd_opt(2023, 1, day)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap(),
+            entities: Default::default(),
+            event_id: None,
+            experiment_id: None,
+            attrs: Some(hashmap![a!("test") => value]),
+        };
+        let mut column_store = ColumnStore {
+            tables: Default::default(),
+            settings: Settings {
+                block_size: 2,
+                enable_compression: true,
+                column_encodings: Default::default(),
+            },
+            last_timestamp: NaiveDateTime::MIN,
+        };
+        for (day, value) in [(1, 1), (2, 2), (3, 3)] {
+            column_store
+                .insert_new_event_incremental_incremental(&event(day, Value::Int(value)))
+                .unwrap();
+        }
+        // the int columns of the encoded and the raw block become num columns
+        column_store
+            .insert_new_event_incremental_incremental(&event(4, Value::Num(4.5)))
+            .unwrap();
+        // a narrower value is widened to the type of the column
+        column_store
+            .insert_new_event_incremental_incremental(&event(5, Value::Bool(true)))
+            .unwrap();
+        // a late narrower value is accepted too
+        column_store
+            .insert_new_event_incremental_incremental(&event(1, Value::Int(0)))
+            .unwrap();
+        assert!(column_store
+            .insert_new_event_incremental_incremental(&event(6, Value::Str("6".into())))
+            .is_err());
+
+        let table = &column_store.tables["game"];
+        assert_eq!(table.schema["test"], AnyColumnDataType::Num);
+        let values = table
+            .blocks
+            .values()
+            .flat_map(|blocks| blocks.iter())
+            .flat_map(|block| block.to_events(&EventType("game".into())).unwrap())
+            .map(|event| event.attrs.unwrap()[&a!("test")].clone())
+            .collect_vec();
+        assert_eq!(
+            values,
+            vec![
+                Value::Num(1.0),
+                Value::Num(2.0),
+                Value::Num(3.0),
+                Value::Num(4.5),
+                Value::Num(1.0)
+            ]
+        );
+    }
+
     #[test]
     fn test_large_number_of_attributes() {
         let dt = NaiveDateTime::parse_from_str("2023-01-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
@@ -1051,6 +1596,7 @@ mod tests {
             settings: Settings {
                 block_size: 10,
                 enable_compression: true,
+                column_encodings: Default::default(),
             },
             last_timestamp: NaiveDateTime::MIN,
         };
@@ -1080,6 +1626,7 @@ mod tests {
             settings: Settings {
                 block_size: 1,
                 enable_compression: false,
+                column_encodings: Default::default(),
             },
             last_timestamp: NaiveDateTime::MIN,
         };
@@ -1132,6 +1679,7 @@ mod tests {
             settings: Settings {
                 block_size,
                 enable_compression: true,
+                column_encodings: Default::default(),
             },
             last_timestamp: NaiveDateTime::MIN,
         };
@@ -0,0 +1,523 @@
+use std::convert::TryFrom;
+use std::ops::Bound;
+
+use anyhow::{anyhow, bail, Context, Result};
+use bit_vec::BitVec;
+use chrono::{NaiveDate, NaiveDateTime};
+use ordered_float::OrderedFloat;
+
+use crate::event_store::column_event_store::encoded_column::{
+    EncodedColumnVec, NonNullableEncodedColumnVec, NullableEncodedColumnVec,
+};
+use crate::event_store::column_event_store::encoding::dictionary::{
+    DictionaryEncodedVec, DictionaryEncodedVecOption,
+};
+use crate::event_store::column_event_store::encoding::rle::{
+    RunLengthEncodedVec, RunLengthEncodedVecOption,
+};
+use crate::event_store::column_event_store::raw_column::{RawColumnVec, RawColumnVecGen};
+use crate::event_store::column_event_store::{Block, ColumnData, ColumnStore};
+use crate::types::{FLOAT, INT};
+use crate::value::Value;
+
+/*
+Predicates are evaluated on the encoded columns whenever the encoding allows it:
+- dictionary: the predicate is evaluated once per dictionary entry, the rows only compare codes
+  (the dictionary is scanned rather than `string_to_index`, which is not kept in table files)
+- run length: the predicate is evaluated once per run, counts and sums multiply by the run length
+- bit packed ints: integer ranges are translated to offsets to the minimum of the block
+The other encodings (LZ4, bit packed floats) are decoded first.
+Nulls never match a predicate and are skipped by sums.
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnPredicate {
+    Eq(Value),
+    In(Vec<Value>),
+    Range(Bound<Value>, Bound<Value>),
+}
+
+fn value_eq(value: &Value, literal: &Value) -> bool {
+    value == literal || value.partial_cmp(literal) == Some(std::cmp::Ordering::Equal)
+}
+
+impl ColumnPredicate {
+    pub fn matches(&self, value: &Value) -> bool {
+        if matches!(value, Value::None) {
+            return false;
+        }
+        match self {
+            ColumnPredicate::Eq(literal) => value_eq(value, literal),
+            ColumnPredicate::In(literals) => {
+                literals.iter().any(|literal| value_eq(value, literal))
+            }
+            ColumnPredicate::Range(low, high) => {
+                let above_low = match low {
+                    Bound::Included(low) => value >= low,
+                    Bound::Excluded(low) => value > low,
+                    Bound::Unbounded => true,
+                };
+                let below_high = match high {
+                    Bound::Included(high) => value <= high,
+                    Bound::Excluded(high) => value < high,
+                    Bound::Unbounded => true,
+                };
+                above_low && below_high
+            }
+        }
+    }
+
+    /// Inclusive bounds of the integers matching the predicate, `None` if it is not a range
+    fn int_bounds(&self) -> Option<(i64, i64)> {
+        fn low(bound: &Bound<Value>) -> Option<i64> {
+            match bound {
+                Bound::Unbounded => Some(i64::MIN),
+                Bound::Included(Value::Int(v)) => Some(*v as i64),
+                Bound::Excluded(Value::Int(v)) => Some(*v as i64 + 1),
+                Bound::Included(Value::Num(v)) => Some(v.ceil() as i64),
+                Bound::Excluded(Value::Num(v)) => Some(v.floor() as i64 + 1),
+                _ => None,
+            }
+        }
+        fn high(bound: &Bound<Value>) -> Option<i64> {
+            match bound {
+                Bound::Unbounded => Some(i64::MAX),
+                Bound::Included(Value::Int(v)) => Some(*v as i64),
+                Bound::Excluded(Value::Int(v)) => Some(*v as i64 - 1),
+                Bound::Included(Value::Num(v)) => Some(v.floor() as i64),
+                Bound::Excluded(Value::Num(v)) => Some(v.ceil() as i64 - 1),
+                _ => None,
+            }
+        }
+        match self {
+            ColumnPredicate::Eq(value) => {
+                let bound = Bound::Included(value.clone());
+                Some((low(&bound)?, high(&bound)?))
+            }
+            ColumnPredicate::Range(l, h) => Some((low(l)?, high(h)?)),
+            ColumnPredicate::In(_) => None,
+        }
+    }
+
+    /// Offsets to `min` of the integers matching the predicate
+    fn offset_filter(&self, min: INT) -> Option<impl Fn(u64) -> bool> {
+        let (low, high) = self.int_bounds()?;
+        let low = low.saturating_sub(min as i64);
+        let high = high.saturating_sub(min as i64);
+        Some(move |offset: u64| (offset as i64) >= low && (offset as i64) <= high)
+    }
+}
+
+pub(crate) trait ToValue {
+    fn to_value(&self) -> Value;
+}
+
+macro_rules! implement_to_value {
+    ($type:ty, $v:ident => $value:expr) => {
+        impl ToValue for $type {
+            fn to_value(&self) -> Value {
+                let $v = self;
+                $value
+            }
+        }
+    };
+}
+
+implement_to_value!(bool, v => Value::Bool(*v));
+implement_to_value!(INT, v => Value::Int(*v));
+implement_to_value!(OrderedFloat<FLOAT>, v => Value::Num(v.0));
+implement_to_value!(String, v => Value::Str(v.clone()));
+implement_to_value!(Vec<bool>, v => Value::VecBool(v.clone()));
+implement_to_value!(Vec<INT>, v => Value::VecInt(v.clone()));
+implement_to_value!(Vec<OrderedFloat<FLOAT>>, v => Value::VecNum(v.iter().map(|v| v.0).collect()));
+implement_to_value!(Vec<String>, v => Value::VecStr(v.clone()));
+implement_to_value!(NaiveDate, v => Value::Date(*v));
+implement_to_value!(NaiveDateTime, v => Value::DateTime(*v));
+
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self) -> Value {
+        self.as_ref().map_or(Value::None, |v| v.to_value())
+    }
+}
+
+#[derive(Default)]
+struct SumAccumulator {
+    int: i64,
+    num: f64,
+    is_num: bool,
+}
+
+impl SumAccumulator {
+    fn add(&mut self, value: &Value, count: usize) -> Result<()> {
+        match value {
+            Value::Int(v) => {
+                self.int = (*v as i64)
+                    .checked_mul(count as i64)
+                    .and_then(|v| self.int.checked_add(v))
+                    .context("The sum of the ints overflows")?
+            }
+            Value::Num(v) => {
+                self.is_num = true;
+                self.num += *v as f64 * count as f64;
+            }
+            Value::None => {}
+            other => bail!("Cannot sum {:?}", other),
+        }
+        Ok(())
+    }
+
+    /// The sum of ints is a num when it doesn't fit an int
+    fn finish(self) -> Value {
+        match INT::try_from(self.int) {
+            Ok(int) if !self.is_num => Value::Int(int),
+            _ => Value::Num((self.num + self.int as f64) as FLOAT),
+        }
+    }
+}
+
+fn is_selected(selection: Option<&BitVec>, row: usize) -> bool {
+    selection.is_none_or(|selection| selection.get(row).unwrap_or(false))
+}
+
+fn select_values<'a, T: ToValue + 'a>(
+    values: impl Iterator<Item = &'a T>,
+    predicate: &ColumnPredicate,
+) -> BitVec {
+    values
+        .map(|value| predicate.matches(&value.to_value()))
+        .collect()
+}
+
+fn sum_values<'a, T: ToValue + 'a>(
+    values: impl Iterator<Item = &'a T>,
+    selection: Option<&BitVec>,
+) -> Result<Value> {
+    let mut sum = SumAccumulator::default();
+    for (row, value) in values.enumerate() {
+        if is_selected(selection, row) {
+            sum.add(&value.to_value(), 1)?;
+        }
+    }
+    Ok(sum.finish())
+}
+
+fn select_runs<T: ToValue>(values: &[T], lengths: &[usize], predicate: &ColumnPredicate) -> BitVec {
+    let mut selection = BitVec::with_capacity(lengths.iter().sum());
+    for (value, length) in values.iter().zip(lengths.iter()) {
+        let matches = predicate.matches(&value.to_value());
+        selection.grow(*length, matches);
+    }
+    selection
+}
+
+fn count_runs<T: ToValue>(values: &[T], lengths: &[usize], predicate: &ColumnPredicate) -> usize {
+    values
+        .iter()
+        .zip(lengths.iter())
+        .filter(|(value, _)| predicate.matches(&value.to_value()))
+        .map(|(_, length)| length)
+        .sum()
+}
+
+fn sum_runs<T: ToValue>(
+    values: &[T],
+    lengths: &[usize],
+    selection: Option<&BitVec>,
+) -> Result<Value> {
+    let mut sum = SumAccumulator::default();
+    let mut start = 0;
+    for (value, length) in values.iter().zip(lengths.iter()) {
+        let count = match selection {
+            Some(_) => (start..start + length)
+                .filter(|row| is_selected(selection, *row))
+                .count(),
+            None => *length,
+        };
+        if count > 0 {
+            sum.add(&value.to_value(), count)?;
+        }
+        start += length;
+    }
+    Ok(sum.finish())
+}
+
+fn select_codes<T: ToValue>(
+    dictionary: &[T],
+    codes: &[u32],
+    predicate: &ColumnPredicate,
+) -> BitVec {
+    let matching: Vec<bool> = dictionary
+        .iter()
+        .map(|value| predicate.matches(&value.to_value()))
+        .collect();
+    codes.iter().map(|code| matching[*code as usize]).collect()
+}
+
+fn sum_codes<T: ToValue>(
+    dictionary: &[T],
+    codes: &[u32],
+    selection: Option<&BitVec>,
+) -> Result<Value> {
+    let mut counts = vec![0; dictionary.len()];
+    for (row, code) in codes.iter().enumerate() {
+        if is_selected(selection, row) {
+            counts[*code as usize] += 1;
+        }
+    }
+    let mut sum = SumAccumulator::default();
+    for (value, count) in dictionary.iter().zip(counts) {
+        sum.add(&value.to_value(), count)?;
+    }
+    Ok(sum.finish())
+}
+
+macro_rules! with_encoded_vec {
+    ($enum:ident, $vec:expr, $v:ident => $body:expr) => {
+        match $vec {
+            $enum::Bool($v) => $body,
+            $enum::Int($v) => $body,
+            $enum::Str($v) => $body,
+            $enum::VecBool($v) => $body,
+            $enum::VecInt($v) => $body,
+            $enum::VecStr($v) => $body,
+            $enum::Date($v) => $body,
+            $enum::DateTime($v) => $body,
+        }
+    };
+}
+
+macro_rules! with_raw_values {
+    ($raw:expr, $values:ident => $body:expr) => {
+        match $raw {
+            RawColumnVec::Bool(v) => with_raw_values!(@gen v, $values => $body),
+            RawColumnVec::Num(v) => with_raw_values!(@gen v, $values => $body),
+            RawColumnVec::Int(v) => with_raw_values!(@gen v, $values => $body),
+            RawColumnVec::Str(v) => with_raw_values!(@gen v, $values => $body),
+            RawColumnVec::VecBool(v) => with_raw_values!(@gen v, $values => $body),
+            RawColumnVec::VecNum(v) => with_raw_values!(@gen v, $values => $body),
+            RawColumnVec::VecInt(v) => with_raw_values!(@gen v, $values => $body),
+            RawColumnVec::VecStr(v) => with_raw_values!(@gen v, $values => $body),
+            RawColumnVec::Date(v) => with_raw_values!(@gen v, $values => $body),
+            RawColumnVec::DateTime(v) => with_raw_values!(@gen v, $values => $body),
+        }
+    };
+    (@gen $gen:expr, $values:ident => $body:expr) => {
+        match $gen {
+            RawColumnVecGen::Nullable(v) => {
+                let $values = v.iter();
+                $body
+            }
+            RawColumnVecGen::NonNullable(v) => {
+                let $values = v.iter();
+                $body
+            }
+        }
+    };
+}
+
+/// Selection of the encodings that can be filtered without decoding, `None` for the others
+fn select_encoded(encoded: &EncodedColumnVec, predicate: &ColumnPredicate) -> Option<BitVec> {
+    let selection = match encoded {
+        EncodedColumnVec::NonNullable(NonNullableEncodedColumnVec::RunLengthEncodedVec(rle)) => {
+            with_encoded_vec!(RunLengthEncodedVec, rle, v => select_runs(&v.values, &v.lengths, predicate))
+        }
+        EncodedColumnVec::Nullable(NullableEncodedColumnVec::RunLengthEncodedVec(rle)) => {
+            with_encoded_vec!(RunLengthEncodedVecOption, rle, v => select_runs(&v.values, &v.lengths, predicate))
+        }
+        EncodedColumnVec::NonNullable(NonNullableEncodedColumnVec::DictionaryEncodedVec(dict)) => {
+            with_encoded_vec!(DictionaryEncodedVec, dict, v => select_codes(&v.dictionary, &v.values, predicate))
+        }
+        EncodedColumnVec::Nullable(NullableEncodedColumnVec::DictionaryEncodedVec(dict)) => {
+            with_encoded_vec!(DictionaryEncodedVecOption, dict, v => select_codes(&v.dictionary, &v.values, predicate))
+        }
+        EncodedColumnVec::NonNullable(NonNullableEncodedColumnVec::BitPackedIntVec(bp)) => {
+            bp.filter_offsets(predicate.offset_filter(bp.min())?)
+        }
+        EncodedColumnVec::Nullable(NullableEncodedColumnVec::BitPackedIntVec(bp)) => {
+            bp.filter_offsets(predicate.offset_filter(bp.min())?)
+        }
+        _ => return None,
+    };
+    Some(selection)
+}
+
+fn sum_encoded(encoded: &EncodedColumnVec, selection: Option<&BitVec>) -> Option<Result<Value>> {
+    let sum = match encoded {
+        EncodedColumnVec::NonNullable(NonNullableEncodedColumnVec::RunLengthEncodedVec(rle)) => {
+            with_encoded_vec!(RunLengthEncodedVec, rle, v => sum_runs(&v.values, &v.lengths, selection))
+        }
+        EncodedColumnVec::Nullable(NullableEncodedColumnVec::RunLengthEncodedVec(rle)) => {
+            with_encoded_vec!(RunLengthEncodedVecOption, rle, v => sum_runs(&v.values, &v.lengths, selection))
+        }
+        EncodedColumnVec::NonNullable(NonNullableEncodedColumnVec::DictionaryEncodedVec(dict)) => {
+            with_encoded_vec!(DictionaryEncodedVec, dict, v => sum_codes(&v.dictionary, &v.values, selection))
+        }
+        EncodedColumnVec::Nullable(NullableEncodedColumnVec::DictionaryEncodedVec(dict)) => {
+            with_encoded_vec!(DictionaryEncodedVecOption, dict, v => sum_codes(&v.dictionary, &v.values, selection))
+        }
+        _ => return None,
+    };
+    Some(sum)
+}
+
+impl ColumnData {
+    /// Rows of the column matching the predicate
+    pub fn select(&self, predicate: &ColumnPredicate) -> BitVec {
+        match self {
+            ColumnData::Raw(raw) => {
+                with_raw_values!(raw, values => select_values(values, predicate))
+            }
+            ColumnData::Encoded(encoded) => select_encoded(encoded, predicate).unwrap_or_else(
+                || with_raw_values!(&encoded.decode(), values => select_values(values, predicate)),
+            ),
+        }
+    }
+
+    /// Number of rows matching the predicate, run length encoded columns count whole runs
+    pub fn count(&self, predicate: &ColumnPredicate) -> usize {
+        match self {
+            ColumnData::Encoded(EncodedColumnVec::NonNullable(
+                NonNullableEncodedColumnVec::RunLengthEncodedVec(rle),
+            )) => {
+                with_encoded_vec!(RunLengthEncodedVec, rle, v => count_runs(&v.values, &v.lengths, predicate))
+            }
+            ColumnData::Encoded(EncodedColumnVec::Nullable(
+                NullableEncodedColumnVec::RunLengthEncodedVec(rle),
+            )) => {
+                with_encoded_vec!(RunLengthEncodedVecOption, rle, v => count_runs(&v.values, &v.lengths, predicate))
+            }
+            _ => self
+                .select(predicate)
+                .iter()
+                .filter(|selected| *selected)
+                .count(),
+        }
+    }
+
+    /// Sum of the non null values of the selected rows (all the rows without a selection)
+    pub fn sum(&self, selection: Option<&BitVec>) -> Result<Value> {
+        match self {
+            ColumnData::Raw(raw) => with_raw_values!(raw, values => sum_values(values, selection)),
+            ColumnData::Encoded(encoded) => match sum_encoded(encoded, selection) {
+                Some(sum) => sum,
+                None => {
+                    with_raw_values!(&encoded.decode(), values => sum_values(values, selection))
+                }
+            },
+        }
+    }
+}
+
+impl Block {
+    /// Rows of the block matching the predicate, no row matches a missing column
+    pub fn select(&self, column: &str, predicate: &ColumnPredicate) -> BitVec {
+        match self.columns.get(column) {
+            Some(column) => column.select(predicate),
+            None => BitVec::from_elem(self.n_rows, false),
+        }
+    }
+
+    pub fn count(&self, column: &str, predicate: &ColumnPredicate) -> usize {
+        self.columns
+            .get(column)
+            .map_or(0, |column| column.count(predicate))
+    }
+}
+
+impl ColumnStore {
+    /// Number of events of the table whose column matches the predicate
+    pub fn count_where(
+        &self,
+        table: &str,
+        column: &str,
+        predicate: &ColumnPredicate,
+    ) -> Result<usize> {
+        let table = self
+            .tables
+            .get(table)
+            .ok_or_else(|| anyhow!("cannot find table {}", table))?;
+        Ok(table
+            .blocks
+            .values()
+            .flat_map(|blocks| blocks.iter())
+            .map(|block| block.count(column, predicate))
+            .sum())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_store::column_event_store::encoded_column::ColumnEncoding;
+
+    fn encodings(raw: &RawColumnVec) -> Vec<ColumnData> {
+        let mut columns = vec![ColumnData::Raw(raw.clone())];
+        for encoding in [
+            ColumnEncoding::RunLength,
+            ColumnEncoding::Dictionary,
+            ColumnEncoding::BitPacked,
+            ColumnEncoding::LZ4,
+        ] {
+            if let Ok(encoded) = raw.encode_as(encoding) {
+                columns.push(ColumnData::Encoded(encoded));
+            }
+        }
+        columns
+    }
+
+    #[test]
+    fn test_predicates_on_encoded_columns() {
+        let categories = RawColumnVec::Str(RawColumnVecGen::Nullable(
+            (0..100)
+                .map(|i| match i % 10 {
+                    0 => None,
+                    n => Some(format!("category_{}", n % 3)),
+                })
+                .collect(),
+        ));
+        let predicate = ColumnPredicate::In(vec![
+            Value::Str("category_0".to_string()),
+            Value::Str("category_2".to_string()),
+        ]);
+        let expected: BitVec = (0..100).map(|i| i % 10 != 0 && (i % 10) % 3 != 1).collect();
+        let columns = encodings(&categories);
+        assert_eq!(columns.len(), 4);
+        for column in columns {
+            assert_eq!(column.select(&predicate), expected);
+            assert_eq!(column.count(&predicate), 60);
+        }
+
+        let ints = RawColumnVec::Int(RawColumnVecGen::NonNullable(
+            (0..100).map(|i| 1000 + i / 4).collect(),
+        ));
+        let range = ColumnPredicate::Range(
+            Bound::Excluded(Value::Int(1010)),
+            Bound::Included(Value::Num(1012.5)),
+        );
+        let expected: BitVec = (0..100).map(|i| (44..52).contains(&i)).collect();
+        let columns = encodings(&ints);
+        assert_eq!(columns.len(), 5);
+        for column in columns {
+            assert_eq!(column.select(&range), expected);
+            assert_eq!(column.count(&range), 8);
+            assert_eq!(column.count(&ColumnPredicate::Eq(Value::Int(1003))), 4);
+            assert_eq!(column.sum(None).unwrap(), Value::Int(100 * 1000 + 4 * 300));
+            assert_eq!(
+                column.sum(Some(&expected)).unwrap(),
+                Value::Int(4 * (1011 + 1012))
+            );
+        }
+
+        // a sum which doesn't fit an int is a num
+        let large = RawColumnVec::Int(RawColumnVecGen::NonNullable(vec![INT::MAX; 3]));
+        for column in encodings(&large) {
+            assert_eq!(
+                column.sum(None).unwrap(),
+                Value::Num((3 * INT::MAX as i64) as FLOAT)
+            );
+        }
+
+        let booleans = RawColumnVec::Bool(RawColumnVecGen::NonNullable(vec![true; 10]));
+        assert!(ColumnData::Raw(booleans).sum(None).is_err());
+    }
+}
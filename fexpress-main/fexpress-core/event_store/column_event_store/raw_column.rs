@@ -1,4 +1,5 @@
-use crate::event_store::column_event_store::encoded_column::EncodedColumnVec;
+use crate::event_store::column_event_store::analyzer::choose_encoding;
+use crate::event_store::column_event_store::encoded_column::{ColumnEncoding, EncodedColumnVec};
 use anyhow::{bail, Context, Result};
 use chrono::{NaiveDate, NaiveDateTime};
 
@@ -7,6 +8,12 @@ use ordered_float::OrderedFloat;
 use crate::event_store::column_event_store::encoded_column::{
     NonNullableEncodedColumnVec, NullableEncodedColumnVec,
 };
+use crate::event_store::column_event_store::encoding::bitpacking_float::{
+    BitPackedFloatVec, BitPackedFloatVecOption,
+};
+use crate::event_store::column_event_store::encoding::bitpacking_int::{
+    BitPackedIntVec, BitPackedIntVecOption,
+};
 use crate::event_store::column_event_store::encoding::dictionary::{
     DictionaryEncodedVecGen, DictionaryEncodedVecOptionGen,
 };
@@ -17,10 +24,12 @@ use crate::event_store::column_event_store::encoding::rle::{
     RunLengthEncodedVecGen, RunLengthEncodedVecOptionGen,
 };
 
+use crate::event_store::schema::widen_value;
 use crate::types::{FLOAT, INT};
-use crate::value::Value;
+use crate::value::{Value, ValueType};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum RawColumnVecGen<T> {
     Nullable(Vec<Option<T>>),
     NonNullable(Vec<T>),
@@ -40,6 +49,14 @@ impl<T: Clone> RawColumnVecGen<T> {
         }
     }
 
+    /// Value of the row, `None` for nulls and rows outside of the column
+    pub fn get(&self, row: usize) -> Option<T> {
+        match self {
+            RawColumnVecGen::Nullable(v) => v.get(row).cloned().flatten(),
+            RawColumnVecGen::NonNullable(v) => v.get(row).cloned(),
+        }
+    }
+
     pub fn push_value(&mut self, value: T) {
         match self {
             RawColumnVecGen::Nullable(v) => v.push(Some(value.clone())),
@@ -67,7 +84,7 @@ impl<T: Clone> RawColumnVecGen<T> {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum RawColumnVec {
     Bool(RawColumnVecGen<bool>),
     Num(RawColumnVecGen<OrderedFloat<FLOAT>>),
@@ -82,34 +99,51 @@ pub enum RawColumnVec {
 }
 
 macro_rules! encode_column {
-    ($data:expr, Nullable) => {{
-        let rle_encoding = RunLengthEncodedVecOptionGen::encode($data.to_vec());
-        let dict_encoding = DictionaryEncodedVecOptionGen::encode($data.to_vec());
-        let rle_size = rle_encoding.values.len() + rle_encoding.lengths.len();
-        let dict_size = dict_encoding.values.len() + dict_encoding.dictionary.len();
-        if rle_size <= dict_size {
-            EncodedColumnVec::Nullable(NullableEncodedColumnVec::RunLengthEncodedVec(
-                rle_encoding.into(),
-            ))
-        } else {
-            EncodedColumnVec::Nullable(NullableEncodedColumnVec::DictionaryEncodedVec(
-                dict_encoding.into(),
-            ))
+    ($column:expr, $encoding:expr) => {{
+        match ($column, $encoding) {
+            (RawColumnVecGen::Nullable(nullable), ColumnEncoding::RunLength) => {
+                EncodedColumnVec::Nullable(NullableEncodedColumnVec::RunLengthEncodedVec(
+                    RunLengthEncodedVecOptionGen::encode(nullable.to_vec()).into(),
+                ))
+            }
+            (RawColumnVecGen::NonNullable(nonnullable), ColumnEncoding::RunLength) => {
+                EncodedColumnVec::NonNullable(NonNullableEncodedColumnVec::RunLengthEncodedVec(
+                    RunLengthEncodedVecGen::encode(nonnullable.to_vec()).into(),
+                ))
+            }
+            (RawColumnVecGen::Nullable(nullable), ColumnEncoding::Dictionary) => {
+                EncodedColumnVec::Nullable(NullableEncodedColumnVec::DictionaryEncodedVec(
+                    DictionaryEncodedVecOptionGen::encode(nullable.to_vec()).into(),
+                ))
+            }
+            (RawColumnVecGen::NonNullable(nonnullable), ColumnEncoding::Dictionary) => {
+                EncodedColumnVec::NonNullable(NonNullableEncodedColumnVec::DictionaryEncodedVec(
+                    DictionaryEncodedVecGen::encode(nonnullable.to_vec()).into(),
+                ))
+            }
+            (column, ColumnEncoding::LZ4) => compress_column!(column),
+            (_, encoding) => bail!("{:?} encoding is not supported for the column", encoding),
         }
     }};
-    ($data:expr, NonNullable) => {{
-        let rle_encoding = RunLengthEncodedVecGen::encode($data.to_vec());
-        let dict_encoding = DictionaryEncodedVecGen::encode($data.to_vec());
-        let rle_size = rle_encoding.values.len() + rle_encoding.lengths.len();
-        let dict_size = dict_encoding.values.len() + dict_encoding.dictionary.len();
-        if rle_size <= dict_size {
-            EncodedColumnVec::NonNullable(NonNullableEncodedColumnVec::RunLengthEncodedVec(
-                rle_encoding.into(),
-            ))
-        } else {
-            EncodedColumnVec::NonNullable(NonNullableEncodedColumnVec::DictionaryEncodedVec(
-                dict_encoding.into(),
-            ))
+}
+
+macro_rules! compress_column {
+    ($column:expr) => {{
+        match $column {
+            RawColumnVecGen::Nullable(nullable) => {
+                let compressed = LZ4CompressedVecOptionGen::encode(nullable.clone())
+                    .context("Cannot compress vector")?;
+                EncodedColumnVec::Nullable(NullableEncodedColumnVec::LZ4EncodedVec(
+                    compressed.into(),
+                ))
+            }
+            RawColumnVecGen::NonNullable(nonnullable) => {
+                let compressed = LZ4CompressedVecGen::encode(nonnullable.clone())
+                    .context("Cannot compress vector")?;
+                EncodedColumnVec::NonNullable(NonNullableEncodedColumnVec::LZ4EncodedVec(
+                    compressed.into(),
+                ))
+            }
         }
     }};
 }
@@ -130,6 +164,39 @@ impl RawColumnVec {
         }
     }
 
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            RawColumnVec::Bool(_) => ValueType::Bool,
+            RawColumnVec::Num(_) => ValueType::Num,
+            RawColumnVec::Int(_) => ValueType::Int,
+            RawColumnVec::Str(_) => ValueType::Str,
+            RawColumnVec::VecBool(_) => ValueType::VecBool,
+            RawColumnVec::VecNum(_) => ValueType::VecNum,
+            RawColumnVec::VecInt(_) => ValueType::VecInt,
+            RawColumnVec::VecStr(_) => ValueType::VecCat,
+            RawColumnVec::Date(_) => ValueType::Date,
+            RawColumnVec::DateTime(_) => ValueType::DateTime,
+        }
+    }
+
+    pub fn get_value(&self, row: usize) -> Value {
+        let value = match self {
+            RawColumnVec::Bool(v) => v.get(row).map(Value::Bool),
+            RawColumnVec::Num(v) => v.get(row).map(|v| Value::Num(v.0)),
+            RawColumnVec::Int(v) => v.get(row).map(Value::Int),
+            RawColumnVec::Str(v) => v.get(row).map(Value::Str),
+            RawColumnVec::VecBool(v) => v.get(row).map(Value::VecBool),
+            RawColumnVec::VecNum(v) => v
+                .get(row)
+                .map(|v| Value::VecNum(v.iter().map(|v| v.0).collect())),
+            RawColumnVec::VecInt(v) => v.get(row).map(Value::VecInt),
+            RawColumnVec::VecStr(v) => v.get(row).map(Value::VecStr),
+            RawColumnVec::Date(v) => v.get(row).map(Value::Date),
+            RawColumnVec::DateTime(v) => v.get(row).map(Value::DateTime),
+        };
+        value.unwrap_or(Value::None)
+    }
+
     pub fn push_value(&mut self, value: Value) -> Result<()> {
         match (self, value) {
             (RawColumnVec::Bool(col), Value::Bool(value)) => col.push_value(value),
@@ -148,6 +215,9 @@ impl RawColumnVec {
             (vec, value) => {
                 if matches!(value, Value::None) {
                     vec.push_none()?;
+                } else if let Some(widened) = widen_value(&value, &vec.value_type()) {
+                    // values of narrower types of the coercion lattice are widened
+                    vec.push_value(widened)?;
                 } else {
                     bail!("Trying to push value type {:?} raw vector {:?}", value, vec)
                 }
@@ -172,75 +242,63 @@ impl RawColumnVec {
         Ok(())
     }
 
+    /// Encodes the column with the encoding picked by the analyzer
     pub fn encode(&self) -> Result<EncodedColumnVec> {
-        match self {
-            RawColumnVec::Bool(v) => match v {
-                RawColumnVecGen::Nullable(nullable) => Ok(encode_column!(nullable, Nullable)),
-                RawColumnVecGen::NonNullable(nonnullable) => {
-                    Ok(encode_column!(nonnullable, NonNullable))
-                }
-            },
-            RawColumnVec::Num(_v) => todo!(),
-            RawColumnVec::Int(v) => match v {
-                RawColumnVecGen::Nullable(nullable) => Ok(encode_column!(nullable, Nullable)),
-                RawColumnVecGen::NonNullable(nonnullable) => {
-                    Ok(encode_column!(nonnullable, NonNullable))
-                }
-            },
-            RawColumnVec::Str(v) => match v {
-                RawColumnVecGen::Nullable(nullable) => Ok(encode_column!(nullable, Nullable)),
-                RawColumnVecGen::NonNullable(nonnullable) => {
-                    Ok(encode_column!(nonnullable, NonNullable))
-                }
-            },
-            RawColumnVec::VecBool(v) => match v {
-                RawColumnVecGen::Nullable(nullable) => Ok(encode_column!(nullable, Nullable)),
-                RawColumnVecGen::NonNullable(nonnullable) => {
-                    Ok(encode_column!(nonnullable, NonNullable))
-                }
-            },
-            RawColumnVec::VecNum(v) => match v {
-                RawColumnVecGen::Nullable(nullable) => {
-                    let compressed = LZ4CompressedVecOptionGen::encode(nullable.clone())
-                        .context("Cannot compress vector")?;
-                    Ok(EncodedColumnVec::Nullable(
-                        NullableEncodedColumnVec::LZ4EncodedVec(compressed.into()),
-                    ))
-                }
-                RawColumnVecGen::NonNullable(nonnullable) => {
-                    let compressed = LZ4CompressedVecGen::encode(nonnullable.clone())
-                        .context("Cannot compress vector")?;
-                    Ok(EncodedColumnVec::NonNullable(
-                        NonNullableEncodedColumnVec::LZ4EncodedVec(compressed.into()),
-                    ))
-                }
-            },
-            RawColumnVec::VecInt(v) => match v {
-                RawColumnVecGen::Nullable(nullable) => Ok(encode_column!(nullable, Nullable)),
-                RawColumnVecGen::NonNullable(nonnullable) => {
-                    Ok(encode_column!(nonnullable, NonNullable))
-                }
-            },
-            RawColumnVec::VecStr(v) => match v {
-                RawColumnVecGen::Nullable(nullable) => Ok(encode_column!(nullable, Nullable)),
-                RawColumnVecGen::NonNullable(nonnullable) => {
-                    Ok(encode_column!(nonnullable, NonNullable))
-                }
-            },
-            RawColumnVec::Date(v) => match v {
-                RawColumnVecGen::Nullable(nullable) => Ok(encode_column!(nullable, Nullable)),
-                RawColumnVecGen::NonNullable(nonnullable) => {
-                    Ok(encode_column!(nonnullable, NonNullable))
-                }
-            },
-            RawColumnVec::DateTime(v) => match v {
-                RawColumnVecGen::Nullable(nullable) => Ok(encode_column!(nullable, Nullable)),
-                RawColumnVecGen::NonNullable(nonnullable) => {
-                    Ok(encode_column!(nonnullable, NonNullable))
-                }
-            },
+        self.encode_as(choose_encoding(self))
+    }
+
+    /// Whether `encode_as` can encode the column with the encoding
+    pub fn supports_encoding(&self, encoding: ColumnEncoding) -> bool {
+        let float_column = matches!(self, RawColumnVec::Num(_) | RawColumnVec::VecNum(_));
+        match encoding {
+            ColumnEncoding::Raw | ColumnEncoding::LZ4 => true,
+            ColumnEncoding::BitPacked => {
+                matches!(self, RawColumnVec::Int(_) | RawColumnVec::Num(_))
+            }
+            ColumnEncoding::RunLength | ColumnEncoding::Dictionary => !float_column,
         }
     }
+
+    pub fn encode_as(&self, encoding: ColumnEncoding) -> Result<EncodedColumnVec> {
+        let encoded = match (self, encoding) {
+            (RawColumnVec::Int(RawColumnVecGen::Nullable(nullable)), ColumnEncoding::BitPacked) => {
+                EncodedColumnVec::Nullable(NullableEncodedColumnVec::BitPackedIntVec(
+                    BitPackedIntVecOption::encode(nullable.clone()),
+                ))
+            }
+            (
+                RawColumnVec::Int(RawColumnVecGen::NonNullable(nonnullable)),
+                ColumnEncoding::BitPacked,
+            ) => EncodedColumnVec::NonNullable(NonNullableEncodedColumnVec::BitPackedIntVec(
+                BitPackedIntVec::encode(nonnullable.clone()),
+            )),
+            (RawColumnVec::Num(RawColumnVecGen::Nullable(nullable)), ColumnEncoding::BitPacked) => {
+                EncodedColumnVec::Nullable(NullableEncodedColumnVec::BitPackedFloatVec(
+                    BitPackedFloatVecOption::encode(nullable.clone()),
+                ))
+            }
+            (
+                RawColumnVec::Num(RawColumnVecGen::NonNullable(nonnullable)),
+                ColumnEncoding::BitPacked,
+            ) => EncodedColumnVec::NonNullable(NonNullableEncodedColumnVec::BitPackedFloatVec(
+                BitPackedFloatVec::encode(nonnullable.clone()),
+            )),
+            (RawColumnVec::Num(v), ColumnEncoding::LZ4) => compress_column!(v),
+            (RawColumnVec::VecNum(v), ColumnEncoding::LZ4) => compress_column!(v),
+            (RawColumnVec::Num(_), encoding) | (RawColumnVec::VecNum(_), encoding) => {
+                bail!("{:?} encoding is not supported for float columns", encoding)
+            }
+            (RawColumnVec::Bool(v), encoding) => encode_column!(v, encoding),
+            (RawColumnVec::Int(v), encoding) => encode_column!(v, encoding),
+            (RawColumnVec::Str(v), encoding) => encode_column!(v, encoding),
+            (RawColumnVec::VecBool(v), encoding) => encode_column!(v, encoding),
+            (RawColumnVec::VecInt(v), encoding) => encode_column!(v, encoding),
+            (RawColumnVec::VecStr(v), encoding) => encode_column!(v, encoding),
+            (RawColumnVec::Date(v), encoding) => encode_column!(v, encoding),
+            (RawColumnVec::DateTime(v), encoding) => encode_column!(v, encoding),
+        };
+        Ok(encoded)
+    }
 }
 
 impl RawColumnVec {
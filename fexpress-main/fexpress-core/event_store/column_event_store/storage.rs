@@ -0,0 +1,360 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::Utc;
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+
+use crate::event_store::column_event_store::raw_column::{RawColumnVec, RawColumnVecGen};
+use crate::event_store::column_event_store::{
+    AnyColumnDataType, Block, ColumnData, Settings, Table,
+};
+use crate::interval::NaiveDateTimeInterval;
+use crate::map::HashMap;
+use crate::types::Timestamp;
+
+/*
+Layout of a table file:
+
+    magic | version | column chunk ... column chunk | table metadata | metadata offset | magic
+
+Every column chunk is the bincode of an encoded column of a block. The table metadata keeps
+the schema and for every block its time range and the position of its column chunks, so
+a mapped file only decodes the columns of the blocks a query needs.
+ */
+const TABLE_FILE_MAGIC: &[u8; 4] = b"FXCT";
+/// Version of the table file format, files written with another version are rejected
+pub const TABLE_FILE_VERSION: u32 = 1;
+pub const TABLE_FILE_EXTENSION: &str = "fxcol";
+const HEADER_LEN: usize = 8;
+const TRAILER_LEN: usize = 12;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColumnChunkMeta {
+    pub name: String,
+    offset: u64,
+    len: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockMeta {
+    pub start_time: Timestamp,
+    pub end_time: Timestamp,
+    pub n_rows: usize,
+    pub columns: Vec<ColumnChunkMeta>,
+}
+
+impl BlockMeta {
+    /// Whether the block may hold events of the interval (both ends inclusive)
+    pub fn overlaps(&self, interval: &NaiveDateTimeInterval) -> bool {
+        interval.start_dt.is_none_or(|start| self.end_time >= start)
+            && interval.end_dt.is_none_or(|end| self.start_time <= end)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TableMeta {
+    pub name: String,
+    pub schema: Vec<(String, AnyColumnDataType)>,
+    pub blocks: Vec<BlockMeta>,
+}
+
+impl Table {
+    /// Writes the blocks of the table with encoded columns to a table file, the columns
+    /// still raw are encoded as the settings say
+    pub fn write_to_file(&self, path: &Path, settings: &Settings) -> Result<()> {
+        let file =
+            File::create(path).with_context(|| format!("Cannot create {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(TABLE_FILE_MAGIC)?;
+        writer.write_all(&TABLE_FILE_VERSION.to_le_bytes())?;
+
+        let mut offset = HEADER_LEN as u64;
+        let mut blocks = Vec::new();
+        for block in self.blocks.values().flat_map(|blocks| blocks.iter()) {
+            let mut columns = Vec::with_capacity(block.columns.len());
+            for (name, column) in block.columns.iter() {
+                let mut column = column.clone();
+                if let ColumnData::Raw(raw) = &column {
+                    let encoding = settings.column_encoding(name, raw);
+                    column
+                        .encode_as(encoding)
+                        .with_context(|| format!("Cannot encode column {}", name))?;
+                }
+                let bytes = bincode::serialize(&column)
+                    .with_context(|| format!("Cannot serialize column {}", name))?;
+                writer.write_all(&bytes)?;
+                columns.push(ColumnChunkMeta {
+                    name: name.clone(),
+                    offset,
+                    len: bytes.len() as u64,
+                });
+                offset += bytes.len() as u64;
+            }
+            columns.sort_by(|a, b| a.name.cmp(&b.name));
+            blocks.push(BlockMeta {
+                start_time: block.start_time,
+                end_time: block.end_time,
+                n_rows: block.n_rows,
+                columns,
+            });
+        }
+
+        let mut schema: Vec<_> = self
+            .schema
+            .iter()
+            .map(|(name, typ)| (name.clone(), typ.clone()))
+            .collect();
+        schema.sort_by(|a, b| a.0.cmp(&b.0));
+        let meta = TableMeta {
+            name: self.name.clone(),
+            schema,
+            blocks,
+        };
+        writer.write_all(&bincode::serialize(&meta)?)?;
+        writer.write_all(&offset.to_le_bytes())?;
+        writer.write_all(TABLE_FILE_MAGIC)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Table file mapped into memory, the columns are decoded when a block is read
+#[derive(Debug)]
+pub struct MappedTable {
+    pub meta: TableMeta,
+    mmap: Mmap,
+}
+
+impl MappedTable {
+    pub fn open(path: &Path) -> Result<MappedTable> {
+        let file = File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+        // the table files are written once and never modified while they are mapped
+        let mmap = unsafe { Mmap::map(&file) }
+            .with_context(|| format!("Cannot map {}", path.display()))?;
+
+        if mmap.len() < HEADER_LEN + TRAILER_LEN
+            || &mmap[..4] != TABLE_FILE_MAGIC
+            || &mmap[mmap.len() - 4..] != TABLE_FILE_MAGIC
+        {
+            bail!("{} is not a table file", path.display());
+        }
+        let version = u32::from_le_bytes(mmap[4..HEADER_LEN].try_into()?);
+        if version != TABLE_FILE_VERSION {
+            bail!(
+                "Table file {} has version {}, only version {} is supported",
+                path.display(),
+                version,
+                TABLE_FILE_VERSION
+            );
+        }
+        let meta_end = mmap.len() - TRAILER_LEN;
+        let meta_offset = u64::from_le_bytes(mmap[meta_end..meta_end + 8].try_into()?) as usize;
+        if meta_offset < HEADER_LEN || meta_offset > meta_end {
+            bail!("Table file {} is corrupted", path.display());
+        }
+        let meta: TableMeta = bincode::deserialize(&mmap[meta_offset..meta_end])
+            .with_context(|| format!("Cannot read the metadata of {}", path.display()))?;
+
+        Ok(MappedTable { meta, mmap })
+    }
+
+    pub fn schema(&self) -> HashMap<String, AnyColumnDataType> {
+        self.meta.schema.iter().cloned().collect()
+    }
+
+    pub fn n_rows(&self) -> usize {
+        self.meta.blocks.iter().map(|block| block.n_rows).sum()
+    }
+
+    /// Blocks which may hold events of the interval, the other blocks are never decoded
+    pub fn blocks_in_range<'a>(
+        &'a self,
+        interval: &'a NaiveDateTimeInterval,
+    ) -> impl Iterator<Item = &'a BlockMeta> + 'a {
+        self.meta
+            .blocks
+            .iter()
+            .filter(move |block| block.overlaps(interval))
+    }
+
+    /// Reads the still encoded column of the block, `None` if the block doesn't have it
+    pub fn read_column(&self, block: &BlockMeta, name: &str) -> Result<Option<ColumnData>> {
+        let chunk = match block
+            .columns
+            .binary_search_by(|chunk| chunk.name.as_str().cmp(name))
+        {
+            Ok(i) => &block.columns[i],
+            Err(_) => return Ok(None),
+        };
+        let start = chunk.offset as usize;
+        let end = start + chunk.len as usize;
+        let bytes = self
+            .mmap
+            .get(start..end)
+            .ok_or_else(|| anyhow!("Column {} is outside of the table file", name))?;
+        let column = bincode::deserialize(bytes)
+            .with_context(|| format!("Cannot read column {} of table {}", name, self.meta.name))?;
+        Ok(Some(column))
+    }
+
+    /// Decodes the columns of the block, `event_time` is always read
+    pub fn read_block(&self, block: &BlockMeta, columns: &[String]) -> Result<Block> {
+        let mut decoded = HashMap::new();
+        let names = std::iter::once("event_time").chain(columns.iter().map(|c| c.as_str()));
+        for name in names {
+            if decoded.contains_key(name) {
+                continue;
+            }
+            if let Some(mut column) = self.read_column(block, name)? {
+                column.decode();
+                decoded.insert(name.to_string(), column);
+            }
+        }
+
+        let index_by_event_time = match decoded.get("event_time") {
+            Some(ColumnData::Raw(RawColumnVec::DateTime(RawColumnVecGen::NonNullable(times)))) => {
+                times
+                    .iter()
+                    .enumerate()
+                    .map(|(row, time)| (*time, row))
+                    .collect()
+            }
+            _ => BTreeMap::new(),
+        };
+
+        Ok(Block {
+            start_time: block.start_time,
+            end_time: block.end_time,
+            columns: decoded,
+            n_rows: block.n_rows,
+            index_by_event_time,
+            last_insertion_time: Utc::now(),
+            compression_stats: Default::default(),
+        })
+    }
+
+    /// Decodes the columns of the blocks that overlap the interval
+    pub fn scan(&self, interval: &NaiveDateTimeInterval, columns: &[String]) -> Result<Vec<Block>> {
+        self.blocks_in_range(interval)
+            .map(|block| self.read_block(block, columns))
+            .collect()
+    }
+}
+
+/// Column store whose tables are mapped from the files written by `ColumnStore::save`
+#[derive(Debug, Default)]
+pub struct MappedColumnStore {
+    pub tables: HashMap<String, MappedTable>,
+}
+
+impl MappedColumnStore {
+    pub fn open(dir: &str) -> Result<MappedColumnStore> {
+        let mut tables = HashMap::new();
+        for entry in fs::read_dir(dir).with_context(|| format!("Cannot read {}", dir))? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|ext| ext == TABLE_FILE_EXTENSION)
+            {
+                let table = MappedTable::open(&path)?;
+                tables.insert(table.meta.name.clone(), table);
+            }
+        }
+        Ok(MappedColumnStore { tables })
+    }
+
+    pub fn scan(
+        &self,
+        table: &str,
+        interval: &NaiveDateTimeInterval,
+        columns: &[String],
+    ) -> Result<Vec<Block>> {
+        self.tables
+            .get(table)
+            .ok_or_else(|| anyhow!("cannot find table {}", table))?
+            .scan(interval, columns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_store::column_event_store::ColumnStore;
+    use crate::tests::fake_nba::generate_nba_game_events;
+
+    #[test]
+    fn test_table_file_round_trip() {
+        let mut store = ColumnStore::new(Settings {
+            block_size: 10,
+            enable_compression: true,
+            column_encodings: Default::default(),
+        });
+        let mut games = generate_nba_game_events(100);
+        games.sort_by_key(|event| event.event_time);
+        for game in &games {
+            store
+                .insert_new_event_incremental_incremental(game)
+                .unwrap();
+        }
+
+        let dir = std::env::temp_dir().join("fexpress_test_table_file_round_trip");
+        let _ = fs::remove_dir_all(&dir);
+        store.save(dir.to_str().unwrap()).unwrap();
+        let mapped = MappedColumnStore::open(dir.to_str().unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let table = store.tables.get("game").unwrap();
+        let mapped_table = mapped.tables.get("game").unwrap();
+        assert_eq!(mapped_table.schema(), table.schema);
+        assert_eq!(mapped_table.n_rows(), games.len());
+
+        // all the columns of every block are the same after decoding
+        let blocks = mapped_table
+            .scan(
+                &NaiveDateTimeInterval {
+                    start_dt: None,
+                    end_dt: None,
+                },
+                &[],
+            )
+            .unwrap();
+        assert_eq!(blocks.len(), mapped_table.meta.blocks.len());
+        for (block, mapped_block_meta) in table
+            .blocks
+            .values()
+            .flat_map(|blocks| blocks.iter())
+            .zip(mapped_table.meta.blocks.iter())
+        {
+            let names: Vec<String> = block.columns.keys().cloned().collect();
+            let mapped_block = mapped_table.read_block(mapped_block_meta, &names).unwrap();
+            assert_eq!(mapped_block.n_rows, block.n_rows);
+            for (name, column) in block.columns.iter() {
+                let mut column = column.clone();
+                column.decode();
+                assert_eq!(mapped_block.columns.get(name), Some(&column));
+            }
+        }
+
+        // blocks outside of the interval are pruned
+        let first = &mapped_table.meta.blocks[1];
+        let interval = NaiveDateTimeInterval {
+            start_dt: Some(first.start_time),
+            end_dt: Some(first.end_time),
+        };
+        let pruned = mapped
+            .scan("game", &interval, &["event_id".to_string()])
+            .unwrap();
+        assert!(!pruned.is_empty() && pruned.len() < mapped_table.meta.blocks.len());
+        assert!(pruned
+            .iter()
+            .all(|block| block.end_time >= first.start_time && block.start_time <= first.end_time));
+        assert_eq!(table.blocks_in_range(&interval).count(), pruned.len());
+        assert!(pruned[0].columns.contains_key("event_time"));
+        assert!(!pruned[0].columns.contains_key("entity.home"));
+    }
+}
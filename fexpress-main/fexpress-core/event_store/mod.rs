@@ -13,11 +13,16 @@ use crate::value::{Value, ValueType};
 use crate::ast::core::Expr;
 use crate::event_index::QueryConfig;
 use crate::event_store::postgres::postgres_event_store::PostgresEventStore;
+use crate::event_store::retention::RetentionPolicy;
+use crate::event_store::row_event_store::attribute_index::{AttributeFilter, IndexScope};
 use crate::event_store::row_event_store::memory_event_store::MemoryEventStore;
+use crate::event_store::schema::{EventTypeSchema, SchemaVersion, SchemaViolation};
 
 pub mod column_event_store;
 pub mod postgres;
+pub mod retention;
 pub mod row_event_store;
+pub mod schema;
 mod test_implementations;
 
 #[enum_dispatch]
@@ -28,11 +33,37 @@ pub trait EventStore {
     /// Insert new event
     fn insert_batch(&self, events: Vec<Event>) -> Result<()>;
 
+    /// Declares the schema of an event type, the events inserted afterwards are checked against it
+    fn declare_schema(&self, schema: EventTypeSchema) -> Result<()>;
+
+    /// Violations of the schemas declared in warn mode
+    fn schema_warnings(&self) -> Vec<SchemaViolation>;
+
+    /// Versions of the inferred schema of the event type, a new version is recorded when an
+    /// attribute is added or its type is widened
+    fn schema_versions(&self, event_type: &EventType) -> Vec<SchemaVersion>;
+
+    /// Removes the event with the id, returns whether it existed
+    fn delete_event(&self, event_id: &EventID) -> Result<bool>;
+
+    /// Inserts the event or replaces the event with the same event_id
+    fn upsert(&self, event: Event) -> Result<()>;
+
+    /// Removes all the events of the entity (e.g. for an erasure request), returns the number
+    /// of removed events. Durable stores compact the log before returning: the erasure is
+    /// durable only once the old snapshot and log files with the events are removed
+    fn delete_entity(&self, entity: &Entity) -> Result<usize>;
+
+    /// Removes the events older than the time to live of their event type at `now`, returns
+    /// the number of removed events
+    fn apply_retention(&self, policy: &RetentionPolicy, now: &Timestamp) -> Result<usize>;
+
     /// Get list of entities
     fn get_entities(&self, experiment_id: &Option<SmallString>) -> Vec<Entity>;
 
-    /// Update schema of the events
-    fn update_schema(&self, event: &Event);
+    /// Update schema of the events, fails without changing the schema when an attribute
+    /// conflicts with its stored type
+    fn update_schema(&self, event: &Event) -> Result<()>;
 
     /// Extract events for entity and event_type
     fn query_entity_event_type(
@@ -76,6 +107,38 @@ pub trait EventStore {
         query_config: &QueryConfig,
     ) -> Option<Vec<(Timestamp, Vec<Arc<Event>>)>>;
 
+    /// Events of all the entities and the events without any entity, each event once, for the
+    /// aggregations marked as GLOBAL. Unlike `query_interval` the events on the observation
+    /// date follow `QueryConfig.include_events_on_obs_date`
+    fn query_global_events(
+        &self,
+        event_type: Option<&EventType>,
+        interval: &NaiveDateTimeInterval,
+        query_config: &QueryConfig,
+    ) -> Option<Vec<(Timestamp, Vec<Arc<Event>>)>>;
+
+    /// Extract events matching one of the attribute conditions through a secondary attribute
+    /// index, `None` if no index can answer the conditions
+    fn query_attribute_index(
+        &self,
+        filters: &[AttributeFilter],
+        scope: &IndexScope,
+        event_type: Option<&EventType>,
+        interval: &NaiveDateTimeInterval,
+        query_config: &QueryConfig,
+    ) -> Option<Vec<(Timestamp, Vec<Arc<Event>>)>>;
+
+    /// Latest value of the event type attribute known for the entities at the given time and
+    /// the time of the event it comes from
+    fn query_attribute_as_of(
+        &self,
+        entities: &Entities,
+        event_type: &EventType,
+        attribute: &AttributeName,
+        ts: &Timestamp,
+        query_config: &QueryConfig,
+    ) -> Option<(Timestamp, Value)>;
+
     /// Filter events based on an expression
     fn filter_events(
         &self,
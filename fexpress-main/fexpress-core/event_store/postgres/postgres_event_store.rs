@@ -7,13 +7,16 @@ use std::sync::{Arc, RwLock};
 use crate::ast::core::Expr;
 use crate::map::{HashMap, HashSet};
 use crate::sstring::SmallString;
-use anyhow::Result;
+use anyhow::{bail, Result};
 use chrono::NaiveDateTime;
 use postgres::{Client, NoTls, Row};
 
 use crate::datetime_utils::parse_utc_from_str;
 use crate::event::{AttributeName, Entity, Event, EventType};
 use crate::event_index::QueryConfig;
+use crate::event_store::retention::RetentionPolicy;
+use crate::event_store::row_event_store::attribute_index::{AttributeFilter, IndexScope};
+use crate::event_store::schema::{EventTypeSchema, SchemaVersion, SchemaViolation};
 use crate::event_store::EventStore;
 use crate::interval::NaiveDateTimeInterval;
 use crate::types::{Entities, EventID, Timestamp};
@@ -278,6 +281,34 @@ impl EventStore for PostgresEventStore {
         // }
     }
 
+    fn declare_schema(&self, _schema: EventTypeSchema) -> Result<()> {
+        bail!("Declared schemas are not supported by the postgres event store")
+    }
+
+    fn schema_warnings(&self) -> Vec<SchemaViolation> {
+        vec![]
+    }
+
+    fn schema_versions(&self, _event_type: &EventType) -> Vec<SchemaVersion> {
+        vec![]
+    }
+
+    fn delete_event(&self, _event_id: &EventID) -> Result<bool> {
+        bail!("Deleting events is not supported by the postgres event store")
+    }
+
+    fn upsert(&self, _event: Event) -> Result<()> {
+        bail!("Upserting events is not supported by the postgres event store")
+    }
+
+    fn delete_entity(&self, _entity: &Entity) -> Result<usize> {
+        bail!("Deleting entities is not supported by the postgres event store")
+    }
+
+    fn apply_retention(&self, _policy: &RetentionPolicy, _now: &Timestamp) -> Result<usize> {
+        bail!("Retention policies are not supported by the postgres event store")
+    }
+
     fn get_entities(&self, _experiment_id: &Option<SmallString>) -> Vec<Entity> {
         unimplemented!()
         // let mut client = self.client.write().unwrap();
@@ -302,7 +333,7 @@ impl EventStore for PostgresEventStore {
         // entities
     }
 
-    fn update_schema(&self, _event: &Event) {
+    fn update_schema(&self, _event: &Event) -> Result<()> {
         unimplemented!()
         // let mut client = self.client.write().unwrap();
         // if let Some(attrs) = &event.attrs {
@@ -512,6 +543,41 @@ impl EventStore for PostgresEventStore {
         todo!()
     }
 
+    fn query_global_events(
+        &self,
+        event_type: Option<&EventType>,
+        interval: &NaiveDateTimeInterval,
+        query_config: &QueryConfig,
+    ) -> Option<Vec<(Timestamp, Vec<Arc<Event>>)>> {
+        match event_type {
+            Some(event_type) => self.query_event_type(event_type, query_config, Some(interval)),
+            None => self.query_interval(interval, query_config),
+        }
+    }
+
+    fn query_attribute_index(
+        &self,
+        _filters: &[AttributeFilter],
+        _scope: &IndexScope,
+        _event_type: Option<&EventType>,
+        _interval: &NaiveDateTimeInterval,
+        _query_config: &QueryConfig,
+    ) -> Option<Vec<(Timestamp, Vec<Arc<Event>>)>> {
+        None
+    }
+
+    fn query_attribute_as_of(
+        &self,
+        _entities: &Entities,
+        _event_type: &EventType,
+        _attribute: &AttributeName,
+        _ts: &Timestamp,
+        _query_config: &QueryConfig,
+    ) -> Option<(Timestamp, Value)> {
+        // the attributes are not indexed by time, no value is known as of the time
+        None
+    }
+
     fn filter_events(
         &self,
         _condition: &Expr,
@@ -0,0 +1,105 @@
+use std::ops::Sub;
+
+use anyhow::Result;
+use chrono::Duration;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::event::{Event, EventType};
+use crate::map::HashMap;
+use crate::obs_dates::Period;
+use crate::types::Timestamp;
+
+/// Time to live of the events, the events older than the time to live of their event type
+/// are dropped when the policy is applied
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct RetentionPolicy {
+    /// time to live of the event types without their own
+    #[serde(default)]
+    pub default_ttl: Option<Period>,
+    /// event_type -> time to live
+    #[serde(default)]
+    pub ttl: HashMap<String, Period>,
+}
+
+impl RetentionPolicy {
+    pub fn ttl(&self, event_type: &EventType) -> Option<&Period> {
+        self.ttl
+            .get(event_type.0.as_str())
+            .or(self.default_ttl.as_ref())
+    }
+
+    /// Validates the periods and returns the cutoff of every event type relative to `now`
+    pub fn cutoffs(&self, now: &Timestamp) -> Result<RetentionCutoffs> {
+        let cutoff = |period: &Period| -> Result<Timestamp> {
+            Ok(now.sub(period.to_duration()?.max(Duration::zero())))
+        };
+        Ok(RetentionCutoffs {
+            default_cutoff: self.default_ttl.as_ref().map(cutoff).transpose()?,
+            cutoffs: self
+                .ttl
+                .iter()
+                .map(|(event_type, period)| Ok((event_type.clone(), cutoff(period)?)))
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+/// Oldest event time kept for every event type
+#[derive(Clone, Debug)]
+pub struct RetentionCutoffs {
+    default_cutoff: Option<Timestamp>,
+    cutoffs: HashMap<String, Timestamp>,
+}
+
+impl RetentionCutoffs {
+    pub fn is_expired(&self, event: &Event) -> bool {
+        self.cutoffs
+            .get(event.event_type.0.as_str())
+            .or(self.default_cutoff.as_ref())
+            .is_some_and(|cutoff| event.event_time < *cutoff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::NaiveDateTime;
+
+    use super::*;
+    use crate::interval::DatePart;
+
+    #[test]
+    fn test_retention_cutoffs() {
+        let event = |event_type: &str, event_time: &str| -> Event {
+            serde_json::from_str(&format!(
+                r#"{{"event_type": "{}", "event_time": "{}", "entities": {{"user": "a"}}}}"#,
+                event_type, event_time
+            ))
+            .unwrap()
+        };
+        let policy: RetentionPolicy = serde_json::from_str(
+            r#"{"default_ttl": {"date_part": "Day", "nth": 30}, "ttl": {"click": {"date_part": "Hour", "nth": 1}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            policy.ttl(&EventType("order".into())).unwrap().date_part,
+            DatePart::Day
+        );
+
+        let now = NaiveDateTime::from_str("2023-02-01T00:00:00").unwrap();
+        let cutoffs = policy.cutoffs(&now).unwrap();
+        assert!(cutoffs.is_expired(&event("order", "2022-12-31T00:00:00")));
+        assert!(!cutoffs.is_expired(&event("order", "2023-01-31T00:00:00")));
+        assert!(cutoffs.is_expired(&event("click", "2023-01-31T22:00:00")));
+        assert!(!cutoffs.is_expired(&event("click", "2023-01-31T23:30:00")));
+
+        let keep_all = RetentionPolicy::default().cutoffs(&now).unwrap();
+        assert!(!keep_all.is_expired(&event("order", "2000-01-01T00:00:00")));
+
+        let invalid: RetentionPolicy =
+            serde_json::from_str(r#"{"default_ttl": {"date_part": "All", "nth": 1}}"#).unwrap();
+        assert!(invalid.cutoffs(&now).is_err());
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::str::FromStr;
+
+use anyhow::bail;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use slotmap::DefaultKey;
+
+use crate::event::{AttributeName, Event};
+use crate::event_store::row_event_store::memory_event_store::TimeBTree;
+use crate::map::{HashMap, HashSet};
+use crate::sstring::SmallString;
+use crate::types::{Entities, Timestamp};
+use crate::value::{Value, ValueType};
+
+/*
+Secondary indices on attributes map every value of the attribute to the events holding it.
+The events of a value are kept in a TimeBTree like the other indices so that only the events
+of the window are read. Hash indices answer equality and `in`, BTree indices answer ranges too.
+
+An index answers equality and `in` only when the literals have the same type as all the indexed
+values (floats excluded as they are compared with a tolerance), and ranges only on numeric
+values in BTree indices, otherwise the events are scanned.
+The index only narrows down the events, the where clause is still evaluated on them.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum AttributeIndexKind {
+    Hash,
+    BTree,
+}
+
+impl FromStr for AttributeIndexKind {
+    type Err = anyhow::Error;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind.to_lowercase().as_str() {
+            "hash" => Ok(AttributeIndexKind::Hash),
+            "btree" => Ok(AttributeIndexKind::BTree),
+            _ => bail!("Unknown index kind {}, expected hash or btree", kind),
+        }
+    }
+}
+
+/// Condition on an attribute extracted from a where clause
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeFilter {
+    Eq(AttributeName, Value),
+    In(AttributeName, Vec<Value>),
+    Range(AttributeName, Bound<Value>, Bound<Value>),
+}
+
+impl AttributeFilter {
+    pub fn attribute(&self) -> &AttributeName {
+        match self {
+            AttributeFilter::Eq(attribute, _)
+            | AttributeFilter::In(attribute, _)
+            | AttributeFilter::Range(attribute, _, _) => attribute,
+        }
+    }
+}
+
+/// Events a lookup is restricted to, the same events as the queries without an index
+#[derive(Debug, Clone)]
+pub enum IndexScope<'a> {
+    /// events of the entities and the global events
+    Entities {
+        entities: &'a Entities,
+        experiment_id: &'a Option<SmallString>,
+    },
+    /// events of all the entities and the global events
+    All,
+}
+
+impl IndexScope<'_> {
+    pub fn contains(&self, event: &Event) -> bool {
+        match self {
+            IndexScope::All => event.experiment_id.is_none(),
+            IndexScope::Entities {
+                entities,
+                experiment_id,
+            } => {
+                let has_entities = !entities.is_empty()
+                    && entities
+                        .iter()
+                        .all(|(typ, id)| event.entities.get(typ) == Some(id));
+                match &event.experiment_id {
+                    Some(event_experiment_id) => {
+                        experiment_id.as_ref() == Some(event_experiment_id) && has_entities
+                    }
+                    None => event.entities.is_empty() || has_entities,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum IndexEntries {
+    Hash(HashMap<Value, TimeBTree>),
+    BTree(BTreeMap<Value, TimeBTree>),
+}
+
+#[derive(Debug, Clone)]
+pub struct AttributeIndex {
+    entries: IndexEntries,
+    value_types: HashSet<ValueType>,
+    // values which cannot be ordered with the other values are not indexed
+    // and the index cannot answer any condition
+    incomplete: bool,
+}
+
+fn is_numeric(value_type: &ValueType) -> bool {
+    matches!(value_type, ValueType::Int | ValueType::Num)
+}
+
+fn is_scalar(value_type: &ValueType) -> bool {
+    matches!(
+        value_type,
+        ValueType::Bool
+            | ValueType::Int
+            | ValueType::Num
+            | ValueType::Str
+            | ValueType::Date
+            | ValueType::DateTime
+    )
+}
+
+fn value_type(value: &Value) -> ValueType {
+    value.clone().into()
+}
+
+impl AttributeIndex {
+    pub fn new(kind: AttributeIndexKind) -> Self {
+        let entries = match kind {
+            AttributeIndexKind::Hash => IndexEntries::Hash(HashMap::new()),
+            AttributeIndexKind::BTree => IndexEntries::BTree(BTreeMap::new()),
+        };
+        AttributeIndex {
+            entries,
+            value_types: HashSet::new(),
+            incomplete: false,
+        }
+    }
+
+    pub fn kind(&self) -> AttributeIndexKind {
+        match self.entries {
+            IndexEntries::Hash(_) => AttributeIndexKind::Hash,
+            IndexEntries::BTree(_) => AttributeIndexKind::BTree,
+        }
+    }
+
+    /// Whether values of the types can be compared by the index
+    fn comparable(&self, a: &ValueType, b: &ValueType) -> bool {
+        match self.entries {
+            IndexEntries::Hash(_) => a == b,
+            IndexEntries::BTree(_) => a == b || (is_numeric(a) && is_numeric(b)),
+        }
+    }
+
+    fn comparable_with_all(&self, value_type: &ValueType) -> bool {
+        self.value_types
+            .iter()
+            .all(|indexed| self.comparable(indexed, value_type))
+    }
+
+    pub fn insert(&mut self, value: &Value, ts: Timestamp, key: DefaultKey) {
+        if value.is_null() {
+            return;
+        }
+        let typ = value_type(value);
+        // the ordering of values is only defined between scalars of comparable types
+        if !is_scalar(&typ) || (!self.comparable_with_all(&typ) && self.is_btree()) {
+            self.incomplete = true;
+            self.value_types.insert(typ);
+            return;
+        }
+        self.value_types.insert(typ);
+        let treemap = match &mut self.entries {
+            IndexEntries::Hash(entries) => entries.entry(value.clone()).or_default(),
+            IndexEntries::BTree(entries) => entries.entry(value.clone()).or_default(),
+        };
+        treemap.entry(ts).or_default().push(key);
+    }
+
+    fn is_btree(&self) -> bool {
+        self.kind() == AttributeIndexKind::BTree
+    }
+
+    pub fn clear(&mut self) {
+        match &mut self.entries {
+            IndexEntries::Hash(entries) => entries.clear(),
+            IndexEntries::BTree(entries) => entries.clear(),
+        }
+        self.value_types.clear();
+        self.incomplete = false;
+    }
+
+    pub fn remove_keys(&mut self, keys: &HashSet<DefaultKey>) {
+        let remove = |treemap: &mut TimeBTree| {
+            treemap.retain(|_, ts_keys| {
+                ts_keys.retain(|key| !keys.contains(key));
+                !ts_keys.is_empty()
+            });
+            !treemap.is_empty()
+        };
+        match &mut self.entries {
+            IndexEntries::Hash(entries) => entries.retain(|_, treemap| remove(treemap)),
+            IndexEntries::BTree(entries) => entries.retain(|_, treemap| remove(treemap)),
+        }
+    }
+
+    /// Removes the event with the value of the attribute at the time
+    pub fn remove(&mut self, value: &Value, ts: &Timestamp, key: DefaultKey) {
+        let remove = |treemap: &mut TimeBTree| {
+            if let Some(ts_keys) = treemap.get_mut(ts) {
+                ts_keys.retain(|ts_key| *ts_key != key);
+                if ts_keys.is_empty() {
+                    treemap.remove(ts);
+                }
+            }
+            treemap.is_empty()
+        };
+        // values which cannot be ordered with the indexed values were not indexed, the
+        // entries of an incomplete index are not read so its stale keys are harmless
+        let typ = value_type(value);
+        if self.is_btree()
+            && (self.incomplete || !is_scalar(&typ) || !self.comparable_with_all(&typ))
+        {
+            return;
+        }
+        match &mut self.entries {
+            IndexEntries::Hash(entries) => {
+                if entries.get_mut(value).is_some_and(remove) {
+                    entries.remove(value);
+                }
+            }
+            IndexEntries::BTree(entries) => {
+                if entries.get_mut(value).is_some_and(remove) {
+                    entries.remove(value);
+                }
+            }
+        }
+    }
+
+    fn get(&self, value: &Value) -> Vec<&TimeBTree> {
+        match &self.entries {
+            IndexEntries::Hash(entries) => entries.get(value).into_iter().collect(),
+            IndexEntries::BTree(entries) => entries
+                .range((Bound::Included(value), Bound::Included(value)))
+                .map(|(_, treemap)| treemap)
+                .collect(),
+        }
+    }
+
+    /// Events of the values matching the filter, `None` if the index cannot answer the filter
+    pub fn lookup(&self, filter: &AttributeFilter) -> Option<Vec<&TimeBTree>> {
+        if self.incomplete {
+            return None;
+        }
+        let literals: Vec<&Value> = match filter {
+            AttributeFilter::Eq(_, value) => vec![value],
+            AttributeFilter::In(_, values) => values.iter().collect(),
+            AttributeFilter::Range(_, low, high) => [low, high]
+                .iter()
+                .filter_map(|bound| match bound {
+                    Bound::Included(value) | Bound::Excluded(value) => Some(value),
+                    Bound::Unbounded => None,
+                })
+                .collect(),
+        };
+        for literal in literals.iter() {
+            let typ = value_type(literal);
+            if !is_scalar(&typ) || !self.comparable_with_all(&typ) {
+                return None;
+            }
+        }
+
+        if matches!(filter, AttributeFilter::Eq(..) | AttributeFilter::In(..)) {
+            // floats are compared with a tolerance and ints are not equal to floats in `in`,
+            // so only values of the same exact type are looked up
+            let exact = literals.iter().all(|literal| {
+                let typ = value_type(literal);
+                typ != ValueType::Num && self.value_types.iter().all(|indexed| *indexed == typ)
+            });
+            if !exact {
+                return None;
+            }
+        }
+
+        match (filter, &self.entries) {
+            (AttributeFilter::Eq(_, value), _) => Some(self.get(value)),
+            (AttributeFilter::In(_, values), _) => {
+                Some(values.iter().flat_map(|value| self.get(value)).collect())
+            }
+            (AttributeFilter::Range(_, low, high), IndexEntries::BTree(entries)) => {
+                // ranges are evaluated on floats, other types are compared differently
+                if !self.value_types.iter().all(is_numeric)
+                    || !literals
+                        .iter()
+                        .all(|literal| is_numeric(&value_type(literal)))
+                {
+                    return None;
+                }
+                // BTreeMap::range panics on reversed ranges
+                let empty = match (low, high) {
+                    (Bound::Included(low), Bound::Included(high)) => low.cmp(high).is_gt(),
+                    (
+                        Bound::Included(low) | Bound::Excluded(low),
+                        Bound::Included(high) | Bound::Excluded(high),
+                    ) => low.cmp(high).is_ge(),
+                    _ => false,
+                };
+                if empty {
+                    return Some(vec![]);
+                }
+                Some(
+                    entries
+                        .range((low.as_ref(), high.as_ref()))
+                        .map(|(_, treemap)| treemap)
+                        .collect(),
+                )
+            }
+            (AttributeFilter::Range(..), IndexEntries::Hash(_)) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slotmap::SlotMap;
+
+    #[test]
+    fn test_attribute_index_lookup() {
+        let mut keys: SlotMap<DefaultKey, ()> = SlotMap::new();
+        let ts = Timestamp::parse_from_str("2023-01-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let mut hash = AttributeIndex::new(AttributeIndexKind::Hash);
+        let mut btree = AttributeIndex::new(AttributeIndexKind::BTree);
+        for i in 0..10 {
+            let key = keys.insert(());
+            hash.insert(&Value::Str(format!("category_{}", i % 3)), ts, key);
+            btree.insert(&Value::Int(i), ts, key);
+        }
+        let n_keys = |treemaps: Option<Vec<&TimeBTree>>| {
+            treemaps.map(|treemaps| {
+                treemaps
+                    .iter()
+                    .flat_map(|treemap| treemap.values())
+                    .map(|keys| keys.len())
+                    .sum::<usize>()
+            })
+        };
+
+        let attribute = AttributeName::new("attr");
+        let category = |i: usize| Value::Str(format!("category_{}", i));
+        assert_eq!(
+            n_keys(hash.lookup(&AttributeFilter::Eq(attribute.clone(), category(0)))),
+            Some(4)
+        );
+        assert_eq!(
+            n_keys(hash.lookup(&AttributeFilter::In(
+                attribute.clone(),
+                vec![category(1), category(2), category(5)]
+            ))),
+            Some(6)
+        );
+        // hash indices cannot answer ranges, literals of other types are not looked up
+        let range = AttributeFilter::Range(
+            attribute.clone(),
+            Bound::Excluded(Value::Int(2)),
+            Bound::Included(Value::Num(5.5)),
+        );
+        assert_eq!(n_keys(hash.lookup(&range)), None);
+        assert_eq!(
+            n_keys(hash.lookup(&AttributeFilter::Eq(attribute.clone(), Value::Int(1)))),
+            None
+        );
+
+        assert_eq!(n_keys(btree.lookup(&range)), Some(3));
+        assert_eq!(
+            n_keys(btree.lookup(&AttributeFilter::Range(
+                attribute.clone(),
+                Bound::Included(Value::Int(7)),
+                Bound::Excluded(Value::Int(3)),
+            ))),
+            Some(0)
+        );
+        assert_eq!(
+            n_keys(btree.lookup(&AttributeFilter::Eq(attribute.clone(), Value::Int(9)))),
+            Some(1)
+        );
+
+        // a string among the ints makes the index unusable instead of mis-ordering the values
+        btree.insert(&Value::Str("a".to_string()), ts, keys.insert(()));
+        assert_eq!(n_keys(btree.lookup(&range)), None);
+    }
+}
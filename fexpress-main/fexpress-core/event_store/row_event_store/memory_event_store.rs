@@ -1,7 +1,7 @@
 #![allow(clippy::unwrap_used)]
 
 use std::collections::BTreeMap;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
 
 use crate::map::{HashMap, HashSet};
 use crate::sstring::SmallString;
@@ -11,19 +11,55 @@ use slotmap::{DefaultKey, SlotMap};
 
 use crate::algo::intersect::intersect;
 use crate::ast::core::Expr;
+use crate::errors::FeatureExpressError;
 use crate::eval::{eval_simple_expr, EvalContext};
 use crate::event::{AttributeName, Entity, Event, EventType};
 use crate::event_index::{EventContext, EventScopeConfig};
+use crate::event_store::retention::RetentionPolicy;
+use crate::event_store::schema::{
+    widen_type, widen_value, EventTypeSchema, SchemaRegistry, SchemaVersion, SchemaViolation,
+};
 use crate::event_store::{EventStore, EventStoreImpl, QueryConfig};
 use crate::interval::NaiveDateTimeInterval;
 use crate::types::{Entities, EventID, Timestamp};
 use crate::value::{Value, ValueType};
+
+use super::attribute_index::{AttributeFilter, AttributeIndex, AttributeIndexKind, IndexScope};
+use super::snapshot::{SnapshotEvent, SnapshotTimestamp};
+use super::wal::{WalRecord, WriteAheadLog};
 use std::iter::FromIterator;
 
 //https://users.rust-lang.org/t/data-structure-with-views-indexes-into-itself/9803
 
 pub type TimeBTree = BTreeMap<Timestamp, Vec<DefaultKey>>;
 
+/// Removes the key from the events at the time, returns whether the index is empty
+fn remove_key(treemap: &mut TimeBTree, ts: &Timestamp, key: DefaultKey) -> bool {
+    if let Some(keys) = treemap.get_mut(ts) {
+        keys.retain(|k| *k != key);
+        if keys.is_empty() {
+            treemap.remove(ts);
+        }
+    }
+    treemap.is_empty()
+}
+
+/// Removes the key from the index of `k` and drops the index once it is empty
+fn remove_key_from<K: std::hash::Hash + Eq>(
+    indices: &mut HashMap<K, TimeBTree>,
+    k: &K,
+    ts: &Timestamp,
+    key: DefaultKey,
+) -> bool {
+    if indices
+        .get_mut(k)
+        .is_some_and(|treemap| remove_key(treemap, ts, key))
+    {
+        indices.remove(k);
+    }
+    indices.is_empty()
+}
+
 /*
 
 Change this data structure to remove additional atributes related to experiments and store every
@@ -72,6 +108,10 @@ pub struct MemoryEventStore {
     pub index_by_entity_ts: Arc<RwLock<HashMap<Entity, TimeBTree>>>,
     // (event_type, entity_type, entity_id) -> index
     pub index_by_event_type_entity_ts: Arc<RwLock<HashMap<EventType, HashMap<Entity, TimeBTree>>>>,
+    /// versions of every attribute of an entity used by the as-of lookups
+    // (entity_type, entity_id) -> (event_type, attribute_name) -> index
+    pub index_by_entity_attribute_ts:
+        Arc<RwLock<HashMap<Entity, HashMap<(EventType, AttributeName), TimeBTree>>>>,
     /// keep separate indices for experiments
     /// I think they need to be separate because in most of the cases if the experiment_id is null
     /// we only are interested in the indices without experiments. If we are extracting features
@@ -88,6 +128,68 @@ pub struct MemoryEventStore {
     /// We are basically creating a structure that disambiguates valuetype
     /// just by looking at the attribute name.
     pub attr_value_types: Arc<RwLock<HashMap<AttributeName, HashSet<ValueType>>>>,
+    /// log of the inserted events when the store is opened with `open_durable`
+    pub wal: Arc<Mutex<Option<WriteAheadLog>>>,
+    /// secondary indices declared on attributes
+    pub attribute_indexes: Arc<RwLock<HashMap<AttributeName, AttributeIndex>>>,
+    /// declared schemas the inserted events are checked against
+    pub schema_registry: Arc<RwLock<SchemaRegistry>>,
+    /// versions of the inferred schema of every event type
+    pub schema_versions: Arc<RwLock<HashMap<EventType, Vec<SchemaVersion>>>>,
+}
+
+/// Widens the inferred types of an event type with the attributes of the event, fails without
+/// changing the types when an attribute conflicts with its stored type. Returns whether the
+/// types changed.
+fn widen_event_type_schema(
+    event_type_schema: &mut HashMap<AttributeName, ValueType>,
+    event: &Event,
+    declared: bool,
+) -> Result<bool> {
+    let mut changes = vec![];
+    for (attr_name, value_new) in event.extract_attributes_values() {
+        let value_type_new: ValueType = value_new.clone().into();
+        let value_type = match event_type_schema.get(&attr_name) {
+            Some(value_type) => value_type,
+            None => {
+                changes.push((attr_name, value_type_new));
+                continue;
+            }
+        };
+        // the types widen along the coercion lattice, e.g. an int attribute becomes a num
+        // attribute when the first float arrives
+        let widened = widen_type(value_type, &value_type_new).filter(|widened| {
+            // the stored strings are not checked so a string attribute doesn't become a date
+            // attribute, the schema of the event type must be declared to read them as dates
+            let from_str = *value_type == ValueType::Str && *widened != ValueType::Str;
+            // a value of a narrower type must be readable as the stored type
+            !from_str
+                && (*widened == value_type_new
+                    || value_type_new == ValueType::None
+                    || widen_value(&value_new, widened).is_some())
+        });
+        match widened {
+            Some(widened) if widened != *value_type => changes.push((attr_name, widened)),
+            Some(_) => {}
+            // events of declared schemas in warn mode may have other types than the stored
+            // events, the schema keeps the stored type
+            None if declared => {}
+            None => {
+                return Err(FeatureExpressError::IngestionError(format!(
+                    "The value {} of the attribute {} of {} is {}, the stored events have {}",
+                    value_new.to_string(),
+                    attr_name,
+                    event.event_type,
+                    value_type_new,
+                    value_type
+                ))
+                .into())
+            }
+        }
+    }
+    let changed = !changes.is_empty();
+    event_type_schema.extend(changes);
+    Ok(changed)
 }
 
 fn merge_event_vectors(
@@ -133,11 +235,16 @@ impl MemoryEventStore {
             global_index_event_type_ts: Arc::new(Default::default()),
             index_by_entity_ts: Arc::new(Default::default()),
             index_by_event_type_entity_ts: Arc::new(Default::default()),
+            index_by_entity_attribute_ts: Arc::new(Default::default()),
             experiment_index_by_ts: Arc::new(Default::default()),
             experiment_index_by_entity_ts: Arc::new(Default::default()),
             experiment_index_by_entity_event_type_ts: Arc::new(Default::default()),
             schema: Default::default(),
             attr_value_types: Default::default(),
+            wal: Default::default(),
+            attribute_indexes: Default::default(),
+            schema_registry: Default::default(),
+            schema_versions: Default::default(),
         }
     }
 
@@ -186,6 +293,7 @@ impl MemoryEventStore {
             .unwrap();
 
         let event = sm.get(key).ok_or(anyhow!("Cannot read Event"))?;
+        self.index_attributes(event, key);
         if let Some(event_id) = &event.event_id {
             index_by_event_id.insert(event_id.clone(), key.clone());
         }
@@ -236,6 +344,7 @@ impl MemoryEventStore {
         let mut index_by_entity_event_type_ts = self.index_by_event_type_entity_ts.write().unwrap();
 
         let event = sm.get(key).ok_or(anyhow!("Cannot find Event"))?;
+        self.index_attributes(event, key);
 
         if let Some(event_id) = &event.event_id {
             index_by_event_id.insert(event_id.clone(), key.clone());
@@ -259,6 +368,21 @@ impl MemoryEventStore {
                     .or_default()
                     .push(key);
             }
+
+            let mut index_by_entity_attribute_ts =
+                self.index_by_entity_attribute_ts.write().unwrap();
+            let attribute_names = event.extract_attributes_values().into_keys().collect_vec();
+            for entity in event.entities() {
+                let entity_attributes = index_by_entity_attribute_ts.entry(entity).or_default();
+                for attribute_name in attribute_names.iter() {
+                    entity_attributes
+                        .entry((event.event_type.clone(), attribute_name.clone()))
+                        .or_default()
+                        .entry(event.event_time)
+                        .or_default()
+                        .push(key);
+                }
+            }
         } else {
             let mut global_index_by_ts = self.global_index_ts.write().unwrap();
             let mut global_index_by_event_type_ts =
@@ -280,6 +404,288 @@ impl MemoryEventStore {
         Ok(())
     }
 
+    fn index_attributes(&self, event: &Event, key: DefaultKey) {
+        let mut attribute_indexes = self.attribute_indexes.write().unwrap();
+        if attribute_indexes.is_empty() {
+            return;
+        }
+        let values = event.extract_attributes_values();
+        for (attribute, index) in attribute_indexes.iter_mut() {
+            if let Some(value) = values.get(attribute) {
+                index.insert(value, event.event_time, key);
+            }
+        }
+    }
+
+    /// Declares a secondary index on the attribute and indexes the stored events, nested
+    /// attributes are named with dots (e.g. `home_stats.points`)
+    pub fn create_attribute_index(
+        &self,
+        attribute: &AttributeName,
+        kind: AttributeIndexKind,
+    ) -> Result<()> {
+        // the events are locked before the indexes like in the insert and remove paths
+        let sm = self.sm.read().unwrap();
+        let mut attribute_indexes = self.attribute_indexes.write().unwrap();
+        if let Some(index) = attribute_indexes.get(attribute) {
+            if index.kind() == kind {
+                return Ok(());
+            }
+            bail!(
+                "Attribute {} already has a {:?} index",
+                attribute,
+                index.kind()
+            );
+        }
+        let mut index = AttributeIndex::new(kind);
+        for (key, event) in sm.iter() {
+            if let Some(value) = event.extract_attributes_values().get(attribute) {
+                index.insert(value, event.event_time, key);
+            }
+        }
+        attribute_indexes.insert(attribute.clone(), index);
+        Ok(())
+    }
+
+    /// Removes the index of the attribute, returns whether there was one
+    pub fn drop_attribute_index(&self, attribute: &AttributeName) -> bool {
+        let mut attribute_indexes = self.attribute_indexes.write().unwrap();
+        attribute_indexes.remove(attribute).is_some()
+    }
+
+    /// Attributes with an index and the kind of their index
+    pub fn attribute_indexes(&self) -> Vec<(AttributeName, AttributeIndexKind)> {
+        let attribute_indexes = self.attribute_indexes.read().unwrap();
+        attribute_indexes
+            .iter()
+            .map(|(attribute, index)| (attribute.clone(), index.kind()))
+            .sorted_by(|a, b| a.0 .0.cmp(&b.0 .0))
+            .collect()
+    }
+
+    fn remove_from_attribute_indexes(&self, keys: &HashSet<DefaultKey>) {
+        let mut attribute_indexes = self.attribute_indexes.write().unwrap();
+        for index in attribute_indexes.values_mut() {
+            index.remove_keys(keys);
+        }
+    }
+
+    /// Removes the events from the slotmap and from all the indices, returns the number of
+    /// removed events. The schema keeps the attributes of the removed events.
+    fn remove_events(&self, keys: &HashSet<DefaultKey>) -> usize {
+        let mut sm = self.sm.write().unwrap();
+        let removed = keys
+            .iter()
+            .filter_map(|key| sm.remove(*key).map(|event| (*key, event)))
+            .collect_vec();
+        if removed.is_empty() {
+            return 0;
+        }
+
+        let mut index_by_event_id = self.index_by_event_id.write().unwrap();
+        let mut global_index_ts = self.global_index_ts.write().unwrap();
+        let mut global_index_event_type_ts = self.global_index_event_type_ts.write().unwrap();
+        let mut index_by_entity_ts = self.index_by_entity_ts.write().unwrap();
+        let mut index_by_event_type_entity_ts = self.index_by_event_type_entity_ts.write().unwrap();
+        let mut index_by_entity_attribute_ts = self.index_by_entity_attribute_ts.write().unwrap();
+        let mut experiment_index_by_ts = self.experiment_index_by_ts.write().unwrap();
+        let mut experiment_index_by_entity_ts = self.experiment_index_by_entity_ts.write().unwrap();
+        let mut experiment_index_by_entity_event_type_ts = self
+            .experiment_index_by_entity_event_type_ts
+            .write()
+            .unwrap();
+        let mut attribute_indexes = self.attribute_indexes.write().unwrap();
+
+        for (key, event) in removed.iter() {
+            let (key, ts) = (*key, &event.event_time);
+            if let Some(event_id) = &event.event_id {
+                if index_by_event_id.get(event_id) == Some(&key) {
+                    index_by_event_id.remove(event_id);
+                }
+            }
+            let values = event.extract_attributes_values();
+            for (attribute, index) in attribute_indexes.iter_mut() {
+                if let Some(value) = values.get(attribute) {
+                    index.remove(value, ts, key);
+                }
+            }
+
+            // the same indices as on insert
+            if let Some(experiment_id) = &event.experiment_id {
+                remove_key_from(&mut experiment_index_by_ts, experiment_id, ts, key);
+                for entity in event.entities() {
+                    for index in [
+                        &mut experiment_index_by_entity_ts,
+                        &mut experiment_index_by_entity_event_type_ts,
+                    ] {
+                        if index
+                            .get_mut(experiment_id)
+                            .is_some_and(|index| remove_key_from(index, &entity, ts, key))
+                        {
+                            index.remove(experiment_id);
+                        }
+                    }
+                }
+            } else if !event.entities.is_empty() {
+                for entity in event.entities() {
+                    remove_key_from(&mut index_by_entity_ts, &entity, ts, key);
+                    if index_by_event_type_entity_ts
+                        .get_mut(&event.event_type)
+                        .is_some_and(|index| remove_key_from(index, &entity, ts, key))
+                    {
+                        index_by_event_type_entity_ts.remove(&event.event_type);
+                    }
+                    if let Some(entity_attributes) = index_by_entity_attribute_ts.get_mut(&entity) {
+                        for attribute_name in values.keys() {
+                            remove_key_from(
+                                entity_attributes,
+                                &(event.event_type.clone(), attribute_name.clone()),
+                                ts,
+                                key,
+                            );
+                        }
+                        if entity_attributes.is_empty() {
+                            index_by_entity_attribute_ts.remove(&entity);
+                        }
+                    }
+                }
+            } else {
+                remove_key(&mut global_index_ts, ts, key);
+                remove_key_from(&mut global_index_event_type_ts, &event.event_type, ts, key);
+            }
+        }
+        removed.len()
+    }
+
+    /// Removes the event without writing to the log, returns whether the event existed
+    pub(crate) fn remove_event_by_id(&self, event_id: &EventID) -> bool {
+        let key = self
+            .index_by_event_id
+            .read()
+            .unwrap()
+            .get(event_id)
+            .copied();
+        match key {
+            Some(key) => self.remove_events(&HashSet::from_iter([key])) > 0,
+            None => false,
+        }
+    }
+
+    /// Replaces the event with the same id without writing to the log
+    pub(crate) fn replace_event(&self, event: Event) -> Result<()> {
+        let event_id = event
+            .event_id
+            .as_ref()
+            .ok_or_else(|| anyhow!("Only events with an event_id can be upserted"))?;
+        let old_event = self.get_event_by_id(event_id);
+        self.remove_event_by_id(event_id);
+        if let Err(err) = self.insert_event(event) {
+            // the replaced event is restored so a failed upsert doesn't lose it
+            if let Some(old_event) = old_event {
+                self.insert_event((*old_event).clone())?;
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Removes the events of the entity, experiments included, without writing to the log
+    pub(crate) fn remove_entity_events(&self, entity: &Entity) -> usize {
+        let mut keys = HashSet::new();
+        if let Some(treemap) = self.index_by_entity_ts.read().unwrap().get(entity) {
+            keys.extend(treemap.values().flatten().copied());
+        }
+        for index in self.experiment_index_by_entity_ts.read().unwrap().values() {
+            if let Some(treemap) = index.get(entity) {
+                keys.extend(treemap.values().flatten().copied());
+            }
+        }
+        self.remove_events(&keys)
+    }
+
+    /// Removes the events older than the time to live of their event type without writing to
+    /// the log, returns the number of removed events
+    pub(crate) fn remove_expired_events(
+        &self,
+        policy: &RetentionPolicy,
+        now: &Timestamp,
+    ) -> Result<usize> {
+        let cutoffs = policy.cutoffs(now)?;
+        let keys: HashSet<DefaultKey> = self
+            .sm
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, event)| cutoffs.is_expired(event))
+            .map(|(key, _)| key)
+            .collect();
+        Ok(self.remove_events(&keys))
+    }
+
+    /// Writes the record to the log of a durable store before it is applied
+    fn log_record(&self, record: WalRecord) -> Result<MutexGuard<'_, Option<WriteAheadLog>>> {
+        let mut wal = self.wal.lock().unwrap();
+        if let Some(wal) = wal.as_mut() {
+            wal.append_record(&record)?;
+        }
+        Ok(wal)
+    }
+
+    /// Checks that the events can be inserted before they are logged, a logged event which
+    /// cannot be inserted would fail the replay of the log: the event ids must be new and
+    /// unique and the attribute types must agree with the schema
+    fn check_new_events(&self, events: &[Event]) -> Result<()> {
+        {
+            let index_by_event_id = self.index_by_event_id.read().unwrap();
+            let mut event_ids = HashSet::new();
+            for event_id in events.iter().filter_map(|event| event.event_id.as_ref()) {
+                if index_by_event_id.contains_key(event_id) || !event_ids.insert(event_id) {
+                    bail!("An event with the ID {} already exists.", event_id);
+                }
+            }
+        }
+        self.check_schema(events)
+    }
+
+    /// Checks that the attribute types of the events agree with the schema
+    fn check_schema(&self, events: &[Event]) -> Result<()> {
+        let schema = self.schema.read().unwrap();
+        let schema_registry = self.schema_registry.read().unwrap();
+        let mut widened_schema: HashMap<&EventType, HashMap<AttributeName, ValueType>> =
+            HashMap::new();
+        for event in events {
+            let event_type_schema = widened_schema
+                .entry(&event.event_type)
+                .or_insert_with(|| schema.get(&event.event_type.0).cloned().unwrap_or_default());
+            let declared = schema_registry.get(&event.event_type).is_some();
+            widen_event_type_schema(event_type_schema, event, declared)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the event against the declared schema of its event type before it is logged
+    fn validate_event(&self, event: Event) -> Result<Event> {
+        // checked under the read lock, the write lock is only taken to record warnings
+        let (event, warnings) = self.schema_registry.read().unwrap().check(event)?;
+        if !warnings.is_empty() {
+            self.schema_registry
+                .write()
+                .unwrap()
+                .record_warnings(warnings);
+        }
+        Ok(event)
+    }
+
+    /// Declared schemas of the event types
+    pub fn declared_schemas(&self) -> Vec<EventTypeSchema> {
+        let schema_registry = self.schema_registry.read().unwrap();
+        schema_registry
+            .schemas()
+            .cloned()
+            .sorted_by(|a, b| a.event_type.cmp(&b.event_type))
+            .collect()
+    }
+
     fn get_event_by_id(&self, event_id: &EventID) -> Option<Arc<Event>> {
         let index_by_event_id = self.index_by_event_id.read().unwrap();
         let sm = self.sm.read().unwrap();
@@ -317,6 +723,59 @@ impl MemoryEventStore {
             .collect()
     }
 
+    /// Merges the events from many indices into a single time ordered vector.
+    /// Events related to several entities are present in many indices so they are deduplicated.
+    fn extract_unique_events_from_treemaps<'a>(
+        &self,
+        sm_ref: &SlotMap<DefaultKey, Arc<Event>>,
+        interval: Option<&NaiveDateTimeInterval>,
+        treemaps: impl Iterator<Item = &'a TimeBTree>,
+        query_config: &QueryConfig,
+    ) -> Option<Vec<(Timestamp, Vec<Arc<Event>>)>> {
+        let mut seen = HashSet::new();
+        let mut timestamp_event_map: BTreeMap<Timestamp, Vec<Arc<Event>>> = BTreeMap::new();
+        for treemap in treemaps {
+            let entries = if let Some(interval) = interval {
+                // events on the observation date are only included when requested
+                let end_dt = if query_config.include_events_on_obs_date {
+                    interval.end_dt_safe()
+                } else {
+                    interval.end_dt_exclusive_safe()
+                };
+                treemap
+                    .range(interval.start_dt_safe()..=end_dt)
+                    .collect_vec()
+            } else {
+                treemap.iter().collect_vec()
+            };
+            for (ts, keys) in entries {
+                let events = keys
+                    .iter()
+                    .filter(|key| seen.insert(**key))
+                    .filter_map(|key| sm_ref.get(*key).cloned());
+                timestamp_event_map.entry(*ts).or_default().extend(events);
+            }
+        }
+        timestamp_event_map.retain(|_, events| !events.is_empty());
+
+        if timestamp_event_map.is_empty() {
+            None
+        } else {
+            Some(timestamp_event_map.into_iter().collect())
+        }
+    }
+
+    /// Updates the schema and the indices without writing the event to the log
+    pub(crate) fn insert_event(&self, event: Event) -> Result<()> {
+        self.update_schema(&event)?;
+        if let Some(ref experiment_id) = event.experiment_id {
+            self.insert_with_experiment_id(&event, experiment_id)?;
+        } else {
+            self.insert_without_experiment_id(&event)?;
+        }
+        Ok(())
+    }
+
     pub fn all_events_memory_store(&self) -> Result<Vec<Arc<Event>>> {
         let sm = self.sm.read().unwrap();
         Ok(sm.values().cloned().collect())
@@ -369,20 +828,144 @@ impl MemoryEventStore {
 
 impl EventStore for MemoryEventStore {
     fn insert(&self, event: Event) -> Result<()> {
-        self.update_schema(&event);
-        if let Some(ref experiment_id) = event.experiment_id {
-            self.insert_with_experiment_id(&event, experiment_id)?;
-        } else {
-            self.insert_without_experiment_id(&event)?;
+        let event = self.validate_event(event)?;
+        let mut wal = self.wal.lock().unwrap();
+        self.check_new_events(std::slice::from_ref(&event))?;
+        if let Some(wal) = wal.as_mut() {
+            wal.append(std::slice::from_ref(&event))?;
         }
-        Ok(())
+        self.insert_event(event)?;
+        self.compact_if_needed(&mut wal)
     }
 
     fn insert_batch(&self, events: Vec<Event>) -> Result<()> {
+        // the batch is a single record of the log so it is replayed completely or not at all
+        let events = events
+            .into_iter()
+            .map(|event| self.validate_event(event))
+            .collect::<Result<Vec<_>>>()?;
+        let mut wal = self.wal.lock().unwrap();
+        self.check_new_events(&events)?;
+        if let Some(wal) = wal.as_mut() {
+            wal.append(&events)?;
+        }
         for event in events.into_iter() {
-            self.insert(event)?;
+            self.insert_event(event)?;
         }
-        Ok(())
+        self.compact_if_needed(&mut wal)
+    }
+
+    fn declare_schema(&self, schema: EventTypeSchema) -> Result<()> {
+        // no events are inserted between the check and the declaration
+        let mut wal = self.wal.lock().unwrap();
+        // the stored events keep their types so the declared types must agree with them
+        let stored_schema = self.schema.read().unwrap();
+        if let Some(stored_types) = stored_schema.get(schema.event_type.as_str()) {
+            let sm = self.sm.read().unwrap();
+            let conflicts = schema
+                .attributes
+                .iter()
+                .filter_map(|(name, attribute)| {
+                    let name = AttributeName::new(name);
+                    let stored_type = stored_types.get(&name)?;
+                    // the stored values must widen to the declared type
+                    if widen_type(stored_type, &attribute.value_type).as_ref()
+                        != Some(&attribute.value_type)
+                    {
+                        return Some(format!("{} is {} in the stored events", name, stored_type));
+                    }
+                    // the stored strings read as dates must be dates
+                    if *stored_type == ValueType::Str && attribute.value_type != ValueType::Str {
+                        let invalid = sm
+                            .values()
+                            .filter(|event| event.event_type.0 == schema.event_type.as_str())
+                            .filter_map(|event| event.attrs.as_ref()?.get(&name))
+                            .find(|value| {
+                                matches!(value, Value::Str(_))
+                                    && widen_value(value, &attribute.value_type).is_none()
+                            })?;
+                        return Some(format!(
+                            "{} is {} in the stored events",
+                            name,
+                            invalid.to_string()
+                        ));
+                    }
+                    None
+                })
+                .sorted()
+                .collect_vec();
+            if !conflicts.is_empty() {
+                return Err(FeatureExpressError::IngestionError(format!(
+                    "Cannot declare the schema of {}: {}",
+                    schema.event_type,
+                    conflicts.join(", ")
+                ))
+                .into());
+            }
+        }
+        drop(stored_schema);
+        if let Some(wal) = wal.as_mut() {
+            wal.append_record(&WalRecord::DeclareSchema(schema.clone()))?;
+        }
+        self.schema_registry.write().unwrap().declare(schema);
+        self.compact_if_needed(&mut wal)
+    }
+
+    fn schema_warnings(&self) -> Vec<SchemaViolation> {
+        self.schema_registry.read().unwrap().warnings().to_vec()
+    }
+
+    fn schema_versions(&self, event_type: &EventType) -> Vec<SchemaVersion> {
+        self.schema_versions
+            .read()
+            .unwrap()
+            .get(event_type)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn delete_event(&self, event_id: &EventID) -> Result<bool> {
+        let mut wal = self.log_record(WalRecord::DeleteEvent(event_id.clone()))?;
+        let deleted = self.remove_event_by_id(event_id);
+        self.compact_if_needed(&mut wal)?;
+        Ok(deleted)
+    }
+
+    fn upsert(&self, event: Event) -> Result<()> {
+        if event.event_id.is_none() {
+            bail!("Only events with an event_id can be upserted");
+        }
+        let event = self.validate_event(event)?;
+        let mut wal = self.wal.lock().unwrap();
+        // the event is checked before it is logged like the inserted events
+        self.check_schema(std::slice::from_ref(&event))?;
+        if let Some(wal) = wal.as_mut() {
+            wal.append_record(&WalRecord::Upsert(SnapshotEvent::from(&event)))?;
+        }
+        self.replace_event(event)?;
+        self.compact_if_needed(&mut wal)
+    }
+
+    fn delete_entity(&self, entity: &Entity) -> Result<usize> {
+        let mut wal = self.log_record(WalRecord::DeleteEntity(entity.clone()))?;
+        let n_deleted = self.remove_entity_events(entity);
+        // the erased events stay in the snapshot and the log until they are rewritten
+        if let Some(wal) = wal.as_mut() {
+            self.compact_log(wal)?;
+        }
+        Ok(n_deleted)
+    }
+
+    fn apply_retention(&self, policy: &RetentionPolicy, now: &Timestamp) -> Result<usize> {
+        // an invalid policy is rejected before it is logged
+        policy.cutoffs(now)?;
+        let mut wal = self.log_record(WalRecord::Retention(
+            policy.clone(),
+            SnapshotTimestamp::from(now),
+        ))?;
+        let n_deleted = self.remove_expired_events(policy, now)?;
+        self.compact_if_needed(&mut wal)?;
+        Ok(n_deleted)
     }
 
     fn get_entities(&self, experiment_id: &Option<SmallString>) -> Vec<Entity> {
@@ -410,34 +993,41 @@ impl EventStore for MemoryEventStore {
         }
     }
 
-    fn update_schema(&self, event: &Event) {
+    fn update_schema(&self, event: &Event) -> Result<()> {
         let mut schema = self.schema.write().unwrap();
 
         let event_type = &event.event_type;
-        if let Some(event_type_schema) = schema.get(&event_type.0) {
-            for (attr_name, new_attr_value) in event.extract_attributes_values() {
-                let new_attr_value_type: ValueType = new_attr_value.clone().into();
-                if let Some(value_type) = event_type_schema.get(&attr_name) {
-                    if new_attr_value_type != *value_type {
-                        panic!("New attribute value {:?} ({:?}) type doesn't match existing schema value type {:?}", new_attr_value, new_attr_value_type, value_type);
-                    }
-                }
-            }
-        }
-
-        // update the schema
+        let declared = self
+            .schema_registry
+            .read()
+            .unwrap()
+            .get(event_type)
+            .is_some();
+        let new_event_type = !schema.contains_key(&event_type.0);
         let event_type_entry = schema.entry(event_type.0.clone()).or_default();
-        let mut attr_value_types = self.attr_value_types.write().unwrap();
+        let changed = widen_event_type_schema(event_type_entry, event, declared)? || new_event_type;
 
+        let mut attr_value_types = self.attr_value_types.write().unwrap();
         for (attr_name, value_new) in event.extract_attributes_values() {
-            let value_type_new: ValueType = value_new.into();
-            event_type_entry.insert(attr_name.clone(), value_type_new.clone());
-
             attr_value_types
-                .entry(attr_name.clone())
+                .entry(attr_name)
                 .or_default()
-                .insert(value_type_new);
+                .insert(value_new.into());
+        }
+
+        if changed {
+            let mut schema_versions = self.schema_versions.write().unwrap();
+            let versions = schema_versions.entry(event_type.clone()).or_default();
+            versions.push(SchemaVersion {
+                version: versions.len() + 1,
+                since: event.event_time,
+                attributes: event_type_entry
+                    .iter()
+                    .map(|(name, value_type)| (name.0.to_string(), value_type.clone()))
+                    .collect(),
+            });
         }
+        Ok(())
     }
 
     fn query_entity_event_type(
@@ -743,6 +1333,148 @@ impl EventStore for MemoryEventStore {
         }
     }
 
+    fn query_global_events(
+        &self,
+        event_type: Option<&EventType>,
+        interval: &NaiveDateTimeInterval,
+        query_config: &QueryConfig,
+    ) -> Option<Vec<(Timestamp, Vec<Arc<Event>>)>> {
+        let sm = self.sm.read().unwrap();
+        match event_type {
+            Some(event_type) => {
+                let index_by_event_type_entity_ts =
+                    self.index_by_event_type_entity_ts.read().unwrap();
+                let global_index_event_type_ts = self.global_index_event_type_ts.read().unwrap();
+                let treemaps = index_by_event_type_entity_ts
+                    .get(event_type)
+                    .into_iter()
+                    .flat_map(|entity_map| entity_map.values())
+                    .chain(global_index_event_type_ts.get(event_type));
+                self.extract_unique_events_from_treemaps(
+                    &sm,
+                    Some(interval),
+                    treemaps,
+                    query_config,
+                )
+            }
+            None => {
+                let index_by_entity_ts = self.index_by_entity_ts.read().unwrap();
+                let global_index_ts = self.global_index_ts.read().unwrap();
+                let treemaps = index_by_entity_ts
+                    .values()
+                    .chain(std::iter::once(&*global_index_ts));
+                self.extract_unique_events_from_treemaps(
+                    &sm,
+                    Some(interval),
+                    treemaps,
+                    query_config,
+                )
+            }
+        }
+    }
+
+    fn query_attribute_index(
+        &self,
+        filters: &[AttributeFilter],
+        scope: &IndexScope,
+        event_type: Option<&EventType>,
+        interval: &NaiveDateTimeInterval,
+        query_config: &QueryConfig,
+    ) -> Option<Vec<(Timestamp, Vec<Arc<Event>>)>> {
+        let sm = self.sm.read().unwrap();
+        let attribute_indexes = self.attribute_indexes.read().unwrap();
+        let treemaps = filters.iter().find_map(|filter| {
+            attribute_indexes
+                .get(filter.attribute())
+                .and_then(|index| index.lookup(filter))
+        })?;
+
+        // the same bounds as the queries of the scope without an index
+        let (start_dt, end_dt) = match (scope, query_config.include_events_on_obs_date) {
+            (IndexScope::Entities { .. }, true) => {
+                (interval.start_dt_exclusive_safe(), interval.end_dt_safe())
+            }
+            (IndexScope::Entities { .. }, false) | (IndexScope::All, false) => {
+                (interval.start_dt_safe(), interval.end_dt_exclusive_safe())
+            }
+            (IndexScope::All, true) => (interval.start_dt_safe(), interval.end_dt_safe()),
+        };
+        if start_dt > end_dt {
+            return Some(vec![]);
+        }
+
+        let mut seen = HashSet::new();
+        let mut timestamp_event_map: BTreeMap<Timestamp, Vec<(DefaultKey, Arc<Event>)>> =
+            BTreeMap::new();
+        for treemap in treemaps {
+            for (ts, keys) in treemap.range(start_dt..=end_dt) {
+                for key in keys.iter().filter(|key| seen.insert(**key)) {
+                    let event = match sm.get(*key) {
+                        Some(event) => event,
+                        None => continue,
+                    };
+                    if event_type.is_some_and(|event_type| event.event_type != *event_type)
+                        || !scope.contains(event)
+                    {
+                        continue;
+                    }
+                    timestamp_event_map
+                        .entry(*ts)
+                        .or_default()
+                        .push((*key, event.clone()));
+                }
+            }
+        }
+        Some(
+            timestamp_event_map
+                .into_iter()
+                .map(|(ts, mut events)| {
+                    events.sort_by_key(|(key, _)| *key);
+                    (ts, events.into_iter().map(|(_, event)| event).collect())
+                })
+                .collect(),
+        )
+    }
+
+    fn query_attribute_as_of(
+        &self,
+        entities: &Entities,
+        event_type: &EventType,
+        attribute: &AttributeName,
+        ts: &Timestamp,
+        query_config: &QueryConfig,
+    ) -> Option<(Timestamp, Value)> {
+        let sm = self.sm.read().unwrap();
+        let index_by_entity_attribute_ts = self.index_by_entity_attribute_ts.read().unwrap();
+        let index_key = (event_type.clone(), attribute.clone());
+
+        // the latest version across the entities, events on the observation date
+        // are visible only when they are explicitly included
+        entities
+            .iter()
+            .filter_map(|(entity_type, entity_id)| {
+                let treemap = index_by_entity_attribute_ts
+                    .get(&Entity {
+                        typ: entity_type.clone(),
+                        id: entity_id.0.clone(),
+                    })?
+                    .get(&index_key)?;
+                if query_config.include_events_on_obs_date {
+                    treemap.range(..=*ts).next_back()
+                } else {
+                    treemap.range(..*ts).next_back()
+                }
+            })
+            .max_by_key(|(version_ts, _)| **version_ts)
+            .and_then(|(version_ts, keys)| Some((*version_ts, sm.get(*keys.last()?)?)))
+            .and_then(|(version_ts, event)| {
+                Some((
+                    version_ts,
+                    event.extract_attributes_values().remove(attribute)?,
+                ))
+            })
+    }
+
     fn filter_events(
         &self,
         condition: &Expr,
@@ -763,6 +1495,7 @@ impl EventStore for MemoryEventStore {
             event_types: vec![],
             event: None,
             event_on_obs_date: None,
+            agg_cache: None,
         };
         let sm = self.sm.read().unwrap();
         let mut results = Vec::new();
@@ -819,12 +1552,21 @@ impl EventStore for MemoryEventStore {
         let mut index_by_entity_event_type_ts = self.index_by_event_type_entity_ts.write().unwrap();
         index_by_entity_event_type_ts.clear();
 
+        let mut index_by_entity_attribute_ts = self.index_by_entity_attribute_ts.write().unwrap();
+        index_by_entity_attribute_ts.clear();
+
         let mut schema = self.schema.write().unwrap();
         schema.clear();
 
         let mut attr_value_types = self.attr_value_types.write().unwrap();
         attr_value_types.clear();
 
+        // the declared indices are kept
+        let mut attribute_indexes = self.attribute_indexes.write().unwrap();
+        for index in attribute_indexes.values_mut() {
+            index.clear();
+        }
+
         let mut experiment_index_by_ts = self.experiment_index_by_ts.write().unwrap();
         experiment_index_by_ts.clear();
 
@@ -844,13 +1586,16 @@ impl EventStore for MemoryEventStore {
         let mut experiment_index_by_ts = self.experiment_index_by_ts.write().unwrap();
 
         // clean the slotmap
+        let mut removed = HashSet::new();
         for (_, hm) in experiment_index_by_ts.iter() {
             for keys in hm.values() {
                 for key in keys {
                     sm.remove(*key);
+                    removed.insert(*key);
                 }
             }
         }
+        self.remove_from_attribute_indexes(&removed);
 
         experiment_index_by_ts.clear();
 
@@ -871,13 +1616,16 @@ impl EventStore for MemoryEventStore {
 
         // clean the slotmap
         let hm = experiment_index_by_ts.get(&experiment_id);
+        let mut removed = HashSet::new();
         if let Some(hm) = hm {
             for keys in hm.values() {
                 for key in keys {
                     sm.remove(*key);
+                    removed.insert(*key);
                 }
             }
         }
+        self.remove_from_attribute_indexes(&removed);
 
         experiment_index_by_ts.remove(experiment_id.clone().as_str());
 
@@ -906,10 +1654,10 @@ impl EventStore for MemoryEventStore {
 mod test {
     use std::str::FromStr;
 
-    use chrono::{NaiveDateTime, Utc};
+    use chrono::{NaiveDate, NaiveDateTime, Timelike, Utc};
 
     use crate::datetime_utils::parse_utc_from_str;
-    use crate::event::EntityType;
+    use crate::event::{AttributeKey, EntityType};
     use crate::event_index::{EventContext, EventScopeConfig, RawQuery};
     use crate::features::Features;
     use crate::obs_dates::{Fixed, ObservationDatesConfig};
@@ -954,7 +1702,6 @@ mod test {
     }
 
     #[test]
-    #[should_panic]
     fn test_schema_conflict() {
         let event_db = MemoryEventStore::default();
 
@@ -968,16 +1715,29 @@ mod test {
 
         event_db.insert(event);
 
-        // creating an event with a diffent attribute data type
+        // creating an event with a diffent attribute data type which is not a wider type
         let event = Event {
             event_type: EventType("test".into()),
             event_time: Utc::now().naive_utc(),
             entities: btreemap!["".into() => "1".into()],
-            attrs: Some(hashmap![a!("a") => Value::Num(1.0)]),
+            attrs: Some(hashmap![a!("a") => Value::Str("1".into())]),
             ..Default::default()
         };
 
-        event_db.insert(event);
+        let err = event_db.insert(event).unwrap_err();
+        match err.downcast_ref::<FeatureExpressError>() {
+            Some(FeatureExpressError::IngestionError(message)) => {
+                assert!(
+                    message.contains("attribute a of test is Str"),
+                    "{}",
+                    message
+                );
+                assert!(message.contains("have Int"), "{}", message);
+            }
+            _ => panic!("Unexpected error {:?}", err),
+        }
+        assert_eq!(event_db.get_n_events(), 1);
+        assert_eq!(event_db.get_schema()["test"][&a!("a")], ValueType::Int);
     }
 
     #[test]
@@ -1224,6 +1984,119 @@ mod test {
         assert_eq!(values, vec![Value::Num(1200.0), Value::Num(1300.0)]);
     }
 
+    #[test]
+    pub fn test_global_aggregation() {
+        let mut store = create_store_with_sample_events();
+        let mut entity_types = HashSet::new();
+        entity_types.insert(EntityType(SmallString::from("entity_type_a")));
+        let obs_dates = ObservationDatesConfig::Fixed(Fixed::new_from_str_vec(
+            entity_types,
+            vec!["2020-01-04T00:00:00".into()],
+        ));
+        let features_def = vec![
+            "SUM(pressure) OVER past as f".to_string(),
+            "SUM(pressure) OVER past GLOBAL as f_global".to_string(),
+            "SUM(pressure) OVER past GLOBAL WHERE event_type = 'test' as f_global_type".to_string(),
+            "SUM(pressure) OVER last 2 days FROM * as f_global_2d".to_string(),
+        ];
+        let entity_query =
+            EventScopeConfig::RelatedEntitiesEvents(vec![EntityType("entity_type_a".into())]);
+        let features = store
+            .extract_features_from_expr(
+                &obs_dates,
+                entity_query,
+                RawQuery::VecExpr(features_def),
+                &QueryConfig::default(),
+                None,
+                None,
+            )
+            .unwrap();
+        let mut values = features.get("f").unwrap().clone();
+        values.sort();
+        assert_eq!(values, vec![Value::Num(300.0), Value::Num(1200.0)]);
+        assert_eq!(
+            features.get("f_global").unwrap().clone(),
+            vec![Value::Num(1500.0); 2]
+        );
+        assert_eq!(
+            features.get("f_global_type").unwrap().clone(),
+            vec![Value::Num(1500.0); 2]
+        );
+        assert_eq!(
+            features.get("f_global_2d").unwrap().clone(),
+            vec![Value::Num(500.0); 2]
+        );
+    }
+
+    #[test]
+    pub fn test_asof_lookup() {
+        let mut store = EventContext::default();
+        let profile_updates = [
+            ("1", "2020-01-01T00:00:00+00:00", "PL"),
+            ("2", "2020-01-05T00:00:00+00:00", "DE"),
+        ];
+        for (event_id, event_time, country) in profile_updates {
+            store
+                .new_event(Event {
+                    event_type: EventType("profile".into()),
+                    event_time: parse_utc_from_str(event_time),
+                    entities: btreemap!["user".into() => "a".into()],
+                    event_id: Some(event_id.into()),
+                    experiment_id: None,
+                    attrs: Some(hashmap![a!("country") => Value::Str(country.into())]),
+                })
+                .unwrap();
+        }
+        let mut entity_types = HashSet::new();
+        entity_types.insert(EntityType(SmallString::from("user")));
+        let obs_dates = ObservationDatesConfig::Fixed(Fixed::new_from_str_vec(
+            entity_types,
+            vec![
+                "2019-12-31T00:00:00".into(),
+                "2020-01-03T00:00:00".into(),
+                "2020-01-05T00:00:00".into(),
+                "2020-01-06T00:00:00".into(),
+            ],
+        ));
+        let extract = |store: &mut EventContext, query_config: &QueryConfig| {
+            store
+                .extract_features_from_expr(
+                    &obs_dates,
+                    EventScopeConfig::RelatedEntitiesEvents(vec![EntityType("user".into())]),
+                    RawQuery::VecExpr(vec!["asof(profile.country) as country".into()]),
+                    query_config,
+                    None,
+                    None,
+                )
+                .unwrap()
+                .remove("country")
+                .unwrap()
+        };
+
+        assert_eq!(
+            extract(&mut store, &QueryConfig::default()),
+            vec![
+                Value::None,
+                Value::Str("PL".into()),
+                Value::Str("PL".into()),
+                Value::Str("DE".into()),
+            ]
+        );
+        let query_config = QueryConfig {
+            include_events_on_obs_date: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            extract(&mut store, &query_config),
+            vec![
+                Value::None,
+                Value::Str("PL".into()),
+                Value::Str("DE".into()),
+                Value::Str("DE".into()),
+            ]
+        );
+    }
+
     fn create_store_with_sample_events() -> EventContext {
         let mut store = EventContext::default();
 
@@ -1270,6 +2143,11 @@ mod test {
         let query_config = QueryConfig {
             parallel: false,
             include_events_on_obs_date: true,
+            strict_leakage: false,
+            business_calendar: None,
+            time_zone: None,
+            time_zone_attribute: None,
+            expanded_keys: Default::default(),
         };
 
         // Define the parameters for the query
@@ -1301,6 +2179,11 @@ mod test {
         let query_config = QueryConfig {
             parallel: false,
             include_events_on_obs_date: true,
+            strict_leakage: false,
+            business_calendar: None,
+            time_zone: None,
+            time_zone_attribute: None,
+            expanded_keys: Default::default(),
         };
         // Define the parameters for the query
         let interval = NaiveDateTimeInterval {
@@ -1322,4 +2205,494 @@ mod test {
             assert!(interval.contains(&timestamp, true)); // Timestamps should be within the interval
         }
     }
+
+    #[test]
+    fn test_all_events_bounds() {
+        let store = create_store_with_sample_events();
+        store
+            .event_store
+            .insert(Event {
+                event_type: EventType("test".into()),
+                event_time: parse_utc_from_str("2020-01-02T12:00:00+00:00"),
+                event_id: Some("no_entity".into()),
+                attrs: Some(hashmap![a!("pressure") => Value::Num(1.0)]),
+                ..Default::default()
+            })
+            .unwrap();
+        let query_config = QueryConfig::default();
+        assert!(!query_config.include_events_on_obs_date);
+        let interval = NaiveDateTimeInterval {
+            start_dt: Some(NaiveDateTime::from_str("2020-01-01T00:00:00").unwrap()),
+            end_dt: Some(NaiveDateTime::from_str("2020-01-03T00:00:00").unwrap()),
+        };
+        let ids = |events: Option<Vec<(Timestamp, Vec<Arc<Event>>)>>| {
+            events
+                .unwrap()
+                .into_iter()
+                .flat_map(|(_, events)| events)
+                .map(|event| event.event_id.clone().unwrap().to_string())
+                .collect_vec()
+        };
+        let event_type = EventType("test".into());
+
+        // the queries of all the events read the entity indices and include the end of the
+        // interval whatever the query config
+        let all_events = vec!["1", "2", "3"];
+        assert_eq!(
+            ids(store.event_store.query_interval(&interval, &query_config)),
+            all_events
+        );
+        assert_eq!(
+            ids(store
+                .event_store
+                .query_event_type(&event_type, &query_config, Some(&interval))),
+            all_events
+        );
+        // the global events include the events without entities and follow the query config
+        let global_events = vec!["1", "2", "no_entity"];
+        assert_eq!(
+            ids(store
+                .event_store
+                .query_global_events(None, &interval, &query_config)),
+            global_events
+        );
+        assert_eq!(
+            ids(store
+                .event_store
+                .query_global_events(Some(&event_type), &interval, &query_config)),
+            global_events
+        );
+    }
+
+    #[test]
+    fn test_attribute_index_matches_scan() {
+        let new_context = |with_index: bool| {
+            let mut context = EventContext::default();
+            for i in 0..40 {
+                if with_index && i == 20 {
+                    // half of the events are indexed when the index is created
+                    context
+                        .create_attribute_index("category", AttributeIndexKind::Hash)
+                        .unwrap();
+                    context
+                        .create_attribute_index("amount", AttributeIndexKind::BTree)
+                        .unwrap();
+                }
+                let event = Event {
+                    event_type: EventType(if i % 4 == 0 { "refund" } else { "order" }.into()),
+                    event_time: parse_utc_from_str("2020-01-01T00:00:00+00:00")
+                        + chrono::Duration::hours(6 * i),
+                    entities: if i % 10 == 9 {
+                        btreemap![]
+                    } else {
+                        btreemap!["user".into() => format!("user_{}", i % 3).into()]
+                    },
+                    event_id: Some(format!("{}", i).into()),
+                    experiment_id: if i % 7 == 6 {
+                        Some("experiment_1".into())
+                    } else {
+                        None
+                    },
+                    attrs: Some(hashmap![
+                        a!("category") => Value::Str(format!("category_{}", i % 5).into()),
+                        a!("amount") => Value::Int((i * 13 % 50) as crate::types::INT)
+                    ]),
+                };
+                context.new_event(event).unwrap();
+            }
+            context
+        };
+        let mut scanned = new_context(false);
+        let mut indexed = new_context(true);
+
+        let mut entity_types = HashSet::new();
+        entity_types.insert(EntityType("user".into()));
+        let obs_dates = ObservationDatesConfig::Fixed(Fixed::new_from_str_vec(
+            entity_types,
+            vec!["2020-01-05T00:00:00".into(), "2020-01-11T00:00:00".into()],
+        ));
+        let features_def = vec![
+            "count(*) over past where category = 'category_1' as eq".to_string(),
+            "sum(amount) over last 5 days where category in ('category_0', 'category_3') as in_"
+                .to_string(),
+            "count(*) over past where amount >= 10 and amount < 30 as range".to_string(),
+            "count(*) over past where event_type = 'order' and amount > 40 as event_type_range"
+                .to_string(),
+            "count(*) over past global where category = 'category_2' as global".to_string(),
+        ];
+        for experiment_id in [None, Some("experiment_1".into())] {
+            let extract = |context: &mut EventContext| {
+                context
+                    .extract_features_from_expr(
+                        &obs_dates,
+                        EventScopeConfig::RelatedEntitiesEvents(vec![EntityType("user".into())]),
+                        RawQuery::VecExpr(features_def.clone()),
+                        &QueryConfig::default(),
+                        experiment_id.clone(),
+                        None,
+                    )
+                    .unwrap()
+            };
+            assert_eq!(extract(&mut indexed), extract(&mut scanned));
+        }
+
+        let interval = NaiveDateTimeInterval {
+            start_dt: Some(NaiveDateTime::from_str("2020-01-01T00:00:00").unwrap()),
+            end_dt: Some(NaiveDateTime::from_str("2020-01-11T00:00:00").unwrap()),
+        };
+        let filters = [AttributeFilter::Eq(
+            a!("category"),
+            Value::Str("category_1".into()),
+        )];
+        let n_events = |context: &EventContext| {
+            context
+                .event_store
+                .query_attribute_index(
+                    &filters,
+                    &IndexScope::All,
+                    None,
+                    &interval,
+                    &QueryConfig::default(),
+                )
+                .map(|events| events.iter().map(|(_, events)| events.len()).sum::<usize>())
+        };
+        assert_eq!(n_events(&scanned), None);
+        assert_eq!(n_events(&indexed), Some(7));
+
+        // events of flushed experiments are removed from the indices
+        indexed.event_store.flush_experiments();
+        assert_eq!(n_events(&indexed), Some(7));
+        indexed.event_store.flush();
+        assert_eq!(n_events(&indexed), Some(0));
+    }
+
+    #[test]
+    fn test_deletes_keep_indices_consistent() {
+        let event = |i: usize| Event {
+            event_type: EventType(if i % 3 == 0 { "click" } else { "order" }.into()),
+            event_time: parse_utc_from_str("2020-01-01T00:00:00+00:00")
+                + chrono::Duration::days(i as i64),
+            entities: if i % 8 == 7 {
+                btreemap![]
+            } else {
+                btreemap!["user".into() => format!("user_{}", i % 4).into()]
+            },
+            event_id: Some(format!("{}", i).into()),
+            experiment_id: if i % 5 == 4 {
+                Some("experiment_1".into())
+            } else {
+                None
+            },
+            attrs: Some(hashmap![a!("amount") => Value::Int(i as crate::types::INT)]),
+        };
+        let store = MemoryEventStore::new();
+        store
+            .create_attribute_index(&a!("amount"), AttributeIndexKind::BTree)
+            .unwrap();
+        for i in 0..40 {
+            store.insert(event(i)).unwrap();
+        }
+
+        assert!(store.delete_event(&"10".into()).unwrap());
+        assert!(!store.delete_event(&"10".into()).unwrap());
+        let mut updated = event(11);
+        updated.event_time = parse_utc_from_str("2020-03-01T00:00:00+00:00");
+        updated.attrs = Some(hashmap![a!("amount") => Value::Int(1000)]);
+        store.upsert(updated.clone()).unwrap();
+        store.upsert(event(100)).unwrap();
+        assert!(store.upsert(Event::default()).is_err());
+        let user_1 = Entity {
+            typ: EntityType("user".into()),
+            id: "user_1".into(),
+        };
+        assert_eq!(store.delete_entity(&user_1).unwrap(), 10);
+        let policy: RetentionPolicy =
+            serde_json::from_str(r#"{"ttl": {"click": {"date_part": "Day", "nth": 20}}}"#).unwrap();
+        let now = parse_utc_from_str("2020-02-01T00:00:00+00:00");
+        assert_eq!(store.apply_retention(&policy, &now).unwrap(), 3);
+
+        // a store with only the remaining events
+        let expected = MemoryEventStore::new();
+        expected
+            .create_attribute_index(&a!("amount"), AttributeIndexKind::BTree)
+            .unwrap();
+        let cutoffs = policy.cutoffs(&now).unwrap();
+        for i in (0..40).chain([100]) {
+            let event = if i == 11 { updated.clone() } else { event(i) };
+            if i == 10 || event.entities.values().any(|id| id.0 == "user_1") {
+                continue;
+            }
+            if !cutoffs.is_expired(&event) {
+                expected.insert(event).unwrap();
+            }
+        }
+        assert_eq!(store.get_n_events(), expected.get_n_events());
+
+        let ids = |events: Option<Vec<(Timestamp, Vec<Arc<Event>>)>>| {
+            events
+                .unwrap_or_default()
+                .into_iter()
+                .flat_map(|(_, events)| events.into_iter().map(|e| e.event_id.clone()))
+                .collect_vec()
+        };
+        let query_config = QueryConfig::default();
+        let interval = NaiveDateTimeInterval {
+            start_dt: Some(NaiveDateTime::from_str("2019-01-01T00:00:00").unwrap()),
+            end_dt: Some(NaiveDateTime::from_str("2021-01-01T00:00:00").unwrap()),
+        };
+        for experiment_id in [None, Some(SmallString::from("experiment_1"))] {
+            assert_eq!(
+                store
+                    .get_entities(&experiment_id)
+                    .into_iter()
+                    .sorted()
+                    .collect_vec(),
+                expected
+                    .get_entities(&experiment_id)
+                    .into_iter()
+                    .sorted()
+                    .collect_vec()
+            );
+            for user in 0..4 {
+                let entities = btreemap!["user".into() => format!("user_{}", user).into()];
+                let query = |store: &MemoryEventStore| {
+                    ids(store.query_entity_interval(
+                        &entities,
+                        &interval,
+                        &query_config,
+                        &experiment_id,
+                    ))
+                };
+                assert_eq!(query(&store), query(&expected));
+                let query = |store: &MemoryEventStore| {
+                    ids(store.query_entity_event_type(
+                        &entities,
+                        &EventType("order".into()),
+                        &interval,
+                        &query_config,
+                        &experiment_id,
+                    ))
+                };
+                assert_eq!(query(&store), query(&expected));
+            }
+        }
+        for event_type in ["click", "order"] {
+            let query = |store: &MemoryEventStore| {
+                ids(store.query_event_type(
+                    &EventType(event_type.into()),
+                    &query_config,
+                    Some(&interval),
+                ))
+            };
+            assert_eq!(query(&store), query(&expected));
+        }
+        let query = |store: &MemoryEventStore| {
+            ids(store.query_attribute_index(
+                &[AttributeFilter::Range(
+                    a!("amount"),
+                    std::ops::Bound::Included(Value::Int(5)),
+                    std::ops::Bound::Unbounded,
+                )],
+                &IndexScope::All,
+                None,
+                &interval,
+                &query_config,
+            ))
+        };
+        assert_eq!(query(&store), query(&expected));
+        assert!(store.get_event_by_id(&"10".into()).is_none());
+        assert_eq!(
+            store.get_event_by_id(&"11".into()).unwrap().event_time,
+            updated.event_time
+        );
+        // the emptied indices are dropped
+        assert!(!store
+            .index_by_entity_ts
+            .read()
+            .unwrap()
+            .contains_key(&user_1));
+        assert!(!store
+            .index_by_entity_attribute_ts
+            .read()
+            .unwrap()
+            .contains_key(&user_1));
+    }
+
+    #[test]
+    fn test_declared_schema_on_insert() {
+        let event = |id: &str, amount: Value| Event {
+            event_type: EventType("order".into()),
+            event_time: Utc::now().naive_utc(),
+            entities: btreemap!["user".into() => "1".into()],
+            event_id: Some(id.into()),
+            attrs: Some(hashmap![a!("amount") => amount]),
+            ..Default::default()
+        };
+        let schema = |mode: &str| -> EventTypeSchema {
+            serde_json::from_str(&format!(
+                r#"{{"event_type": "order", "attributes": {{"amount": {{"value_type": "Num", "required": true}}}}, "mode": "{}"}}"#,
+                mode
+            ))
+            .unwrap()
+        };
+        let store = MemoryEventStore::new();
+        store.declare_schema(schema("Strict")).unwrap();
+        store.insert(event("1", Value::Num(1.5))).unwrap();
+        let err = store
+            .insert(event("2", Value::Str("1".into())))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FeatureExpressError>(),
+            Some(FeatureExpressError::IngestionError(_))
+        ));
+        // a rejected event rejects the whole batch
+        assert!(store
+            .insert_batch(vec![
+                event("3", Value::Num(2.0)),
+                event("4", Value::Bool(true))
+            ])
+            .is_err());
+        assert_eq!(store.get_n_events(), 1);
+
+        store.declare_schema(schema("Coerce")).unwrap();
+        store.insert(event("2", Value::Str("1".into()))).unwrap();
+        assert_eq!(
+            store.get_event_by_id(&"2".into()).unwrap().attrs,
+            Some(hashmap![a!("amount") => Value::Num(1.0)])
+        );
+        assert_eq!(
+            store.get_attribute_value_type(&a!("amount")),
+            Some(HashSet::from_iter([ValueType::Num]))
+        );
+
+        // the event is inserted with a warning instead of a schema conflict
+        store.declare_schema(schema("Warn")).unwrap();
+        store
+            .insert(event("3", Value::Str("a lot".into())))
+            .unwrap();
+        assert_eq!(store.get_n_events(), 3);
+        assert_eq!(store.schema_warnings().len(), 1);
+        assert_eq!(store.get_schema()["order"][&a!("amount")], ValueType::Num);
+
+        // the declared types must agree with the stored events
+        let err = store
+            .declare_schema(
+                serde_json::from_str(
+                    r#"{"event_type": "order", "attributes": {"amount": {"value_type": "Int"}}}"#,
+                )
+                .unwrap(),
+            )
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("amount is Num in the stored events"));
+    }
+
+    #[test]
+    fn test_schema_evolution() {
+        let event = |hour: u32, attrs: HashMap<AttributeName, Value>| Event {
+            event_type: EventType("order".into()),
+            event_time: NaiveDate::from_ymd_opt(2023, 1, 1)
+                .unwrap()
+                .and_hms_opt(hour, 0, 0)
+                .unwrap(),
+            entities: btreemap!["user".into() => "1".into()],
+            attrs: Some(attrs),
+            ..Default::default()
+        };
+        let store = MemoryEventStore::new();
+        store
+            .insert(event(0, hashmap![a!("price") => Value::Int(10)]))
+            .unwrap();
+        store
+            .insert(event(1, hashmap![a!("price") => Value::Num(10.5)]))
+            .unwrap();
+        // narrower values don't change the schema
+        store
+            .insert(event(2, hashmap![a!("price") => Value::Bool(true)]))
+            .unwrap();
+        store
+            .insert(event(
+                3,
+                hashmap![a!("price") => Value::Int(3), a!("paid") => Value::Date(NaiveDate::from_ymd_opt(2023, 1, 2).unwrap())],
+            ))
+            .unwrap();
+        // strings which are dates are stored in a date attribute
+        store
+            .insert(event(
+                4,
+                hashmap![a!("paid") => Value::Str("2023-01-01".into())],
+            ))
+            .unwrap();
+        assert!(store
+            .insert(event(
+                5,
+                hashmap![a!("paid") => Value::Str("unpaid".into())]
+            ))
+            .is_err());
+        // the stored strings are not checked so a string attribute doesn't widen to a date
+        store
+            .insert(event(5, hashmap![a!("note") => Value::Str("late".into())]))
+            .unwrap();
+        assert!(store
+            .insert(event(
+                6,
+                hashmap![a!("note") => Value::Date(NaiveDate::from_ymd_opt(2023, 1, 2).unwrap())],
+            ))
+            .is_err());
+
+        let versions = store.schema_versions(&EventType("order".into()));
+        assert_eq!(
+            versions
+                .iter()
+                .map(|version| (
+                    version.version,
+                    version.since.hour(),
+                    version.attributes.get("price").cloned(),
+                    version.attributes.get("paid").cloned()
+                ))
+                .collect_vec(),
+            vec![
+                (1, 0, Some(ValueType::Int), None),
+                (2, 1, Some(ValueType::Num), None),
+                (3, 3, Some(ValueType::Num), Some(ValueType::Date)),
+                (4, 5, Some(ValueType::Num), Some(ValueType::Date)),
+            ]
+        );
+        assert_eq!(store.get_schema()["order"][&a!("note")], ValueType::Str);
+        let declared = |attributes: &str| -> EventTypeSchema {
+            serde_json::from_str(&format!(
+                r#"{{"event_type": "order", "attributes": {}, "mode": "Coerce"}}"#,
+                attributes
+            ))
+            .unwrap()
+        };
+        let err = store
+            .declare_schema(declared(r#"{"note": {"value_type": "Date"}}"#))
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("note is late in the stored events"));
+        assert_eq!(store.get_schema()["order"][&a!("price")], ValueType::Num);
+
+        // the stored values are widened when they are read with the widened type
+        let price = Expr::AttrNum(AttributeKey::Single("price".into()));
+        let paid = Expr::AttrDate(AttributeKey::Single("paid".into()));
+        let events = store.all_events_sorted_memory_store().unwrap();
+        let values = events
+            .iter()
+            .map(|event| {
+                (
+                    eval_simple_expr(&price, Some(event), None, &HashMap::new()).unwrap(),
+                    eval_simple_expr(&paid, Some(event), None, &HashMap::new()).unwrap(),
+                )
+            })
+            .collect_vec();
+        assert_eq!(values[0].0, Value::Num(10.0));
+        assert_eq!(values[2].0, Value::Num(1.0));
+        assert_eq!(
+            values[4].1,
+            Value::Date(NaiveDate::from_ymd_opt(2023, 1, 1).unwrap())
+        );
+    }
 }
@@ -1,2 +1,5 @@
 // pub mod event_store;
+pub mod attribute_index;
 pub mod memory_event_store;
+pub mod snapshot;
+pub mod wal;
@@ -0,0 +1,372 @@
+#![allow(clippy::unwrap_used)]
+
+use std::convert::{TryFrom, TryInto};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use anyhow::{bail, Context, Result};
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::event::{AttributeName, EntityID, EntityType, Event, EventType};
+use crate::event_store::schema::{EventTypeSchema, SchemaVersion};
+use crate::map::HashMap;
+use crate::sstring::SmallString;
+use crate::types::{Timestamp, FLOAT, INT};
+use crate::value::{Value, ValueWithAlias};
+
+use super::memory_event_store::MemoryEventStore;
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"FXSS";
+/// Suffix of the snapshots being written
+pub(crate) const TMP_SUFFIX: &str = ".tmp";
+/// Version of the snapshot format, snapshots written with another version are rejected
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+// `Value` is serialized untagged (it is also the JSON representation of the events),
+// which bincode cannot read back, so the snapshot keeps its own tagged copy of it
+#[derive(Serialize, Deserialize)]
+enum SnapshotValue {
+    None,
+    Wildcard,
+    Bool(bool),
+    Num(FLOAT),
+    Int(INT),
+    Str(SmallString),
+    MapNum(Vec<(AttributeName, FLOAT)>),
+    MapStr(Vec<(AttributeName, SmallString)>),
+    VecBool(Vec<bool>),
+    VecNum(Vec<FLOAT>),
+    VecInt(Vec<INT>),
+    VecStr(Vec<SmallString>),
+    // days from the common era
+    Date(i32),
+    DateTime(SnapshotTimestamp),
+    Map(Vec<(AttributeName, SnapshotValue)>),
+    ValueWithAlias(Option<SmallString>, Box<SnapshotValue>),
+    NotCalculatedYet,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SnapshotTimestamp {
+    secs: i64,
+    nsecs: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SnapshotEvent {
+    event_type: SmallString,
+    event_time: SnapshotTimestamp,
+    entities: Vec<(SmallString, SmallString)>,
+    event_id: Option<SmallString>,
+    experiment_id: Option<SmallString>,
+    attrs: Option<Vec<(AttributeName, SnapshotValue)>>,
+}
+
+impl From<&Timestamp> for SnapshotTimestamp {
+    fn from(ts: &Timestamp) -> Self {
+        SnapshotTimestamp {
+            secs: ts.timestamp(),
+            nsecs: ts.timestamp_subsec_nanos(),
+        }
+    }
+}
+
+impl TryFrom<SnapshotTimestamp> for Timestamp {
+    type Error = anyhow::Error;
+
+    fn try_from(ts: SnapshotTimestamp) -> Result<Self> {
+        NaiveDateTime::from_timestamp_opt(ts.secs, ts.nsecs)
+            .with_context(|| format!("Invalid timestamp {}.{} in snapshot", ts.secs, ts.nsecs))
+    }
+}
+
+impl From<&Value> for SnapshotValue {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::None => SnapshotValue::None,
+            Value::Wildcard => SnapshotValue::Wildcard,
+            Value::Bool(v) => SnapshotValue::Bool(*v),
+            Value::Num(v) => SnapshotValue::Num(*v),
+            Value::Int(v) => SnapshotValue::Int(*v),
+            Value::Str(v) => SnapshotValue::Str(v.clone()),
+            Value::MapNum(m) => {
+                SnapshotValue::MapNum(m.iter().map(|(k, v)| (k.clone(), *v)).collect())
+            }
+            Value::MapStr(m) => {
+                SnapshotValue::MapStr(m.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+            }
+            Value::VecBool(v) => SnapshotValue::VecBool(v.clone()),
+            Value::VecNum(v) => SnapshotValue::VecNum(v.clone()),
+            Value::VecInt(v) => SnapshotValue::VecInt(v.clone()),
+            Value::VecStr(v) => SnapshotValue::VecStr(v.clone()),
+            Value::Date(d) => SnapshotValue::Date(d.num_days_from_ce()),
+            Value::DateTime(dt) => SnapshotValue::DateTime(dt.into()),
+            Value::Map(m) => SnapshotValue::Map(
+                m.iter()
+                    .map(|(k, v)| (k.clone(), v.as_ref().into()))
+                    .collect(),
+            ),
+            Value::ValueWithAlias(v) => {
+                SnapshotValue::ValueWithAlias(v.alias.clone(), Box::new((&v.value).into()))
+            }
+            Value::NotCalculatedYet => SnapshotValue::NotCalculatedYet,
+        }
+    }
+}
+
+impl TryFrom<SnapshotValue> for Value {
+    type Error = anyhow::Error;
+
+    fn try_from(value: SnapshotValue) -> Result<Self> {
+        Ok(match value {
+            SnapshotValue::None => Value::None,
+            SnapshotValue::Wildcard => Value::Wildcard,
+            SnapshotValue::Bool(v) => Value::Bool(v),
+            SnapshotValue::Num(v) => Value::Num(v),
+            SnapshotValue::Int(v) => Value::Int(v),
+            SnapshotValue::Str(v) => Value::Str(v),
+            SnapshotValue::MapNum(m) => Value::MapNum(m.into_iter().collect()),
+            SnapshotValue::MapStr(m) => Value::MapStr(m.into_iter().collect()),
+            SnapshotValue::VecBool(v) => Value::VecBool(v),
+            SnapshotValue::VecNum(v) => Value::VecNum(v),
+            SnapshotValue::VecInt(v) => Value::VecInt(v),
+            SnapshotValue::VecStr(v) => Value::VecStr(v),
+            SnapshotValue::Date(days) => Value::Date(
+                NaiveDate::from_num_days_from_ce_opt(days)
+                    .with_context(|| format!("Invalid date {} in snapshot", days))?,
+            ),
+            SnapshotValue::DateTime(ts) => Value::DateTime(ts.try_into()?),
+            SnapshotValue::Map(m) => Value::Map(
+                m.into_iter()
+                    .map(|(k, v)| Ok((k, Box::new(v.try_into()?))))
+                    .collect::<Result<HashMap<_, _>>>()?,
+            ),
+            SnapshotValue::ValueWithAlias(alias, value) => {
+                Value::ValueWithAlias(Box::new(ValueWithAlias {
+                    alias,
+                    value: (*value).try_into()?,
+                }))
+            }
+            SnapshotValue::NotCalculatedYet => Value::NotCalculatedYet,
+        })
+    }
+}
+
+impl From<&Event> for SnapshotEvent {
+    fn from(event: &Event) -> Self {
+        SnapshotEvent {
+            event_type: event.event_type.0.clone(),
+            event_time: (&event.event_time).into(),
+            entities: event
+                .entities
+                .iter()
+                .map(|(typ, id)| (typ.0.clone(), id.0.clone()))
+                .collect(),
+            event_id: event.event_id.clone(),
+            experiment_id: event.experiment_id.clone(),
+            attrs: event.attrs.as_ref().map(|attrs| {
+                attrs
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.into()))
+                    .collect()
+            }),
+        }
+    }
+}
+
+impl TryFrom<SnapshotEvent> for Event {
+    type Error = anyhow::Error;
+
+    fn try_from(event: SnapshotEvent) -> Result<Self> {
+        Ok(Event {
+            event_type: EventType(event.event_type),
+            event_time: event.event_time.try_into()?,
+            entities: event
+                .entities
+                .into_iter()
+                .map(|(typ, id)| (EntityType(typ), EntityID(id)))
+                .collect(),
+            event_id: event.event_id,
+            experiment_id: event.experiment_id,
+            attrs: event
+                .attrs
+                .map(|attrs| {
+                    attrs
+                        .into_iter()
+                        .map(|(name, value)| Ok((name, value.try_into()?)))
+                        .collect::<Result<HashMap<_, _>>>()
+                })
+                .transpose()?,
+        })
+    }
+}
+
+impl MemoryEventStore {
+    /// Writes all the events to a snapshot file, returns the number of written events.
+    ///
+    /// The file starts with a magic number, the format version and the number of events,
+    /// followed by the LZ4 frame compressed bincode of the declared schemas, the events in
+    /// insertion order and the schema versions.
+    /// The snapshot is written next to the file and renamed over it once it is synced, so an
+    /// existing snapshot is never left half written.
+    pub fn save_snapshot(&self, path: &str) -> Result<usize> {
+        let tmp_path = format!("{}{}", path, TMP_SUFFIX);
+        let n_events = self
+            .write_snapshot(&tmp_path)
+            .with_context(|| format!("Cannot write the snapshot {}", path))?;
+        fs::rename(&tmp_path, path).with_context(|| format!("Cannot rename {}", tmp_path))?;
+        Ok(n_events)
+    }
+
+    fn write_snapshot(&self, path: &str) -> Result<usize> {
+        let file = File::create(path).with_context(|| format!("Cannot create {}", path))?;
+        let mut writer = BufWriter::new(file);
+        let sm = self.sm.read().unwrap();
+        let declared_schemas = self.declared_schemas();
+        let schema_versions: Vec<(SmallString, Vec<SchemaVersion>)> = self
+            .schema_versions
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(event_type, versions)| (event_type.0.clone(), versions.clone()))
+            .collect();
+
+        writer.write_all(SNAPSHOT_MAGIC)?;
+        writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+        writer.write_all(&(sm.len() as u64).to_le_bytes())?;
+
+        let mut encoder = lz4_flex::frame::FrameEncoder::new(writer);
+        bincode::serialize_into(&mut encoder, &declared_schemas)
+            .with_context(|| format!("Cannot write the snapshot {}", path))?;
+        for event in sm.values() {
+            bincode::serialize_into(&mut encoder, &SnapshotEvent::from(event.as_ref()))
+                .with_context(|| format!("Cannot write the snapshot {}", path))?;
+        }
+        bincode::serialize_into(&mut encoder, &schema_versions)
+            .with_context(|| format!("Cannot write the snapshot {}", path))?;
+        let mut writer = encoder
+            .finish()
+            .with_context(|| format!("Cannot write the snapshot {}", path))?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        Ok(sm.len())
+    }
+
+    /// Reads a snapshot written by `save_snapshot`, the indices and the schema are rebuilt
+    /// while the events are inserted. The declared schemas are declared before the events
+    /// are inserted and the schema versions are restored afterwards.
+    pub fn load_snapshot(path: &str) -> Result<MemoryEventStore> {
+        let file = File::open(path).with_context(|| format!("Cannot open {}", path))?;
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0u8; 4];
+        reader
+            .read_exact(&mut magic)
+            .with_context(|| format!("{} is not a snapshot", path))?;
+        if &magic != SNAPSHOT_MAGIC {
+            bail!("{} is not a snapshot", path);
+        }
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != SNAPSHOT_VERSION {
+            bail!(
+                "Snapshot {} has version {}, only version {} is supported",
+                path,
+                version,
+                SNAPSHOT_VERSION
+            );
+        }
+        let mut n_events = [0u8; 8];
+        reader.read_exact(&mut n_events)?;
+        let n_events = u64::from_le_bytes(n_events);
+
+        let store = MemoryEventStore::new();
+        let mut decoder = lz4_flex::frame::FrameDecoder::new(reader);
+        let declared_schemas: Vec<EventTypeSchema> = bincode::deserialize_from(&mut decoder)
+            .with_context(|| format!("Cannot read the schemas of the snapshot {}", path))?;
+        {
+            let mut schema_registry = store.schema_registry.write().unwrap();
+            for schema in declared_schemas {
+                schema_registry.declare(schema);
+            }
+        }
+        for i in 0..n_events {
+            let event: SnapshotEvent = bincode::deserialize_from(&mut decoder)
+                .with_context(|| format!("Cannot read event {} of the snapshot {}", i, path))?;
+            store.insert_event(event.try_into()?)?;
+        }
+        // the versions recorded while the events were inserted miss the deleted events
+        let schema_versions: Vec<(SmallString, Vec<SchemaVersion>)> =
+            bincode::deserialize_from(&mut decoder).with_context(|| {
+                format!("Cannot read the schema versions of the snapshot {}", path)
+            })?;
+        *store.schema_versions.write().unwrap() = schema_versions
+            .into_iter()
+            .map(|(event_type, versions)| (EventType(event_type), versions))
+            .collect();
+        Ok(store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_store::{EventStore, QueryConfig};
+    use crate::types::Entities;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let store = MemoryEventStore::new();
+        let events = vec![
+            r#"{"event_type": "bet", "event_time": "2023-01-02T10:00:00", "entities": {"user": "a"}, "event_id": "1", "attrs": {"amount": 10.5, "game": "poker", "day": "2023-01-02", "tags": ["x", "y"]}}"#,
+            r#"{"event_type": "bet", "event_time": "2023-01-01T09:30:00.250", "entities": {"user": "b"}, "event_id": "2", "attrs": {"amount": 3.0, "game": "chess"}}"#,
+            r#"{"event_type": "login", "event_time": "2023-01-03T00:00:00", "entities": {"user": "a"}, "experiment_id": "exp"}"#,
+        ];
+        for event in events {
+            store.insert(serde_json::from_str(event).unwrap()).unwrap();
+        }
+
+        let path = std::env::temp_dir().join("fexpress_test_snapshot_round_trip.snapshot");
+        let path = path.to_str().unwrap();
+        // an existing snapshot is replaced
+        assert_eq!(MemoryEventStore::new().save_snapshot(path).unwrap(), 0);
+        assert_eq!(store.save_snapshot(path).unwrap(), 3);
+        assert!(!std::path::Path::new(&format!("{}{}", path, TMP_SUFFIX)).exists());
+        let loaded = MemoryEventStore::load_snapshot(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            loaded.all_events_sorted_memory_store().unwrap(),
+            store.all_events_sorted_memory_store().unwrap()
+        );
+        assert_eq!(
+            *loaded.schema.read().unwrap(),
+            *store.schema.read().unwrap()
+        );
+
+        // indices are rebuilt
+        let entities: Entities = vec![(EntityType("user".into()), EntityID("a".into()))]
+            .into_iter()
+            .collect();
+        let events = loaded
+            .query_entity(&entities, &QueryConfig::default(), Some("exp".into()))
+            .unwrap();
+        assert_eq!(events.iter().map(|(_, e)| e.len()).sum::<usize>(), 2);
+    }
+
+    #[test]
+    fn test_snapshot_version_mismatch() {
+        let path = std::env::temp_dir().join("fexpress_test_snapshot_version.snapshot");
+        let path = path.to_str().unwrap();
+        let mut bytes = SNAPSHOT_MAGIC.to_vec();
+        bytes.extend((SNAPSHOT_VERSION + 1).to_le_bytes());
+        bytes.extend(0u64.to_le_bytes());
+        std::fs::write(path, bytes).unwrap();
+
+        let err = MemoryEventStore::load_snapshot(path).unwrap_err();
+        std::fs::remove_file(path).unwrap();
+        assert!(err.to_string().contains("version"));
+    }
+}
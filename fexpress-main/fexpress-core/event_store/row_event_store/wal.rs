@@ -0,0 +1,643 @@
+#![allow(clippy::unwrap_used)]
+
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hasher;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use twox_hash::XxHash32;
+
+use crate::event::{Entity, Event};
+use crate::event_store::retention::RetentionPolicy;
+use crate::event_store::schema::EventTypeSchema;
+use crate::sstring::SmallString;
+
+use super::memory_event_store::MemoryEventStore;
+use super::snapshot::{SnapshotEvent, SnapshotTimestamp};
+
+const WAL_MAGIC: &[u8; 4] = b"FXWL";
+/// Version of the log format, logs written with another version are rejected
+pub const WAL_VERSION: u32 = 3;
+// the records of the first version only hold inserted events
+const WAL_VERSION_INSERTS_ONLY: u32 = 1;
+// the records of the second version don't declare schemas, otherwise they are the same
+const WAL_VERSION_WITHOUT_SCHEMAS: u32 = 2;
+const WAL_HEADER_LEN: u64 = 8;
+// every record starts with the length and the checksum of the payload
+const RECORD_HEADER_LEN: u64 = 8;
+
+const SNAPSHOT_PREFIX: &str = "snapshot.";
+const WAL_PREFIX: &str = "wal.";
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct DurabilityConfig {
+    /// fsync the log after every insert, otherwise the records only survive a crash of
+    /// the process and not a crash of the machine
+    #[serde(default)]
+    pub sync: bool,
+    /// compact the log into a snapshot once it holds this many events
+    #[serde(default)]
+    pub compact_after_events: Option<usize>,
+}
+
+/// Append-only log of the inserted events of a durable `MemoryEventStore`.
+///
+/// The directory of a durable store holds the files of one generation: `snapshot.<gen>`
+/// with the events compacted so far (missing for generation 0) and `wal.<gen>` with the
+/// events inserted afterwards. Compaction writes the snapshot of the next generation before
+/// the files of the previous generation are removed, so a crash in between keeps a
+/// consistent state.
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    dir: PathBuf,
+    generation: u64,
+    file: File,
+    config: DurabilityConfig,
+    n_events: usize,
+}
+
+fn snapshot_path(dir: &Path, generation: u64) -> PathBuf {
+    dir.join(format!("{}{}", SNAPSHOT_PREFIX, generation))
+}
+
+fn wal_path(dir: &Path, generation: u64) -> PathBuf {
+    dir.join(format!("{}{}", WAL_PREFIX, generation))
+}
+
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .with_context(|| format!("Invalid path {}", path.display()))
+}
+
+fn checksum(payload: &[u8]) -> u32 {
+    let mut hasher = XxHash32::with_seed(0);
+    hasher.write(payload);
+    hasher.finish() as u32
+}
+
+/// Generations of the complete snapshots in the directory
+fn snapshot_generations(dir: &Path) -> Result<Vec<u64>> {
+    let mut generations = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Cannot read {}", dir.display()))? {
+        let name = entry?.file_name();
+        let name = name.to_string_lossy();
+        if let Some(generation) = name.strip_prefix(SNAPSHOT_PREFIX) {
+            if let Ok(generation) = generation.parse() {
+                generations.push(generation);
+            }
+        }
+    }
+    generations.sort_unstable();
+    Ok(generations)
+}
+
+/// Removes the files of the other generations and the unfinished snapshots
+fn remove_stale_files(dir: &Path, generation: u64) -> Result<()> {
+    let current = [
+        format!("{}{}", SNAPSHOT_PREFIX, generation),
+        format!("{}{}", WAL_PREFIX, generation),
+    ];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let is_store_file = name.starts_with(SNAPSHOT_PREFIX) || name.starts_with(WAL_PREFIX);
+        if is_store_file && !current.iter().any(|c| c == name.as_ref()) {
+            fs::remove_file(entry.path())
+                .with_context(|| format!("Cannot remove {}", entry.path().display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Operation on the store written as one record of the log
+#[derive(Serialize, Deserialize)]
+pub(crate) enum WalRecord {
+    Insert(Vec<SnapshotEvent>),
+    Upsert(SnapshotEvent),
+    DeleteEvent(SmallString),
+    DeleteEntity(Entity),
+    // the cutoffs are computed from the time the policy was applied at
+    Retention(RetentionPolicy, SnapshotTimestamp),
+    DeclareSchema(EventTypeSchema),
+}
+
+impl WalRecord {
+    /// Number of operations of the record counted for the compaction
+    fn n_events(&self) -> usize {
+        match self {
+            WalRecord::Insert(events) => events.len(),
+            _ => 1,
+        }
+    }
+
+    fn apply(self, store: &MemoryEventStore) -> Result<()> {
+        match self {
+            WalRecord::Insert(events) => {
+                for event in events {
+                    store.insert_event(event.try_into()?)?;
+                }
+            }
+            WalRecord::Upsert(event) => store.replace_event(event.try_into()?)?,
+            WalRecord::DeleteEvent(event_id) => {
+                store.remove_event_by_id(&event_id);
+            }
+            WalRecord::DeleteEntity(entity) => {
+                store.remove_entity_events(&entity);
+            }
+            WalRecord::Retention(policy, now) => {
+                store.remove_expired_events(&policy, &now.try_into()?)?;
+            }
+            // the schema was checked against the stored events when it was logged
+            WalRecord::DeclareSchema(schema) => {
+                store.schema_registry.write().unwrap().declare(schema);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn create_wal_file(path: &Path) -> Result<File> {
+    let mut file =
+        File::create(path).with_context(|| format!("Cannot create {}", path.display()))?;
+    file.write_all(WAL_MAGIC)?;
+    file.write_all(&WAL_VERSION.to_le_bytes())?;
+    file.sync_all()?;
+    Ok(file)
+}
+
+/// Applies the records of the log to the store, returns the number of replayed events and the
+/// version of the log.
+///
+/// A record cut off at the end of the log (the process died while it was written) is
+/// dropped and the log is truncated after the last complete record.
+fn replay(store: &MemoryEventStore, path: &Path) -> Result<(usize, u32)> {
+    let file = File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let mut header = [0u8; WAL_HEADER_LEN as usize];
+    reader
+        .read_exact(&mut header)
+        .with_context(|| format!("{} is not a write-ahead log", path.display()))?;
+    if &header[..4] != WAL_MAGIC {
+        bail!("{} is not a write-ahead log", path.display());
+    }
+    let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    if ![
+        WAL_VERSION,
+        WAL_VERSION_WITHOUT_SCHEMAS,
+        WAL_VERSION_INSERTS_ONLY,
+    ]
+    .contains(&version)
+    {
+        bail!(
+            "Write-ahead log {} has version {}, only versions up to {} are supported",
+            path.display(),
+            version,
+            WAL_VERSION
+        );
+    }
+
+    let mut offset = WAL_HEADER_LEN;
+    let mut n_events = 0;
+    loop {
+        let mut record_header = [0u8; RECORD_HEADER_LEN as usize];
+        match reader.read_exact(&mut record_header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        }
+        let len = u32::from_le_bytes([
+            record_header[0],
+            record_header[1],
+            record_header[2],
+            record_header[3],
+        ]) as u64;
+        let expected_checksum = u32::from_le_bytes([
+            record_header[4],
+            record_header[5],
+            record_header[6],
+            record_header[7],
+        ]);
+        let record_end = offset + RECORD_HEADER_LEN + len;
+        if record_end > file_len {
+            break;
+        }
+        let mut payload = vec![0u8; len as usize];
+        reader.read_exact(&mut payload)?;
+        if checksum(&payload) != expected_checksum {
+            if record_end == file_len {
+                break;
+            }
+            bail!(
+                "Write-ahead log {} is corrupted at offset {}",
+                path.display(),
+                offset
+            );
+        }
+
+        let record = if version == WAL_VERSION_INSERTS_ONLY {
+            bincode::deserialize(&payload).map(WalRecord::Insert)
+        } else {
+            bincode::deserialize(&payload)
+        }
+        .with_context(|| {
+            format!(
+                "Cannot read the record at offset {} of {}",
+                offset,
+                path.display()
+            )
+        })?;
+        n_events += record.n_events();
+        record.apply(store)?;
+        offset = record_end;
+    }
+
+    if offset < file_len {
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(offset)
+            .with_context(|| format!("Cannot truncate {}", path.display()))?;
+    }
+    Ok((n_events, version))
+}
+
+impl WriteAheadLog {
+    /// Appends the events as one record
+    pub fn append(&mut self, events: &[Event]) -> Result<()> {
+        let events: Vec<SnapshotEvent> = events.iter().map(SnapshotEvent::from).collect();
+        self.append_record(&WalRecord::Insert(events))
+    }
+
+    pub(crate) fn append_record(&mut self, record: &WalRecord) -> Result<()> {
+        let payload = bincode::serialize(record)?;
+        if payload.len() > u32::MAX as usize {
+            bail!(
+                "Cannot log a batch of {} bytes, split the batch",
+                payload.len()
+            );
+        }
+
+        let mut bytes = Vec::with_capacity(RECORD_HEADER_LEN as usize + payload.len());
+        bytes.extend((payload.len() as u32).to_le_bytes());
+        bytes.extend(checksum(&payload).to_le_bytes());
+        bytes.extend(payload);
+        self.file
+            .write_all(&bytes)
+            .context("Cannot append to the write-ahead log")?;
+        if self.config.sync {
+            self.file.sync_data()?;
+        }
+        self.n_events += record.n_events();
+        Ok(())
+    }
+
+    /// Number of events in the log since the last compaction
+    pub fn n_events(&self) -> usize {
+        self.n_events
+    }
+}
+
+impl MemoryEventStore {
+    /// Opens a store whose inserts are appended to a log in `dir` before they are indexed.
+    ///
+    /// The latest snapshot of the directory is loaded and the log is replayed on top of it,
+    /// so a store reopened after a restart holds the same events.
+    pub fn open_durable(dir: &str, config: DurabilityConfig) -> Result<MemoryEventStore> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+
+        let generation = snapshot_generations(&dir)?.last().copied().unwrap_or(0);
+        remove_stale_files(&dir, generation)?;
+
+        let snapshot = snapshot_path(&dir, generation);
+        let store = if snapshot.exists() {
+            MemoryEventStore::load_snapshot(path_str(&snapshot)?)?
+        } else {
+            MemoryEventStore::new()
+        };
+
+        let wal = wal_path(&dir, generation);
+        let (file, n_events, version) = if wal.exists() {
+            let (n_events, version) = replay(&store, &wal)?;
+            let file = OpenOptions::new()
+                .append(true)
+                .open(&wal)
+                .with_context(|| format!("Cannot open {}", wal.display()))?;
+            (file, n_events, version)
+        } else {
+            (create_wal_file(&wal)?, 0, WAL_VERSION)
+        };
+
+        let mut wal = WriteAheadLog {
+            dir,
+            generation,
+            file,
+            config,
+            n_events,
+        };
+        // records of the current version cannot be appended to a log of an older version
+        if version != WAL_VERSION {
+            store.compact_log(&mut wal)?;
+        }
+        *store.wal.lock().unwrap() = Some(wal);
+        Ok(store)
+    }
+
+    /// Whether the store writes its operations to a log
+    pub fn is_durable(&self) -> bool {
+        self.wal.lock().unwrap().is_some()
+    }
+
+    /// Writes all the events to a new snapshot and starts an empty log
+    pub fn compact(&self) -> Result<()> {
+        let mut wal = self.wal.lock().unwrap();
+        match wal.as_mut() {
+            Some(wal) => self.compact_log(wal),
+            None => bail!("The event store is not durable, open it with open_durable"),
+        }
+    }
+
+    pub(crate) fn compact_if_needed(&self, wal: &mut Option<WriteAheadLog>) -> Result<()> {
+        if let Some(wal) = wal.as_mut() {
+            if let Some(compact_after_events) = wal.config.compact_after_events {
+                if wal.n_events >= compact_after_events {
+                    self.compact_log(wal)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the caller holds the lock of the log so no events are inserted during the compaction
+    pub(crate) fn compact_log(&self, wal: &mut WriteAheadLog) -> Result<()> {
+        let generation = wal.generation + 1;
+        // the snapshot is written to a temporary file and renamed once it is synced
+        self.save_snapshot(path_str(&snapshot_path(&wal.dir, generation))?)?;
+
+        wal.file = create_wal_file(&wal_path(&wal.dir, generation))?;
+        wal.generation = generation;
+        wal.n_events = 0;
+        remove_stale_files(&wal.dir, generation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datetime_utils::parse_naive_date_time;
+    use crate::event::{AttributeName, EntityType, EventType};
+    use crate::event_store::EventStore;
+    use crate::value::Value;
+
+    fn event(i: usize) -> Event {
+        serde_json::from_str(&format!(
+            r#"{{"event_type": "bet", "event_time": "2023-01-01T00:00:{:02}", "entities": {{"user": "u{}"}}, "event_id": "{}", "attrs": {{"amount": {}.5}}}}"#,
+            i,
+            i % 3,
+            i,
+            i
+        ))
+        .unwrap()
+    }
+
+    fn event_ids(store: &MemoryEventStore) -> Vec<String> {
+        store
+            .all_events_sorted()
+            .unwrap()
+            .iter()
+            .map(|event| event.event_id.clone().unwrap().to_string())
+            .collect()
+    }
+
+    fn test_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        dir.to_str().unwrap().to_string()
+    }
+
+    fn n_files(dir: &str) -> usize {
+        fs::read_dir(dir).unwrap().count()
+    }
+
+    #[test]
+    fn test_wal_replay() {
+        let dir = test_dir("fexpress_test_wal_replay");
+        {
+            let store = MemoryEventStore::open_durable(&dir, Default::default()).unwrap();
+            store.insert(event(0)).unwrap();
+            store.insert_batch(vec![event(1), event(2)]).unwrap();
+        }
+
+        let store = MemoryEventStore::open_durable(&dir, Default::default()).unwrap();
+        assert_eq!(store.get_n_events(), 3);
+        assert_eq!(store.wal.lock().unwrap().as_ref().unwrap().n_events(), 3);
+
+        // the events inserted after the replay are appended to the same log
+        store.insert(event(3)).unwrap();
+        drop(store);
+
+        // a record cut off while it was written is dropped
+        let wal = wal_path(Path::new(&dir), 0);
+        let len = fs::metadata(&wal).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&wal).unwrap();
+        file.write_all(&[100, 0, 0, 0, 1, 2]).unwrap();
+        drop(file);
+
+        let store = MemoryEventStore::open_durable(&dir, Default::default()).unwrap();
+        assert_eq!(store.get_n_events(), 4);
+        assert_eq!(fs::metadata(&wal).unwrap().len(), len);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rejected_events_are_not_logged() {
+        let dir = test_dir("fexpress_test_rejected_events_are_not_logged");
+        {
+            let store = MemoryEventStore::open_durable(&dir, Default::default()).unwrap();
+            store.insert(event(0)).unwrap();
+            assert!(store.insert(event(0)).is_err());
+            // duplicates inside the batch
+            assert!(store.insert_batch(vec![event(1), event(1)]).is_err());
+            // a type conflict with the stored events
+            let mut conflicting = event(2);
+            conflicting.attrs = Some(hashmap![a!("amount") => Value::Str("a lot".into())]);
+            assert!(store.insert_batch(vec![event(3), conflicting]).is_err());
+            assert_eq!(store.get_n_events(), 1);
+            assert_eq!(store.wal.lock().unwrap().as_ref().unwrap().n_events(), 1);
+        }
+
+        let store = MemoryEventStore::open_durable(&dir, Default::default()).unwrap();
+        assert_eq!(event_ids(&store), vec!["0"]);
+        store.insert(event(1)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_wal_compaction() {
+        let dir = test_dir("fexpress_test_wal_compaction");
+        let config = DurabilityConfig {
+            sync: true,
+            compact_after_events: Some(4),
+        };
+        {
+            let store = MemoryEventStore::open_durable(&dir, config.clone()).unwrap();
+            for i in 0..10 {
+                store.insert(event(i)).unwrap();
+            }
+            // compacted after 4 and 8 events
+            assert_eq!(store.wal.lock().unwrap().as_ref().unwrap().generation, 2);
+            assert_eq!(n_files(&dir), 2);
+        }
+
+        let store = MemoryEventStore::open_durable(&dir, config).unwrap();
+        assert_eq!(store.get_n_events(), 10);
+        assert_eq!(store.wal.lock().unwrap().as_ref().unwrap().n_events(), 2);
+
+        store.compact().unwrap();
+        assert!(snapshot_path(Path::new(&dir), 3).exists());
+        assert_eq!(n_files(&dir), 2);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(MemoryEventStore::new().compact().is_err());
+    }
+
+    #[test]
+    fn test_wal_replays_deletes() {
+        let dir = test_dir("fexpress_test_wal_replays_deletes");
+        let entity = Entity {
+            typ: EntityType("user".into()),
+            id: "u1".into(),
+        };
+        let policy: RetentionPolicy =
+            serde_json::from_str(r#"{"default_ttl": {"date_part": "Second", "nth": 5}}"#).unwrap();
+        let now = parse_naive_date_time("2023-01-01T00:00:10").unwrap();
+        let expected_ids = {
+            let store = MemoryEventStore::open_durable(&dir, Default::default()).unwrap();
+            store.insert_batch((0..9).map(event).collect()).unwrap();
+            // the erasure compacts the log so the records after it are replayed
+            store.delete_entity(&entity).unwrap();
+            store.delete_event(&"0".into()).unwrap();
+            let mut updated = event(2);
+            updated.event_time = parse_naive_date_time("2023-01-01T00:00:30").unwrap();
+            store.upsert(updated).unwrap();
+            // events before 00:00:05
+            store.apply_retention(&policy, &now).unwrap();
+            event_ids(&store)
+        };
+        assert_eq!(expected_ids, vec!["5", "6", "8", "2"]);
+
+        let store = MemoryEventStore::open_durable(&dir, Default::default()).unwrap();
+        assert_eq!(event_ids(&store), expected_ids);
+        assert_eq!(store.wal.lock().unwrap().as_ref().unwrap().n_events(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_entity_erasure_removes_the_events_from_disk() {
+        let dir = test_dir("fexpress_test_entity_erasure");
+        let entity = Entity {
+            typ: EntityType("user".into()),
+            id: "u1".into(),
+        };
+        let store = MemoryEventStore::open_durable(&dir, Default::default()).unwrap();
+        store.insert_batch((0..6).map(event).collect()).unwrap();
+        assert_eq!(store.delete_entity(&entity).unwrap(), 2);
+        for entry in fs::read_dir(&dir).unwrap() {
+            let content = fs::read(entry.unwrap().path()).unwrap();
+            assert!(!content.windows(2).any(|bytes| bytes == b"u1"));
+        }
+        drop(store);
+
+        let store = MemoryEventStore::open_durable(&dir, Default::default()).unwrap();
+        assert_eq!(event_ids(&store), vec!["0", "2", "3", "5"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_upsert_is_not_logged() {
+        let dir = test_dir("fexpress_test_failed_upsert");
+        {
+            let store = MemoryEventStore::open_durable(&dir, Default::default()).unwrap();
+            store.insert_batch((0..2).map(event).collect()).unwrap();
+            let mut updated = event(1);
+            updated.attrs = Some(hashmap! {AttributeName::new("amount") => Value::Str("x".into())});
+            assert!(store.upsert(updated).is_err());
+            assert_eq!(event_ids(&store), vec!["0", "1"]);
+        }
+        let store = MemoryEventStore::open_durable(&dir, Default::default()).unwrap();
+        assert_eq!(event_ids(&store), vec!["0", "1"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_declared_schemas_survive_reopening() {
+        let dir = test_dir("fexpress_test_declared_schemas_survive_reopening");
+        let schema: EventTypeSchema = serde_json::from_str(
+            r#"{"event_type": "bet", "attributes": {"amount": {"value_type": "Num", "required": true}}}"#,
+        )
+        .unwrap();
+        let mut violating = event(9);
+        violating.attrs = None;
+        {
+            let store = MemoryEventStore::open_durable(&dir, Default::default()).unwrap();
+            store.insert(event(0)).unwrap();
+            store.declare_schema(schema.clone()).unwrap();
+            store.insert(event(1)).unwrap();
+            assert!(store.insert(violating.clone()).is_err());
+        }
+
+        // the declaration is replayed from the log
+        let store = MemoryEventStore::open_durable(&dir, Default::default()).unwrap();
+        assert_eq!(store.declared_schemas(), vec![schema.clone()]);
+        assert!(store.insert(violating.clone()).is_err());
+        let versions = store.schema_versions(&EventType("bet".into()));
+        assert_eq!(versions.len(), 1);
+
+        // and read from the snapshot after a compaction
+        store.delete_event(&"0".into()).unwrap();
+        store.compact().unwrap();
+        drop(store);
+        let store = MemoryEventStore::open_durable(&dir, Default::default()).unwrap();
+        assert_eq!(store.declared_schemas(), vec![schema]);
+        assert!(store.insert(violating).is_err());
+        // the versions keep the time of the deleted event
+        assert_eq!(store.schema_versions(&EventType("bet".into())), versions);
+        assert_eq!(event_ids(&store), vec!["1"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_wal_of_inserts_only_is_replayed() {
+        let dir = test_dir("fexpress_test_wal_of_inserts_only");
+        fs::create_dir_all(&dir).unwrap();
+        let events: Vec<SnapshotEvent> = (0..3).map(|i| SnapshotEvent::from(&event(i))).collect();
+        let payload = bincode::serialize(&events).unwrap();
+        let mut file = File::create(wal_path(Path::new(&dir), 0)).unwrap();
+        file.write_all(WAL_MAGIC).unwrap();
+        file.write_all(&WAL_VERSION_INSERTS_ONLY.to_le_bytes())
+            .unwrap();
+        file.write_all(&(payload.len() as u32).to_le_bytes())
+            .unwrap();
+        file.write_all(&checksum(&payload).to_le_bytes()).unwrap();
+        file.write_all(&payload).unwrap();
+        drop(file);
+
+        // the old log is compacted so that the new records are not appended to it
+        let store = MemoryEventStore::open_durable(&dir, Default::default()).unwrap();
+        assert_eq!(store.get_n_events(), 3);
+        assert_eq!(store.wal.lock().unwrap().as_ref().unwrap().generation, 1);
+        store.delete_event(&"1".into()).unwrap();
+        drop(store);
+
+        let store = MemoryEventStore::open_durable(&dir, Default::default()).unwrap();
+        assert_eq!(event_ids(&store), vec!["0", "2"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
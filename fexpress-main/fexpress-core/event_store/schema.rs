@@ -0,0 +1,529 @@
+use std::fmt;
+
+use anyhow::Result;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::datetime_utils::{parse_naive_date_time, parse_naivedate};
+use crate::errors::FeatureExpressError;
+use crate::event::{AttributeName, Event, EventType};
+use crate::map::HashMap;
+use crate::types::{Timestamp, FLOAT, INT};
+use crate::value::{Value, ValueType};
+
+/*
+Event types can declare the attributes of their events. The declared schema is checked when
+an event is inserted (before it is written to the log of a durable store) so that the same
+attribute doesn't end up with ints, floats and strings:
+- strict: events which don't match the schema are rejected
+- coerce: values of other types are converted to the declared type (1 -> 1.0, "3" -> 3),
+  events which still don't match are rejected
+- warn: values are converted when possible and the remaining violations are kept as warnings,
+  the event is inserted anyway
+Event types without a declared schema are not checked.
+
+Without a declared schema the types of an attribute can change over time (e.g. a price ingested
+as an int and later as a float). The inferred schema then widens the type of the attribute
+along the coercion lattice instead of failing, the stored values keep their type and are
+widened when they are read:
+  Bool -> Int -> Num
+  Str -> Date -> DateTime (strings are parsed with the date formats)
+Types from different chains (e.g. Int and Str) have no common type and still conflict. Every
+change of the inferred schema of an event type is recorded as a new schema version.
+ */
+
+/// Maximum number of kept warnings, the oldest ones are dropped
+pub const MAX_SCHEMA_WARNINGS: usize = 1000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SchemaMode {
+    #[default]
+    Strict,
+    Coerce,
+    Warn,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AttributeSchema {
+    pub value_type: ValueType,
+    /// required attributes cannot be missing or null
+    #[serde(default)]
+    pub required: bool,
+    /// allowed values of string attributes
+    #[serde(default)]
+    pub categories: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EventTypeSchema {
+    pub event_type: String,
+    #[serde(default)]
+    pub attributes: HashMap<String, AttributeSchema>,
+    /// entity types of the events, `None` allows any entities
+    #[serde(default)]
+    pub entity_types: Option<Vec<String>>,
+    /// whether the events can have attributes which are not declared
+    #[serde(default)]
+    pub allow_undeclared_attributes: bool,
+    #[serde(default)]
+    pub mode: SchemaMode,
+}
+
+/// Difference between an event and the schema of its event type
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SchemaViolation {
+    pub event_type: String,
+    pub event_id: Option<String>,
+    pub attribute: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "event type {}", self.event_type)?;
+        if let Some(event_id) = &self.event_id {
+            write!(f, " (event {})", event_id)?;
+        }
+        if let Some(attribute) = &self.attribute {
+            write!(f, ", attribute {}", attribute)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Converts the value to the type if it represents the same value, e.g. 1 -> 1.0 or "1" -> 1
+pub fn coerce_value(value: &Value, value_type: &ValueType) -> Option<Value> {
+    let coerced = match (value, value_type) {
+        (Value::Int(v), ValueType::Num) => Value::Num(*v as FLOAT),
+        // -INT::MIN is exact as a float unlike INT::MAX, the floats out of the range would
+        // saturate
+        (Value::Num(v), ValueType::Int)
+            if v.fract() == 0.0 && *v >= INT::MIN as FLOAT && *v < -(INT::MIN as FLOAT) =>
+        {
+            Value::Int(*v as INT)
+        }
+        (Value::Bool(v), ValueType::Int) => Value::Int(*v as INT),
+        (Value::Bool(v), ValueType::Num) => Value::Num(*v as INT as FLOAT),
+        (Value::Int(_) | Value::Num(_) | Value::Bool(_), ValueType::Str) => {
+            Value::Str(value.to_string())
+        }
+        (Value::Str(v), ValueType::Int) => Value::Int(v.trim().parse().ok()?),
+        (Value::Str(v), ValueType::Num) => Value::Num(v.trim().parse().ok()?),
+        (Value::Str(v), ValueType::Bool) => match v.trim().to_lowercase().as_str() {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ => return None,
+        },
+        (Value::Str(v), ValueType::Date) => Value::Date(parse_naivedate(v.trim())?),
+        (Value::Str(v), ValueType::DateTime) => Value::DateTime(parse_naive_date_time(v.trim())?),
+        (Value::Date(v), ValueType::DateTime) => Value::DateTime(v.and_hms_opt(0, 0, 0)?),
+        (Value::Date(v), ValueType::Str) => Value::Str(v.format("%Y-%m-%d").to_string()),
+        (Value::DateTime(v), ValueType::Str) => {
+            Value::Str(v.format("%Y-%m-%dT%H:%M:%S%.f").to_string())
+        }
+        _ => return None,
+    };
+    Some(coerced)
+}
+
+/// Chain of the coercion lattice and the position of the type in it. `Num` is a 32 bit float so
+/// the ints above 2^24 lose their precision when they are widened to it
+fn lattice_position(value_type: &ValueType) -> Option<(usize, usize)> {
+    match value_type {
+        ValueType::Bool => Some((0, 0)),
+        ValueType::Int => Some((0, 1)),
+        ValueType::Num => Some((0, 2)),
+        ValueType::Str => Some((1, 0)),
+        ValueType::Date => Some((1, 1)),
+        ValueType::DateTime => Some((1, 2)),
+        _ => None,
+    }
+}
+
+/// Narrowest type of the coercion lattice both types widen to, `None` when they conflict
+pub fn widen_type(a: &ValueType, b: &ValueType) -> Option<ValueType> {
+    if a == b || *b == ValueType::None {
+        return Some(a.clone());
+    }
+    if *a == ValueType::None {
+        return Some(b.clone());
+    }
+    let (chain_a, position_a) = lattice_position(a)?;
+    let (chain_b, position_b) = lattice_position(b)?;
+    (chain_a == chain_b).then(|| {
+        if position_a >= position_b {
+            a.clone()
+        } else {
+            b.clone()
+        }
+    })
+}
+
+/// Converts the value to a wider type of the coercion lattice, `None` when the type is not
+/// wider than the type of the value or the string cannot be parsed. An int widened to `Num`
+/// is rounded to the nearest float when it is above 2^24
+pub fn widen_value(value: &Value, value_type: &ValueType) -> Option<Value> {
+    match (value, value_type) {
+        (Value::Bool(_), ValueType::Int | ValueType::Num)
+        | (Value::Int(_), ValueType::Num)
+        | (Value::Str(_), ValueType::Date | ValueType::DateTime)
+        | (Value::Date(_), ValueType::DateTime) => coerce_value(value, value_type),
+        _ => None,
+    }
+}
+
+/// Inferred types of the attributes of an event type after a change
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SchemaVersion {
+    /// starts at 1 with the first event of the event type
+    pub version: usize,
+    /// event time of the event which changed the schema
+    pub since: Timestamp,
+    pub attributes: HashMap<String, ValueType>,
+}
+
+fn is_null(value: &Value) -> bool {
+    matches!(value, Value::None)
+}
+
+impl EventTypeSchema {
+    fn violation(
+        &self,
+        event: &Event,
+        attribute: Option<&str>,
+        message: String,
+    ) -> SchemaViolation {
+        SchemaViolation {
+            event_type: self.event_type.clone(),
+            event_id: event.event_id.as_ref().map(|id| id.to_string()),
+            attribute: attribute.map(|a| a.to_string()),
+            message,
+        }
+    }
+
+    /// Checks the event against the schema, values of other types are converted unless the
+    /// mode is strict. Returns the converted event and the remaining violations.
+    pub fn check(&self, mut event: Event) -> (Event, Vec<SchemaViolation>) {
+        let mut violations = vec![];
+
+        if let Some(entity_types) = &self.entity_types {
+            for entity_type in event.entities.keys() {
+                if !entity_types.iter().any(|t| *t == entity_type.0.as_str()) {
+                    violations.push(self.violation(
+                        &event,
+                        None,
+                        format!("undeclared entity type {}", entity_type.0),
+                    ));
+                }
+            }
+            for entity_type in entity_types {
+                if !event.entities.keys().any(|t| t.0.as_str() == entity_type) {
+                    violations.push(self.violation(
+                        &event,
+                        None,
+                        format!("missing entity type {}", entity_type),
+                    ));
+                }
+            }
+        }
+
+        let mut attrs = event.attrs.take().unwrap_or_default();
+        for (name, attribute) in self.attributes.iter().sorted_by_key(|(name, _)| *name) {
+            let value = match attrs.get_mut(&AttributeName::new(name)) {
+                Some(value) if !is_null(value) => value,
+                _ => {
+                    if attribute.required {
+                        violations.push(self.violation(
+                            &event,
+                            Some(name),
+                            "missing required attribute".to_string(),
+                        ));
+                    }
+                    continue;
+                }
+            };
+            let value_type: ValueType = value.clone().into();
+            // JSON numbers are read as floats so whole floats are ints in every mode
+            let whole_float =
+                value_type == ValueType::Num && attribute.value_type == ValueType::Int;
+            if value_type != attribute.value_type {
+                match coerce_value(value, &attribute.value_type) {
+                    Some(coerced) if self.mode != SchemaMode::Strict || whole_float => {
+                        *value = coerced
+                    }
+                    _ => {
+                        violations.push(self.violation(
+                            &event,
+                            Some(name),
+                            format!(
+                                "expected {}, got {} {}",
+                                attribute.value_type,
+                                value_type,
+                                value.to_string()
+                            ),
+                        ));
+                        continue;
+                    }
+                }
+            }
+            if let (Some(categories), Value::Str(v)) = (&attribute.categories, &*value) {
+                if !categories.iter().any(|c| *c == v.as_str()) {
+                    violations.push(self.violation(
+                        &event,
+                        Some(name),
+                        format!("{} is not one of the categories {:?}", v, categories),
+                    ));
+                }
+            }
+        }
+
+        if !self.allow_undeclared_attributes {
+            for name in attrs.keys().map(|name| name.0.as_str()).sorted() {
+                if !self.attributes.contains_key(name) {
+                    violations.push(self.violation(
+                        &event,
+                        Some(name),
+                        "undeclared attribute".to_string(),
+                    ));
+                }
+            }
+        }
+
+        if !attrs.is_empty() {
+            event.attrs = Some(attrs);
+        }
+        (event, violations)
+    }
+}
+
+/// Declared schemas of the event types
+#[derive(Clone, Debug, Default)]
+pub struct SchemaRegistry {
+    schemas: HashMap<EventType, EventTypeSchema>,
+    warnings: Vec<SchemaViolation>,
+}
+
+impl SchemaRegistry {
+    pub fn declare(&mut self, schema: EventTypeSchema) {
+        self.schemas
+            .insert(EventType(schema.event_type.clone()), schema);
+    }
+
+    pub fn get(&self, event_type: &EventType) -> Option<&EventTypeSchema> {
+        self.schemas.get(event_type)
+    }
+
+    pub fn schemas(&self) -> impl Iterator<Item = &EventTypeSchema> {
+        self.schemas.values()
+    }
+
+    /// Checks the event against the schema of its event type, returns the event to insert or
+    /// an `IngestionError` listing the violations
+    pub fn validate(&mut self, event: Event) -> Result<Event> {
+        let (event, warnings) = self.check(event)?;
+        self.record_warnings(warnings);
+        Ok(event)
+    }
+
+    /// Same as `validate` without recording the warnings, returns the event to insert and the
+    /// violations of an event type in warn mode
+    pub fn check(&self, event: Event) -> Result<(Event, Vec<SchemaViolation>)> {
+        let schema = match self.schemas.get(&event.event_type) {
+            Some(schema) => schema,
+            None => return Ok((event, vec![])),
+        };
+        let (event, violations) = schema.check(event);
+        if violations.is_empty() || schema.mode == SchemaMode::Warn {
+            return Ok((event, violations));
+        }
+        Err(FeatureExpressError::IngestionError(format!(
+            "The event does not match the declared schema: {}",
+            violations.iter().join("; ")
+        ))
+        .into())
+    }
+
+    pub fn record_warnings(&mut self, warnings: Vec<SchemaViolation>) {
+        self.warnings.extend(warnings);
+        if self.warnings.len() > MAX_SCHEMA_WARNINGS {
+            let n_dropped = self.warnings.len() - MAX_SCHEMA_WARNINGS;
+            self.warnings.drain(..n_dropped);
+        }
+    }
+
+    /// Violations of the event types in warn mode, the latest `MAX_SCHEMA_WARNINGS` are kept
+    pub fn warnings(&self) -> &[SchemaViolation] {
+        &self.warnings
+    }
+
+    pub fn clear_warnings(&mut self) {
+        self.warnings.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(attrs: &str) -> Event {
+        serde_json::from_str(&format!(
+            r#"{{"event_type": "order", "event_time": "2023-01-01T00:00:00", "entities": {{"user": "a"}}, "event_id": "1", "attrs": {}}}"#,
+            attrs
+        ))
+        .unwrap()
+    }
+
+    fn schema(mode: SchemaMode) -> EventTypeSchema {
+        serde_json::from_value(serde_json::json!({
+            "event_type": "order",
+            "attributes": {
+                "amount": {"value_type": "Num", "required": true},
+                "quantity": {"value_type": "Int"},
+                "channel": {"value_type": "Str", "categories": ["web", "app"]},
+            },
+            "entity_types": ["user"],
+            "mode": mode,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_schema_modes() {
+        // the whole number is read as a float
+        let valid = event(r#"{"amount": 10.5, "quantity": 2, "channel": "web"}"#);
+        let mixed = event(r#"{"amount": 10, "quantity": "3", "channel": "web"}"#);
+        let invalid = event(r#"{"amount": "ten", "channel": "shop", "coupon": "x"}"#);
+
+        let mut registry = SchemaRegistry::default();
+        registry.declare(schema(SchemaMode::Strict));
+        assert_eq!(
+            registry
+                .validate(valid)
+                .unwrap()
+                .attrs
+                .unwrap()
+                .get(&AttributeName::new("quantity")),
+            Some(&Value::Int(2))
+        );
+        let err = registry.validate(mixed.clone()).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FeatureExpressError>(),
+            Some(FeatureExpressError::IngestionError(_))
+        ));
+        assert!(err
+            .to_string()
+            .contains("attribute quantity: expected Int, got Str 3"));
+        // a whole float which doesn't fit is a violation in every mode
+        let large = event(r#"{"amount": 10.5, "quantity": 1e10, "channel": "web"}"#);
+        let err = registry.validate(large.clone()).unwrap_err();
+        assert!(err.to_string().contains("attribute quantity: expected Int"));
+
+        registry.declare(schema(SchemaMode::Coerce));
+        assert!(registry.validate(large).is_err());
+        let coerced = registry.validate(mixed).unwrap();
+        assert_eq!(
+            coerced
+                .attrs
+                .as_ref()
+                .unwrap()
+                .get(&AttributeName::new("amount")),
+            Some(&Value::Num(10.0))
+        );
+        assert_eq!(
+            coerced
+                .attrs
+                .as_ref()
+                .unwrap()
+                .get(&AttributeName::new("quantity")),
+            Some(&Value::Int(3))
+        );
+        let err = registry.validate(invalid.clone()).unwrap_err().to_string();
+        assert!(err.contains("attribute amount: expected Num, got Str ten"));
+        assert!(err.contains("shop is not one of the categories"));
+        assert!(err.contains("attribute coupon: undeclared attribute"));
+
+        registry.declare(schema(SchemaMode::Warn));
+        let mut global = invalid.clone();
+        global.entities.clear();
+        let (_, warnings) = registry.check(global.clone()).unwrap();
+        assert_eq!(warnings.len(), 4);
+        assert!(registry.warnings().is_empty());
+        assert_eq!(registry.validate(global).unwrap().attrs, invalid.attrs);
+        assert_eq!(registry.warnings().len(), 4);
+        assert_eq!(
+            registry.warnings()[0].message,
+            "missing entity type user".to_string()
+        );
+
+        // event types without a schema are not checked
+        let mut other = invalid;
+        other.event_type = EventType("refund".into());
+        assert!(registry.validate(other).is_ok());
+    }
+
+    #[test]
+    fn test_coerce_value() {
+        assert_eq!(
+            coerce_value(&Value::Int(1), &ValueType::Num),
+            Some(Value::Num(1.0))
+        );
+        assert_eq!(coerce_value(&Value::Num(1.5), &ValueType::Int), None);
+        // the floats out of the integer range are not saturated
+        assert_eq!(coerce_value(&Value::Num(1e10), &ValueType::Int), None);
+        assert_eq!(
+            coerce_value(&Value::Num(-2147483648.0), &ValueType::Int),
+            Some(Value::Int(INT::MIN))
+        );
+        assert_eq!(
+            coerce_value(&Value::Num(2.0), &ValueType::Str),
+            Some(Value::Str("2".into()))
+        );
+        assert_eq!(
+            coerce_value(&Value::Str("2023-01-02".into()), &ValueType::Date),
+            parse_naivedate("2023-01-02").map(Value::Date)
+        );
+        assert_eq!(coerce_value(&Value::Str("x".into()), &ValueType::Int), None);
+    }
+
+    #[test]
+    fn test_coercion_lattice() {
+        assert_eq!(
+            widen_type(&ValueType::Int, &ValueType::Num),
+            Some(ValueType::Num)
+        );
+        assert_eq!(
+            widen_type(&ValueType::Num, &ValueType::Bool),
+            Some(ValueType::Num)
+        );
+        assert_eq!(
+            widen_type(&ValueType::Str, &ValueType::Date),
+            Some(ValueType::Date)
+        );
+        assert_eq!(
+            widen_type(&ValueType::None, &ValueType::DateTime),
+            Some(ValueType::DateTime)
+        );
+        assert_eq!(widen_type(&ValueType::Int, &ValueType::Str), None);
+        assert_eq!(widen_type(&ValueType::VecNum, &ValueType::Num), None);
+
+        assert_eq!(
+            widen_value(&Value::Bool(true), &ValueType::Num),
+            Some(Value::Num(1.0))
+        );
+        assert_eq!(
+            widen_value(&Value::Str("2023-01-02".into()), &ValueType::DateTime),
+            parse_naive_date_time("2023-01-02").map(Value::DateTime)
+        );
+        // narrowing is not widening
+        assert_eq!(widen_value(&Value::Num(1.0), &ValueType::Int), None);
+        assert_eq!(widen_value(&Value::Int(1), &ValueType::Int), None);
+        assert_eq!(widen_value(&Value::Str("x".into()), &ValueType::Date), None);
+        assert_eq!(
+            coerce_value(
+                &Value::Date(parse_naivedate("2023-01-02").unwrap()),
+                &ValueType::Str
+            ),
+            Some(Value::Str("2023-01-02".into()))
+        );
+    }
+}
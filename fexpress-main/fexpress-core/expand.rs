@@ -0,0 +1,244 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use itertools::Itertools;
+
+use crate::ast::core::ExpandExpr;
+use crate::feature_frame::{map_get, map_keys, unalias};
+use crate::map::HashMap;
+use crate::types::FLOAT;
+use crate::value::Value;
+
+/// Separates the feature name from the key in the names of the expanded columns
+pub const EXPANDED_KEY_SEPARATOR: &str = "__";
+/// Column name suffix of the column holding the keys without their own column
+pub const OTHER_KEY: &str = "other";
+
+/// Column created by `EXPAND` from a grouped feature
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpandedColumn {
+    pub feature: String,
+    /// `None` for the `__other` column
+    pub key: Option<String>,
+}
+
+pub fn expanded_column_name(feature: &str, key: &str) -> String {
+    format!("{}{}{}", feature, EXPANDED_KEY_SEPARATOR, key)
+}
+
+enum OutputColumn {
+    Feature(usize),
+    Key(usize, String),
+    Other(usize, BTreeSet<String>),
+}
+
+/// Feature columns after the grouped features were expanded
+pub(crate) struct ExpandedRows {
+    pub names: Vec<String>,
+    /// index of the feature each column was created from, with the expanded key
+    pub sources: Vec<(usize, Option<ExpandedColumn>)>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// The most frequent keys by the number of rows they appear in, sorted by name
+fn top_keys<'a>(values: impl Iterator<Item = &'a Value>, top: Option<usize>) -> Vec<String> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for value in values {
+        for key in map_keys(unalias(value)) {
+            *counts.entry(key).or_insert(0) += 1;
+        }
+    }
+    counts
+        .into_iter()
+        .sorted_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)))
+        .take(top.unwrap_or(usize::MAX))
+        .map(|(key, _)| key.to_string())
+        .sorted()
+        .collect()
+}
+
+// sum of the numeric values of the keys without their own column
+fn sum_other(value: &Value, keys: &BTreeSet<String>) -> Value {
+    match unalias(value) {
+        Value::MapNum(map) => Value::Num(
+            map.iter()
+                .filter(|(key, _)| !keys.contains(key.0.as_str()))
+                .map(|(_, value)| *value)
+                .sum(),
+        ),
+        Value::Map(map) => Value::Num(
+            map.iter()
+                .filter(|(key, _)| !keys.contains(key.0.as_str()))
+                .filter_map(|(_, value)| Option::<FLOAT>::from((**value).clone()))
+                .sum(),
+        ),
+        _ => Value::None,
+    }
+}
+
+/// Replaces the map columns of the features with `EXPAND` by one column per key.
+/// The keys come from `expanded_keys` if the feature is listed there, otherwise
+/// from the rows.
+pub(crate) fn expand_rows(
+    names: Vec<String>,
+    expansions: &[Option<ExpandExpr>],
+    rows: Vec<Vec<Value>>,
+    expanded_keys: &BTreeMap<String, Vec<String>>,
+) -> ExpandedRows {
+    if expansions.iter().all(Option::is_none) {
+        return ExpandedRows {
+            sources: (0..names.len()).map(|i| (i, None)).collect(),
+            names,
+            rows,
+        };
+    }
+
+    let mut columns = vec![];
+    for (i, (name, expand)) in names.iter().zip(expansions).enumerate() {
+        let expand = match expand {
+            Some(expand) => expand,
+            None => {
+                columns.push(OutputColumn::Feature(i));
+                continue;
+            }
+        };
+        let keys = match expanded_keys.get(name) {
+            Some(keys) => keys.clone(),
+            None => top_keys(rows.iter().map(|row| &row[i]), expand.top),
+        };
+        let other_keys: BTreeSet<String> = keys.iter().cloned().collect();
+        columns.extend(keys.into_iter().map(|key| OutputColumn::Key(i, key)));
+        if expand.other {
+            columns.push(OutputColumn::Other(i, other_keys));
+        }
+    }
+
+    let sources = columns
+        .iter()
+        .map(|column| match column {
+            OutputColumn::Feature(i) => (*i, None),
+            OutputColumn::Key(i, key) => (
+                *i,
+                Some(ExpandedColumn {
+                    feature: names[*i].clone(),
+                    key: Some(key.clone()),
+                }),
+            ),
+            OutputColumn::Other(i, _) => (
+                *i,
+                Some(ExpandedColumn {
+                    feature: names[*i].clone(),
+                    key: None,
+                }),
+            ),
+        })
+        .collect_vec();
+    let expanded_names = sources
+        .iter()
+        .map(|(i, expanded)| match expanded {
+            None => names[*i].clone(),
+            Some(ExpandedColumn { key: Some(key), .. }) => expanded_column_name(&names[*i], key),
+            Some(ExpandedColumn { key: None, .. }) => expanded_column_name(&names[*i], OTHER_KEY),
+        })
+        .collect();
+    let rows = rows
+        .into_iter()
+        .map(|row| {
+            columns
+                .iter()
+                .map(|column| match column {
+                    OutputColumn::Feature(i) => row[*i].clone(),
+                    OutputColumn::Key(i, key) => map_get(unalias(&row[*i]), key),
+                    OutputColumn::Other(i, keys) => sum_other(&row[*i], keys),
+                })
+                .collect()
+        })
+        .collect();
+
+    ExpandedRows {
+        names: expanded_names,
+        sources,
+        rows,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::AttributeName;
+
+    fn counts(entries: &[(&str, FLOAT)]) -> Value {
+        Value::MapNum(
+            entries
+                .iter()
+                .map(|(k, v)| (AttributeName(from_str!(*k)), *v))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_expand_rows() {
+        let names = vec!["n".to_string(), "by_category".to_string()];
+        let expansions = vec![
+            None,
+            Some(ExpandExpr {
+                top: Some(2),
+                other: true,
+            }),
+        ];
+        let rows = vec![
+            vec![Value::Int(1), counts(&[("a", 1.0), ("b", 2.0)])],
+            vec![Value::Int(2), counts(&[("b", 3.0), ("c", 4.0), ("d", 5.0)])],
+            vec![Value::Int(3), Value::None],
+            vec![Value::Int(4), counts(&[("a", 6.0)])],
+        ];
+
+        // a and b are the most frequent keys
+        let trained = expand_rows(names.clone(), &expansions, rows, &BTreeMap::new());
+        assert_eq!(
+            trained.names,
+            vec![
+                "n",
+                "by_category__a",
+                "by_category__b",
+                "by_category__other"
+            ]
+        );
+        assert_eq!(
+            trained.rows[1],
+            vec![Value::Int(2), Value::None, Value::Num(3.0), Value::Num(9.0)]
+        );
+        assert_eq!(
+            trained.rows[2],
+            vec![Value::Int(3), Value::None, Value::None, Value::None]
+        );
+        assert_eq!(
+            trained.sources[3],
+            (
+                1,
+                Some(ExpandedColumn {
+                    feature: "by_category".into(),
+                    key: None
+                })
+            )
+        );
+
+        // scoring keeps the columns of the training run, unseen keys go to other
+        let frozen: BTreeMap<String, Vec<String>> = vec![(
+            "by_category".to_string(),
+            vec!["a".to_string(), "b".to_string()],
+        )]
+        .into_iter()
+        .collect();
+        let scored = expand_rows(
+            names,
+            &expansions,
+            vec![vec![Value::Int(5), counts(&[("e", 7.0), ("e2", 1.0)])]],
+            &frozen,
+        );
+        assert_eq!(scored.names, trained.names);
+        assert_eq!(
+            scored.rows[0],
+            vec![Value::Int(5), Value::None, Value::None, Value::Num(8.0)]
+        );
+    }
+}
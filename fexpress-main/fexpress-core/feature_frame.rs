@@ -0,0 +1,983 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::iter::FromIterator;
+
+use anyhow::{bail, Result};
+use bit_vec::BitVec;
+use chrono::{NaiveDate, NaiveDateTime};
+use itertools::Itertools;
+use prettytable::format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR;
+use prettytable::{Row as PTRow, Table};
+use rayon::prelude::*;
+
+use crate::event::{AttributeName, EntityType};
+use crate::expand::ExpandedColumn;
+use crate::obs_dates::ObservationTime;
+use crate::sstring::SmallString;
+use crate::types::{Entities, FLOAT, INT};
+use crate::utils::transpose_vv;
+use crate::value::{Value, ValueType};
+
+// strings are dictionary encoded when at most this fraction of the values is distinct
+const DICTIONARY_MAX_DISTINCT_RATIO: f64 = 0.5;
+
+/// Values of a typed column. Null rows hold a placeholder value and are marked
+/// in the validity bitmap of the column.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColumnValues {
+    /// column without any value
+    Null(usize),
+    Bool(Vec<bool>),
+    Num(Vec<FLOAT>),
+    Int(Vec<INT>),
+    Str(Vec<SmallString>),
+    Dictionary {
+        keys: Vec<u32>,
+        dictionary: Vec<SmallString>,
+    },
+    Date(Vec<NaiveDate>),
+    DateTime(Vec<NaiveDateTime>),
+    /// the items of row `i` are the rows `offsets[i]..offsets[i + 1]` of `values`
+    List {
+        offsets: Vec<usize>,
+        values: Box<TypedColumn>,
+    },
+    /// one child column per key of the maps, sorted by key
+    Struct(Vec<(SmallString, TypedColumn)>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypedColumn {
+    pub validity: BitVec,
+    pub values: ColumnValues,
+}
+
+pub(crate) fn unalias(value: &Value) -> &Value {
+    match value {
+        Value::ValueWithAlias(value) => unalias(&value.value),
+        value => value,
+    }
+}
+
+fn is_null(value: &Value) -> bool {
+    matches!(value, Value::None | Value::NotCalculatedYet)
+}
+
+fn value_type(value: &Value) -> ValueType {
+    match unalias(value) {
+        Value::None | Value::NotCalculatedYet => ValueType::None,
+        Value::Bool(_) => ValueType::Bool,
+        Value::Num(_) => ValueType::Num,
+        Value::Int(_) => ValueType::Int,
+        Value::Date(_) => ValueType::Date,
+        Value::DateTime(_) => ValueType::DateTime,
+        Value::VecBool(_) => ValueType::VecBool,
+        Value::VecNum(_) => ValueType::VecNum,
+        Value::VecInt(_) => ValueType::VecInt,
+        Value::VecStr(_) => ValueType::VecCat,
+        Value::MapNum(_) => ValueType::MapNum,
+        Value::MapStr(_) => ValueType::MapStr,
+        Value::Map(_) => ValueType::Map,
+        _ => ValueType::Str,
+    }
+}
+
+fn is_map_type(value_type: &ValueType) -> bool {
+    matches!(
+        value_type,
+        ValueType::MapNum | ValueType::MapStr | ValueType::Map
+    )
+}
+
+fn merge_types(a: ValueType, b: ValueType) -> ValueType {
+    match (a, b) {
+        (a, b) if a == b => a,
+        (ValueType::None, b) => b,
+        (a, ValueType::None) => a,
+        (ValueType::Int, ValueType::Num) | (ValueType::Num, ValueType::Int) => ValueType::Num,
+        (a, b) if is_map_type(&a) && is_map_type(&b) => ValueType::Map,
+        // values of different types are kept as text
+        _ => ValueType::Str,
+    }
+}
+
+/// Infers the type of a column, `ValueType::None` if all the values are null
+pub fn infer_value_type(values: &[Value]) -> ValueType {
+    values.iter().fold(ValueType::None, |typ, value| {
+        merge_types(typ, value_type(value))
+    })
+}
+
+// true if the value is not null and can be stored in a column of the type
+fn fits(value: &Value, column_type: &ValueType) -> bool {
+    match column_type {
+        _ if is_null(value) => false,
+        ValueType::Str | ValueType::Wildcard | ValueType::NotCalculatedYet => true,
+        column_type => merge_types(value_type(value), column_type.clone()) == *column_type,
+    }
+}
+
+pub(crate) fn map_keys(value: &Value) -> Vec<&str> {
+    match value {
+        Value::MapNum(map) => map.keys().map(|k| k.0.as_str()).collect(),
+        Value::MapStr(map) => map.keys().map(|k| k.0.as_str()).collect(),
+        Value::Map(map) => map.keys().map(|k| k.0.as_str()).collect(),
+        _ => vec![],
+    }
+}
+
+pub(crate) fn map_get(value: &Value, key: &str) -> Value {
+    match value {
+        Value::MapNum(map) => map.get(key).map(|v| Value::Num(*v)),
+        Value::MapStr(map) => map.get(key).map(|v| Value::Str(v.clone())),
+        Value::Map(map) => map.get(key).map(|v| *v.clone()),
+        _ => None,
+    }
+    .unwrap_or(Value::None)
+}
+
+fn string_values(values: &[&Value], validity: &BitVec) -> ColumnValues {
+    let strings = values
+        .iter()
+        .map(|value| match value {
+            Value::Str(s) => s.clone(),
+            value if is_null(value) => from_str!(""),
+            value => from_string!(value.to_string()),
+        })
+        .collect_vec();
+
+    let n_valid = validity.iter().filter(|valid| *valid).count();
+    let distinct: BTreeSet<&SmallString> = strings
+        .iter()
+        .zip(validity.iter())
+        .filter(|(_, valid)| *valid)
+        .map(|(s, _)| s)
+        .collect();
+    if n_valid == 0 || distinct.len() as f64 > DICTIONARY_MAX_DISTINCT_RATIO * n_valid as f64 {
+        return ColumnValues::Str(strings);
+    }
+
+    // the dictionary keeps the order in which the values were seen
+    let mut dictionary: Vec<SmallString> = vec![];
+    let mut positions: crate::map::HashMap<SmallString, u32> = crate::map::HashMap::new();
+    let keys = strings
+        .into_iter()
+        .zip(validity.iter())
+        .map(|(s, valid)| {
+            if !valid {
+                return 0;
+            }
+            *positions.entry(s.clone()).or_insert_with(|| {
+                dictionary.push(s);
+                (dictionary.len() - 1) as u32
+            })
+        })
+        .collect();
+    ColumnValues::Dictionary { keys, dictionary }
+}
+
+fn take_vec<T: Clone>(values: &[T], indices: &[usize]) -> Vec<T> {
+    indices.iter().map(|i| values[*i].clone()).collect()
+}
+
+impl TypedColumn {
+    /// Builds the column with the type inferred from the values
+    pub fn from_values(values: &[Value]) -> TypedColumn {
+        Self::from_values_with_type(values, &infer_value_type(values))
+    }
+
+    /// Builds the column of the given type, values of a different type are
+    /// converted to text in string columns and are null otherwise
+    pub fn from_values_with_type(values: &[Value], value_type: &ValueType) -> TypedColumn {
+        let values = values.iter().map(unalias).collect_vec();
+        let validity: BitVec = values.iter().map(|v| fits(v, value_type)).collect();
+        let column_values = match value_type {
+            ValueType::None => ColumnValues::Null(values.len()),
+            ValueType::Bool => ColumnValues::Bool(
+                values
+                    .iter()
+                    .map(|v| matches!(v, Value::Bool(true)))
+                    .collect(),
+            ),
+            ValueType::Int => ColumnValues::Int(
+                values
+                    .iter()
+                    .map(|v| match v {
+                        Value::Int(v) => *v,
+                        _ => 0,
+                    })
+                    .collect(),
+            ),
+            ValueType::Num => ColumnValues::Num(
+                values
+                    .iter()
+                    .map(|v| match v {
+                        Value::Num(v) => *v,
+                        Value::Int(v) => *v as FLOAT,
+                        _ => 0.0,
+                    })
+                    .collect(),
+            ),
+            ValueType::Date => ColumnValues::Date(
+                values
+                    .iter()
+                    .map(|v| match v {
+                        Value::Date(v) => *v,
+                        _ => NaiveDate::default(),
+                    })
+                    .collect(),
+            ),
+            ValueType::DateTime => ColumnValues::DateTime(
+                values
+                    .iter()
+                    .map(|v| match v {
+                        Value::DateTime(v) => *v,
+                        _ => NaiveDateTime::default(),
+                    })
+                    .collect(),
+            ),
+            ValueType::VecBool | ValueType::VecNum | ValueType::VecInt | ValueType::VecCat => {
+                let item_type = match value_type {
+                    ValueType::VecBool => ValueType::Bool,
+                    ValueType::VecNum => ValueType::Num,
+                    ValueType::VecInt => ValueType::Int,
+                    _ => ValueType::Str,
+                };
+                let mut offsets = vec![0];
+                let mut items = vec![];
+                for value in &values {
+                    match value {
+                        Value::VecBool(v) => items.extend(v.iter().map(|v| Value::Bool(*v))),
+                        Value::VecNum(v) => items.extend(v.iter().map(|v| Value::Num(*v))),
+                        Value::VecInt(v) => items.extend(v.iter().map(|v| Value::Int(*v))),
+                        Value::VecStr(v) => items.extend(v.iter().map(|v| Value::Str(v.clone()))),
+                        _ => {}
+                    }
+                    offsets.push(items.len());
+                }
+                ColumnValues::List {
+                    offsets,
+                    values: Box::new(Self::from_values_with_type(&items, &item_type)),
+                }
+            }
+            ValueType::MapNum | ValueType::MapStr | ValueType::Map => {
+                let keys: BTreeSet<&str> = values.iter().flat_map(|v| map_keys(v)).collect();
+                ColumnValues::Struct(
+                    keys.into_iter()
+                        .map(|key| {
+                            let child = values.iter().map(|v| map_get(v, key)).collect_vec();
+                            (from_str!(key), Self::from_values(&child))
+                        })
+                        .collect(),
+                )
+            }
+            _ => string_values(&values, &validity),
+        };
+        TypedColumn {
+            validity,
+            values: column_values,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.validity.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validity.is_empty()
+    }
+
+    pub fn is_valid(&self, row: usize) -> bool {
+        self.validity.get(row).unwrap_or(false)
+    }
+
+    pub fn null_count(&self) -> usize {
+        self.validity.iter().filter(|valid| !*valid).count()
+    }
+
+    /// Type of the values returned by `get`
+    pub fn value_type(&self) -> ValueType {
+        match &self.values {
+            ColumnValues::Null(_) => ValueType::None,
+            ColumnValues::Bool(_) => ValueType::Bool,
+            ColumnValues::Num(_) => ValueType::Num,
+            ColumnValues::Int(_) => ValueType::Int,
+            ColumnValues::Str(_) | ColumnValues::Dictionary { .. } => ValueType::Str,
+            ColumnValues::Date(_) => ValueType::Date,
+            ColumnValues::DateTime(_) => ValueType::DateTime,
+            ColumnValues::List { values, .. } => match values.value_type() {
+                ValueType::Bool => ValueType::VecBool,
+                ValueType::Num => ValueType::VecNum,
+                ValueType::Int => ValueType::VecInt,
+                _ => ValueType::VecCat,
+            },
+            ColumnValues::Struct(fields) => {
+                let types = fields
+                    .iter()
+                    .map(|(_, column)| column.value_type())
+                    .collect_vec();
+                match types.first() {
+                    Some(ValueType::Num) if types.iter().all(|t| *t == ValueType::Num) => {
+                        ValueType::MapNum
+                    }
+                    Some(ValueType::Str) if types.iter().all(|t| *t == ValueType::Str) => {
+                        ValueType::MapStr
+                    }
+                    _ => ValueType::Map,
+                }
+            }
+        }
+    }
+
+    /// Value of the row, `Value::None` for null rows
+    pub fn get(&self, row: usize) -> Value {
+        if !self.is_valid(row) {
+            return Value::None;
+        }
+        match &self.values {
+            ColumnValues::Null(_) => Value::None,
+            ColumnValues::Bool(v) => Value::Bool(v[row]),
+            ColumnValues::Num(v) => Value::Num(v[row]),
+            ColumnValues::Int(v) => Value::Int(v[row]),
+            ColumnValues::Str(v) => Value::Str(v[row].clone()),
+            ColumnValues::Dictionary { keys, dictionary } => {
+                Value::Str(dictionary[keys[row] as usize].clone())
+            }
+            ColumnValues::Date(v) => Value::Date(v[row]),
+            ColumnValues::DateTime(v) => Value::DateTime(v[row]),
+            ColumnValues::List { offsets, values } => {
+                let items = (offsets[row]..offsets[row + 1]).map(|i| values.get(i));
+                match self.value_type() {
+                    ValueType::VecBool => Value::VecBool(
+                        items
+                            .filter_map(|v| match v {
+                                Value::Bool(v) => Some(v),
+                                _ => None,
+                            })
+                            .collect(),
+                    ),
+                    ValueType::VecNum => Value::VecNum(
+                        items
+                            .filter_map(|v| match v {
+                                Value::Num(v) => Some(v),
+                                _ => None,
+                            })
+                            .collect(),
+                    ),
+                    ValueType::VecInt => Value::VecInt(
+                        items
+                            .filter_map(|v| match v {
+                                Value::Int(v) => Some(v),
+                                _ => None,
+                            })
+                            .collect(),
+                    ),
+                    _ => Value::VecStr(
+                        items
+                            .filter_map(|v| match v {
+                                Value::Str(v) => Some(v),
+                                _ => None,
+                            })
+                            .collect(),
+                    ),
+                }
+            }
+            ColumnValues::Struct(fields) => {
+                let entries = fields
+                    .iter()
+                    .map(|(key, column)| (AttributeName(key.clone()), column.get(row)))
+                    .filter(|(_, value)| !is_null(value));
+                match self.value_type() {
+                    ValueType::MapNum => Value::MapNum(
+                        entries
+                            .filter_map(|(k, v)| Option::<FLOAT>::from(v).map(|v| (k, v)))
+                            .collect(),
+                    ),
+                    ValueType::MapStr => Value::MapStr(
+                        entries
+                            .filter_map(|(k, v)| match v {
+                                Value::Str(v) => Some((k, v)),
+                                _ => None,
+                            })
+                            .collect(),
+                    ),
+                    _ => Value::Map(entries.map(|(k, v)| (k, Box::new(v))).collect()),
+                }
+            }
+        }
+    }
+
+    /// Selects the rows with the given indices
+    pub fn take(&self, indices: &[usize]) -> TypedColumn {
+        let validity = indices.iter().map(|i| self.is_valid(*i)).collect();
+        let values = match &self.values {
+            ColumnValues::Null(_) => ColumnValues::Null(indices.len()),
+            ColumnValues::Bool(v) => ColumnValues::Bool(take_vec(v, indices)),
+            ColumnValues::Num(v) => ColumnValues::Num(take_vec(v, indices)),
+            ColumnValues::Int(v) => ColumnValues::Int(take_vec(v, indices)),
+            ColumnValues::Str(v) => ColumnValues::Str(take_vec(v, indices)),
+            ColumnValues::Dictionary { keys, dictionary } => ColumnValues::Dictionary {
+                keys: take_vec(keys, indices),
+                dictionary: dictionary.clone(),
+            },
+            ColumnValues::Date(v) => ColumnValues::Date(take_vec(v, indices)),
+            ColumnValues::DateTime(v) => ColumnValues::DateTime(take_vec(v, indices)),
+            ColumnValues::List { offsets, values } => {
+                let mut new_offsets = vec![0];
+                let mut item_indices = vec![];
+                for i in indices {
+                    item_indices.extend(offsets[*i]..offsets[*i + 1]);
+                    new_offsets.push(item_indices.len());
+                }
+                ColumnValues::List {
+                    offsets: new_offsets,
+                    values: Box::new(values.take(&item_indices)),
+                }
+            }
+            ColumnValues::Struct(fields) => ColumnValues::Struct(
+                fields
+                    .iter()
+                    .map(|(key, column)| (key.clone(), column.take(indices)))
+                    .collect(),
+            ),
+        };
+        TypedColumn { validity, values }
+    }
+
+    /// Sum of the numeric values, null rows are skipped
+    pub fn sum(&self) -> FLOAT {
+        (0..self.len())
+            .filter_map(|row| Option::<FLOAT>::from(self.get(row)))
+            .sum()
+    }
+}
+
+/// Description of a feature column
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeatureMeta {
+    pub name: String,
+    /// expression the feature was calculated from, if known
+    pub expr: Option<String>,
+    /// type inferred from the calculated values
+    pub value_type: ValueType,
+    /// true if the feature is a target defined with `LABEL`
+    pub label: bool,
+    /// feature and key the column was created from by `GROUP BY ... EXPAND`
+    pub expanded: Option<ExpandedColumn>,
+}
+
+impl FeatureMeta {
+    pub fn new(name: String) -> FeatureMeta {
+        FeatureMeta {
+            name,
+            expr: None,
+            value_type: ValueType::None,
+            label: false,
+            expanded: None,
+        }
+    }
+}
+
+/// Entities and observation time of each row of a frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameIndex {
+    /// one column per entity type, null if the row does not have that entity
+    pub entities: Vec<(EntityType, TypedColumn)>,
+    pub obs_dt: TypedColumn,
+    /// id of the event that defined the observation time, only present if the
+    /// observation times come from events
+    pub event_id: Option<TypedColumn>,
+}
+
+impl FrameIndex {
+    pub fn new(index: &[(Entities, ObservationTime)]) -> FrameIndex {
+        let entity_types: BTreeSet<&EntityType> = index
+            .iter()
+            .flat_map(|(entities, _)| entities.keys())
+            .collect();
+        let entities = entity_types
+            .into_iter()
+            .map(|entity_type| {
+                let values = index
+                    .iter()
+                    .map(|(entities, _)| match entities.get(entity_type) {
+                        Some(id) => Value::Str(id.0.clone()),
+                        None => Value::None,
+                    })
+                    .collect_vec();
+                (
+                    entity_type.clone(),
+                    TypedColumn::from_values_with_type(&values, &ValueType::Str),
+                )
+            })
+            .collect();
+        let obs_dt = index
+            .iter()
+            .map(|(_, obs_time)| Value::DateTime(obs_time.datetime))
+            .collect_vec();
+        let event_id = index
+            .iter()
+            .any(|(_, obs_time)| obs_time.event_id.is_some())
+            .then(|| {
+                let event_id = index
+                    .iter()
+                    .map(|(_, obs_time)| match &obs_time.event_id {
+                        Some(event_id) => Value::Str(event_id.clone()),
+                        None => Value::None,
+                    })
+                    .collect_vec();
+                TypedColumn::from_values_with_type(&event_id, &ValueType::Str)
+            });
+        FrameIndex {
+            entities,
+            obs_dt: TypedColumn::from_values_with_type(&obs_dt, &ValueType::DateTime),
+            event_id,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.obs_dt.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.obs_dt.is_empty()
+    }
+
+    /// Index columns with their names, entity columns are named by the entity type
+    pub fn columns(&self) -> Vec<(String, &TypedColumn)> {
+        self.entities
+            .iter()
+            .map(|(entity_type, column)| (entity_type.0.to_string(), column))
+            .chain(Some(("obs_dt".to_string(), &self.obs_dt)))
+            .chain(
+                self.event_id
+                    .as_ref()
+                    .map(|event_id| ("event_id".to_string(), event_id)),
+            )
+            .collect()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.columns().into_iter().map(|(name, _)| name).collect()
+    }
+
+    /// Rows of index values in the order of `columns`
+    pub fn to_rows(&self) -> Vec<Vec<Value>> {
+        let columns = self.columns();
+        (0..self.len())
+            .map(|row| columns.iter().map(|(_, column)| column.get(row)).collect())
+            .collect()
+    }
+
+    pub fn take(&self, indices: &[usize]) -> FrameIndex {
+        FrameIndex {
+            entities: self
+                .entities
+                .iter()
+                .map(|(entity_type, column)| (entity_type.clone(), column.take(indices)))
+                .collect(),
+            obs_dt: self.obs_dt.take(indices),
+            event_id: self
+                .event_id
+                .as_ref()
+                .map(|event_id| event_id.take(indices)),
+        }
+    }
+}
+
+/// Result of a query with one typed column per feature
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeatureFrame {
+    pub index: Option<FrameIndex>,
+    pub features: Vec<FeatureMeta>,
+    pub columns: Vec<TypedColumn>,
+    n_rows: usize,
+}
+
+impl FeatureFrame {
+    pub fn new(
+        index: Option<FrameIndex>,
+        features: Vec<FeatureMeta>,
+        columns: Vec<TypedColumn>,
+    ) -> Result<FeatureFrame> {
+        if features.len() != columns.len() {
+            bail!(
+                "{} features but {} columns in the frame",
+                features.len(),
+                columns.len()
+            );
+        }
+        let n_rows = match (&index, columns.first()) {
+            (Some(index), _) => index.len(),
+            (None, Some(column)) => column.len(),
+            (None, None) => 0,
+        };
+        if let Some(column) = columns.iter().find(|column| column.len() != n_rows) {
+            bail!(
+                "Column of length {} in the frame of {} rows",
+                column.len(),
+                n_rows
+            );
+        }
+        Ok(FeatureFrame {
+            index,
+            features,
+            columns,
+            n_rows,
+        })
+    }
+
+    /// Builds the frame from rows of values, the types of the features are inferred
+    pub fn from_rows(
+        index: Option<FrameIndex>,
+        features: Vec<FeatureMeta>,
+        rows: Vec<Vec<Value>>,
+    ) -> Result<FeatureFrame> {
+        if let Some(row) = rows.iter().find(|row| row.len() != features.len()) {
+            bail!(
+                "Row of {} values for {} features",
+                row.len(),
+                features.len()
+            );
+        }
+        let values = if rows.is_empty() {
+            vec![vec![]; features.len()]
+        } else {
+            transpose_vv(rows)
+        };
+        let columns = values
+            .par_iter()
+            .map(|values| TypedColumn::from_values(values))
+            .collect::<Vec<_>>();
+        let features = features
+            .into_iter()
+            .zip(values.iter())
+            .map(|(feature, values)| FeatureMeta {
+                value_type: infer_value_type(values),
+                ..feature
+            })
+            .collect();
+        Self::new(index, features, columns)
+    }
+
+    /// Builds the frame from the result of `EventContext::extract_records_from_expr`
+    pub fn from_records(names: Vec<String>, rows: Vec<Vec<Value>>) -> Result<FeatureFrame> {
+        Self::from_rows(
+            None,
+            names.into_iter().map(FeatureMeta::new).collect(),
+            rows,
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        self.n_rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n_rows == 0
+    }
+
+    /// Keys of the features with `GROUP BY ... EXPAND`, pass them as
+    /// `QueryConfig::expanded_keys` to get the same columns in later queries
+    pub fn expanded_keys(&self) -> BTreeMap<String, Vec<String>> {
+        let mut keys: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for column in self.features.iter().filter_map(|f| f.expanded.as_ref()) {
+            let feature_keys = keys.entry(column.feature.clone()).or_default();
+            if let Some(key) = &column.key {
+                feature_keys.push(key.clone());
+            }
+        }
+        keys
+    }
+
+    pub fn feature_names(&self) -> Vec<&str> {
+        self.features
+            .iter()
+            .map(|feature| feature.name.as_str())
+            .collect()
+    }
+
+    /// Feature column or index column with the given name
+    pub fn col(&self, name: &str) -> Option<&TypedColumn> {
+        self.features
+            .iter()
+            .position(|feature| feature.name == name)
+            .map(|i| &self.columns[i])
+            .or_else(|| {
+                self.index.as_ref().and_then(|index| {
+                    index
+                        .columns()
+                        .into_iter()
+                        .find(|(column_name, _)| column_name == name)
+                        .map(|(_, column)| column)
+                })
+            })
+    }
+
+    pub fn row(&self, row: usize) -> Vec<Value> {
+        self.columns.iter().map(|column| column.get(row)).collect()
+    }
+
+    /// Rows of feature values in the layout of `EventContext::extract_records_from_expr`
+    pub fn to_rows(&self) -> Vec<Vec<Value>> {
+        (0..self.n_rows).map(|row| self.row(row)).collect()
+    }
+
+    /// Selects the rows with the given indices
+    pub fn take(&self, indices: &[usize]) -> FeatureFrame {
+        FeatureFrame {
+            index: self.index.as_ref().map(|index| index.take(indices)),
+            features: self.features.clone(),
+            columns: self
+                .columns
+                .iter()
+                .map(|column| column.take(indices))
+                .collect(),
+            n_rows: indices.len(),
+        }
+    }
+
+    /// Selects the first n rows
+    pub fn head(&self, n: usize) -> FeatureFrame {
+        self.take(&(0..n.min(self.n_rows)).collect_vec())
+    }
+
+    /// Selects the last n rows
+    pub fn tail(&self, n: usize) -> FeatureFrame {
+        self.take(&(self.n_rows.saturating_sub(n)..self.n_rows).collect_vec())
+    }
+
+    /// Selects feature columns by names
+    pub fn select_columns(&self, names: Vec<&str>) -> Option<FeatureFrame> {
+        let indices = names
+            .iter()
+            .map(|name| self.features.iter().position(|f| f.name == *name))
+            .collect::<Option<Vec<usize>>>()?;
+        Some(FeatureFrame {
+            index: self.index.clone(),
+            features: take_vec(&self.features, &indices),
+            columns: take_vec(&self.columns, &indices),
+            n_rows: self.n_rows,
+        })
+    }
+
+    /// Keeps the first row for each combination of values of the key columns
+    pub fn drop_duplicates(&self, keys: Vec<&str>) -> Option<FeatureFrame> {
+        let key_columns = keys
+            .iter()
+            .map(|key| self.col(key))
+            .collect::<Option<Vec<_>>>()?;
+
+        let mut unique_rows: HashSet<Vec<Value>> = HashSet::new();
+        let indices = (0..self.n_rows)
+            .filter(|row| unique_rows.insert(key_columns.iter().map(|c| c.get(*row)).collect()))
+            .collect_vec();
+
+        Some(self.take(&indices))
+    }
+
+    /// Prints the index and the features as a table
+    pub fn display(&self) {
+        let mut table = Table::new();
+        table.set_format(*FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+        let index_columns = self
+            .index
+            .as_ref()
+            .map(|index| index.columns())
+            .unwrap_or_default();
+        let columns = index_columns
+            .into_iter()
+            .chain(
+                self.features
+                    .iter()
+                    .map(|feature| feature.name.clone())
+                    .zip(self.columns.iter()),
+            )
+            .collect_vec();
+
+        table.add_row(PTRow::from_iter(columns.iter().map(|(name, _)| name)));
+        for row in 0..self.n_rows {
+            table.add_row(PTRow::from_iter(
+                columns
+                    .iter()
+                    .map(|(_, column)| column.get(row).to_string()),
+            ));
+        }
+
+        table.printstd();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::EntityID;
+    use crate::map::HashMap;
+
+    fn map_num(entries: &[(&str, FLOAT)]) -> Value {
+        Value::MapNum(
+            entries
+                .iter()
+                .map(|(k, v)| (AttributeName(from_str!(*k)), *v))
+                .collect::<HashMap<_, _>>(),
+        )
+    }
+
+    #[test]
+    fn test_typed_columns() {
+        let frame = FeatureFrame::from_records(
+            vec![
+                "count".into(),
+                "avg".into(),
+                "category".into(),
+                "items".into(),
+                "by_category".into(),
+                "empty".into(),
+            ],
+            vec![
+                vec![
+                    Value::Int(1),
+                    Value::Num(0.5),
+                    Value::Str("a".into()),
+                    Value::VecInt(vec![1, 2]),
+                    map_num(&[("x", 1.0)]),
+                    Value::None,
+                ],
+                vec![
+                    Value::Int(2),
+                    Value::Int(3),
+                    Value::Str("a".into()),
+                    Value::None,
+                    map_num(&[("y", 2.0)]),
+                    Value::None,
+                ],
+                vec![
+                    Value::None,
+                    Value::None,
+                    Value::Str("b".into()),
+                    Value::VecInt(vec![3]),
+                    Value::None,
+                    Value::None,
+                ],
+                vec![
+                    Value::Int(4),
+                    Value::Num(1.5),
+                    Value::Str("a".into()),
+                    Value::VecInt(vec![]),
+                    map_num(&[("x", 3.0), ("y", 4.0)]),
+                    Value::None,
+                ],
+            ],
+        )
+        .unwrap();
+
+        let types = frame
+            .features
+            .iter()
+            .map(|f| f.value_type.clone())
+            .collect_vec();
+        assert_eq!(
+            types,
+            vec![
+                ValueType::Int,
+                ValueType::Num,
+                ValueType::Str,
+                ValueType::VecInt,
+                ValueType::MapNum,
+                ValueType::None
+            ]
+        );
+
+        let count = frame.col("count").unwrap();
+        assert_eq!(count.values, ColumnValues::Int(vec![1, 2, 0, 4]));
+        assert_eq!(count.null_count(), 1);
+        assert_eq!(count.sum(), 7.0);
+        assert_eq!(
+            frame.col("avg").unwrap().values,
+            ColumnValues::Num(vec![0.5, 3.0, 0.0, 1.5])
+        );
+        assert_eq!(
+            frame.col("category").unwrap().values,
+            ColumnValues::Dictionary {
+                keys: vec![0, 0, 1, 0],
+                dictionary: vec!["a".into(), "b".into()]
+            }
+        );
+        match &frame.col("items").unwrap().values {
+            ColumnValues::List { offsets, values } => {
+                assert_eq!(offsets, &vec![0, 2, 2, 3, 3]);
+                assert_eq!(values.values, ColumnValues::Int(vec![1, 2, 3]));
+            }
+            other => panic!("Unexpected column {:?}", other),
+        }
+        match &frame.col("by_category").unwrap().values {
+            ColumnValues::Struct(fields) => {
+                assert_eq!(
+                    fields.iter().map(|(k, _)| k.as_str()).collect_vec(),
+                    vec!["x", "y"]
+                );
+                assert_eq!(
+                    fields[0].1.values,
+                    ColumnValues::Num(vec![1.0, 0.0, 0.0, 3.0])
+                );
+            }
+            other => panic!("Unexpected column {:?}", other),
+        }
+        assert_eq!(frame.col("empty").unwrap().values, ColumnValues::Null(4));
+
+        // values round trip, integers in the numeric column are widened
+        let rows = frame.to_rows();
+        assert_eq!(rows[1][1], Value::Num(3.0));
+        assert_eq!(rows[1][3], Value::None);
+        assert_eq!(rows[3][3], Value::VecInt(vec![]));
+        assert_eq!(rows[3][4], map_num(&[("x", 3.0), ("y", 4.0)]));
+        assert_eq!(rows[2][2], Value::Str("b".into()));
+    }
+
+    #[test]
+    fn test_frame_index() {
+        let user = |id: &str| -> Entities {
+            vec![(EntityType("user".into()), EntityID(from_str!(id)))]
+                .into_iter()
+                .collect()
+        };
+        let obs_time =
+            |day: u32| ObservationTime::from(NaiveDate::from_ymd(2023, 1, day).and_hms(0, 0, 0));
+        let index = FrameIndex::new(&[
+            (user("a"), obs_time(1)),
+            (user("a"), obs_time(2)),
+            (user("b"), obs_time(1)),
+        ]);
+        let frame = FeatureFrame::from_rows(
+            Some(index),
+            vec![FeatureMeta::new("mixed".into())],
+            vec![
+                vec![Value::Int(1)],
+                vec![Value::Bool(true)],
+                vec![Value::Int(1)],
+            ],
+        )
+        .unwrap();
+        assert_eq!(frame.features[0].value_type, ValueType::Str);
+
+        let dedup = frame.drop_duplicates(vec!["user"]).unwrap();
+        assert_eq!(dedup.len(), 2);
+        assert_eq!(
+            dedup.col("obs_dt").unwrap().get(1),
+            Value::DateTime(NaiveDate::from_ymd(2023, 1, 1).and_hms(0, 0, 0))
+        );
+        assert_eq!(dedup.col("user").unwrap().get(1), Value::Str("b".into()));
+        assert!(dedup.col("event_id").is_none());
+        assert_eq!(
+            dedup.index.as_ref().unwrap().names(),
+            vec!["user", "obs_dt"]
+        );
+        assert_eq!(frame.tail(1).row(0), vec![Value::Str("1".into())]);
+        assert!(FeatureFrame::from_rows(
+            Some(FrameIndex::new(&[])),
+            vec![FeatureMeta::new("x".into())],
+            vec![vec![Value::Int(1)]]
+        )
+        .is_err());
+    }
+}
@@ -1,10 +1,13 @@
+use crate::feature_frame::{FeatureFrame, FeatureMeta, FrameIndex};
 use crate::obs_dates::ObservationTime;
 use crate::sstring::SmallString;
 use crate::types::Entities;
 use crate::value::Value;
 use anyhow::Result;
 use csv::Writer;
+use itertools::Itertools;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 
 type Feature = SmallString;
 type Index = (Entities, ObservationTime);
@@ -186,18 +189,15 @@ impl FeatureMatrix {
     }
 }
 
-pub struct DenseFeatureMatrix {
-    pub matrix: Vec<Vec<Value>>,
-    pub index_mapping: HashMap<usize, Index>,
-    pub feature_mapping: HashMap<usize, Feature>,
-}
+impl TryFrom<FeatureMatrix> for FeatureFrame {
+    type Error = anyhow::Error;
 
-impl From<FeatureMatrix> for DenseFeatureMatrix {
-    fn from(matrix: FeatureMatrix) -> Self {
-        let indices: Vec<Index> = matrix.matrix.keys().cloned().collect();
-        let features: Vec<Feature> = matrix.feature_mapping().values().cloned().collect();
+    fn try_from(matrix: FeatureMatrix) -> Result<Self> {
+        // sorted so that the row and column order does not depend on the hashing
+        let indices: Vec<Index> = matrix.matrix.keys().cloned().sorted().collect();
+        let features: Vec<Feature> = matrix.feature_mapping().into_values().sorted().collect();
 
-        let dense_matrix: Vec<Vec<Value>> = indices
+        let rows: Vec<Vec<Value>> = indices
             .iter()
             .map(|index| {
                 features
@@ -214,17 +214,13 @@ impl From<FeatureMatrix> for DenseFeatureMatrix {
             })
             .collect();
 
-        let index_mapping: HashMap<usize, Index> = indices
-            .into_iter()
-            .enumerate()
-            .map(|(i, index)| (i, index))
-            .collect();
-        let feature_mapping = matrix.feature_mapping();
-
-        Self {
-            matrix: dense_matrix,
-            index_mapping,
-            feature_mapping,
-        }
+        FeatureFrame::from_rows(
+            Some(FrameIndex::new(&indices)),
+            features
+                .iter()
+                .map(|feature| FeatureMeta::new(feature.to_string()))
+                .collect(),
+            rows,
+        )
     }
 }
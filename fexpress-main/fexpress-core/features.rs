@@ -1,9 +1,10 @@
 use crate::algo::topo_sort::topological_sort;
-use crate::ast::core::Expr;
+use crate::ast::core::{ExpandExpr, Expr};
+use crate::ast::leakage::{classify_interval, WindowClass};
 use crate::ast::traverse::traverse_expr;
 use crate::map::HashMap;
 use crate::sstring::SmallString;
-use anyhow::{Context, Error, Result};
+use anyhow::{bail, Context, Error, Result};
 use itertools::Itertools;
 use pest::Parser;
 use std::convert::TryFrom;
@@ -11,7 +12,7 @@ use std::fmt::{Debug, Formatter};
 use std::str::FromStr;
 
 use crate::event::{AttributeKey, Event};
-use crate::event_index::RawQuery;
+use crate::event_index::{QueryConfig, RawQuery};
 use crate::parser::expr_parser::{generate_ast, ExprParser, Rule};
 use crate::types::Timestamp;
 use crate::value::Value;
@@ -68,6 +69,46 @@ impl Features {
         }
     }
 
+    /// Names of the features marked with `LABEL`.
+    pub fn label_names(&self) -> Vec<String> {
+        self.features
+            .iter()
+            .filter(|feature| feature.label)
+            .map(|feature| feature.get_name())
+            .collect()
+    }
+
+    /// When the query defines labels, the remaining features can only use intervals
+    /// that end at the observation date, otherwise they would leak the target.
+    /// The intervals are classified like in the leakage report.
+    pub fn validate_labels(&self) -> Result<()> {
+        if !self.features.iter().any(|feature| feature.label) {
+            return Ok(());
+        }
+        // whether the events on the observation date are included doesn't make an interval
+        // future touching
+        let query_config = QueryConfig::default();
+        for feature in self.features.iter().filter(|feature| !feature.label) {
+            let future_intervals = traverse_expr(&feature.expr, &|expr| match expr {
+                Expr::Aggr(aggr) => match classify_interval(&aggr.when, &query_config) {
+                    (WindowClass::FutureTouching, reason) => Some((aggr.when.clone(), reason)),
+                    _ => None,
+                },
+                _ => None,
+            });
+            if let Some((interval, reason)) = future_intervals.first() {
+                bail!(
+                    "Feature {} uses a future facing interval ({}: {}) in a query with labels, \
+                     only labels can look past the observation date",
+                    feature.get_name(),
+                    interval.to_string().trim(),
+                    reason
+                );
+            }
+        }
+        Ok(())
+    }
+
     fn sort_features(features: &Vec<Feature>) -> Option<Vec<usize>> {
         // prepare a graph of dependencies for features - look for variable assignments first
         // then look for usages in expressions
@@ -115,7 +156,9 @@ impl TryFrom<RawQuery> for Features {
 
     fn try_from(value: RawQuery) -> Result<Self> {
         let features_vec = value.to_features_vec()?;
-        Ok(Features::new(features_vec))
+        let features = Features::new(features_vec);
+        features.validate_labels()?;
+        Ok(features)
     }
 }
 
@@ -124,9 +167,28 @@ pub struct Feature {
     pub raw: String,
     pub expr: Expr,
     pub alias: Option<String>,
+    /// true if the feature is a target defined with `LABEL`
+    pub label: bool,
 }
 
 impl Feature {
+    pub fn from_expr(raw: String, expr: Expr) -> Self {
+        let (label, expr) = match expr {
+            Expr::Label(expr) => (true, *expr),
+            _ => (false, expr),
+        };
+        let (alias, expr) = match expr {
+            Expr::Alias(alias, expr) => (Some(alias.to_string()), *expr),
+            _ => (None, expr),
+        };
+        Feature {
+            raw,
+            expr,
+            alias,
+            label,
+        }
+    }
+
     pub fn get_name(&self) -> String {
         if let Some(alias) = self.alias.clone() {
             alias
@@ -134,6 +196,14 @@ impl Feature {
             self.raw.clone()
         }
     }
+
+    /// `EXPAND` of the feature if it is a grouped aggregation
+    pub fn expand(&self) -> Option<&ExpandExpr> {
+        match &self.expr {
+            Expr::Aggr(aggr) => aggr.expand.as_ref(),
+            _ => None,
+        }
+    }
 }
 
 impl AsRef<Expr> for Feature {
@@ -154,15 +224,7 @@ impl FromStr for Feature {
         let successful_parse = ExprParser::parse(Rule::single_expression, s);
         let pairs = successful_parse.context("parsing error")?;
         let expr = generate_ast(pairs);
-        let (alias, expr) = match expr {
-            Expr::Alias(alias, expr) => (Some(alias.to_string()), expr),
-            _ => (None, Box::new(expr)),
-        };
-        Ok(Feature {
-            raw: s.into(),
-            expr: *expr,
-            alias,
-        })
+        Ok(Feature::from_expr(s.into(), expr))
     }
 }
 
@@ -356,4 +418,62 @@ mod tests {
         let sorted_indices = Features::sort_features(&features_vec).unwrap();
         check_dependencies_satisfied(sorted_indices, &features_vec);
     }
+
+    #[test]
+    fn test_label_features() {
+        let query = RawQuery::SelectExpr(
+            r#"
+        SELECT
+            count(*) over last 30 days as orders_30d,
+            LABEL any(event_type = 'churn') over next 30 days as churned
+        FOR
+            @entities := user
+        "#
+            .into(),
+        );
+        let features = Features::try_from(query).unwrap();
+        assert_eq!(features.label_names(), vec!["churned".to_string()]);
+        assert!(!features.features[0].label);
+        assert!(features.features[1].label);
+        assert!(matches!(features.features[1].expr, Expr::Aggr(_)));
+    }
+
+    #[test]
+    fn test_future_features_rejected_in_queries_with_labels() {
+        let query = RawQuery::SelectExpr(
+            r#"
+        SELECT
+            count(*) over past as orders,
+            count(*) over nextweek as orders_next_week,
+            LABEL any(event_type = 'churn') over next 30 days as churned
+        FOR
+            @entities := user
+        "#
+            .into(),
+        );
+        let err = Features::try_from(query).unwrap_err();
+        assert!(err.to_string().contains("orders_next_week"));
+
+        // the intervals given by expressions are classified like in the leakage report
+        let with_label = |feature: &str| {
+            RawQuery::SelectExpr(format!(
+                "SELECT {}, LABEL any(event_type = 'churn') over next 30 days as churned \
+                 FOR @entities := user",
+                feature
+            ))
+        };
+        let err = Features::try_from(with_label(
+            "count(*) over between date_sub(date(obs_dt), 7) to current_date() as recent",
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("current time"));
+        assert!(Features::try_from(with_label(
+            "count(*) over between date_sub(date(obs_dt), 7) to date_sub(date(obs_dt), 1) as recent",
+        ))
+        .is_ok());
+
+        // without labels future facing features are allowed
+        let query = RawQuery::VecExpr(vec!["count(*) over future as orders_future".into()]);
+        assert!(Features::try_from(query).is_ok());
+    }
 }
@@ -2,6 +2,7 @@ use crate::ast::core::Expr;
 use crate::ast::traverse::ExprVisitor;
 use crate::event::AttributeName;
 
+use crate::event_store::schema::widen_type;
 use crate::event_store::EventStore;
 use crate::features::Features;
 use crate::map::HashMap;
@@ -10,7 +11,6 @@ use crate::sstring::SmallString;
 use crate::types::Entities;
 use crate::value::ValueType;
 use anyhow::Result;
-use itertools::Itertools;
 
 pub struct UntypedAttributeRewriteVisitor {
     pub attribute_types: HashMap<AttributeName, HashSet<ValueType>>,
@@ -21,27 +21,30 @@ impl ExprVisitor for UntypedAttributeRewriteVisitor {
         if let Expr::AttrUntyped(key) = expr {
             let attribute_name = AttributeName(key.to_kstring());
             if let Some(attribute_values) = self.attribute_types.get(&attribute_name) {
-                if attribute_values.len() == 1 {
-                    let attribute_value = attribute_values.iter().collect_vec();
-                    if let Some(value_type) = attribute_value.first() {
-                        *expr = match value_type {
-                            ValueType::Bool => Expr::AttrBool(key.clone()),
-                            ValueType::Num => Expr::AttrNum(key.clone()),
-                            ValueType::Int => Expr::AttrInt(key.clone()),
-                            ValueType::MapNum => Expr::AttrMapNum(key.clone()),
-                            ValueType::MapStr => Expr::AttrMapStr(key.clone()),
-                            ValueType::Str => Expr::AttrMapStr(key.clone()),
-                            ValueType::Date => Expr::AttrDate(key.clone()),
-                            ValueType::DateTime => Expr::AttrDateTime(key.clone()),
-                            ValueType::VecCat => Expr::AttrVecStr(key.clone()),
-                            ValueType::VecNum => Expr::AttrVecNum(key.clone()),
-                            ValueType::VecInt => Expr::AttrVecInt(key.clone()),
-                            ValueType::VecBool => Expr::AttrVecBool(key.clone()),
-                            ValueType::Map => expr.clone(),
-                            ValueType::None => expr.clone(),
-                            ValueType::Wildcard => expr.clone(),
-                            ValueType::NotCalculatedYet => expr.clone(),
-                        }
+                // attributes stored with several types are read as the widest of them
+                let value_type = attribute_values
+                    .iter()
+                    .try_fold(ValueType::None, |widened, value_type| {
+                        widen_type(&widened, value_type)
+                    });
+                if let Some(value_type) = value_type {
+                    *expr = match value_type {
+                        ValueType::Bool => Expr::AttrBool(key.clone()),
+                        ValueType::Num => Expr::AttrNum(key.clone()),
+                        ValueType::Int => Expr::AttrInt(key.clone()),
+                        ValueType::MapNum => Expr::AttrMapNum(key.clone()),
+                        ValueType::MapStr => Expr::AttrMapStr(key.clone()),
+                        ValueType::Str => Expr::AttrMapStr(key.clone()),
+                        ValueType::Date => Expr::AttrDate(key.clone()),
+                        ValueType::DateTime => Expr::AttrDateTime(key.clone()),
+                        ValueType::VecCat => Expr::AttrVecStr(key.clone()),
+                        ValueType::VecNum => Expr::AttrVecNum(key.clone()),
+                        ValueType::VecInt => Expr::AttrVecInt(key.clone()),
+                        ValueType::VecBool => Expr::AttrVecBool(key.clone()),
+                        ValueType::Map => expr.clone(),
+                        ValueType::None => expr.clone(),
+                        ValueType::Wildcard => expr.clone(),
+                        ValueType::NotCalculatedYet => expr.clone(),
                     }
                 }
             }
@@ -68,6 +71,7 @@ pub fn rewrite_untyped_attributes(
 mod tests {
     use super::*;
 
+    use crate::event::AttributeKey;
     use crate::event_index::EventContext;
     use crate::event_index::EventScopeConfig;
     use crate::event_index::QueryConfig;
@@ -78,11 +82,41 @@ mod tests {
     use regex::Regex;
     use std::convert::TryFrom;
 
+    #[test]
+    fn test_rewrite_widened_attributes() {
+        let rewrite = |value_types: Vec<ValueType>| -> Expr {
+            let mut visitor = UntypedAttributeRewriteVisitor {
+                attribute_types: hashmap![a!("price") => value_types.into_iter().collect()],
+            };
+            let mut expr = Expr::AttrUntyped(AttributeKey::Single("price".into()));
+            visitor.visit(&mut expr);
+            expr
+        };
+        let price = AttributeKey::Single("price".into());
+        assert_eq!(
+            rewrite(vec![ValueType::Int, ValueType::Num, ValueType::None]),
+            Expr::AttrNum(price.clone())
+        );
+        assert_eq!(
+            rewrite(vec![ValueType::Date, ValueType::DateTime]),
+            Expr::AttrDateTime(price.clone())
+        );
+        assert_eq!(
+            rewrite(vec![ValueType::Int, ValueType::Str]),
+            Expr::AttrUntyped(price)
+        );
+    }
+
     #[test]
     fn test_rewrite() {
         let event_store_settings = QueryConfig {
             parallel: true,
             include_events_on_obs_date: true,
+            strict_leakage: false,
+            business_calendar: None,
+            time_zone: None,
+            time_zone_attribute: None,
+            expanded_keys: Default::default(),
         };
         let entity_query = EventScopeConfig::AllEvents;
         let mut event_context = EventContext::new_memory();
@@ -111,6 +145,11 @@ mod tests {
         let query_config = QueryConfig {
             include_events_on_obs_date: true,
             parallel: false,
+            strict_leakage: false,
+            business_calendar: None,
+            time_zone: None,
+            time_zone_attribute: None,
+            expanded_keys: Default::default(),
         };
 
         let mut features = Features::try_from(raw_query).unwrap();
@@ -0,0 +1,666 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use itertools::Itertools;
+use rayon::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::datetime_utils::parse_naive_date_time;
+use crate::event::{AttributeName, EntityID, EntityType, Event, EventType};
+use crate::event_store::{EventStore, EventStoreImpl};
+use crate::map::HashMap;
+use crate::sstring::SmallString;
+use crate::types::{Entities, FLOAT, INT};
+use crate::value::Value;
+
+// number of events inserted by a single `insert_batch` call
+const INSERT_CHUNK_SIZE: usize = 10_000;
+
+fn default_delimiter() -> char {
+    ','
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_batch_size() -> usize {
+    100_000
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub enum FileFormat {
+    Csv {
+        #[serde(default = "default_delimiter")]
+        delimiter: char,
+    },
+    /// One JSON object per line
+    NdJson,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub enum EventTypeMapping {
+    /// All the events of the file have the same type
+    Constant(String),
+    /// Name of the column with the event type
+    Column(String),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, Default)]
+pub enum TimestampFormat {
+    /// ISO 8601 date times, date times with a time zone are converted to UTC
+    #[default]
+    Auto,
+    /// `chrono` format string, e.g. `%d/%m/%Y %H:%M`
+    Format(String),
+    EpochSeconds,
+    EpochMilliseconds,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, JsonSchema, Eq, PartialEq)]
+pub enum AttributeType {
+    Bool,
+    Int,
+    Num,
+    Str,
+    Date,
+    DateTime,
+}
+
+/// Describes how the rows of a file or the columns of an Arrow record batch are mapped to events
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EventMapping {
+    /// column with the time of the event
+    pub event_time: String,
+    #[serde(default)]
+    pub timestamp_format: TimestampFormat,
+    pub event_type: EventTypeMapping,
+    /// entity type -> column with the entity id, rows without the id skip the entity
+    #[serde(default)]
+    pub entities: BTreeMap<String, String>,
+    #[serde(default)]
+    pub event_id: Option<String>,
+    #[serde(default)]
+    pub experiment_id: Option<String>,
+    /// types of the attribute columns
+    #[serde(default)]
+    pub attributes: BTreeMap<String, AttributeType>,
+    /// load the columns not mentioned in the config as attributes with inferred types
+    #[serde(default = "default_true")]
+    pub include_unmapped_columns: bool,
+}
+
+impl EventMapping {
+    /// Columns used for the event itself and not as attributes
+    pub(crate) fn reserved_columns(&self) -> BTreeSet<&str> {
+        let mut columns: BTreeSet<&str> = self.entities.values().map(|c| c.as_str()).collect();
+        columns.insert(self.event_time.as_str());
+        if let EventTypeMapping::Column(column) = &self.event_type {
+            columns.insert(column.as_str());
+        }
+        columns.extend(self.event_id.as_deref());
+        columns.extend(self.experiment_id.as_deref());
+        columns
+    }
+
+    /// Adds the types inferred for the unmapped columns, the existing types are kept
+    fn add_inferred_types(&mut self, column_types: ColumnTypes) {
+        for (column, typ) in column_types {
+            self.attributes.entry(column).or_insert(typ);
+        }
+    }
+
+    /// Whether the column is loaded as an attribute
+    pub(crate) fn is_attribute(&self, reserved_columns: &BTreeSet<&str>, column: &str) -> bool {
+        !reserved_columns.contains(column)
+            && (self.include_unmapped_columns || self.attributes.contains_key(column))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct IngestionConfig {
+    pub format: FileFormat,
+    #[serde(flatten)]
+    pub mapping: EventMapping,
+    /// number of rows read, checked and inserted at a time, the types of the unmapped columns are
+    /// inferred from the first batch with values in them
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+}
+
+/// A single value of a row, CSV fields are always text
+#[derive(Debug)]
+enum Field<'a> {
+    Text(&'a str),
+    Json(&'a serde_json::Value),
+}
+
+impl<'a> Field<'a> {
+    fn as_text(&self) -> Option<Cow<'a, str>> {
+        match self {
+            Field::Text("") => None,
+            Field::Text(text) => Some(Cow::Borrowed(text)),
+            Field::Json(serde_json::Value::Null) => None,
+            Field::Json(serde_json::Value::String(text)) => Some(Cow::Borrowed(text.as_str())),
+            Field::Json(value) => Some(Cow::Owned(value.to_string())),
+        }
+    }
+
+    /// Type of the value when the column has no type in the config, `None` for nulls
+    fn infer_type(&self) -> Result<Option<AttributeType>> {
+        let text = match self.as_text() {
+            Some(text) => text,
+            None => return Ok(None),
+        };
+        Ok(Some(match self {
+            Field::Json(serde_json::Value::Bool(_)) => AttributeType::Bool,
+            Field::Json(serde_json::Value::Number(n)) => match n.as_i64() {
+                Some(v) if INT::try_from(v).is_ok() => AttributeType::Int,
+                _ => AttributeType::Num,
+            },
+            Field::Json(serde_json::Value::String(_)) => AttributeType::Str,
+            Field::Json(value) => bail!("Unsupported attribute value {}", value),
+            Field::Text(_) => {
+                if text.parse::<INT>().is_ok() {
+                    AttributeType::Int
+                } else if text.parse::<FLOAT>().is_ok() {
+                    AttributeType::Num
+                } else if text == "true" || text == "false" {
+                    AttributeType::Bool
+                } else {
+                    AttributeType::Str
+                }
+            }
+        }))
+    }
+
+    fn to_value(&self, typ: AttributeType) -> Result<Value> {
+        let text = match self.as_text() {
+            Some(text) => text,
+            None => return Ok(Value::None),
+        };
+        Ok(match typ {
+            AttributeType::Bool => match text.to_lowercase().as_str() {
+                "true" | "1" => Value::Bool(true),
+                "false" | "0" => Value::Bool(false),
+                _ => bail!("Cannot parse {} as Bool", text),
+            },
+            AttributeType::Int => Value::Int(
+                text.parse()
+                    .with_context(|| format!("Cannot parse {} as Int", text))?,
+            ),
+            AttributeType::Num => Value::Num(
+                text.parse()
+                    .with_context(|| format!("Cannot parse {} as Num", text))?,
+            ),
+            AttributeType::Str => Value::Str(text.as_ref().into()),
+            AttributeType::Date => Value::Date(
+                NaiveDate::parse_from_str(&text, "%Y-%m-%d")
+                    .with_context(|| format!("Cannot parse {} as Date", text))?,
+            ),
+            AttributeType::DateTime => Value::DateTime(
+                parse_timestamp(&text, &TimestampFormat::Auto)
+                    .with_context(|| format!("Cannot parse {} as DateTime", text))?,
+            ),
+        })
+    }
+}
+
+/// Types of the columns without a type in the config
+type ColumnTypes = BTreeMap<String, AttributeType>;
+
+/// A column has a single type, ints and nums are nums and other mixed values are strings
+fn merge_column_types(mut types: ColumnTypes, other: ColumnTypes) -> ColumnTypes {
+    for (column, typ) in other {
+        types
+            .entry(column)
+            .and_modify(|current| {
+                *current = match (*current, typ) {
+                    (a, b) if a == b => a,
+                    (AttributeType::Int, AttributeType::Num)
+                    | (AttributeType::Num, AttributeType::Int) => AttributeType::Num,
+                    _ => AttributeType::Str,
+                }
+            })
+            .or_insert(typ);
+    }
+    types
+}
+
+/// Infers the types of the unmapped attribute columns of a row
+fn row_types<'a>(
+    mapping: &EventMapping,
+    reserved_columns: &BTreeSet<&str>,
+    fields: impl Iterator<Item = (&'a str, Field<'a>)>,
+) -> Result<ColumnTypes> {
+    let mut types = ColumnTypes::new();
+    for (column, field) in fields {
+        if !mapping.is_attribute(reserved_columns, column)
+            || mapping.attributes.contains_key(column)
+        {
+            continue;
+        }
+        if let Some(typ) = field
+            .infer_type()
+            .with_context(|| format!("Invalid value of column {}", column))?
+        {
+            types.insert(column.to_string(), typ);
+        }
+    }
+    Ok(types)
+}
+
+pub(crate) fn parse_timestamp(text: &str, format: &TimestampFormat) -> Result<NaiveDateTime> {
+    match format {
+        TimestampFormat::Auto => DateTime::parse_from_rfc3339(text)
+            .map(|dt| dt.naive_utc())
+            .ok()
+            .or_else(|| parse_naive_date_time(text))
+            .ok_or_else(|| anyhow!("Cannot parse timestamp {}", text)),
+        TimestampFormat::Format(format) => NaiveDateTime::parse_from_str(text, format)
+            .with_context(|| format!("Cannot parse timestamp {} with format {}", text, format)),
+        TimestampFormat::EpochSeconds => text
+            .parse::<f64>()
+            .ok()
+            .and_then(|secs| {
+                NaiveDateTime::from_timestamp_opt(
+                    secs.floor() as i64,
+                    (secs.fract() * 1e9).round() as u32,
+                )
+            })
+            .ok_or_else(|| anyhow!("Cannot parse epoch seconds {}", text)),
+        TimestampFormat::EpochMilliseconds => text
+            .parse::<i64>()
+            .ok()
+            .and_then(NaiveDateTime::from_timestamp_millis)
+            .ok_or_else(|| anyhow!("Cannot parse epoch milliseconds {}", text)),
+    }
+}
+
+/// Builds an event from the fields of a row
+fn row_to_event<'a>(
+    mapping: &EventMapping,
+    reserved_columns: &BTreeSet<&str>,
+    fields: impl Iterator<Item = (&'a str, Field<'a>)>,
+) -> Result<Event> {
+    let fields: HashMap<&str, Field> = fields.collect();
+    let text = |column: &str| fields.get(column).and_then(|field| field.as_text());
+
+    let event_time = text(&mapping.event_time)
+        .with_context(|| format!("Missing event time in column {}", mapping.event_time))?;
+    let event_time = parse_timestamp(&event_time, &mapping.timestamp_format)?;
+    let event_type = match &mapping.event_type {
+        EventTypeMapping::Constant(event_type) => event_type.as_str().into(),
+        EventTypeMapping::Column(column) => text(column)
+            .with_context(|| format!("Missing event type in column {}", column))?
+            .as_ref()
+            .into(),
+    };
+    let entities: Entities = mapping
+        .entities
+        .iter()
+        .filter_map(|(entity_type, column)| {
+            text(column).map(|id| {
+                (
+                    EntityType(entity_type.as_str().into()),
+                    EntityID(id.as_ref().into()),
+                )
+            })
+        })
+        .collect();
+
+    let mut attrs = HashMap::new();
+    for (column, field) in fields.iter() {
+        if !mapping.is_attribute(reserved_columns, column) {
+            continue;
+        }
+        // the columns without a type have only nulls
+        let typ = match mapping.attributes.get(*column) {
+            Some(typ) => *typ,
+            None => continue,
+        };
+        let value = field
+            .to_value(typ)
+            .with_context(|| format!("Invalid value of column {}", column))?;
+        if !value.is_null() {
+            attrs.insert(AttributeName(SmallString::from(*column)), value);
+        }
+    }
+
+    Ok(Event {
+        event_type: EventType(event_type),
+        event_time,
+        entities,
+        event_id: mapping
+            .event_id
+            .as_deref()
+            .and_then(text)
+            .map(|id| id.as_ref().into()),
+        experiment_id: mapping
+            .experiment_id
+            .as_deref()
+            .and_then(text)
+            .map(|id| id.as_ref().into()),
+        attrs: if attrs.is_empty() { None } else { Some(attrs) },
+    })
+}
+
+/// Inserts the events using `insert_batch` from many threads
+pub(crate) fn insert_parallel(event_store: &EventStoreImpl, events: Vec<Event>) -> Result<()> {
+    let chunks = events
+        .into_iter()
+        .chunks(INSERT_CHUNK_SIZE)
+        .into_iter()
+        .map(|chunk| chunk.collect_vec())
+        .collect_vec();
+    chunks
+        .into_par_iter()
+        .try_for_each(|chunk| event_store.insert_batch(chunk))
+}
+
+fn csv_fields<'a>(
+    headers: &'a csv::StringRecord,
+    record: &'a csv::StringRecord,
+) -> impl Iterator<Item = (&'a str, Field<'a>)> {
+    headers
+        .iter()
+        .zip(record.iter())
+        .map(|(column, value)| (column, Field::Text(value)))
+}
+
+fn json_fields<'a>(
+    row: &'a serde_json::Map<String, serde_json::Value>,
+) -> impl Iterator<Item = (&'a str, Field<'a>)> {
+    row.iter()
+        .map(|(column, value)| (column.as_str(), Field::Json(value)))
+}
+
+/// Inserts the events, returns the number of inserted events
+fn insert_events(event_store: &EventStoreImpl, events: Vec<Event>) -> Result<usize> {
+    let n_events = events.len();
+    insert_parallel(event_store, events)?;
+    Ok(n_events)
+}
+
+// The rows are converted and inserted one batch at a time so that a file doesn't have to fit in
+// memory. A load is not atomic, the batches before an invalid row stay inserted. The type of an
+// unmapped column is inferred from the first batch with a value in it and kept for the next
+// batches, the columns with mixed values should have a type in the mapping.
+fn load_csv<R: Read>(
+    event_store: &EventStoreImpl,
+    reader: R,
+    delimiter: char,
+    config: &IngestionConfig,
+) -> Result<usize> {
+    let delimiter = u8::try_from(delimiter).context("CSV delimiter must be an ASCII character")?;
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .from_reader(reader);
+    let headers = reader.headers()?.clone();
+    let reserved_columns = config.mapping.reserved_columns();
+    let mut mapping = config.mapping.clone();
+    let mut n_rows = 0;
+    let mut n_events = 0;
+    for batch in &reader.records().chunks(config.batch_size.max(1)) {
+        let records: Vec<csv::StringRecord> = batch.collect::<Result<_, _>>()?;
+        let column_types = records
+            .par_iter()
+            .enumerate()
+            .map(|(i, record)| {
+                row_types(&mapping, &reserved_columns, csv_fields(&headers, record))
+                    .with_context(|| format!("Cannot load row {}", n_rows + i + 1))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .fold(ColumnTypes::new(), merge_column_types);
+        mapping.add_inferred_types(column_types);
+
+        let events = records
+            .par_iter()
+            .enumerate()
+            .map(|(i, record)| {
+                row_to_event(&mapping, &reserved_columns, csv_fields(&headers, record))
+                    .with_context(|| format!("Cannot load row {}", n_rows + i + 1))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        n_rows += records.len();
+        n_events += insert_events(event_store, events)?;
+    }
+    Ok(n_events)
+}
+
+fn load_ndjson<R: BufRead>(
+    event_store: &EventStoreImpl,
+    reader: R,
+    config: &IngestionConfig,
+) -> Result<usize> {
+    let reserved_columns = config.mapping.reserved_columns();
+    let mut mapping = config.mapping.clone();
+    let mut n_lines = 0;
+    let mut n_events = 0;
+    for batch in &reader.lines().chunks(config.batch_size.max(1)) {
+        let lines: Vec<String> = batch.collect::<Result<_, _>>()?;
+        // line numbers and rows
+        let rows = lines
+            .par_iter()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                let line_number = n_lines + i + 1;
+                let row: serde_json::Map<String, serde_json::Value> = serde_json::from_str(line)
+                    .with_context(|| format!("Cannot load line {}", line_number))?;
+                Ok((line_number, row))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        n_lines += lines.len();
+        let column_types = rows
+            .par_iter()
+            .map(|(line_number, row)| {
+                row_types(&mapping, &reserved_columns, json_fields(row))
+                    .with_context(|| format!("Cannot load line {}", line_number))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .fold(ColumnTypes::new(), merge_column_types);
+        mapping.add_inferred_types(column_types);
+
+        let events = rows
+            .par_iter()
+            .map(|(line_number, row)| {
+                row_to_event(&mapping, &reserved_columns, json_fields(row))
+                    .with_context(|| format!("Cannot load line {}", line_number))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        n_events += insert_events(event_store, events)?;
+    }
+    Ok(n_events)
+}
+
+/// Loads the events from a CSV or NDJSON reader, returns the number of loaded events.
+/// The load is not atomic, an invalid row fails it with the batches before the row inserted.
+pub fn load_events<R: Read>(
+    event_store: &EventStoreImpl,
+    reader: R,
+    config: &IngestionConfig,
+) -> Result<usize> {
+    match config.format {
+        FileFormat::Csv { delimiter } => load_csv(event_store, reader, delimiter, config),
+        FileFormat::NdJson => load_ndjson(event_store, BufReader::new(reader), config),
+    }
+}
+
+/// Loads the events from a CSV or NDJSON file, returns the number of loaded events.
+/// The load is not atomic, see [`load_events`].
+pub fn load_file(
+    event_store: &EventStoreImpl,
+    path: impl AsRef<Path>,
+    config: &IngestionConfig,
+) -> Result<usize> {
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+    load_events(event_store, file, config)
+        .with_context(|| format!("Cannot load {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::event_index::QueryConfig;
+
+    fn config(format: FileFormat) -> IngestionConfig {
+        serde_json::from_value(serde_json::json!({
+            "format": serde_json::to_value(format).unwrap(),
+            "event_time": "ts",
+            "event_type": {"Constant": "order"},
+            "entities": {"user": "user_id"},
+            "attributes": {"amount": "Num"},
+            "batch_size": 2,
+        }))
+        .unwrap()
+    }
+
+    fn user_events(event_store: &EventStoreImpl, user: &str) -> Vec<Event> {
+        let entities = btreemap!(EntityType("user".into()) => EntityID(user.into()));
+        event_store
+            .query_entity(&entities, &QueryConfig::default(), None)
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|(_, events)| events.into_iter().map(|e| (*e).clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_load_csv() {
+        let csv = "ts,user_id,amount,channel\n\
+            2023-01-01 10:00:00,a,10,web\n\
+            2023-01-02T10:00:00Z,a,5.5,\n\
+            2023-01-03 10:00:00,b,1,app\n";
+        let event_store = EventStoreImpl::default();
+        let n = load_events(
+            &event_store,
+            csv.as_bytes(),
+            &config(FileFormat::Csv { delimiter: ',' }),
+        )
+        .unwrap();
+        assert_eq!(n, 3);
+
+        let events = user_events(&event_store, "a");
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[1].event_time,
+            NaiveDate::from_ymd(2023, 1, 2).and_hms(10, 0, 0)
+        );
+        let attrs = events[0].attrs.as_ref().unwrap();
+        assert_eq!(attrs.get("amount"), Some(&Value::Num(10.0)));
+        assert_eq!(attrs.get("channel"), Some(&Value::Str("web".into())));
+        // empty fields are not loaded
+        assert!(events[1].attrs.as_ref().unwrap().get("channel").is_none());
+    }
+
+    #[test]
+    fn test_load_ndjson() {
+        let ndjson = r#"{"ts": 1672567200000, "user_id": "a", "amount": 10, "kind": "web", "paid": true}
+
+{"ts": 1672653600000, "user_id": "a", "amount": 2.5}
+"#;
+        let mut config = config(FileFormat::NdJson);
+        config.mapping.timestamp_format = TimestampFormat::EpochMilliseconds;
+        config.mapping.event_type = EventTypeMapping::Column("kind".into());
+        let event_store = EventStoreImpl::default();
+        let err = load_events(&event_store, ndjson.as_bytes(), &config).unwrap_err();
+        assert!(format!("{:#}", err).contains("Missing event type"));
+
+        config.mapping.event_type = EventTypeMapping::Constant("order".into());
+        let event_store = EventStoreImpl::default();
+        assert_eq!(
+            load_events(&event_store, ndjson.as_bytes(), &config).unwrap(),
+            2
+        );
+        let events = user_events(&event_store, "a");
+        assert_eq!(
+            events[0].event_time,
+            NaiveDate::from_ymd(2023, 1, 1).and_hms(10, 0, 0)
+        );
+        let attrs = events[0].attrs.as_ref().unwrap();
+        assert_eq!(attrs.get("paid"), Some(&Value::Bool(true)));
+        assert_eq!(attrs.get("amount"), Some(&Value::Num(10.0)));
+    }
+
+    #[test]
+    fn test_column_types_are_inferred_from_first_batch() {
+        // batches of 2 rows, `note` has no value in the first batch
+        let csv = "ts,user_id,code,score,note\n\
+            2023-01-01 10:00:00,a,1,2,\n\
+            2023-01-02 10:00:00,a,x,2.5,\n\
+            2023-01-03 10:00:00,a,,3,late\n";
+        let event_store = EventStoreImpl::default();
+        let n = load_events(
+            &event_store,
+            csv.as_bytes(),
+            &config(FileFormat::Csv { delimiter: ',' }),
+        )
+        .unwrap();
+        assert_eq!(n, 3);
+        let events = user_events(&event_store, "a");
+        let attrs = events[0].attrs.as_ref().unwrap();
+        assert_eq!(attrs.get("code"), Some(&Value::Str("1".into())));
+        assert_eq!(attrs.get("score"), Some(&Value::Num(2.0)));
+        let attrs = events[2].attrs.as_ref().unwrap();
+        assert_eq!(attrs.get("score"), Some(&Value::Num(3.0)));
+        assert_eq!(attrs.get("note"), Some(&Value::Str("late".into())));
+
+        let ndjson = r#"{"ts": "2023-01-01", "user_id": "a", "score": 1}
+{"ts": "2023-01-02", "user_id": "a", "score": 1.5}
+"#;
+        let event_store = EventStoreImpl::default();
+        load_events(&event_store, ndjson.as_bytes(), &config(FileFormat::NdJson)).unwrap();
+        let events = user_events(&event_store, "a");
+        assert_eq!(
+            events[0].attrs.as_ref().unwrap().get("score"),
+            Some(&Value::Num(1.0))
+        );
+
+        // the type inferred from the first batch is kept for the next ones
+        let csv = "ts,user_id,code\n\
+            2023-01-01 10:00:00,a,1\n\
+            2023-01-02 10:00:00,a,2\n\
+            2023-01-03 10:00:00,a,x\n";
+        let event_store = EventStoreImpl::default();
+        let err = load_events(
+            &event_store,
+            csv.as_bytes(),
+            &config(FileFormat::Csv { delimiter: ',' }),
+        )
+        .unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.contains("Cannot load row 3"), "{}", message);
+        assert!(message.contains("Cannot parse x as Int"), "{}", message);
+    }
+
+    #[test]
+    fn test_invalid_row_keeps_previous_batches() {
+        // the invalid line is in the second batch
+        let ndjson = r#"{"ts": "2023-01-01", "user_id": "a", "kind": "web"}
+{"ts": "2023-01-02", "user_id": "a", "kind": "app"}
+{"ts": "2023-01-03", "user_id": "a", "kind": "web"}
+{"user_id": "a", "kind": "app"}
+"#;
+        let event_store = EventStoreImpl::default();
+        let err =
+            load_events(&event_store, ndjson.as_bytes(), &config(FileFormat::NdJson)).unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.contains("Cannot load line 4"), "{}", message);
+        assert!(message.contains("Missing event time"), "{}", message);
+        // the load is not atomic, the batch before the invalid row stays inserted
+        assert_eq!(user_events(&event_store, "a").len(), 2);
+    }
+}
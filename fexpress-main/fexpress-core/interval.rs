@@ -3,11 +3,14 @@ use std::ops::{Add, Sub};
 use std::str::FromStr;
 
 use crate::ast::core::Expr;
+use crate::calendar::{get_calendar, HolidayCalendar};
+use crate::datetime_utils::{local_to_utc, utc_to_local};
 use crate::eval::{eval_simple_expr, EvalContext};
 use crate::map::HashMap;
 use crate::obs_dates::ObservationTime;
 use crate::value::Value;
-use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Weekday};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use chrono_tz::Tz;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use strum::{AsRefStr, EnumString};
@@ -352,6 +355,40 @@ impl Display for NewInterval {
 
 impl NewInterval {
     pub fn materialize_interval(&self, dt: &NaiveDateTime) -> Option<NaiveDateTimeInterval> {
+        let calendar = get_calendar(None).ok()?;
+        self.materialize_interval_in_calendar(dt, calendar.as_ref())
+    }
+
+    /// Materializes the interval with the calendar boundaries of the keyword intervals
+    /// (`yesterday`, `mtd`, ...) computed in the local time of the time zone.
+    /// The observation date and the materialized interval are in UTC.
+    pub fn materialize_interval_in_zone(
+        &self,
+        dt: &NaiveDateTime,
+        calendar: &dyn HolidayCalendar,
+        time_zone: Option<&Tz>,
+    ) -> Option<NaiveDateTimeInterval> {
+        match (self, time_zone) {
+            (NewInterval::KeywordDate(_), Some(time_zone)) => {
+                let local = utc_to_local(*dt, time_zone);
+                self.materialize_interval_in_calendar(&local, calendar)
+                    .map(|interval| NaiveDateTimeInterval {
+                        start_dt: interval
+                            .start_dt
+                            .map(|start| local_to_utc(start, time_zone)),
+                        end_dt: interval.end_dt.map(|end| local_to_utc(end, time_zone)),
+                    })
+            }
+            _ => self.materialize_interval_in_calendar(dt, calendar),
+        }
+    }
+
+    /// Materializes the interval using the calendar for the business day keywords
+    pub fn materialize_interval_in_calendar(
+        &self,
+        dt: &NaiveDateTime,
+        calendar: &dyn HolidayCalendar,
+    ) -> Option<NaiveDateTimeInterval> {
         match self {
             NewInterval::FixedInterval(interval) => match interval.direction {
                 Direction::Next => Some(NaiveDateTimeInterval {
@@ -608,22 +645,14 @@ impl NewInterval {
                     ),
                 }),
                 KeywordInterval::NextWorkDay => {
-                    let mut next_day = dt.date() + Duration::days(1);
-                    while next_day.weekday() == Weekday::Sat || next_day.weekday() == Weekday::Sun {
-                        next_day += Duration::days(1);
-                    }
+                    let next_day = calendar.next_business_day(dt.date());
                     Some(NaiveDateTimeInterval {
                         start_dt: Some(next_day.and_hms_opt(0, 0, 0)).expect("Cannot add hms"),
                         end_dt: Some(next_day.and_hms_opt(23, 59, 59)).expect("Cannot add hms"),
                     })
                 }
                 KeywordInterval::PreviousWorkDay => {
-                    let mut previous_day = dt.date() - Duration::days(1);
-                    while previous_day.weekday() == Weekday::Sat
-                        || previous_day.weekday() == Weekday::Sun
-                    {
-                        previous_day -= Duration::days(1);
-                    }
+                    let previous_day = calendar.previous_business_day(dt.date());
                     Some(NaiveDateTimeInterval {
                         start_dt: Some(previous_day.and_hms_opt(0, 0, 0)).expect("Cannot add hms"),
                         end_dt: Some(previous_day.and_hms_opt(23, 59, 59)).expect("Cannot add hms"),
@@ -722,7 +751,7 @@ impl FromStr for DatePart {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct NaiveDateTimeInterval {
     pub start_dt: Option<NaiveDateTime>,
     pub end_dt: Option<NaiveDateTime>,
@@ -1196,4 +1225,28 @@ mod tests {
             })
         );
     }
+
+    #[test]
+    fn test_nextbusinessday_with_holidays() {
+        use crate::calendar::FixedHolidayCalendar;
+
+        let dt = NaiveDate::from_ymd_opt(2023, 5, 19)
+            .expect("Cannot build date using from_ymd")
+            .and_hms_opt(12, 0, 0)
+            .expect("Cannot build a date"); // a Friday
+        let monday = NaiveDate::from_ymd_opt(2023, 5, 22).expect("Cannot construct date");
+        let calendar = FixedHolidayCalendar {
+            holidays: vec![monday].into_iter().collect(),
+            ..Default::default()
+        };
+        let interval = NewInterval::KeywordDate(KeywordInterval::NextWorkDay);
+        let tuesday = monday.succ_opt().expect("Cannot construct date");
+        assert_eq!(
+            interval.materialize_interval_in_calendar(&dt, &calendar),
+            Some(NaiveDateTimeInterval {
+                start_dt: tuesday.and_hms_opt(0, 0, 0),
+                end_dt: tuesday.and_hms_opt(23, 59, 59),
+            })
+        );
+    }
 }
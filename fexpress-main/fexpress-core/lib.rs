@@ -21,10 +21,12 @@ mod macros;
 pub mod map;
 #[macro_use]
 pub mod sstring;
+mod agg_cache;
 mod aggr;
 pub mod algo;
+pub mod arrow_io;
 pub mod ast;
-mod dataframe;
+pub mod calendar;
 mod datetime_utils;
 pub mod errors;
 mod eval;
@@ -32,10 +34,13 @@ pub mod evaluation;
 pub mod event;
 pub mod event_index;
 pub mod event_store;
+pub mod expand;
+pub mod feature_frame;
 pub mod feature_matrix;
 mod features;
 pub mod features_rewrite;
 pub mod impls;
+pub mod ingest;
 pub mod interval;
 pub mod naive_aggregate_funcs;
 pub mod obs_dates;
@@ -7,18 +7,26 @@ use std::sync::Arc;
 use crate::algo::is_sorted::is_sorted;
 use crate::map::{HashMap, HashSet};
 use anyhow::{anyhow, bail, Context, Result};
-use chrono::{Duration, NaiveDateTime};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use chrono_tz::Tz;
 use itertools::Itertools;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 use vec1::{vec1, Vec1};
 
 use crate::ast::core::Expr;
+use crate::calendar::{get_calendar, HolidayCalendar};
+use crate::datetime_utils::{local_to_utc, parse_time_zone, utc_to_local};
+use crate::eval::{eval_context_dispatcher, EvalContext};
 use crate::event::{EntityID, EntityType, Event};
-use crate::event_index::QueryConfig;
-use crate::event_store::EventStore;
+use crate::event_index::{EventContext, EventScopeConfig, QueryConfig};
+use crate::event_store::{EventStore, EventStoreImpl};
 use crate::interval::DatePart;
 use crate::map::Entry;
 use crate::types::{Entities, EventID, Timestamp};
+use crate::value::Value;
 use crate::vec1::Vec1Wrapper;
 use schemars::JsonSchema;
 
@@ -171,6 +179,20 @@ pub enum ObservationDatesConfig {
     // this variant also is for entites but it uses some condition to filter the events
     ConditionalEvents(ConditionalEvents),
     EntitiesEventSpecific(EntitiesEventSpecific),
+    // sampling strategies for training sets, reproducible from the seed
+    /// Draws `n` uniformly distributed observation dates per entity
+    RandomPerEntity(RandomPerEntity),
+    /// Draws the observation dates of every entity from a Poisson process
+    Poisson(Poisson),
+    /// Draws the same number of observation dates with a positive and a negative label
+    StratifiedByLabel(StratifiedByLabel),
+    /// Moves event based observation dates relative to their events and thins them out
+    RelativeToEvents(RelativeToEvents),
+    /// Selects the entities first and generates the observation dates only for them
+    EntityFiltered(EntityFiltered),
+    /// Generates the observation dates from a calendar rule, e.g. the last business day of
+    /// every month or every Monday at 09:00
+    Calendar(CalendarSchedule),
 }
 
 impl ObservationDatesConfig {
@@ -194,8 +216,19 @@ impl ObservationDatesConfig {
 
     pub fn materialize_observation_dates(
         &mut self,
-        event_store: Box<dyn EventStore>,
+        event_store: EventStoreImpl,
         query_config: &QueryConfig,
+    ) -> Result<ObservationDates> {
+        self.materialize_for_entities(event_store, query_config, None)
+    }
+
+    /// Generates the observation dates only for the `selected` entities, for all the entities
+    /// if `None`
+    fn materialize_for_entities(
+        &mut self,
+        event_store: EventStoreImpl,
+        query_config: &QueryConfig,
+        selected: Option<&HashSet<Entities>>,
     ) -> Result<ObservationDates> {
         let mut observation_dates = match self {
             ObservationDatesConfig::ConditionalEvents(conditional) => {
@@ -211,7 +244,10 @@ impl ObservationDatesConfig {
                 let events =
                     event_store.filter_events(&Box::new(condition_expr), query_config, &hm)?;
                 let mut hm: HashMap<Entities, Vec1Wrapper<ObservationTime>> = HashMap::new();
-                for event in events {
+                for event in events
+                    .iter()
+                    .filter(|event| is_selected(selected, &event.entities))
+                {
                     let obs_time = ObservationTime {
                         datetime: event.event_time,
                         event_id: event.event_id.clone(),
@@ -225,6 +261,9 @@ impl ObservationDatesConfig {
                 let mut obs_dates: HashMap<Entities, Vec1Wrapper<ObservationTime>> = HashMap::new();
                 for entity in entities {
                     let entities = btreemap!(entity.typ.clone() => EntityID(entity.id.clone()));
+                    if !is_selected(selected, &entities) {
+                        continue;
+                    }
                     if let Some(events) = event_store.query_entity(&entities, query_config, None) {
                         if let (Some(first), Some(last)) = (events.first(), events.last()) {
                             // add and sub 1 millisecond to always include all events
@@ -247,10 +286,10 @@ impl ObservationDatesConfig {
                 let entities = event_store.get_entities(&None);
                 let mut obs_dates: HashMap<Entities, Vec1Wrapper<ObservationTime>> = HashMap::new();
                 for entity in entities {
-                    if fixed.entity_types.contains(&entity.typ) {
+                    let entities = btreemap!(entity.typ.clone() => EntityID(entity.id.clone()));
+                    if fixed.entity_types.contains(&entity.typ) && is_selected(selected, &entities)
+                    {
                         for dt in &fixed.dates {
-                            let entities =
-                                btreemap!(entity.typ.clone() => EntityID(entity.id.clone()));
                             let obs_time = ObservationTime {
                                 datetime: *dt,
                                 event_id: None,
@@ -262,11 +301,19 @@ impl ObservationDatesConfig {
                 ObservationDates { inner: obs_dates }
             }
             ObservationDatesConfig::EntitySpecific(entity_specific) => ObservationDates {
-                inner: entity_specific.dates.clone(),
+                inner: entity_specific
+                    .dates
+                    .iter()
+                    .filter(|(entities, _)| is_selected(selected, entities))
+                    .map(|(entities, dates)| (entities.clone(), dates.clone()))
+                    .collect(),
             },
             ObservationDatesConfig::EntitiesEventSpecific(ent) => {
                 let mut obs_dates: HashMap<Entities, Vec1Wrapper<ObservationTime>> = HashMap::new();
                 for event in event_store.all_events_sorted().iter().flatten() {
+                    if !is_selected(selected, &event.entities) {
+                        continue;
+                    }
                     if let Some(dates) = ent.dates.get(&event.entities) {
                         let event_ids = dates.iter().map(|e| e.1.clone()).collect_vec();
                         let event_id = event.event_id.clone().ok_or(anyhow!(
@@ -290,6 +337,9 @@ impl ObservationDatesConfig {
             ObservationDatesConfig::AllEvents => {
                 let mut obs_dates: HashMap<Entities, Vec1Wrapper<ObservationTime>> = HashMap::new();
                 for event in event_store.all_events_sorted().iter().flatten() {
+                    if !is_selected(selected, &event.entities) {
+                        continue;
+                    }
                     let obs_time = ObservationTime {
                         datetime: event.event_time,
                         event_id: Some(event.event_id.clone().ok_or(anyhow!(
@@ -304,7 +354,8 @@ impl ObservationDatesConfig {
                 let mut obs_dates: HashMap<Entities, Vec1Wrapper<ObservationTime>> = HashMap::new();
                 for event in event_store.all_events_sorted().iter().flatten() {
                     let event_entity_types = HashSet::from_iter(event.entities.keys().cloned());
-                    if event_entity_types == *entity_types {
+                    if event_entity_types == *entity_types && is_selected(selected, &event.entities)
+                    {
                         let obs_time = ObservationTime {
                             datetime: event.event_time,
                             event_id: Some(event.event_id.clone().ok_or(anyhow!(
@@ -316,6 +367,39 @@ impl ObservationDatesConfig {
                 }
                 ObservationDates { inner: obs_dates }
             }
+            ObservationDatesConfig::RandomPerEntity(random) => {
+                random.materialize(&event_store, query_config, selected)?
+            }
+            ObservationDatesConfig::Poisson(poisson) => {
+                poisson.materialize(&event_store, query_config, selected)?
+            }
+            ObservationDatesConfig::StratifiedByLabel(stratified) => {
+                stratified.materialize(&event_store, query_config, selected)?
+            }
+            ObservationDatesConfig::EntityFiltered(filtered) => {
+                let filtered_entities = filtered
+                    .filter
+                    .select_entities(&event_store, query_config)?
+                    .into_iter()
+                    .filter(|entities| is_selected(selected, entities))
+                    .collect();
+                filtered.dates.materialize_for_entities(
+                    event_store,
+                    query_config,
+                    Some(&filtered_entities),
+                )?
+            }
+            ObservationDatesConfig::Calendar(schedule) => {
+                schedule.materialize(&event_store, query_config, selected)?
+            }
+            ObservationDatesConfig::RelativeToEvents(relative) => {
+                let obs_dates = relative.events.materialize_for_entities(
+                    event_store,
+                    query_config,
+                    selected,
+                )?;
+                relative.adjust(obs_dates, query_config)?
+            }
         };
 
         #[cfg(test)]
@@ -416,11 +500,621 @@ impl Interval {
     }
 }
 
+const MILLISECONDS_IN_DAY: f64 = 86_400_000.0;
+
+/// Selected entities of the given types sorted by type and id so that sampling doesn't depend
+/// on the order in which the event store returns them
+fn sorted_entities(
+    event_store: &dyn EventStore,
+    entity_types: &HashSet<EntityType>,
+    selected: Option<&HashSet<Entities>>,
+) -> Vec<Entities> {
+    event_store
+        .get_entities(&None)
+        .into_iter()
+        .filter(|entity| entity_types.contains(&entity.typ))
+        .sorted_by(|a, b| (&a.typ, &a.id).cmp(&(&b.typ, &b.id)))
+        .map(|entity| btreemap!(entity.typ => EntityID(entity.id)))
+        .filter(|entities| is_selected(selected, entities))
+        .collect()
+}
+
+fn is_selected(selected: Option<&HashSet<Entities>>, entities: &Entities) -> bool {
+    selected.is_none_or(|selected| selected.contains(entities))
+}
+
+/// Time of the first and the last event of the entity
+fn activity_span(
+    event_store: &dyn EventStore,
+    entities: &Entities,
+    query_config: &QueryConfig,
+) -> Option<(Timestamp, Timestamp)> {
+    let events = event_store.query_entity(entities, query_config, None)?;
+    Some((events.first()?.0, events.last()?.0))
+}
+
+/// Time of the first and the last event in the event store
+fn dataset_span(event_store: &dyn EventStore) -> Result<Option<(Timestamp, Timestamp)>> {
+    let events = event_store.all_events_sorted()?;
+    Ok(events
+        .first()
+        .zip(events.last())
+        .map(|(first, last)| (first.event_time, last.event_time)))
+}
+
+fn random_datetime(rng: &mut StdRng, start: Timestamp, end: Timestamp) -> Timestamp {
+    let span = end.sub(start).num_milliseconds().max(0);
+    start.add(Duration::milliseconds(rng.gen_range(0..=span)))
+}
+
+fn insert_sorted(
+    obs_dates: &mut HashMap<Entities, Vec1Wrapper<ObservationTime>>,
+    entities: &Entities,
+    mut datetimes: Vec<Timestamp>,
+) {
+    datetimes.sort();
+    for datetime in datetimes {
+        ObservationDatesConfig::insert_into_dates(obs_dates, entities.clone(), datetime.into());
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RandomPerEntity {
+    pub entity_types: HashSet<EntityType>,
+    pub n: usize,
+    pub seed: u64,
+    /// draw the dates between the first and the last event of the entity instead of
+    /// between the first and the last event of the whole dataset
+    #[serde(default)]
+    pub within_activity_span: bool,
+}
+
+impl RandomPerEntity {
+    fn materialize(
+        &self,
+        event_store: &dyn EventStore,
+        query_config: &QueryConfig,
+        selected: Option<&HashSet<Entities>>,
+    ) -> Result<ObservationDates> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let dataset_span = dataset_span(event_store)?;
+        let mut obs_dates: HashMap<Entities, Vec1Wrapper<ObservationTime>> = HashMap::new();
+        for entities in sorted_entities(event_store, &self.entity_types, selected) {
+            let span = if self.within_activity_span {
+                activity_span(event_store, &entities, query_config)
+            } else {
+                dataset_span
+            };
+            if let Some((start, end)) = span {
+                let datetimes = (0..self.n)
+                    .map(|_| random_datetime(&mut rng, start, end))
+                    .collect_vec();
+                insert_sorted(&mut obs_dates, &entities, datetimes);
+            }
+        }
+        Ok(ObservationDates { inner: obs_dates })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Poisson {
+    pub entity_types: HashSet<EntityType>,
+    /// expected number of observation dates per day of activity of the entity
+    pub rate: f64,
+    pub seed: u64,
+}
+
+impl Poisson {
+    fn materialize(
+        &self,
+        event_store: &dyn EventStore,
+        query_config: &QueryConfig,
+        selected: Option<&HashSet<Entities>>,
+    ) -> Result<ObservationDates> {
+        if !self.rate.is_finite() || self.rate <= 0.0 {
+            bail!("The rate of the Poisson observation dates must be a positive number");
+        }
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut obs_dates: HashMap<Entities, Vec1Wrapper<ObservationTime>> = HashMap::new();
+        for entities in sorted_entities(event_store, &self.entity_types, selected) {
+            if let Some((start, end)) = activity_span(event_store, &entities, query_config) {
+                let mut datetimes = vec![];
+                let mut dt = start;
+                loop {
+                    // exponentially distributed waiting time between the observation dates
+                    let wait_days = -(1.0 - rng.gen::<f64>()).ln() / self.rate;
+                    let wait = Duration::milliseconds((wait_days * MILLISECONDS_IN_DAY) as i64);
+                    // the waiting time can overflow the date time when the rate is tiny
+                    dt = match dt.checked_add_signed(wait) {
+                        Some(dt) if dt <= end => dt,
+                        _ => break,
+                    };
+                    datetimes.push(dt);
+                }
+                insert_sorted(&mut obs_dates, &entities, datetimes);
+            }
+        }
+        Ok(ObservationDates { inner: obs_dates })
+    }
+}
+
+/// The label of an observation date is positive if an event matching the condition
+/// happens for the entity within `horizon_days` after the observation date.
+/// Candidate dates are drawn uniformly from the activity span of every entity and
+/// `n_per_label` of both the positive and the negative candidates are kept.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct StratifiedByLabel {
+    pub entity_types: HashSet<EntityType>,
+    pub condition: String,
+    pub horizon_days: i64,
+    pub n_per_label: usize,
+    pub candidates_per_entity: usize,
+    pub seed: u64,
+}
+
+impl StratifiedByLabel {
+    fn materialize(
+        &self,
+        event_store: &dyn EventStore,
+        query_config: &QueryConfig,
+        selected: Option<&HashSet<Entities>>,
+    ) -> Result<ObservationDates> {
+        let condition_expr = Expr::from_str(&self.condition)
+            .with_context(|| format!("Cannot parse label condition {}", self.condition))?;
+        // the candidates are drawn per entity so a label event with other entities too is a
+        // label of each of its entities of the configured types
+        let mut label_times: HashMap<Entities, Vec<Timestamp>> = HashMap::new();
+        for event in event_store.filter_events(&condition_expr, query_config, &HashMap::new())? {
+            for (entity_type, entity_id) in event
+                .entities
+                .iter()
+                .filter(|(entity_type, _)| self.entity_types.contains(*entity_type))
+            {
+                label_times
+                    .entry(btreemap!(entity_type.clone() => entity_id.clone()))
+                    .or_default()
+                    .push(event.event_time);
+            }
+        }
+        label_times.values_mut().for_each(|times| times.sort());
+        let horizon = Duration::days(self.horizon_days);
+
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut positives = vec![];
+        let mut negatives = vec![];
+        for entities in sorted_entities(event_store, &self.entity_types, selected) {
+            if let Some((start, end)) = activity_span(event_store, &entities, query_config) {
+                let times = label_times.get(&entities);
+                for _ in 0..self.candidates_per_entity {
+                    let dt = random_datetime(&mut rng, start, end);
+                    let positive = times.is_some_and(|times| {
+                        let first_after = times.partition_point(|t| *t <= dt);
+                        times
+                            .get(first_after)
+                            .is_some_and(|t| *t <= dt.add(horizon))
+                    });
+                    if positive {
+                        positives.push((entities.clone(), dt));
+                    } else {
+                        negatives.push((entities.clone(), dt));
+                    }
+                }
+            }
+        }
+
+        let mut obs_dates: HashMap<Entities, Vec<Timestamp>> = HashMap::new();
+        for mut candidates in [positives, negatives] {
+            candidates.shuffle(&mut rng);
+            for (entities, dt) in candidates.into_iter().take(self.n_per_label) {
+                obs_dates.entry(entities).or_default().push(dt);
+            }
+        }
+        let mut inner: HashMap<Entities, Vec1Wrapper<ObservationTime>> = HashMap::new();
+        for (entities, datetimes) in obs_dates {
+            insert_sorted(&mut inner, &entities, datetimes);
+        }
+        Ok(ObservationDates { inner })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Period {
+    pub date_part: DatePart,
+    pub nth: i64,
+}
+
+impl Period {
+    pub fn to_duration(&self) -> Result<Duration> {
+        Ok(match self.date_part {
+            DatePart::Millisecond => Duration::milliseconds(self.nth),
+            DatePart::Second => Duration::seconds(self.nth),
+            DatePart::Minute => Duration::minutes(self.nth),
+            DatePart::Hour => Duration::hours(self.nth),
+            DatePart::Day => Duration::days(self.nth),
+            DatePart::Week => Duration::weeks(self.nth),
+            DatePart::All => bail!("Period cannot be defined with DatePart::All"),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub enum EventAnchor {
+    /// The observation date is the given period before the event. The event itself is not
+    /// available in the context because it happens after the observation date.
+    Before(Period),
+    After(Period),
+    /// The last millisecond of the day of the event in the time zone of `RelativeToEvents`
+    EndOfDay,
+}
+
+impl EventAnchor {
+    /// The end of the day is taken in the time zone if given, in UTC otherwise
+    fn apply(&self, obs_time: ObservationTime, time_zone: Option<&Tz>) -> Result<ObservationTime> {
+        Ok(match self {
+            EventAnchor::Before(period) => ObservationTime {
+                datetime: obs_time.datetime.sub(period.to_duration()?),
+                event_id: None,
+            },
+            EventAnchor::After(period) => ObservationTime {
+                datetime: obs_time.datetime.add(period.to_duration()?),
+                event_id: obs_time.event_id,
+            },
+            EventAnchor::EndOfDay => {
+                let end_of_day = |datetime: NaiveDateTime| {
+                    datetime
+                        .date()
+                        .and_hms_milli_opt(23, 59, 59, 999)
+                        .context("Cannot construct the end of the day")
+                };
+                ObservationTime {
+                    datetime: match time_zone {
+                        Some(time_zone) => local_to_utc(
+                            end_of_day(utc_to_local(obs_time.datetime, time_zone))?,
+                            time_zone,
+                        ),
+                        None => end_of_day(obs_time.datetime)?,
+                    },
+                    event_id: obs_time.event_id,
+                }
+            }
+        })
+    }
+}
+
+/// Observation dates relative to the events selected by another configuration,
+/// usually `AllEvents`, `AllEventsByEntity` or `ConditionalEvents`, e.g. one hour before every
+/// checkout or the end of the day of each login.
+/// After moving the dates, `one_per_day` keeps only the first observation date of each day and
+/// `min_gap` drops the observation dates closer than the gap to the previous kept one.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RelativeToEvents {
+    pub events: Box<ObservationDatesConfig>,
+    #[serde(default)]
+    pub anchor: Option<EventAnchor>,
+    #[serde(default)]
+    pub one_per_day: bool,
+    #[serde(default)]
+    pub min_gap: Option<Period>,
+    /// time zone of the days of `EndOfDay` and `one_per_day`, the time zone of the query if
+    /// not set
+    #[serde(default)]
+    pub time_zone: Option<String>,
+}
+
+impl RelativeToEvents {
+    fn adjust(
+        &self,
+        obs_dates: ObservationDates,
+        query_config: &QueryConfig,
+    ) -> Result<ObservationDates> {
+        let min_gap = self.min_gap.as_ref().map(|p| p.to_duration()).transpose()?;
+        let time_zone = self
+            .time_zone
+            .as_ref()
+            .or(query_config.time_zone.as_ref())
+            .map(|time_zone| parse_time_zone(time_zone))
+            .transpose()?;
+        let local_date = |datetime| match &time_zone {
+            Some(time_zone) => utc_to_local(datetime, time_zone).date(),
+            None => datetime.date(),
+        };
+        let mut inner: HashMap<Entities, Vec1Wrapper<ObservationTime>> = HashMap::new();
+        for (entities, times) in obs_dates.inner {
+            let mut times: Vec<ObservationTime> = match &self.anchor {
+                Some(anchor) => times
+                    .0
+                    .into_iter()
+                    .map(|t| anchor.apply(t, time_zone.as_ref()))
+                    .collect::<Result<_>>()?,
+                None => times.0.into_vec(),
+            };
+            times.sort();
+            let mut last_kept: Option<Timestamp> = None;
+            for obs_time in times {
+                if let Some(last) = last_kept {
+                    if self.one_per_day && local_date(last) == local_date(obs_time.datetime) {
+                        continue;
+                    }
+                    if min_gap.is_some_and(|gap| obs_time.datetime.sub(last) < gap) {
+                        continue;
+                    }
+                }
+                last_kept = Some(obs_time.datetime);
+                ObservationDatesConfig::insert_into_dates(&mut inner, entities.clone(), obs_time);
+            }
+        }
+        Ok(ObservationDates { inner })
+    }
+}
+
+/// Conditions the entities must meet to get observation dates, e.g. users with at least
+/// 5 purchases: `(count(*) over past where event_type = 'purchase') >= 5`.
+/// The condition is evaluated once per entity, after the last event in the event store.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EntityFilter {
+    pub entity_types: HashSet<EntityType>,
+    #[serde(default)]
+    pub condition: Option<String>,
+    /// the first event of the entity must happen at or after this time
+    #[serde(default)]
+    pub min_first_seen: Option<Timestamp>,
+    /// the last event of the entity must happen at or before this time
+    #[serde(default)]
+    pub max_last_seen: Option<Timestamp>,
+}
+
+impl EntityFilter {
+    pub fn select_entities(
+        &self,
+        event_store: &EventStoreImpl,
+        query_config: &QueryConfig,
+    ) -> Result<HashSet<Entities>> {
+        let condition = self
+            .condition
+            .as_ref()
+            .map(|condition| {
+                Expr::from_str(condition)
+                    .with_context(|| format!("Cannot parse entity condition {}", condition))
+            })
+            .transpose()?;
+        let dataset_end = match dataset_span(event_store)? {
+            Some((_, end)) => end,
+            None => return Ok(HashSet::new()),
+        };
+        let condition_query_config = QueryConfig {
+            include_events_on_obs_date: true,
+            ..query_config.clone()
+        };
+        let event_context = EventContext {
+            event_store: event_store.clone(),
+        };
+
+        let mut selected = HashSet::new();
+        for entities in sorted_entities(event_store, &self.entity_types, None) {
+            let (first_seen, last_seen) = match activity_span(event_store, &entities, query_config)
+            {
+                Some(span) => span,
+                None => continue,
+            };
+            if self.min_first_seen.is_some_and(|min| first_seen < min)
+                || self.max_last_seen.is_some_and(|max| last_seen > max)
+            {
+                continue;
+            }
+            if let Some(condition) = &condition {
+                let context = EvalContext {
+                    event_index: Some(&event_context),
+                    event_query_config: Some(EventScopeConfig::RelatedEntitiesEvents(
+                        entities.keys().cloned().collect(),
+                    )),
+                    query_config: Some(&condition_query_config),
+                    entities: Some(entities.clone()),
+                    obs_date: Some(ObsDate {
+                        inner: vec1![dataset_end.into()],
+                    }),
+                    ..Default::default()
+                };
+                let values = eval_context_dispatcher(condition, &context, &HashMap::new())?;
+                if values.get(&dataset_end) != Some(&Value::Bool(true)) {
+                    continue;
+                }
+            }
+            selected.insert(entities);
+        }
+        Ok(selected)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct EntityFiltered {
+    pub filter: EntityFilter,
+    pub dates: Box<ObservationDatesConfig>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+/// A subset of the iCalendar RRULE. The days of every period (day, week starting on Monday
+/// or month) are filtered by `by_weekday`, `by_month_day` and `business_days_only` and then
+/// `by_set_pos` picks one of the remaining days, for example the last business day of every
+/// month is `{"frequency": "Monthly", "business_days_only": true, "by_set_pos": -1}`.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CalendarRule {
+    pub frequency: Frequency,
+    /// 1 = Monday ... 7 = Sunday
+    #[serde(default)]
+    pub by_weekday: Vec<u32>,
+    /// negative values count from the end of the month, -1 is the last day
+    #[serde(default)]
+    pub by_month_day: Vec<i32>,
+    #[serde(default)]
+    pub business_days_only: bool,
+    /// 1 is the first of the matching days in the period, -1 the last one
+    #[serde(default)]
+    pub by_set_pos: Option<i32>,
+}
+
+impl CalendarRule {
+    fn period_start(&self, date: NaiveDate) -> NaiveDate {
+        match self.frequency {
+            Frequency::Daily => date,
+            Frequency::Weekly => {
+                date - Duration::days(date.weekday().num_days_from_monday() as i64)
+            }
+            Frequency::Monthly => date.with_day(1).unwrap_or(date),
+        }
+    }
+
+    fn matches(&self, date: NaiveDate, calendar: &dyn HolidayCalendar) -> bool {
+        let weekday_matches = self.by_weekday.is_empty()
+            || self
+                .by_weekday
+                .contains(&date.weekday().number_from_monday());
+        let month_day_matches = self.by_month_day.is_empty() || {
+            let days_in_month = days_in_month(date);
+            self.by_month_day.iter().any(|day| {
+                let day = if *day < 0 {
+                    days_in_month + 1 + day
+                } else {
+                    *day
+                };
+                day == date.day() as i32
+            })
+        };
+        weekday_matches
+            && month_day_matches
+            && (!self.business_days_only || calendar.is_business_day(date))
+    }
+
+    /// All the dates of the rule between the start and the end date (inclusive)
+    pub fn generate(
+        &self,
+        start: NaiveDate,
+        end: NaiveDate,
+        calendar: &dyn HolidayCalendar,
+    ) -> Vec<NaiveDate> {
+        let mut dates = vec![];
+        let mut period: Vec<NaiveDate> = vec![];
+        // iterate over whole periods so that by_set_pos sees every day of the period
+        let mut day = self.period_start(start);
+        let mut current_period = day;
+        let last_period = self.period_start(end);
+        while self.period_start(day) <= last_period {
+            if self.period_start(day) != current_period {
+                dates.extend(self.select(&period));
+                period.clear();
+                current_period = self.period_start(day);
+            }
+            if self.matches(day, calendar) {
+                period.push(day);
+            }
+            day += Duration::days(1);
+        }
+        dates.extend(self.select(&period));
+        dates
+            .into_iter()
+            .filter(|date| *date >= start && *date <= end)
+            .collect()
+    }
+
+    fn select(&self, period: &[NaiveDate]) -> Vec<NaiveDate> {
+        match self.by_set_pos {
+            None => period.to_vec(),
+            Some(pos) if pos > 0 => period.get(pos as usize - 1).cloned().into_iter().collect(),
+            Some(pos) if pos < 0 => period
+                .len()
+                .checked_sub(pos.unsigned_abs() as usize)
+                .and_then(|index| period.get(index))
+                .cloned()
+                .into_iter()
+                .collect(),
+            Some(_) => vec![],
+        }
+    }
+}
+
+fn days_in_month(date: NaiveDate) -> i32 {
+    let (year, month) = if date.month() == 12 {
+        (date.year() + 1, 1)
+    } else {
+        (date.year(), date.month() + 1)
+    };
+    NaiveDate::from_ymd_opt(year, month, 1)
+        .map(|next_month| next_month.pred_opt().map_or(31, |last| last.day() as i32))
+        .unwrap_or(31)
+}
+
+/// Observation dates generated by a calendar rule between the first and the last event
+/// of every entity
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct CalendarSchedule {
+    pub entity_types: HashSet<EntityType>,
+    pub rule: CalendarRule,
+    /// time of the day of the observation dates, midnight if not set
+    #[serde(default)]
+    pub time: Option<NaiveTime>,
+    /// name of a registered holiday calendar, weekends only if not set
+    #[serde(default)]
+    pub calendar: Option<String>,
+    /// time zone of the dates and the time of the day, the time zone of the query if not set
+    #[serde(default)]
+    pub time_zone: Option<String>,
+}
+
+impl CalendarSchedule {
+    fn materialize(
+        &self,
+        event_store: &dyn EventStore,
+        query_config: &QueryConfig,
+        selected: Option<&HashSet<Entities>>,
+    ) -> Result<ObservationDates> {
+        let calendar = get_calendar(self.calendar.as_deref())?;
+        let time = self.time.unwrap_or(NaiveTime::MIN);
+        let time_zone = self
+            .time_zone
+            .as_ref()
+            .or(query_config.time_zone.as_ref())
+            .map(|time_zone| parse_time_zone(time_zone))
+            .transpose()?;
+        let to_local = |datetime| match &time_zone {
+            Some(time_zone) => utc_to_local(datetime, time_zone),
+            None => datetime,
+        };
+        let mut obs_dates: HashMap<Entities, Vec1Wrapper<ObservationTime>> = HashMap::new();
+        for entities in sorted_entities(event_store, &self.entity_types, selected) {
+            if let Some((start, end)) = activity_span(event_store, &entities, query_config) {
+                let datetimes = self
+                    .rule
+                    .generate(
+                        to_local(start).date(),
+                        to_local(end).date(),
+                        calendar.as_ref(),
+                    )
+                    .into_iter()
+                    .map(|date| match &time_zone {
+                        Some(time_zone) => local_to_utc(date.and_time(time), time_zone),
+                        None => date.and_time(time),
+                    })
+                    .filter(|datetime| *datetime >= start && *datetime <= end)
+                    .collect_vec();
+                insert_sorted(&mut obs_dates, &entities, datetimes);
+            }
+        }
+        Ok(ObservationDates { inner: obs_dates })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use chrono::{TimeZone, Utc};
 
     use super::*;
+    use crate::event::EventType;
+    use crate::event_store::row_event_store::memory_event_store::MemoryEventStore;
 
     #[test]
     fn test_n_between() {
@@ -439,4 +1133,437 @@ mod tests {
         let datetimes = nbetween.generate_from_start_end(start_dt, end_dt);
         assert!(datetimes.len() == 5);
     }
+
+    fn create_store() -> MemoryEventStore {
+        let store = MemoryEventStore::new();
+        for (entity, days) in [("a", 0..30), ("b", 10..20), ("c", 5..40)] {
+            for day in days {
+                let event_type = if day % 7 == 0 { "churn" } else { "visit" };
+                store
+                    .insert(Event {
+                        event_type: EventType(event_type.into()),
+                        event_time: NaiveDateTime::from_str("2021-01-01T00:00:00").unwrap()
+                            + Duration::days(day),
+                        entities: btreemap!["user".into() => entity.into()],
+                        event_id: Some(format!("{}_{}", entity, day).into()),
+                        experiment_id: None,
+                        attrs: None,
+                    })
+                    .unwrap();
+            }
+        }
+        store
+    }
+
+    fn user_entity_types() -> HashSet<EntityType> {
+        HashSet::from_iter(vec![EntityType::from("user")])
+    }
+
+    fn materialize(config: ObservationDatesConfig) -> ObservationDates {
+        let mut config = config;
+        config
+            .materialize_observation_dates(
+                EventStoreImpl::MemoryEventStore(create_store()),
+                &QueryConfig::default(),
+            )
+            .unwrap()
+    }
+
+    fn entity(id: &str) -> Entities {
+        btreemap!["user".into() => id.into()]
+    }
+
+    #[test]
+    fn test_random_per_entity() {
+        let config = |seed, within_activity_span| {
+            ObservationDatesConfig::RandomPerEntity(RandomPerEntity {
+                entity_types: user_entity_types(),
+                n: 5,
+                seed,
+                within_activity_span,
+            })
+        };
+        let obs_dates = materialize(config(42, true));
+        assert_eq!(obs_dates.inner.len(), 3);
+        let b = &obs_dates.inner[&entity("b")].0;
+        assert_eq!(b.len(), 5);
+        let start = NaiveDateTime::from_str("2021-01-11T00:00:00").unwrap();
+        let end = NaiveDateTime::from_str("2021-01-20T00:00:00").unwrap();
+        assert!(b.iter().all(|t| t.datetime >= start && t.datetime <= end));
+
+        // the same seed gives the same dates
+        let again = materialize(config(42, true));
+        assert_eq!(b, &again.inner[&entity("b")].0);
+        let other_seed = materialize(config(7, true));
+        assert_ne!(b, &other_seed.inner[&entity("b")].0);
+
+        // without the activity span the dates cover the whole dataset
+        let obs_dates = materialize(config(42, false));
+        let start = NaiveDateTime::from_str("2021-01-01T00:00:00").unwrap();
+        let end = NaiveDateTime::from_str("2021-02-09T00:00:00").unwrap();
+        assert!(obs_dates
+            .inner
+            .values()
+            .flat_map(|v| v.0.iter())
+            .all(|t| t.datetime >= start && t.datetime <= end));
+    }
+
+    #[test]
+    fn test_poisson() {
+        let obs_dates = materialize(ObservationDatesConfig::Poisson(Poisson {
+            entity_types: user_entity_types(),
+            rate: 2.0,
+            seed: 1,
+        }));
+        // about 2 observation dates per day over 29, 9 and 34 days of activity
+        let count: usize = obs_dates.inner.values().map(|v| v.0.len()).sum();
+        assert!((100..=150).contains(&count), "count: {}", count);
+    }
+
+    #[test]
+    fn test_poisson_rate() {
+        let poisson = |rate| {
+            ObservationDatesConfig::Poisson(Poisson {
+                entity_types: user_entity_types(),
+                rate,
+                seed: 1,
+            })
+            .materialize_observation_dates(
+                EventStoreImpl::MemoryEventStore(create_store()),
+                &QueryConfig::default(),
+            )
+        };
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let err = poisson(rate).unwrap_err();
+            assert!(format!("{:#}", err).contains("must be a positive number"));
+        }
+        // the first waiting time is past the largest date time
+        let obs_dates = poisson(1e-300).unwrap();
+        assert!(obs_dates.inner.is_empty());
+    }
+
+    #[test]
+    fn test_stratified_by_label() {
+        let obs_dates = materialize(ObservationDatesConfig::StratifiedByLabel(
+            StratifiedByLabel {
+                entity_types: user_entity_types(),
+                condition: "event_type = 'churn'".into(),
+                horizon_days: 1,
+                n_per_label: 10,
+                candidates_per_entity: 50,
+                seed: 3,
+            },
+        ));
+        let store = create_store();
+        let churn_times = store
+            .all_events_sorted()
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.event_type == EventType("churn".into()))
+            .map(|e| (e.entities.clone(), e.event_time))
+            .collect_vec();
+        let mut positives = 0;
+        let mut total = 0;
+        for (entities, dates) in &obs_dates.inner {
+            for obs_time in dates.0.iter() {
+                total += 1;
+                if churn_times.iter().any(|(e, t)| {
+                    e == entities
+                        && *t > obs_time.datetime
+                        && *t <= obs_time.datetime + Duration::days(1)
+                }) {
+                    positives += 1;
+                }
+            }
+        }
+        assert_eq!(total, 20);
+        assert_eq!(positives, 10);
+    }
+
+    #[test]
+    fn test_stratified_by_label_with_multi_entity_labels() {
+        let store = MemoryEventStore::new();
+        for day in 0..20 {
+            let churn = day % 7 == 0;
+            let mut entities = entity("a");
+            if churn {
+                entities.insert("merchant".into(), "m".into());
+            }
+            store
+                .insert(Event {
+                    event_type: EventType(if churn { "churn" } else { "visit" }.into()),
+                    event_time: NaiveDateTime::from_str("2021-01-01T00:00:00").unwrap()
+                        + Duration::days(day),
+                    entities,
+                    event_id: Some(day.to_string().into()),
+                    experiment_id: None,
+                    attrs: None,
+                })
+                .unwrap();
+        }
+        let mut config = ObservationDatesConfig::StratifiedByLabel(StratifiedByLabel {
+            entity_types: user_entity_types(),
+            condition: "event_type = 'churn'".into(),
+            horizon_days: 1,
+            n_per_label: 5,
+            candidates_per_entity: 100,
+            seed: 3,
+        });
+        let obs_dates = config
+            .materialize_observation_dates(
+                EventStoreImpl::MemoryEventStore(store),
+                &QueryConfig::default(),
+            )
+            .unwrap();
+        let churn_days = [7, 14].map(|day| {
+            NaiveDateTime::from_str("2021-01-01T00:00:00").unwrap() + Duration::days(day)
+        });
+        let dates = &obs_dates.inner[&entity("a")].0;
+        let positives = dates
+            .iter()
+            .filter(|obs_time| {
+                churn_days
+                    .iter()
+                    .any(|t| *t > obs_time.datetime && *t <= obs_time.datetime + Duration::days(1))
+            })
+            .count();
+        assert_eq!(dates.len(), 10);
+        assert_eq!(positives, 5);
+    }
+
+    #[test]
+    fn test_relative_to_events() {
+        let checkouts = || {
+            Box::new(ObservationDatesConfig::ConditionalEvents(
+                ConditionalEvents {
+                    entity_types: user_entity_types(),
+                    condition: "event_type = 'churn'".into(),
+                },
+            ))
+        };
+        let obs_dates = materialize(ObservationDatesConfig::RelativeToEvents(RelativeToEvents {
+            events: checkouts(),
+            anchor: Some(EventAnchor::Before(Period {
+                date_part: DatePart::Hour,
+                nth: 1,
+            })),
+            one_per_day: false,
+            min_gap: None,
+            time_zone: None,
+        }));
+        let b = &obs_dates.inner[&entity("b")].0;
+        // b is active between day 10 and 19 and churns on day 14
+        assert_eq!(
+            b.as_slice(),
+            &[ObservationTime {
+                datetime: NaiveDateTime::from_str("2021-01-14T23:00:00").unwrap(),
+                event_id: None,
+            }]
+        );
+
+        let obs_dates = materialize(ObservationDatesConfig::RelativeToEvents(RelativeToEvents {
+            events: Box::new(ObservationDatesConfig::AllEvents),
+            anchor: Some(EventAnchor::EndOfDay),
+            one_per_day: true,
+            min_gap: Some(Period {
+                date_part: DatePart::Day,
+                nth: 7,
+            }),
+            time_zone: None,
+        }));
+        let a = &obs_dates.inner[&entity("a")].0;
+        // events from day 0 to 29, one observation date every 7 days at the end of the day
+        assert_eq!(
+            a.iter().map(|t| t.datetime).collect_vec(),
+            vec![0, 7, 14, 21, 28]
+                .into_iter()
+                .map(
+                    |day| NaiveDateTime::from_str("2021-01-01T23:59:59.999").unwrap()
+                        + Duration::days(day)
+                )
+                .collect_vec()
+        );
+        assert_eq!(a[0].event_id, Some("a_0".into()));
+    }
+
+    #[test]
+    fn test_relative_to_events_time_zone() {
+        let relative = RelativeToEvents {
+            events: Box::new(ObservationDatesConfig::AllEvents),
+            anchor: Some(EventAnchor::EndOfDay),
+            one_per_day: true,
+            min_gap: None,
+            time_zone: Some("America/New_York".into()),
+        };
+        let obs_dates = materialize(ObservationDatesConfig::RelativeToEvents(relative.clone()));
+        // the first event at midnight UTC is on the evening of Dec 31 in New York
+        assert_eq!(
+            obs_dates.inner[&entity("a")].0.first().datetime,
+            NaiveDateTime::from_str("2021-01-01T04:59:59.999").unwrap()
+        );
+
+        // both times are on Jan 1 in New York
+        let datetimes = ["2021-01-01T23:00:00", "2021-01-02T01:00:00"]
+            .iter()
+            .map(|dt| NaiveDateTime::from_str(dt).unwrap())
+            .collect_vec();
+        let obs_dates = || {
+            let mut inner = HashMap::new();
+            insert_sorted(&mut inner, &entity("a"), datetimes.clone());
+            ObservationDates { inner }
+        };
+        let relative = RelativeToEvents {
+            anchor: None,
+            time_zone: None,
+            ..relative
+        };
+        let count = |query_config: &QueryConfig| {
+            relative.adjust(obs_dates(), query_config).unwrap().inner[&entity("a")]
+                .0
+                .len()
+        };
+        assert_eq!(count(&QueryConfig::default()), 2);
+        assert_eq!(
+            count(&QueryConfig {
+                time_zone: Some("America/New_York".into()),
+                ..QueryConfig::default()
+            }),
+            1
+        );
+    }
+
+    #[test]
+    fn test_entity_filtered() {
+        let filtered = |condition: Option<&str>, min_first_seen: Option<&str>| {
+            ObservationDatesConfig::EntityFiltered(EntityFiltered {
+                filter: EntityFilter {
+                    entity_types: user_entity_types(),
+                    condition: condition.map(|c| c.to_string()),
+                    min_first_seen: min_first_seen.map(|t| NaiveDateTime::from_str(t).unwrap()),
+                    max_last_seen: None,
+                },
+                dates: Box::new(ObservationDatesConfig::Fixed(Fixed::new_from_str_vec(
+                    user_entity_types(),
+                    vec!["2021-01-15T00:00:00".into()],
+                ))),
+            })
+        };
+        let selected = |config| {
+            materialize(config)
+                .inner
+                .into_keys()
+                .map(|e| e[&EntityType::from("user")].0.clone())
+                .sorted()
+                .collect_vec()
+        };
+        assert_eq!(selected(filtered(None, None)), vec!["a", "b", "c"]);
+        // b has 10 events, a 30 and c 35
+        assert_eq!(
+            selected(filtered(Some("count(*) over past >= 30"), None)),
+            vec!["a", "c"]
+        );
+        assert_eq!(
+            selected(filtered(
+                Some("(count(*) over past where event_type = 'churn') >= 2"),
+                Some("2021-01-03T00:00:00")
+            )),
+            vec!["c"]
+        );
+
+        // the labels are stratified among the selected entities only
+        let obs_dates = materialize(ObservationDatesConfig::EntityFiltered(EntityFiltered {
+            filter: EntityFilter {
+                entity_types: user_entity_types(),
+                condition: Some("count(*) over past >= 30".into()),
+                min_first_seen: None,
+                max_last_seen: None,
+            },
+            dates: Box::new(ObservationDatesConfig::StratifiedByLabel(
+                StratifiedByLabel {
+                    entity_types: user_entity_types(),
+                    condition: "event_type = 'churn'".into(),
+                    horizon_days: 1,
+                    n_per_label: 10,
+                    candidates_per_entity: 100,
+                    seed: 3,
+                },
+            )),
+        }));
+        assert!(!obs_dates.inner.contains_key(&entity("b")));
+        assert_eq!(
+            obs_dates
+                .inner
+                .values()
+                .map(|dates| dates.0.len())
+                .sum::<usize>(),
+            20
+        );
+    }
+
+    #[test]
+    fn test_calendar_rule() {
+        let calendar = get_calendar(None).unwrap();
+        let last_business_day = CalendarRule {
+            frequency: Frequency::Monthly,
+            by_weekday: vec![],
+            by_month_day: vec![],
+            business_days_only: true,
+            by_set_pos: Some(-1),
+        };
+        let start = NaiveDate::from_ymd(2023, 1, 15);
+        let end = NaiveDate::from_ymd(2023, 4, 30);
+        assert_eq!(
+            last_business_day.generate(start, end, calendar.as_ref()),
+            vec![
+                NaiveDate::from_ymd(2023, 1, 31),
+                NaiveDate::from_ymd(2023, 2, 28),
+                NaiveDate::from_ymd(2023, 3, 31),
+                // April 30th 2023 is a Sunday
+                NaiveDate::from_ymd(2023, 4, 28),
+            ]
+        );
+
+        let last_day = CalendarRule {
+            frequency: Frequency::Monthly,
+            by_weekday: vec![],
+            by_month_day: vec![-1],
+            business_days_only: false,
+            by_set_pos: None,
+        };
+        assert_eq!(
+            last_day.generate(start, NaiveDate::from_ymd(2023, 3, 1), calendar.as_ref()),
+            vec![
+                NaiveDate::from_ymd(2023, 1, 31),
+                NaiveDate::from_ymd(2023, 2, 28)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_calendar_schedule() {
+        // every Monday at 09:00, a is active from 2021-01-01 (Friday) to 2021-01-30
+        let obs_dates = materialize(ObservationDatesConfig::Calendar(CalendarSchedule {
+            entity_types: user_entity_types(),
+            rule: CalendarRule {
+                frequency: Frequency::Weekly,
+                by_weekday: vec![1],
+                by_month_day: vec![],
+                business_days_only: false,
+                by_set_pos: None,
+            },
+            time: Some(NaiveTime::from_hms(9, 0, 0)),
+            calendar: None,
+            time_zone: None,
+        }));
+        assert_eq!(
+            obs_dates.inner[&entity("a")]
+                .iter()
+                .map(|t| t.datetime)
+                .collect_vec(),
+            vec!["2021-01-04", "2021-01-11", "2021-01-18", "2021-01-25"]
+                .into_iter()
+                .map(|d| NaiveDateTime::from_str(&format!("{}T09:00:00", d)).unwrap())
+                .collect_vec()
+        );
+    }
 }
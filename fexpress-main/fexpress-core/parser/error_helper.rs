@@ -97,6 +97,7 @@ fn rule_to_strings(pair: &Rule) -> Vec<String> {
         ],
         Rule::having_keyword => vec!["having".to_string()],
         Rule::group_by_keyword => vec!["group by".to_string()],
+        Rule::expand_keyword => vec!["expand".to_string()],
         Rule::where_keyword => vec!["where".to_string()],
         _ => vec![],
     }
@@ -1,8 +1,8 @@
 use std::str::FromStr;
 
 use crate::ast::core::{
-    AggrExpr, AggregateFunction, Expr, ExprFunc, ExprFuncDiscriminants, HavingExpr, HavingExprType,
-    SelectExpr,
+    AggrExpr, AggregateFunction, ExpandExpr, Expr, ExprFunc, ExprFuncDiscriminants, HavingExpr,
+    HavingExprType, SelectExpr,
 };
 use crate::sstring::SmallString;
 use anyhow::{anyhow, bail, Context, Result};
@@ -19,7 +19,7 @@ use pest_derive::Parser;
 use strum::{IntoEnumIterator, ParseError};
 use strum_macros::EnumString;
 
-use crate::event::AttributeKey;
+use crate::event::{AttributeKey, EventType};
 use crate::interval::{
     BetweenDatesExpressions, Direction, DirectionOnly, FixedInterval, KeywordInterval, NewInterval,
     Unit,
@@ -178,6 +178,11 @@ pub fn build_term(pair: Pair<Rule>) -> Expr {
         Rule::wildcard => Expr::Wildcard,
         Rule::variable_assignment => parse_variable_assignment(pair.into_inner())
             .unwrap_or_else(|_| Expr::ParsingError("Cannot parse variable assignment".into())),
+        Rule::label_definition => pair
+            .into_inner()
+            .find(|p| p.as_rule() != Rule::label_keyword)
+            .map(|p| Expr::Label(Box::new(build_term(p))))
+            .unwrap_or_else(|| Expr::ParsingError("Cannot parse label definition".into())),
         Rule::date_from_expr => generate_ast(pair.into_inner()),
         Rule::date_to_expr => generate_ast(pair.into_inner()),
         pair => Expr::ParsingError(format!("Unexpected term rule: {:?}", pair)),
@@ -200,6 +205,7 @@ pub fn parse_full_query(pairs: Pairs<Rule>) -> Expr {
     for pair in pairs.into_iter() {
         let maybe_parsed_expression = match pair.as_rule() {
             Rule::variable_assignment => Some(build_term(pair)),
+            Rule::label_definition => Some(build_term(pair)),
             Rule::expr_with_alias => Some(build_term(pair)),
             Rule::expr_without_alias => Some(build_term(pair)),
             _ => None,
@@ -328,6 +334,9 @@ pub fn parse_func(pairs: Pairs<Rule>) -> Result<Expr> {
 
 fn parse_function_name(name: &str, args: Vec<Expr>) -> Result<Expr> {
     let variant_string = name.to_lowercase();
+    if variant_string == "asof" {
+        return parse_asof(args);
+    }
 
     let variant = ExprFuncDiscriminants::iter()
         .find(|discriminant| discriminant.to_string().to_case(Case::Snake) == variant_string);
@@ -467,6 +476,18 @@ fn parse_function_name(name: &str, args: Vec<Expr>) -> Result<Expr> {
     Ok(Expr::Function(expr_func))
 }
 
+// asof(event_type.attribute) where the attribute can be nested as well
+fn parse_asof(args: Vec<Expr>) -> Result<Expr> {
+    match args.as_slice() {
+        [Expr::AttrUntyped(AttributeKey::Nested(keys))] => {
+            let event_type = EventType(keys.first().clone());
+            let attribute = AttributeKey::from_str(&keys[1..].join("."))?;
+            Ok(Expr::AsOf(event_type, attribute))
+        }
+        _ => bail!("asof expects a single argument in the form of event_type.attribute"),
+    }
+}
+
 pub fn extract_rule(pairs: Vec<Pair<Rule>>, rule: Rule) -> Option<Pair<Rule>> {
     for pair in pairs.into_iter() {
         if pair.as_rule() == rule {
@@ -562,6 +583,9 @@ fn parse_aggfunc0(pair: Pair<Rule>) -> Result<Expr> {
         .map(|pair| pair.into_inner())
         .map(|v| v.as_str().to_string());
 
+    let global = extract_rule(inner_pairs.clone(), Rule::global_keyword).is_some()
+        || extract_rule(inner_pairs.clone(), Rule::from_all).is_some();
+
     let where_expr = extract_rule(inner_pairs.clone(), Rule::where_expr)
         .map(|pair| pair.into_inner())
         .map(generate_ast);
@@ -570,6 +594,10 @@ fn parse_aggfunc0(pair: Pair<Rule>) -> Result<Expr> {
         .map(|pair| pair.into_inner())
         .map(generate_ast);
 
+    let expand = extract_rule(inner_pairs.clone(), Rule::expand_clause)
+        .map(|pair| parse_expand_clause(pair, name))
+        .transpose()?;
+
     let having_expr = extract_rule(inner_pairs.clone(), Rule::having_expr)
         .map(build_term)
         .map(|e| match e {
@@ -582,7 +610,9 @@ fn parse_aggfunc0(pair: Pair<Rule>) -> Result<Expr> {
         arg1,
         interval,
         from.into(),
+        global,
         groupby_expr,
+        expand,
         where_expr,
         having_expr,
     ))
@@ -618,6 +648,9 @@ fn parse_aggfunc1(pair: Pair<Rule>) -> Result<Expr> {
         .map(|pair| pair.into_inner())
         .map(|v| v.as_str().to_string());
 
+    let global = extract_rule(inner_pairs.clone(), Rule::global_keyword).is_some()
+        || extract_rule(inner_pairs.clone(), Rule::from_all).is_some();
+
     let where_expr = extract_rule(inner_pairs.clone(), Rule::where_expr)
         .map(|pair| pair.into_inner())
         .map(generate_ast);
@@ -626,6 +659,10 @@ fn parse_aggfunc1(pair: Pair<Rule>) -> Result<Expr> {
         .map(|pair| pair.into_inner())
         .map(generate_ast);
 
+    let expand = extract_rule(inner_pairs.clone(), Rule::expand_clause)
+        .map(|pair| parse_expand_clause(pair, name))
+        .transpose()?;
+
     let having_expr = extract_rule(inner_pairs.clone(), Rule::having_expr)
         .map(build_term)
         .map(|e| match e {
@@ -639,7 +676,9 @@ fn parse_aggfunc1(pair: Pair<Rule>) -> Result<Expr> {
         arg2,
         interval,
         from,
+        global,
         groupby_expr,
+        expand,
         where_expr,
         having_expr,
     ))
@@ -661,6 +700,25 @@ pub fn parse_having_expr(pairs: Pairs<Rule>) -> Expr {
     })
 }
 
+// the values of the remaining keys are summed into the other column, which is the value of
+// the aggregation over their events only for count and sum
+fn parse_expand_clause(pair: Pair<Rule>, agg_func: &str) -> Result<ExpandExpr> {
+    let inner_pairs = pair.into_inner().collect::<Vec<_>>();
+    let top = extract_rule(inner_pairs.clone(), Rule::expand_top)
+        .and_then(|pair| pair.into_inner().next())
+        .map(|integer| integer.as_str().parse::<usize>())
+        .transpose()
+        .context("Cannot parse the number of expanded keys")?;
+    let other = extract_rule(inner_pairs, Rule::expand_other).is_some();
+    if other && !matches!(agg_func, "count" | "sum") {
+        bail!(
+            "EXPAND WITH OTHER is supported only for count and sum, not for {}",
+            agg_func
+        );
+    }
+    Ok(ExpandExpr { top, other })
+}
+
 pub fn parse_min_or_max(s: &str) -> HavingExprType {
     match s.to_ascii_lowercase().as_str() {
         "min" => HavingExprType::MIN,
@@ -674,7 +732,9 @@ fn match_aggr0(
     arg1: Expr,
     interval: NewInterval,
     from: Option<SmallString>,
+    global: bool,
     groupby_expr: Option<Expr>,
+    expand: Option<ExpandExpr>,
     where_expr: Option<Expr>,
     having_expr: Option<HavingExpr>,
 ) -> Expr {
@@ -686,7 +746,9 @@ fn match_aggr0(
             agg_expr: Box::new(arg1),
             when: interval,
             from,
+            global,
             groupby: groupby_expr,
+            expand,
             cond: where_expr,
             having: having_expr,
         }),
@@ -700,7 +762,9 @@ fn match_aggr1(
     arg2: Expr,
     interval: NewInterval,
     from: Option<SmallString>,
+    global: bool,
     groupby_expr: Option<Expr>,
+    expand: Option<ExpandExpr>,
     where_expr: Option<Expr>,
     having_expr: Option<HavingExpr>,
 ) -> Expr {
@@ -712,7 +776,9 @@ fn match_aggr1(
             agg_expr: Box::new(arg1),
             when: interval,
             from: from,
+            global,
             groupby: groupby_expr,
+            expand,
             cond: where_expr,
             having: having_expr,
         }),
@@ -905,6 +971,54 @@ mod tests {
         let ast = generate_ast(successful_parse.unwrap());
     }
 
+    #[test]
+    fn test_aggregate_with_groupby_expand() {
+        let expand_of = |expr: &str| {
+            let parsed = ExprParser::parse(Rule::single_expression, expr).unwrap();
+            match generate_ast(parsed) {
+                Expr::Alias(_, expr) => match *expr {
+                    Expr::Aggr(aggr) => aggr.expand,
+                    other => panic!("Unexpected expression {:?}", other),
+                },
+                Expr::Aggr(aggr) => aggr.expand,
+                other => panic!("Unexpected expression {:?}", other),
+            }
+        };
+        assert_eq!(expand_of("count(*) over past group by category"), None);
+        assert_eq!(
+            expand_of("count(*) over past group by category expand"),
+            Some(ExpandExpr {
+                top: None,
+                other: false
+            })
+        );
+        assert_eq!(
+            expand_of("count(*) over past group by category EXPAND TOP 20 WITH OTHER as c"),
+            Some(ExpandExpr {
+                top: Some(20),
+                other: true
+            })
+        );
+        assert_eq!(
+            expand_of("sum(amount) over past group by category expand with other"),
+            Some(ExpandExpr {
+                top: None,
+                other: true
+            })
+        );
+        for expr in [
+            "avg(amount) over past group by category expand with other",
+            "max(amount) over past group by category expand top 3 with other as m",
+        ] {
+            let parsed = ExprParser::parse(Rule::single_expression, expr).unwrap();
+            assert!(
+                format!("{:?}", generate_ast(parsed)).contains("ParsingError"),
+                "{}",
+                expr
+            );
+        }
+    }
+
     #[test]
     fn test_aggregate_with_groupby_alias() {
         let successful_parse = ExprParser::parse(
@@ -1066,6 +1180,69 @@ mod tests {
         println!("{:?}", ast);
     }
 
+    #[test]
+    fn test_aggregate_global() {
+        for expr_str in [
+            "avg(value) over last 7 days global",
+            "avg(value) over last 7 days global where event_type = 'order'",
+            "avg(value) over last 7 days from * where value > 0",
+        ] {
+            let successful_parse = ExprParser::parse(Rule::single_expression, expr_str);
+            let ast = generate_ast(successful_parse.unwrap());
+            assert!(ast.as_aggr().unwrap().global, "{}", expr_str);
+        }
+
+        let successful_parse = ExprParser::parse(
+            Rule::single_expression,
+            "avg(value) over last 7 days from order",
+        );
+        let ast = generate_ast(successful_parse.unwrap());
+        assert!(!ast.as_aggr().unwrap().global);
+    }
+
+    #[test]
+    fn test_parse_asof() {
+        let ast = Expr::from_str("asof(profile.address.country)").unwrap();
+        assert_eq!(
+            ast,
+            Expr::AsOf(
+                EventType("profile".into()),
+                AttributeKey::from_str("address.country").unwrap()
+            )
+        );
+        assert!(matches!(
+            Expr::from_str("asof(country)").unwrap(),
+            Expr::ParsingError(_)
+        ));
+    }
+
+    #[test]
+    fn test_parse_label() {
+        let ast = Expr::from_str("label count(*) over next 30 days as churned").unwrap();
+        assert!(matches!(&ast, Expr::Label(inner) if matches!(**inner, Expr::Alias(_, _))));
+
+        let query = Expr::from_str(
+            "SELECT count(*) over past, LABEL any(event_type = 'churn') over next 30 days as churned FOR @entities := user",
+        )
+        .unwrap();
+        let select = query.into_select().unwrap();
+        assert_eq!(select.expressions.len(), 2);
+        assert!(matches!(select.expressions[1], Expr::Label(_)));
+
+        // attributes called label are still attributes
+        assert_eq!(
+            Expr::from_str("label + 1").unwrap(),
+            Expr::Add(
+                Box::new(Expr::AttrUntyped(AttributeKey::from_str("label").unwrap())),
+                Box::new(Expr::LitInt(1))
+            )
+        );
+        assert!(matches!(
+            Expr::from_str("label_value").unwrap(),
+            Expr::AttrUntyped(_)
+        ));
+    }
+
     #[test]
     fn test_parse_nth() {
         let successful_parse =
@@ -1104,6 +1281,7 @@ mod tests {
             event_types: vec![],
             event: None,
             event_on_obs_date: None,
+            agg_cache: None,
         };
         let successful_parse = ExprParser::parse(Rule::single_expression, "pressure");
         let ast = generate_ast(successful_parse.unwrap());
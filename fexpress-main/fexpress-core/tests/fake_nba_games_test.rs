@@ -1,9 +1,9 @@
 #[cfg(test)]
 mod tests {
-    use crate::dataframe::DataFrame;
     use crate::event::{AttributeName, EntityID, EntityType, Event, EventType};
     use crate::event_index::{EventContext, EventScopeConfig, QueryConfig, RawQuery};
     use crate::event_store::EventStore;
+    use crate::feature_frame::FeatureFrame;
     use crate::map::HashMap;
     use crate::obs_dates::ObservationDatesConfig;
     use crate::tests::fake_nba::generate_nba_game_events;
@@ -22,6 +22,11 @@ mod tests {
         let event_store_settings = QueryConfig {
             parallel: true,
             include_events_on_obs_date: true,
+            strict_leakage: false,
+            business_calendar: None,
+            time_zone: None,
+            time_zone_attribute: None,
+            expanded_keys: Default::default(),
         };
         let entity_query = EventScopeConfig::AllEvents;
         let mut event_context = EventContext::new_memory();
@@ -68,6 +73,11 @@ mod tests {
         let query_config = QueryConfig {
             include_events_on_obs_date: true,
             parallel: false,
+            strict_leakage: false,
+            business_calendar: None,
+            time_zone: None,
+            time_zone_attribute: None,
+            expanded_keys: Default::default(),
         };
 
         let features = event_context
@@ -81,7 +91,7 @@ mod tests {
             )
             .unwrap();
 
-        let dataframe = DataFrame::new(features.0, features.1);
+        let dataframe = FeatureFrame::from_records(features.0, features.1).unwrap();
         dataframe.head(10).display();
     }
 }
@@ -108,11 +108,11 @@ mod tests {
     use crate::map::HashSet;
     use itertools::iproduct;
 
-    use crate::dataframe::DataFrame;
     use crate::eval::EvalContext;
     use crate::event::EntityType;
     use crate::event_index::{EventContext, EventScopeConfig, QueryConfig, RawQuery};
     use crate::event_store::EventStore;
+    use crate::feature_frame::FeatureFrame;
     use crate::interval::DatePart;
     use crate::obs_dates::{ConditionalEvents, Fixed, Interval, ObsDate, ObservationDatesConfig};
 
@@ -155,7 +155,7 @@ mod tests {
     //     ).unwrap();
     //
     //     println!("{:?}", features.1.first().unwrap());
-    //     let dataframe = DataFrame::new(features.0, features.1);
+    //     let dataframe = FeatureFrame::from_records(features.0, features.1).unwrap();
     //     dataframe.head(100).display();
     // }
 
@@ -168,18 +168,38 @@ mod tests {
             QueryConfig {
                 parallel: false,
                 include_events_on_obs_date: false,
+                strict_leakage: false,
+                business_calendar: None,
+                time_zone: None,
+                time_zone_attribute: None,
+                expanded_keys: Default::default(),
             },
             QueryConfig {
                 parallel: true,
                 include_events_on_obs_date: false,
+                strict_leakage: false,
+                business_calendar: None,
+                time_zone: None,
+                time_zone_attribute: None,
+                expanded_keys: Default::default(),
             },
             QueryConfig {
                 parallel: false,
                 include_events_on_obs_date: true,
+                strict_leakage: false,
+                business_calendar: None,
+                time_zone: None,
+                time_zone_attribute: None,
+                expanded_keys: Default::default(),
             },
             QueryConfig {
                 parallel: true,
                 include_events_on_obs_date: true,
+                strict_leakage: false,
+                business_calendar: None,
+                time_zone: None,
+                time_zone_attribute: None,
+                expanded_keys: Default::default(),
             },
         ];
 
@@ -241,7 +261,7 @@ mod tests {
                 )
                 .unwrap();
 
-            let dataframe = DataFrame::new(features.0, features.1);
+            let dataframe = FeatureFrame::from_records(features.0, features.1).unwrap();
             dataframe.head(10).display();
             let dataframe_dedup = dataframe.drop_duplicates(vec!["user".into()]).unwrap();
 
@@ -263,6 +283,11 @@ mod tests {
         let settings = QueryConfig {
             parallel: false,
             include_events_on_obs_date: false,
+            strict_leakage: false,
+            business_calendar: None,
+            time_zone: None,
+            time_zone_attribute: None,
+            expanded_keys: Default::default(),
         };
         let mut entity_types = HashSet::new();
         entity_types.insert(EntityType("user".into()));
@@ -1,12 +1,14 @@
 import json
 from collections import Counter
 from dataclasses import dataclass
+from datetime import datetime
 from enum import Enum
 from typing import List
 
 import pandas as pd
 
 from fexpress.fexpress import EventContext
+from fexpress.fexpress import register_calendar as _register_calendar
 
 from fexpress.sdk.event import Event
 from fexpress.sdk.query_config import QueryConfig
@@ -17,6 +19,13 @@ from fexpress.sdk.observation_dates_config import (
 from fexpress.sdk.event_scope_config import event_scope_config_to_dict
 
 
+def register_calendar(name: str, calendar: dict):
+    """Registers a holiday calendar used by `business_calendar` of the query config and by
+    the calendar observation dates, e.g. {"weekend": [6, 7], "holidays": ["2023-12-25"]}.
+    The calendars are shared by all the event contexts, registering a name again replaces it"""
+    _register_calendar(name, json.dumps(calendar))
+
+
 class FeatureExpress:
     def __init__(self):
         self.event_context = EventContext()
@@ -25,6 +34,105 @@ class FeatureExpress:
         event_json = json.dumps(event.to_dict())
         self.event_context.new_json_event(event_json)
 
+    def declare_schema(self, schema: dict):
+        """Declares the attributes of an event type, e.g.
+        {"event_type": "order", "mode": "Coerce", "entity_types": ["user"],
+         "attributes": {"amount": {"value_type": "Num", "required": True},
+                        "channel": {"value_type": "Str", "categories": ["web", "app"]}}}
+        The mode is "Strict" (default), "Coerce" or "Warn"."""
+        self.event_context.declare_schema(json.dumps(schema))
+
+    def schema_warnings(self) -> List[dict]:
+        """Violations of the schemas declared in "Warn" mode"""
+        return json.loads(self.event_context.schema_warnings())
+
+    def schema_versions(self, event_type: str) -> List[dict]:
+        """Attribute types of the event type after every change, the types widen
+        along Bool -> Int -> Num and Str -> Date -> DateTime"""
+        return json.loads(self.event_context.schema_versions(event_type))
+
+    def upsert(self, event: Event):
+        """Inserts the event or replaces the event with the same event_id"""
+        self.event_context.upsert_json_event(json.dumps(event.to_dict()))
+
+    def delete_event(self, event_id: str) -> bool:
+        """Removes the event with the id, returns whether it existed"""
+        return self.event_context.delete_event(event_id)
+
+    def delete_entity(self, entity_type: str, entity_id: str) -> int:
+        """Removes all the events of the entity, returns the number of removed events"""
+        return self.event_context.delete_entity(entity_type, entity_id)
+
+    def apply_retention(self, policy: dict, now: datetime = None) -> int:
+        """Removes the events older than the time to live of their event type, e.g.
+        {"default_ttl": {"date_part": "Day", "nth": 365},
+         "ttl": {"click": {"date_part": "Day", "nth": 30}}}
+        `now` defaults to the current UTC time"""
+        now = now or datetime.utcnow()
+        return self.event_context.apply_retention(json.dumps(policy), now.isoformat())
+
+    def load_file(self, path: str, ingestion_config: dict) -> int:
+        return self.event_context.load_file(path, json.dumps(ingestion_config))
+
+    def load_arrow(self, batch, mapping: dict) -> int:
+        """Loads a pyarrow RecordBatch (or Table) without copying the buffers"""
+        batches = batch.to_batches() if hasattr(batch, "to_batches") else [batch]
+        return sum(
+            self.event_context.load_arrow(b, json.dumps(mapping)) for b in batches
+        )
+
+    def load_parquet(self, path: str, mapping: dict) -> int:
+        return self.event_context.load_parquet(path, json.dumps(mapping))
+
+    @classmethod
+    def open_durable(
+        cls, path: str, sync: bool = False, compact_after_events: int = None
+    ) -> "FeatureExpress":
+        """Event store that logs every new event to the directory `path` and restores
+        the logged events when it is opened again"""
+        fx = cls()
+        fx.event_context.open_durable(
+            path,
+            json.dumps({"sync": sync, "compact_after_events": compact_after_events}),
+        )
+        return fx
+
+    def compact(self):
+        """Compacts the log of a durable event store into a snapshot"""
+        self.event_context.compact()
+
+    def save_snapshot(self, path: str) -> int:
+        """Writes the events to a snapshot file that can be loaded with `load_snapshot`"""
+        return self.event_context.save_snapshot(path)
+
+    def load_snapshot(self, path: str) -> int:
+        """Replaces the events with the events of a snapshot written by `save_snapshot`"""
+        return self.event_context.load_snapshot(path)
+
+    def create_attribute_index(self, attribute: str, kind: str = "hash"):
+        """Declares a secondary index on an attribute used by where clauses comparing it
+        with literals, "hash" indices answer `=` and `in`, "btree" indices answer ranges too"""
+        self.event_context.create_attribute_index(attribute, kind)
+
+    def query_arrow(
+        self,
+        obs_dates_config: ObservationDateConfig,
+        event_scope_config,
+        query_config,
+        query,
+    ):
+        """Same as `query` but returns a pyarrow RecordBatch"""
+        return self.event_context.query_arrow(
+            obs_dates_config_json=json.dumps(
+                observation_dates_config_to_dict(obs_dates_config)
+            ),
+            event_scope_config_json=json.dumps(
+                event_scope_config_to_dict(event_scope_config)
+            ),
+            query_config_json=json.dumps(query_config.to_dict()),
+            query=query,
+        )
+
     def query(
         self,
         obs_dates_config: ObservationDateConfig,
@@ -45,8 +153,15 @@ class FeatureExpress:
             query_config_json=query_config_json,
             query=query,
         )
-        column_names, rows = data
-        return pd.DataFrame(rows, columns=column_names)
+        column_names, rows, index_names, index_rows = data
+        index = pd.DataFrame(index_rows, columns=index_names)
+        index["obs_dt"] = pd.to_datetime(index["obs_dt"])
+        # one index level per entity type, obs_dt and event_id for joining the labels
+        df = pd.DataFrame(
+            rows, columns=column_names, index=pd.MultiIndex.from_frame(index)
+        )
+        df.attrs["labels"] = self.event_context.labels(query)
+        return df
 
     def __getattr__(self, name):
         # Check if the attribute is a callable method on the event_context
@@ -1,4 +1,4 @@
-from dataclasses import dataclass
+from dataclasses import dataclass, field
 from typing import List, Any, Dict, Optional, Union, TypeVar, Callable, Type, cast
 from enum import Enum
 
@@ -46,6 +46,21 @@ def from_int(x: Any) -> int:
     return x
 
 
+def from_bool(x: Any) -> bool:
+    assert isinstance(x, bool)
+    return x
+
+
+def from_float(x: Any) -> float:
+    assert isinstance(x, (float, int)) and not isinstance(x, bool)
+    return float(x)
+
+
+def to_float(x: Any) -> float:
+    assert isinstance(x, float)
+    return x
+
+
 def to_enum(c: Type[EnumT], x: Any) -> EnumT:
     assert isinstance(x, c)
     return x.value
@@ -191,6 +206,329 @@ class Interval:
         return result
 
 
+@dataclass
+class RandomPerEntity:
+    """Draws `n` uniformly distributed observation dates per entity"""
+
+    entity_types: List[str]
+    n: int
+    seed: int
+    within_activity_span: bool = False
+
+    @staticmethod
+    def from_dict(obj: Any) -> "RandomPerEntity":
+        assert isinstance(obj, dict)
+        entity_types = from_list(from_str, obj.get("entity_types"))
+        n = from_int(obj.get("n"))
+        seed = from_int(obj.get("seed"))
+        within_activity_span = from_bool(obj.get("within_activity_span", False))
+        return RandomPerEntity(entity_types, n, seed, within_activity_span)
+
+    def to_dict(self) -> dict:
+        result: dict = {}
+        result["entity_types"] = from_list(from_str, self.entity_types)
+        result["n"] = from_int(self.n)
+        result["seed"] = from_int(self.seed)
+        result["within_activity_span"] = from_bool(self.within_activity_span)
+        return result
+
+
+@dataclass
+class Poisson:
+    """Draws the observation dates of every entity from a Poisson process, `rate` is the
+    expected number of observation dates per day of activity of the entity
+    """
+
+    entity_types: List[str]
+    rate: float
+    seed: int
+
+    @staticmethod
+    def from_dict(obj: Any) -> "Poisson":
+        assert isinstance(obj, dict)
+        entity_types = from_list(from_str, obj.get("entity_types"))
+        rate = from_float(obj.get("rate"))
+        seed = from_int(obj.get("seed"))
+        return Poisson(entity_types, rate, seed)
+
+    def to_dict(self) -> dict:
+        result: dict = {}
+        result["entity_types"] = from_list(from_str, self.entity_types)
+        result["rate"] = to_float(from_float(self.rate))
+        result["seed"] = from_int(self.seed)
+        return result
+
+
+@dataclass
+class StratifiedByLabel:
+    """Draws the same number of observation dates with a positive and a negative label, the
+    label is positive if an event matching the condition happens for the entity within
+    `horizon_days` after the observation date
+    """
+
+    entity_types: List[str]
+    condition: str
+    horizon_days: int
+    n_per_label: int
+    candidates_per_entity: int
+    seed: int
+
+    @staticmethod
+    def from_dict(obj: Any) -> "StratifiedByLabel":
+        assert isinstance(obj, dict)
+        entity_types = from_list(from_str, obj.get("entity_types"))
+        condition = from_str(obj.get("condition"))
+        horizon_days = from_int(obj.get("horizon_days"))
+        n_per_label = from_int(obj.get("n_per_label"))
+        candidates_per_entity = from_int(obj.get("candidates_per_entity"))
+        seed = from_int(obj.get("seed"))
+        return StratifiedByLabel(
+            entity_types,
+            condition,
+            horizon_days,
+            n_per_label,
+            candidates_per_entity,
+            seed,
+        )
+
+    def to_dict(self) -> dict:
+        result: dict = {}
+        result["entity_types"] = from_list(from_str, self.entity_types)
+        result["condition"] = from_str(self.condition)
+        result["horizon_days"] = from_int(self.horizon_days)
+        result["n_per_label"] = from_int(self.n_per_label)
+        result["candidates_per_entity"] = from_int(self.candidates_per_entity)
+        result["seed"] = from_int(self.seed)
+        return result
+
+
+@dataclass
+class Period:
+    date_part: DatePart
+    nth: int
+
+    @staticmethod
+    def from_dict(obj: Any) -> "Period":
+        assert isinstance(obj, dict)
+        date_part = DatePart(obj.get("date_part"))
+        nth = from_int(obj.get("nth"))
+        return Period(date_part, nth)
+
+    def to_dict(self) -> dict:
+        result: dict = {}
+        result["date_part"] = to_enum(DatePart, self.date_part)
+        result["nth"] = from_int(self.nth)
+        return result
+
+
+@dataclass
+class EventAnchorClass:
+    """The observation date is the period before or after the event"""
+
+    before: Optional[Period] = None
+    after: Optional[Period] = None
+
+    @staticmethod
+    def from_dict(obj: Any) -> "EventAnchorClass":
+        assert isinstance(obj, dict)
+        before = from_union([Period.from_dict, from_none], obj.get("Before"))
+        after = from_union([Period.from_dict, from_none], obj.get("After"))
+        return EventAnchorClass(before, after)
+
+    def to_dict(self) -> dict:
+        result: dict = {}
+        if self.before is not None:
+            result["Before"] = to_class(Period, self.before)
+        if self.after is not None:
+            result["After"] = to_class(Period, self.after)
+        return result
+
+
+class EventAnchorEnum(Enum):
+    END_OF_DAY = "EndOfDay"
+
+
+def event_anchor_from_dict(s: Any) -> Union[EventAnchorClass, EventAnchorEnum]:
+    return from_union([EventAnchorClass.from_dict, EventAnchorEnum], s)
+
+
+def event_anchor_to_dict(x: Union[EventAnchorClass, EventAnchorEnum]) -> Any:
+    return from_union(
+        [
+            lambda x: to_class(EventAnchorClass, x),
+            lambda x: to_enum(EventAnchorEnum, x),
+        ],
+        x,
+    )
+
+
+@dataclass
+class RelativeToEvents:
+    """Moves event based observation dates relative to their events and thins them out,
+    `one_per_day` keeps only the first observation date of each day and `min_gap` drops the
+    observation dates closer than the gap to the previous kept one. The days of `EndOfDay` and
+    `one_per_day` are in `time_zone`, the time zone of the query if not set
+    """
+
+    events: Union["ObservationDatesConfigClass", "ObservationDatesConfigEnum"]
+    anchor: Optional[Union[EventAnchorClass, EventAnchorEnum]] = None
+    one_per_day: bool = False
+    min_gap: Optional[Period] = None
+    time_zone: Optional[str] = None
+
+    @staticmethod
+    def from_dict(obj: Any) -> "RelativeToEvents":
+        assert isinstance(obj, dict)
+        events = observation_dates_config_from_dict(obj.get("events"))
+        anchor = from_union([event_anchor_from_dict, from_none], obj.get("anchor"))
+        one_per_day = from_bool(obj.get("one_per_day", False))
+        min_gap = from_union([Period.from_dict, from_none], obj.get("min_gap"))
+        time_zone = from_union([from_str, from_none], obj.get("time_zone"))
+        return RelativeToEvents(events, anchor, one_per_day, min_gap, time_zone)
+
+    def to_dict(self) -> dict:
+        result: dict = {}
+        result["events"] = observation_dates_config_to_dict(self.events)
+        if self.anchor is not None:
+            result["anchor"] = event_anchor_to_dict(self.anchor)
+        result["one_per_day"] = from_bool(self.one_per_day)
+        if self.min_gap is not None:
+            result["min_gap"] = to_class(Period, self.min_gap)
+        if self.time_zone is not None:
+            result["time_zone"] = from_str(self.time_zone)
+        return result
+
+
+@dataclass
+class EntityFilter:
+    """Conditions the entities must meet to get observation dates, the condition is evaluated
+    once per entity after the last event in the event store
+    """
+
+    entity_types: List[str]
+    condition: Optional[str] = None
+    min_first_seen: Optional[str] = None
+    max_last_seen: Optional[str] = None
+
+    @staticmethod
+    def from_dict(obj: Any) -> "EntityFilter":
+        assert isinstance(obj, dict)
+        entity_types = from_list(from_str, obj.get("entity_types"))
+        condition = from_union([from_str, from_none], obj.get("condition"))
+        min_first_seen = from_union([from_str, from_none], obj.get("min_first_seen"))
+        max_last_seen = from_union([from_str, from_none], obj.get("max_last_seen"))
+        return EntityFilter(entity_types, condition, min_first_seen, max_last_seen)
+
+    def to_dict(self) -> dict:
+        result: dict = {}
+        result["entity_types"] = from_list(from_str, self.entity_types)
+        if self.condition is not None:
+            result["condition"] = from_str(self.condition)
+        if self.min_first_seen is not None:
+            result["min_first_seen"] = from_str(self.min_first_seen)
+        if self.max_last_seen is not None:
+            result["max_last_seen"] = from_str(self.max_last_seen)
+        return result
+
+
+@dataclass
+class EntityFiltered:
+    """Selects the entities first and generates the observation dates only for them"""
+
+    filter: EntityFilter
+    dates: Union["ObservationDatesConfigClass", "ObservationDatesConfigEnum"]
+
+    @staticmethod
+    def from_dict(obj: Any) -> "EntityFiltered":
+        assert isinstance(obj, dict)
+        filter = EntityFilter.from_dict(obj.get("filter"))
+        dates = observation_dates_config_from_dict(obj.get("dates"))
+        return EntityFiltered(filter, dates)
+
+    def to_dict(self) -> dict:
+        result: dict = {}
+        result["filter"] = to_class(EntityFilter, self.filter)
+        result["dates"] = observation_dates_config_to_dict(self.dates)
+        return result
+
+
+class Frequency(Enum):
+    DAILY = "Daily"
+    MONTHLY = "Monthly"
+    WEEKLY = "Weekly"
+
+
+@dataclass
+class CalendarRule:
+    """A subset of the iCalendar RRULE, e.g. the last business day of every month is
+    CalendarRule(Frequency.MONTHLY, business_days_only=True, by_set_pos=-1)
+    """
+
+    frequency: Frequency
+    by_weekday: List[int] = field(default_factory=list)
+    by_month_day: List[int] = field(default_factory=list)
+    business_days_only: bool = False
+    by_set_pos: Optional[int] = None
+
+    @staticmethod
+    def from_dict(obj: Any) -> "CalendarRule":
+        assert isinstance(obj, dict)
+        frequency = Frequency(obj.get("frequency"))
+        by_weekday = from_list(from_int, obj.get("by_weekday", []))
+        by_month_day = from_list(from_int, obj.get("by_month_day", []))
+        business_days_only = from_bool(obj.get("business_days_only", False))
+        by_set_pos = from_union([from_int, from_none], obj.get("by_set_pos"))
+        return CalendarRule(
+            frequency, by_weekday, by_month_day, business_days_only, by_set_pos
+        )
+
+    def to_dict(self) -> dict:
+        result: dict = {}
+        result["frequency"] = to_enum(Frequency, self.frequency)
+        result["by_weekday"] = from_list(from_int, self.by_weekday)
+        result["by_month_day"] = from_list(from_int, self.by_month_day)
+        result["business_days_only"] = from_bool(self.business_days_only)
+        if self.by_set_pos is not None:
+            result["by_set_pos"] = from_int(self.by_set_pos)
+        return result
+
+
+@dataclass
+class CalendarSchedule:
+    """Observation dates generated by a calendar rule between the first and the last event of
+    every entity. `time` is the time of the day (midnight if not set), `calendar` the name of a
+    registered holiday calendar and `time_zone` defaults to the time zone of the query
+    """
+
+    entity_types: List[str]
+    rule: CalendarRule
+    time: Optional[str] = None
+    calendar: Optional[str] = None
+    time_zone: Optional[str] = None
+
+    @staticmethod
+    def from_dict(obj: Any) -> "CalendarSchedule":
+        assert isinstance(obj, dict)
+        entity_types = from_list(from_str, obj.get("entity_types"))
+        rule = CalendarRule.from_dict(obj.get("rule"))
+        time = from_union([from_str, from_none], obj.get("time"))
+        calendar = from_union([from_str, from_none], obj.get("calendar"))
+        time_zone = from_union([from_str, from_none], obj.get("time_zone"))
+        return CalendarSchedule(entity_types, rule, time, calendar, time_zone)
+
+    def to_dict(self) -> dict:
+        result: dict = {}
+        result["entity_types"] = from_list(from_str, self.entity_types)
+        result["rule"] = to_class(CalendarRule, self.rule)
+        if self.time is not None:
+            result["time"] = from_str(self.time)
+        if self.calendar is not None:
+            result["calendar"] = from_str(self.calendar)
+        if self.time_zone is not None:
+            result["time_zone"] = from_str(self.time_zone)
+        return result
+
+
 @dataclass
 class ObservationDatesConfigClass:
     """Generates the observation dates in equally spaced intervals
@@ -206,6 +544,12 @@ class ObservationDatesConfigClass:
     all_events_by_entity: Optional[List[str]] = None
     conditional_events: Optional[ConditionalEvents] = None
     entities_event_specific: Optional[EntitiesEventSpecific] = None
+    random_per_entity: Optional[RandomPerEntity] = None
+    poisson: Optional[Poisson] = None
+    stratified_by_label: Optional[StratifiedByLabel] = None
+    relative_to_events: Optional[RelativeToEvents] = None
+    entity_filtered: Optional[EntityFiltered] = None
+    calendar: Optional[CalendarSchedule] = None
 
     @staticmethod
     def from_dict(obj: Any) -> "ObservationDatesConfigClass":
@@ -225,6 +569,22 @@ class ObservationDatesConfigClass:
             [EntitiesEventSpecific.from_dict, from_none],
             obj.get("EntitiesEventSpecific"),
         )
+        random_per_entity = from_union(
+            [RandomPerEntity.from_dict, from_none], obj.get("RandomPerEntity")
+        )
+        poisson = from_union([Poisson.from_dict, from_none], obj.get("Poisson"))
+        stratified_by_label = from_union(
+            [StratifiedByLabel.from_dict, from_none], obj.get("StratifiedByLabel")
+        )
+        relative_to_events = from_union(
+            [RelativeToEvents.from_dict, from_none], obj.get("RelativeToEvents")
+        )
+        entity_filtered = from_union(
+            [EntityFiltered.from_dict, from_none], obj.get("EntityFiltered")
+        )
+        calendar = from_union(
+            [CalendarSchedule.from_dict, from_none], obj.get("Calendar")
+        )
         return ObservationDatesConfigClass(
             interval,
             fixed,
@@ -232,6 +592,12 @@ class ObservationDatesConfigClass:
             all_events_by_entity,
             conditional_events,
             entities_event_specific,
+            random_per_entity,
+            poisson,
+            stratified_by_label,
+            relative_to_events,
+            entity_filtered,
+            calendar,
         )
 
     def to_dict(self) -> dict:
@@ -262,6 +628,34 @@ class ObservationDatesConfigClass:
                 [lambda x: to_class(EntitiesEventSpecific, x), from_none],
                 self.entities_event_specific,
             )
+        if self.random_per_entity is not None:
+            result["RandomPerEntity"] = from_union(
+                [lambda x: to_class(RandomPerEntity, x), from_none],
+                self.random_per_entity,
+            )
+        if self.poisson is not None:
+            result["Poisson"] = from_union(
+                [lambda x: to_class(Poisson, x), from_none], self.poisson
+            )
+        if self.stratified_by_label is not None:
+            result["StratifiedByLabel"] = from_union(
+                [lambda x: to_class(StratifiedByLabel, x), from_none],
+                self.stratified_by_label,
+            )
+        if self.relative_to_events is not None:
+            result["RelativeToEvents"] = from_union(
+                [lambda x: to_class(RelativeToEvents, x), from_none],
+                self.relative_to_events,
+            )
+        if self.entity_filtered is not None:
+            result["EntityFiltered"] = from_union(
+                [lambda x: to_class(EntityFiltered, x), from_none],
+                self.entity_filtered,
+            )
+        if self.calendar is not None:
+            result["Calendar"] = from_union(
+                [lambda x: to_class(CalendarSchedule, x), from_none], self.calendar
+            )
         return result
 
 
@@ -1,5 +1,5 @@
 from dataclasses import dataclass
-from typing import Optional, Any, TypeVar, Type, cast
+from typing import Optional, Any, Dict, List, TypeVar, Type, Callable, cast
 
 
 T = TypeVar("T")
@@ -10,6 +10,11 @@ def from_bool(x: Any) -> bool:
     return x
 
 
+def from_str(x: Any) -> str:
+    assert isinstance(x, str)
+    return x
+
+
 def from_none(x: Any) -> Any:
     assert x is None
     return x
@@ -24,6 +29,16 @@ def from_union(fs, x):
     assert False
 
 
+def from_list(f: Callable[[Any], T], x: Any) -> List[T]:
+    assert isinstance(x, list)
+    return [f(y) for y in x]
+
+
+def from_dict(f: Callable[[Any], T], x: Any) -> Dict[str, T]:
+    assert isinstance(x, dict)
+    return {k: f(v) for (k, v) in x.items()}
+
+
 def to_class(c: Type[T], x: Any) -> dict:
     assert isinstance(x, c)
     return cast(Any, x).to_dict()
@@ -33,6 +48,11 @@ def to_class(c: Type[T], x: Any) -> dict:
 class QueryConfig:
     include_events_on_obs_date: Optional[bool] = None
     parallel: Optional[bool] = None
+    strict_leakage: Optional[bool] = None
+    business_calendar: Optional[str] = None
+    time_zone: Optional[str] = None
+    time_zone_attribute: Optional[str] = None
+    expanded_keys: Optional[Dict[str, List[str]]] = None
 
     @staticmethod
     def from_dict(obj: Any) -> "QueryConfig":
@@ -41,7 +61,27 @@ class QueryConfig:
             [from_bool, from_none], obj.get("include_events_on_obs_date")
         )
         parallel = from_union([from_bool, from_none], obj.get("parallel"))
-        return QueryConfig(include_events_on_obs_date, parallel)
+        strict_leakage = from_union([from_bool, from_none], obj.get("strict_leakage"))
+        business_calendar = from_union(
+            [from_str, from_none], obj.get("business_calendar")
+        )
+        time_zone = from_union([from_str, from_none], obj.get("time_zone"))
+        time_zone_attribute = from_union(
+            [from_str, from_none], obj.get("time_zone_attribute")
+        )
+        expanded_keys = from_union(
+            [lambda x: from_dict(lambda x: from_list(from_str, x), x), from_none],
+            obj.get("expanded_keys"),
+        )
+        return QueryConfig(
+            include_events_on_obs_date,
+            parallel,
+            strict_leakage,
+            business_calendar,
+            time_zone,
+            time_zone_attribute,
+            expanded_keys,
+        )
 
     def to_dict(self) -> dict:
         result: dict = {}
@@ -51,6 +91,25 @@ class QueryConfig:
             )
         if self.parallel is not None:
             result["parallel"] = from_union([from_bool, from_none], self.parallel)
+        if self.strict_leakage is not None:
+            result["strict_leakage"] = from_union(
+                [from_bool, from_none], self.strict_leakage
+            )
+        if self.business_calendar is not None:
+            result["business_calendar"] = from_union(
+                [from_str, from_none], self.business_calendar
+            )
+        if self.time_zone is not None:
+            result["time_zone"] = from_union([from_str, from_none], self.time_zone)
+        if self.time_zone_attribute is not None:
+            result["time_zone_attribute"] = from_union(
+                [from_str, from_none], self.time_zone_attribute
+            )
+        if self.expanded_keys is not None:
+            result["expanded_keys"] = from_union(
+                [lambda x: from_dict(lambda x: from_list(from_str, x), x), from_none],
+                self.expanded_keys,
+            )
         return result
 
 
@@ -1,17 +1,54 @@
+use std::sync::Arc;
+
+use arrow::pyarrow::PyArrowConvert;
+use arrow::record_batch::RecordBatch;
 use pyo3::exceptions;
 use pyo3::prelude::*;
 use pyo3::types::{PyList, PyString};
 
+use fexpress_core::calendar::{register_calendar as register_calendar_r, FixedHolidayCalendar};
 use fexpress_core::event::Event;
 use fexpress_core::event_index::{
     EventContext as EventContextR, EventScopeConfig, QueryConfig, RawQuery,
 };
+use fexpress_core::event_store::retention::RetentionPolicy;
+use fexpress_core::event_store::row_event_store::attribute_index::AttributeIndexKind;
+use fexpress_core::event_store::row_event_store::wal::DurabilityConfig;
+use fexpress_core::event_store::schema::EventTypeSchema;
 use fexpress_core::event_store::EventStore;
+use fexpress_core::feature_frame::FrameIndex;
+use fexpress_core::ingest::{EventMapping, IngestionConfig};
 
 use fexpress_core::obs_dates::ObservationDatesConfig;
 use fexpress_core::sstring::SmallString;
+use fexpress_core::types::Timestamp;
 use fexpress_core::value::Value;
 
+fn extract_raw_query(query: PyObject) -> PyResult<RawQuery> {
+    Python::with_gil(|py| {
+        let query = query.as_ref(py);
+        if let Ok(query_str) = query.downcast::<PyString>() {
+            Ok(RawQuery::SelectExpr(query_str.to_string()))
+        } else if let Ok(exprs_list) = query.downcast::<PyList>() {
+            let mut vec_exprs = Vec::new();
+            for item in exprs_list.iter() {
+                if let Ok(py_str) = item.downcast::<PyString>() {
+                    vec_exprs.push(py_str.to_string());
+                } else {
+                    return Err(PyErr::new::<exceptions::PyTypeError, _>(
+                        "List items must all be strings",
+                    ));
+                }
+            }
+            Ok(RawQuery::VecExpr(vec_exprs))
+        } else {
+            Err(PyErr::new::<exceptions::PyTypeError, _>(
+                "Expected a string or a list of strings",
+            ))
+        }
+    })
+}
+
 #[pyclass(unsendable)]
 pub struct EventContext {
     event_context: EventContextR,
@@ -33,10 +70,176 @@ impl EventContext {
         let event: Event = serde_json::from_str(&event).map_err(|err| {
             PyErr::new::<exceptions::PyValueError, String>(format!("{}", err).into())
         })?;
-        self.event_context.new_event(event);
+        self.event_context
+            .new_event(event)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err)))
+    }
+
+    /// Declares the schema of an event type, the events inserted afterwards are checked against it
+    pub fn declare_schema(&mut self, schema_json: String) -> PyResult<()> {
+        let schema: EventTypeSchema = serde_json::from_str(&schema_json)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{}", err)))?;
+        self.event_context
+            .declare_schema(schema)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err)))
+    }
+
+    /// Violations of the schemas declared in warn mode as a JSON list
+    pub fn schema_warnings(&self) -> PyResult<String> {
+        serde_json::to_string(&self.event_context.schema_warnings())
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{}", err)))
+    }
+
+    /// Versions of the inferred schema of the event type as a JSON list
+    pub fn schema_versions(&self, event_type: String) -> PyResult<String> {
+        serde_json::to_string(&self.event_context.schema_versions(&event_type))
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{}", err)))
+    }
+
+    /// Removes the event with the id, returns whether it existed
+    pub fn delete_event(&mut self, event_id: String) -> PyResult<bool> {
+        self.event_context
+            .delete_event(&event_id)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err)))
+    }
+
+    /// Inserts the event or replaces the event with the same event_id
+    pub fn upsert_json_event(&mut self, event: String) -> PyResult<()> {
+        let event: Event = serde_json::from_str(&event)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{}", err)))?;
+        self.event_context
+            .upsert(event)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err)))
+    }
+
+    /// Removes all the events of the entity, returns the number of removed events
+    pub fn delete_entity(&mut self, entity_type: String, entity_id: String) -> PyResult<usize> {
+        self.event_context
+            .delete_entity(&entity_type, &entity_id)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err)))
+    }
+
+    /// Removes the events older than the time to live of their event type at `now`
+    pub fn apply_retention(
+        &mut self,
+        retention_policy_json: String,
+        now: String,
+    ) -> PyResult<usize> {
+        let policy: RetentionPolicy = serde_json::from_str(&retention_policy_json)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{}", err)))?;
+        let now: Timestamp = now
+            .parse()
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{}", err)))?;
+        self.event_context
+            .apply_retention(&policy, &now)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err)))
+    }
+
+    /// Loads the events from a CSV or NDJSON file, returns the number of loaded events
+    pub fn load_file(&mut self, path: String, ingestion_config_json: String) -> PyResult<usize> {
+        let config: IngestionConfig = serde_json::from_str(&ingestion_config_json)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{}", err)))?;
+        self.event_context
+            .load_file(&path, &config)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err)))
+    }
+
+    /// Loads the rows of a `pyarrow.RecordBatch` as events, returns the number of loaded events
+    pub fn load_arrow(&mut self, batch: &PyAny, mapping_json: String) -> PyResult<usize> {
+        let batch = RecordBatch::from_pyarrow(batch)?;
+        let mapping: EventMapping = serde_json::from_str(&mapping_json)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{}", err)))?;
+        self.event_context
+            .load_record_batch(&batch, &mapping)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err)))
+    }
+
+    /// Loads the events from a Parquet file, returns the number of loaded events
+    pub fn load_parquet(&mut self, path: String, mapping_json: String) -> PyResult<usize> {
+        let mapping: EventMapping = serde_json::from_str(&mapping_json)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{}", err)))?;
+        self.event_context
+            .load_parquet(&path, &mapping)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err)))
+    }
+
+    /// Replaces the events with a durable event store that logs every insert to `path`
+    pub fn open_durable(&mut self, path: String, durability_config_json: String) -> PyResult<()> {
+        let config: DurabilityConfig = serde_json::from_str(&durability_config_json)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{}", err)))?;
+        self.event_context = EventContextR::new_durable(&path, config)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err)))?;
         Ok(())
     }
 
+    /// Compacts the log of a durable event store into a snapshot
+    pub fn compact(&self) -> PyResult<()> {
+        self.event_context
+            .compact()
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err)))
+    }
+
+    /// Writes the events to a snapshot file, returns the number of written events
+    pub fn save_snapshot(&self, path: String) -> PyResult<usize> {
+        self.event_context
+            .save_snapshot(&path)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err)))
+    }
+
+    /// Replaces the events with the events of a snapshot, returns the number of loaded events
+    pub fn load_snapshot(&mut self, path: String) -> PyResult<usize> {
+        self.event_context
+            .load_snapshot(&path)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err)))
+    }
+
+    /// Declares a secondary index on an attribute, `kind` is `hash` or `btree`
+    pub fn create_attribute_index(&self, attribute: String, kind: String) -> PyResult<()> {
+        let kind: AttributeIndexKind = kind
+            .parse()
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err)))?;
+        self.event_context
+            .create_attribute_index(&attribute, kind)
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err)))
+    }
+
+    /// Same as `query` but returns a `pyarrow.RecordBatch`
+    pub fn query_arrow(
+        &mut self,
+        py: Python,
+        obs_dates_config_json: String,
+        event_scope_config_json: String,
+        query: PyObject,
+        query_config_json: String,
+        experiment_id: Option<String>,
+        chunk_size: Option<usize>,
+    ) -> PyResult<PyObject> {
+        let raw_query = extract_raw_query(query)?;
+        let to_py_err = |err: serde_json::Error| {
+            PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err))
+        };
+        let obs_dates_config: ObservationDatesConfig =
+            serde_json::from_str(&obs_dates_config_json).map_err(to_py_err)?;
+        let event_scope_config: EventScopeConfig =
+            serde_json::from_str(&event_scope_config_json).map_err(to_py_err)?;
+        let query_config: QueryConfig =
+            serde_json::from_str(&query_config_json).map_err(to_py_err)?;
+
+        self.event_context
+            .extract_record_batch_from_expr(
+                obs_dates_config,
+                event_scope_config,
+                raw_query,
+                &query_config,
+                experiment_id.map(|v| v.into()),
+                chunk_size,
+            )
+            .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err)))?
+            .to_pyarrow(py)
+    }
+
+    /// Returns the feature names, the rows of features, the index column names
+    /// (one per entity type, `obs_dt` and `event_id` if set) and the rows of the index
     pub fn query(
         &mut self,
         obs_dates_config_json: String,
@@ -45,29 +248,13 @@ impl EventContext {
         query_config_json: String,
         experiment_id: Option<String>,
         chunk_size: Option<usize>,
-    ) -> PyResult<(Vec<SmallString>, Vec<Vec<Value>>)> {
-        let raw_query = Python::with_gil(|py| {
-            let query = query.as_ref(py);
-            if let Ok(query_str) = query.downcast::<PyString>() {
-                Ok(RawQuery::SelectExpr(query_str.to_string()))
-            } else if let Ok(exprs_list) = query.downcast::<PyList>() {
-                let mut vec_exprs = Vec::new();
-                for item in exprs_list.iter() {
-                    if let Ok(py_str) = item.downcast::<PyString>() {
-                        vec_exprs.push(py_str.to_string());
-                    } else {
-                        return Err(PyErr::new::<exceptions::PyTypeError, _>(
-                            "List items must all be strings",
-                        ));
-                    }
-                }
-                Ok(RawQuery::VecExpr(vec_exprs))
-            } else {
-                Err(PyErr::new::<exceptions::PyTypeError, _>(
-                    "Expected a string or a list of strings",
-                ))
-            }
-        })?;
+    ) -> PyResult<(
+        Vec<SmallString>,
+        Vec<Vec<Value>>,
+        Vec<SmallString>,
+        Vec<Vec<Value>>,
+    )> {
+        let raw_query = extract_raw_query(query)?;
 
         let obs_dates_config: ObservationDatesConfig = serde_json::from_str(&obs_dates_config_json)
             .map_err(|err| {
@@ -82,7 +269,7 @@ impl EventContext {
                 PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err).into())
             })?;
 
-        Ok(self
+        let (feature_names, rows, index) = self
             .event_context
             .extract_records_from_expr(
                 obs_dates_config,
@@ -94,7 +281,33 @@ impl EventContext {
             )
             .map_err(|err| {
                 PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err).into())
-            })?)
+            })?;
+        let index = FrameIndex::new(&index);
+        Ok((feature_names, rows, index.names(), index.to_rows()))
+    }
+
+    /// Names of the columns returned by `query` which are labels (targets) and not features
+    pub fn labels(&self, query: PyObject) -> PyResult<Vec<String>> {
+        extract_raw_query(query)?.label_names().map_err(|err| {
+            PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err).into())
+        })
+    }
+
+    /// Classification of the windows of the features relative to the observation date, as JSON
+    pub fn leakage_report(&self, query: PyObject, query_config_json: String) -> PyResult<String> {
+        let query_config: QueryConfig =
+            serde_json::from_str(&query_config_json).map_err(|err| {
+                PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err).into())
+            })?;
+        let report = self
+            .event_context
+            .analyze_leakage(extract_raw_query(query)?, &query_config)
+            .map_err(|err| {
+                PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err).into())
+            })?;
+        serde_json::to_string_pretty(&report).map_err(|err| {
+            PyErr::new::<exceptions::PyValueError, String>(format!("{}", err).into())
+        })
     }
 
     pub fn flush(&self) {
@@ -140,9 +353,19 @@ impl EventContext {
     }
 }
 
+/// Registers a holiday calendar under a name, the calendars are shared by all the event contexts
+#[pyfunction]
+fn register_calendar(name: String, calendar_json: String) -> PyResult<()> {
+    let calendar: FixedHolidayCalendar = serde_json::from_str(&calendar_json)
+        .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{}", err)))?;
+    register_calendar_r(&name, Arc::new(calendar))
+        .map_err(|err| PyErr::new::<exceptions::PyValueError, String>(format!("{:#}", err)))
+}
+
 /// A Python module implemented in Rust.
 #[pymodule]
 fn fexpress(_: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<EventContext>()?;
+    m.add_function(wrap_pyfunction!(register_calendar, m)?)?;
     Ok(())
 }